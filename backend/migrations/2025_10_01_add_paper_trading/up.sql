@@ -0,0 +1,78 @@
+-- Paper-trading accounts (one simulated account per user)
+CREATE TABLE paper_accounts (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL UNIQUE REFERENCES users(id) ON DELETE CASCADE,
+    starting_balance DOUBLE PRECISION NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Simulated balances, same shape as balances
+CREATE TABLE paper_balances (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    asset VARCHAR NOT NULL,
+    available DOUBLE PRECISION NOT NULL DEFAULT 0,
+    locked DOUBLE PRECISION NOT NULL DEFAULT 0,
+    total DOUBLE PRECISION NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE(user_id, asset)
+);
+
+-- Simulated orders, resting limit orders are matched by price-time priority
+CREATE TABLE paper_orders (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    symbol VARCHAR NOT NULL,
+    order_type VARCHAR NOT NULL CHECK (order_type IN ('market', 'limit')),
+    side VARCHAR NOT NULL CHECK (side IN ('buy', 'sell')),
+    quantity DOUBLE PRECISION NOT NULL,
+    price DOUBLE PRECISION, -- NULL for market orders
+    status VARCHAR NOT NULL, -- 'open', 'filled', 'cancelled', 'rejected'
+    filled_quantity DOUBLE PRECISION NOT NULL DEFAULT 0,
+    average_price DOUBLE PRECISION,
+    leverage DOUBLE PRECISION NOT NULL DEFAULT 1,
+    margin_type VARCHAR NOT NULL DEFAULT 'cross',
+    reserved_margin DOUBLE PRECISION NOT NULL DEFAULT 0,
+    fee DOUBLE PRECISION NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Simulated positions, same shape as positions plus funding tracking
+CREATE TABLE paper_positions (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    symbol VARCHAR NOT NULL,
+    side VARCHAR NOT NULL CHECK (side IN ('long', 'short')),
+    size DOUBLE PRECISION NOT NULL,
+    entry_price DOUBLE PRECISION NOT NULL,
+    mark_price DOUBLE PRECISION NOT NULL,
+    unrealized_pnl DOUBLE PRECISION NOT NULL DEFAULT 0,
+    realized_pnl DOUBLE PRECISION NOT NULL DEFAULT 0,
+    margin DOUBLE PRECISION NOT NULL,
+    leverage DOUBLE PRECISION NOT NULL,
+    liquidation_price DOUBLE PRECISION,
+    margin_type VARCHAR NOT NULL DEFAULT 'cross',
+    funding_paid DOUBLE PRECISION NOT NULL DEFAULT 0,
+    last_funding_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE(user_id, symbol)
+);
+
+-- Create indexes for better query performance
+CREATE INDEX idx_paper_balances_user_id ON paper_balances(user_id);
+CREATE INDEX idx_paper_orders_user_status ON paper_orders(user_id, status);
+CREATE INDEX idx_paper_orders_symbol_status ON paper_orders(symbol, status);
+CREATE INDEX idx_paper_positions_user_id ON paper_positions(user_id);
+
+CREATE TRIGGER update_paper_accounts_updated_at BEFORE UPDATE ON paper_accounts
+    FOR EACH ROW EXECUTE FUNCTION update_updated_at_column();
+CREATE TRIGGER update_paper_balances_updated_at BEFORE UPDATE ON paper_balances
+    FOR EACH ROW EXECUTE FUNCTION update_updated_at_column();
+CREATE TRIGGER update_paper_orders_updated_at BEFORE UPDATE ON paper_orders
+    FOR EACH ROW EXECUTE FUNCTION update_updated_at_column();
+CREATE TRIGGER update_paper_positions_updated_at BEFORE UPDATE ON paper_positions
+    FOR EACH ROW EXECUTE FUNCTION update_updated_at_column();
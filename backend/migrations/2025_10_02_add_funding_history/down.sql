@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS funding_payments;
+DROP TABLE IF EXISTS funding_rates;
+DROP TABLE IF EXISTS user_wallets;
@@ -0,0 +1,39 @@
+-- Wallet addresses linked to a user account
+CREATE TABLE user_wallets (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    wallet_address TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Funding rates sampled per market over time
+CREATE TABLE funding_rates (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    symbol VARCHAR NOT NULL,
+    market_id VARCHAR NOT NULL,
+    funding_rate DOUBLE PRECISION NOT NULL,
+    mark_price DOUBLE PRECISION NOT NULL,
+    recorded_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Funding paid (positive) or received (negative) per position and funding interval
+CREATE TABLE funding_payments (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    wallet_address TEXT NOT NULL,
+    symbol VARCHAR NOT NULL,
+    market_id VARCHAR NOT NULL,
+    side VARCHAR NOT NULL CHECK (side IN ('long', 'short')),
+    position_size DOUBLE PRECISION NOT NULL,
+    mark_price DOUBLE PRECISION NOT NULL,
+    funding_rate DOUBLE PRECISION NOT NULL,
+    payment DOUBLE PRECISION NOT NULL,
+    funding_time TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE(wallet_address, market_id, funding_time)
+);
+
+-- Create indexes for better query performance
+CREATE INDEX idx_user_wallets_user_id ON user_wallets(user_id);
+CREATE INDEX idx_funding_rates_symbol_recorded ON funding_rates(symbol, recorded_at DESC);
+CREATE INDEX idx_funding_payments_user_time ON funding_payments(user_id, funding_time DESC);
@@ -0,0 +1,25 @@
+-- Executed trades of linked wallets, reconciled from Kana Labs order history and trades
+CREATE TABLE fills (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    wallet_address TEXT NOT NULL,
+    symbol VARCHAR NOT NULL,
+    market_id VARCHAR NOT NULL,
+    order_id VARCHAR,
+    trade_id VARCHAR NOT NULL,
+    side VARCHAR NOT NULL CHECK (side IN ('buy', 'sell')),
+    size DOUBLE PRECISION NOT NULL,
+    price DOUBLE PRECISION NOT NULL,
+    fee DOUBLE PRECISION NOT NULL DEFAULT 0,
+    -- Realized PnL of the fill, before fees, matched against FIFO lots
+    realized_pnl DOUBLE PRECISION NOT NULL DEFAULT 0,
+    -- Same, matched against the average cost of the open position
+    realized_pnl_avg_cost DOUBLE PRECISION NOT NULL DEFAULT 0,
+    executed_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE(wallet_address, market_id, trade_id)
+);
+
+-- Create indexes for better query performance
+CREATE INDEX idx_fills_user_executed ON fills(user_id, executed_at DESC);
+CREATE INDEX idx_fills_wallet_market_executed ON fills(wallet_address, market_id, executed_at);
@@ -0,0 +1,18 @@
+-- Point-in-time account value of each linked wallet.
+-- Raw snapshots are downsampled to hourly and then daily resolution as they age.
+CREATE TABLE equity_snapshots (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    wallet_address TEXT NOT NULL,
+    equity DOUBLE PRECISION NOT NULL,
+    free_collateral DOUBLE PRECISION NOT NULL,
+    margin_used DOUBLE PRECISION NOT NULL,
+    unrealized_pnl DOUBLE PRECISION NOT NULL,
+    resolution VARCHAR NOT NULL DEFAULT 'raw' CHECK (resolution IN ('raw', 'hourly', 'daily')),
+    recorded_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE(wallet_address, resolution, recorded_at)
+);
+
+-- Create indexes for better query performance
+CREATE INDEX idx_equity_snapshots_user_recorded ON equity_snapshots(user_id, recorded_at);
+CREATE INDEX idx_equity_snapshots_resolution_recorded ON equity_snapshots(resolution, recorded_at);
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS trading_leaderboard;
+ALTER TABLE users DROP COLUMN IF EXISTS leaderboard_opt_out;
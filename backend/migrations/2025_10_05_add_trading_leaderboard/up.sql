@@ -0,0 +1,26 @@
+-- Let users keep their trading results off public leaderboards
+ALTER TABLE users ADD COLUMN leaderboard_opt_out BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Trading leaderboard, recomputed periodically per period ('24h', '7d', '30d', 'all')
+CREATE TABLE trading_leaderboard (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    period VARCHAR(10) NOT NULL CHECK (period IN ('24h', '7d', '30d', 'all')),
+    realized_pnl DOUBLE PRECISION NOT NULL,
+    roi DOUBLE PRECISION,
+    volume DOUBLE PRECISION NOT NULL,
+    win_rate DOUBLE PRECISION,
+    trade_count BIGINT NOT NULL,
+    pnl_rank INTEGER NOT NULL,
+    roi_rank INTEGER,
+    volume_rank INTEGER NOT NULL,
+    win_rate_rank INTEGER,
+    computed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE(user_id, period)
+);
+
+-- Create indexes for better query performance
+CREATE INDEX idx_trading_leaderboard_pnl ON trading_leaderboard(period, pnl_rank);
+CREATE INDEX idx_trading_leaderboard_roi ON trading_leaderboard(period, roi_rank);
+CREATE INDEX idx_trading_leaderboard_volume ON trading_leaderboard(period, volume_rank);
+CREATE INDEX idx_trading_leaderboard_win_rate ON trading_leaderboard(period, win_rate_rank);
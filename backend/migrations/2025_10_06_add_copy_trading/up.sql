@@ -0,0 +1,48 @@
+-- A follower copying a leader's trades
+CREATE TABLE copy_subscriptions (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    follower_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    leader_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- Capital set aside for this leader; trades are sized by allocation / leader equity
+    allocation DOUBLE PRECISION NOT NULL CHECK (allocation > 0),
+    max_leverage DOUBLE PRECISION NOT NULL CHECK (max_leverage >= 1),
+    max_trade_notional DOUBLE PRECISION NOT NULL CHECK (max_trade_notional > 0),
+    mode VARCHAR(10) NOT NULL DEFAULT 'paper' CHECK (mode IN ('paper', 'live')),
+    status VARCHAR(10) NOT NULL DEFAULT 'active' CHECK (status IN ('active', 'stopped')),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    stopped_at TIMESTAMPTZ,
+    UNIQUE(follower_id, leader_id),
+    CHECK (follower_id <> leader_id)
+);
+
+-- Every trade copied (or refused) for a subscription, linked to the leader's source trade
+CREATE TABLE copy_trades (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    subscription_id UUID NOT NULL REFERENCES copy_subscriptions(id) ON DELETE CASCADE,
+    follower_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    leader_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    source_type VARCHAR(20) NOT NULL CHECK (source_type IN ('fill', 'paper_order')),
+    source_id UUID NOT NULL,
+    symbol VARCHAR NOT NULL,
+    side VARCHAR(4) NOT NULL CHECK (side IN ('buy', 'sell')),
+    source_size DOUBLE PRECISION NOT NULL,
+    size DOUBLE PRECISION NOT NULL,
+    price DOUBLE PRECISION NOT NULL,
+    leverage DOUBLE PRECISION NOT NULL,
+    -- 'executed' (paper), 'pending' (live, awaiting the follower's signature), 'rejected' or 'failed'
+    status VARCHAR(10) NOT NULL CHECK (status IN ('executed', 'pending', 'rejected', 'failed')),
+    reason TEXT,
+    paper_order_id UUID REFERENCES paper_orders(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE(subscription_id, source_type, source_id)
+);
+
+-- Create indexes for better query performance
+CREATE INDEX idx_copy_subscriptions_leader_status ON copy_subscriptions(leader_id, status);
+CREATE INDEX idx_copy_subscriptions_follower ON copy_subscriptions(follower_id);
+CREATE INDEX idx_copy_trades_follower_created ON copy_trades(follower_id, created_at DESC);
+CREATE INDEX idx_copy_trades_source ON copy_trades(source_type, source_id);
+
+CREATE TRIGGER update_copy_subscriptions_updated_at BEFORE UPDATE ON copy_subscriptions
+    FOR EACH ROW EXECUTE FUNCTION update_updated_at_column();
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS activity_privacy_settings;
+DROP TABLE IF EXISTS activities;
@@ -0,0 +1,29 @@
+-- Events shown in the activity feed of a user's followers
+CREATE TABLE activities (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    activity_type VARCHAR(30) NOT NULL CHECK (activity_type IN (
+        'position_opened', 'position_closed', 'new_follower', 'referral_joined', 'profile_updated'
+    )),
+    visibility VARCHAR(10) NOT NULL CHECK (visibility IN ('public', 'followers', 'private')),
+    -- The other user involved: the new follower or the referred user
+    subject_user_id UUID REFERENCES users(id) ON DELETE CASCADE,
+    symbol VARCHAR,
+    side VARCHAR(5),
+    size DOUBLE PRECISION,
+    price DOUBLE PRECISION,
+    pnl DOUBLE PRECISION,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Per-event-type visibility chosen by the user; missing rows use the defaults
+CREATE TABLE activity_privacy_settings (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    activity_type VARCHAR(30) NOT NULL,
+    visibility VARCHAR(10) NOT NULL CHECK (visibility IN ('public', 'followers', 'private')),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, activity_type)
+);
+
+-- Create indexes for better query performance
+CREATE INDEX idx_activities_user_created ON activities(user_id, created_at DESC, id DESC);
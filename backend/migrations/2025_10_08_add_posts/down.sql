@@ -0,0 +1,12 @@
+DELETE FROM activities WHERE activity_type = 'post_created';
+ALTER TABLE activities DROP CONSTRAINT activities_activity_type_check;
+ALTER TABLE activities ADD CONSTRAINT activities_activity_type_check CHECK (activity_type IN (
+    'position_opened', 'position_closed', 'new_follower', 'referral_joined', 'profile_updated'
+));
+ALTER TABLE activities DROP COLUMN IF EXISTS post_id;
+
+DROP TABLE IF EXISTS post_likes;
+DROP TABLE IF EXISTS post_comments;
+DROP TABLE IF EXISTS posts;
+
+ALTER TABLE users DROP COLUMN IF EXISTS is_admin;
@@ -0,0 +1,58 @@
+-- Admins can moderate user content
+ALTER TABLE users ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Trade ideas shared by users, optionally with a snapshot of a market and of the
+-- author's position in it at posting time
+CREATE TABLE posts (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    content TEXT NOT NULL,
+    symbol VARCHAR,
+    market_price DOUBLE PRECISION,
+    position_side VARCHAR(5) CHECK (position_side IN ('long', 'short')),
+    position_size DOUBLE PRECISION,
+    entry_price DOUBLE PRECISION,
+    leverage DOUBLE PRECISION,
+    unrealized_pnl DOUBLE PRECISION,
+    like_count INTEGER NOT NULL DEFAULT 0,
+    comment_count INTEGER NOT NULL DEFAULT 0,
+    edited_at TIMESTAMPTZ,
+    -- Set when an admin hides the post; hidden posts are only visible to their author
+    hidden_at TIMESTAMPTZ,
+    hidden_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    hidden_reason TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE post_comments (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    post_id UUID NOT NULL REFERENCES posts(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    content TEXT NOT NULL,
+    hidden_at TIMESTAMPTZ,
+    hidden_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    hidden_reason TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE post_likes (
+    post_id UUID NOT NULL REFERENCES posts(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (post_id, user_id)
+);
+
+-- New posts show up in followers' activity feeds
+ALTER TABLE activities ADD COLUMN post_id UUID REFERENCES posts(id) ON DELETE CASCADE;
+ALTER TABLE activities DROP CONSTRAINT activities_activity_type_check;
+ALTER TABLE activities ADD CONSTRAINT activities_activity_type_check CHECK (activity_type IN (
+    'position_opened', 'position_closed', 'new_follower', 'referral_joined', 'profile_updated', 'post_created'
+));
+
+-- Create indexes for better query performance
+CREATE INDEX idx_posts_user_created ON posts(user_id, created_at DESC);
+CREATE INDEX idx_post_comments_post_created ON post_comments(post_id, created_at);
+
+CREATE TRIGGER update_posts_updated_at BEFORE UPDATE ON posts
+    FOR EACH ROW EXECUTE FUNCTION update_updated_at_column();
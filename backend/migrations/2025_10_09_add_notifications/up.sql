@@ -0,0 +1,20 @@
+-- In-app notifications, also pushed over the private WebSocket channel
+CREATE TABLE notifications (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    notification_type VARCHAR(30) NOT NULL CHECK (notification_type IN (
+        'new_follower', 'referral_signup', 'order_filled'
+    )),
+    title TEXT NOT NULL,
+    body TEXT NOT NULL,
+    -- The user who caused it, e.g. the new follower
+    actor_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    -- The order or fill it is about
+    entity_id UUID,
+    read_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Create indexes for better query performance
+CREATE INDEX idx_notifications_user_created ON notifications(user_id, created_at DESC);
+CREATE INDEX idx_notifications_user_unread ON notifications(user_id) WHERE read_at IS NULL;
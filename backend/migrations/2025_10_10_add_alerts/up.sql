@@ -0,0 +1,45 @@
+-- User-defined alert rules, evaluated by the alert worker
+CREATE TABLE alert_rules (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- price and funding_rate watch a market; pnl, margin_ratio and liquidation_distance
+    -- watch the user's positions (in one market, or all of them when symbol is NULL)
+    alert_type VARCHAR(30) NOT NULL CHECK (alert_type IN (
+        'price', 'funding_rate', 'pnl', 'margin_ratio', 'liquidation_distance'
+    )),
+    symbol VARCHAR,
+    condition VARCHAR(5) NOT NULL CHECK (condition IN ('above', 'below')),
+    threshold DOUBLE PRECISION NOT NULL,
+    -- One-shot rules deactivate after firing; repeating rules fire again on the next crossing
+    repeating BOOLEAN NOT NULL DEFAULT FALSE,
+    cooldown_minutes INTEGER NOT NULL DEFAULT 60 CHECK (cooldown_minutes >= 0),
+    is_active BOOLEAN NOT NULL DEFAULT TRUE,
+    -- Value seen on the previous evaluation, used to fire on crossings only
+    last_value DOUBLE PRECISION,
+    last_triggered_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Every time a rule fired, and whether the email went out
+CREATE TABLE alert_history (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    rule_id UUID NOT NULL REFERENCES alert_rules(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    alert_type VARCHAR(30) NOT NULL,
+    symbol VARCHAR,
+    condition VARCHAR(5) NOT NULL,
+    threshold DOUBLE PRECISION NOT NULL,
+    value DOUBLE PRECISION NOT NULL,
+    delivery_status VARCHAR(15) NOT NULL CHECK (delivery_status IN ('sent', 'rate_limited', 'failed')),
+    error TEXT,
+    triggered_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Create indexes for better query performance
+CREATE INDEX idx_alert_rules_active ON alert_rules(is_active, alert_type);
+CREATE INDEX idx_alert_rules_user ON alert_rules(user_id);
+CREATE INDEX idx_alert_history_user_triggered ON alert_history(user_id, triggered_at DESC);
+
+CREATE TRIGGER update_alert_rules_updated_at BEFORE UPDATE ON alert_rules
+    FOR EACH ROW EXECUTE FUNCTION update_updated_at_column();
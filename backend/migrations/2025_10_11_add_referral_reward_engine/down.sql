@@ -0,0 +1,22 @@
+DROP INDEX IF EXISTS idx_referral_rewards_referred;
+DROP INDEX IF EXISTS idx_referral_rewards_source;
+
+ALTER TABLE referral_rewards
+DROP CONSTRAINT IF EXISTS referral_rewards_status_check,
+DROP CONSTRAINT IF EXISTS referral_rewards_reward_type_check;
+
+ALTER TABLE referral_rewards
+DROP COLUMN IF EXISTS cancel_reason,
+DROP COLUMN IF EXISTS cancelled_at,
+DROP COLUMN IF EXISTS tier,
+DROP COLUMN IF EXISTS rate,
+DROP COLUMN IF EXISTS base_amount,
+DROP COLUMN IF EXISTS source_ref;
+
+-- Keep one reward per referee so the original constraint can be restored
+DELETE FROM referral_rewards r
+USING referral_rewards newer
+WHERE r.referred_user_id = newer.referred_user_id
+  AND (r.created_at, r.id) < (newer.created_at, newer.id);
+
+ALTER TABLE referral_rewards ADD CONSTRAINT referral_rewards_referred_user_id_key UNIQUE (referred_user_id);
@@ -0,0 +1,24 @@
+-- A referral now earns several rewards: one signup bonus, a fee rebate per
+-- referee fill and a bonus per referee deposit
+ALTER TABLE referral_rewards DROP CONSTRAINT IF EXISTS referral_rewards_referred_user_id_key;
+
+ALTER TABLE referral_rewards
+ADD COLUMN source_ref VARCHAR(255),
+ADD COLUMN base_amount DOUBLE PRECISION,
+ADD COLUMN rate DOUBLE PRECISION,
+ADD COLUMN tier INTEGER,
+ADD COLUMN cancelled_at TIMESTAMPTZ,
+ADD COLUMN cancel_reason TEXT;
+
+UPDATE referral_rewards SET status = 'pending' WHERE status IS NULL;
+
+ALTER TABLE referral_rewards
+ADD CONSTRAINT referral_rewards_reward_type_check
+    CHECK (reward_type IN ('signup', 'fee_rebate', 'deposit')),
+ADD CONSTRAINT referral_rewards_status_check
+    CHECK (status IN ('pending', 'paid', 'cancelled'));
+
+-- The event a reward was earned for (referee id, fill id or deposit reference),
+-- so replayed events never pay twice
+CREATE UNIQUE INDEX idx_referral_rewards_source ON referral_rewards(reward_type, source_ref);
+CREATE INDEX idx_referral_rewards_referred ON referral_rewards(referred_user_id);
@@ -0,0 +1,6 @@
+DROP INDEX IF EXISTS idx_users_referral_code;
+ALTER TABLE users DROP COLUMN IF EXISTS referral_code_id;
+
+DROP TABLE IF EXISTS referral_clicks;
+DROP TRIGGER IF EXISTS update_referral_codes_updated_at ON referral_codes;
+DROP TABLE IF EXISTS referral_codes;
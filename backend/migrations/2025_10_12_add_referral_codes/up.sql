@@ -0,0 +1,51 @@
+-- Referral codes separate from usernames, so renaming a user keeps their links working
+CREATE TABLE referral_codes (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    code VARCHAR(32) NOT NULL,
+    is_vanity BOOLEAN NOT NULL DEFAULT FALSE,
+    campaign VARCHAR(64),
+    expires_at TIMESTAMPTZ,
+    max_uses INTEGER CHECK (max_uses IS NULL OR max_uses > 0),
+    use_count INTEGER NOT NULL DEFAULT 0,
+    click_count INTEGER NOT NULL DEFAULT 0,
+    is_active BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Codes are matched case-insensitively
+CREATE UNIQUE INDEX idx_referral_codes_code ON referral_codes(LOWER(code));
+CREATE INDEX idx_referral_codes_user ON referral_codes(user_id, created_at);
+
+CREATE TRIGGER update_referral_codes_updated_at BEFORE UPDATE ON referral_codes
+    FOR EACH ROW EXECUTE FUNCTION update_updated_at_column();
+
+CREATE TABLE referral_clicks (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    referral_code_id UUID NOT NULL REFERENCES referral_codes(id) ON DELETE CASCADE,
+    -- Where the link was shared, e.g. a utm_source
+    source VARCHAR(255),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_referral_clicks_code ON referral_clicks(referral_code_id, created_at);
+
+-- The code a user signed up with
+ALTER TABLE users ADD COLUMN referral_code_id UUID REFERENCES referral_codes(id) ON DELETE SET NULL;
+CREATE INDEX idx_users_referral_code ON users(referral_code_id);
+
+-- Existing links use the username, so it becomes each user's first code
+INSERT INTO referral_codes (user_id, code, is_vanity, created_at)
+SELECT id, username, TRUE, COALESCE(created_at, NOW())
+FROM users
+WHERE username ~ '^[A-Za-z0-9_-]{1,32}$'
+ON CONFLICT DO NOTHING;
+
+UPDATE referral_codes rc
+SET use_count = (SELECT COUNT(*) FROM users u WHERE u.referred_by = rc.user_id);
+
+UPDATE users u
+SET referral_code_id = rc.id
+FROM referral_codes rc
+WHERE rc.user_id = u.referred_by;
@@ -0,0 +1,8 @@
+UPDATE referral_rewards SET status = 'pending' WHERE status = 'held';
+ALTER TABLE referral_rewards DROP CONSTRAINT referral_rewards_status_check;
+ALTER TABLE referral_rewards ADD CONSTRAINT referral_rewards_status_check
+    CHECK (status IN ('pending', 'paid', 'cancelled'));
+
+DROP TABLE IF EXISTS referral_flags;
+DROP TABLE IF EXISTS wallet_link_history;
+DROP TABLE IF EXISTS signup_fingerprints;
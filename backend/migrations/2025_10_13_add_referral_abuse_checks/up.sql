@@ -0,0 +1,48 @@
+-- Where each account signed up from, to spot one person creating several accounts
+CREATE TABLE signup_fingerprints (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    ip_address VARCHAR(45),
+    device_id VARCHAR(128),
+    user_agent TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_signup_fingerprints_ip ON signup_fingerprints(ip_address);
+CREATE INDEX idx_signup_fingerprints_device ON signup_fingerprints(device_id);
+
+-- Every account a wallet was ever linked to; user_wallets forgets unlinked wallets
+CREATE TABLE wallet_link_history (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    wallet_address TEXT NOT NULL,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    linked_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE(wallet_address, user_id)
+);
+
+INSERT INTO wallet_link_history (wallet_address, user_id, linked_at)
+SELECT wallet_address, user_id, created_at FROM user_wallets;
+
+-- Suspicious referral relationships, reviewed by admins
+CREATE TABLE referral_flags (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    referrer_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    referred_user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    reason VARCHAR(32) NOT NULL
+        CHECK (reason IN ('same_ip', 'same_device', 'wallet_reuse', 'circular_referral')),
+    details TEXT,
+    status VARCHAR(20) NOT NULL DEFAULT 'open'
+        CHECK (status IN ('open', 'dismissed', 'confirmed')),
+    reviewed_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    reviewed_at TIMESTAMPTZ,
+    review_note TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE(referred_user_id, reason)
+);
+
+CREATE INDEX idx_referral_flags_status ON referral_flags(status, created_at DESC);
+CREATE INDEX idx_referral_flags_referrer ON referral_flags(referrer_id);
+
+-- Rewards from flagged relationships wait in 'held' until the flag is reviewed
+ALTER TABLE referral_rewards DROP CONSTRAINT referral_rewards_status_check;
+ALTER TABLE referral_rewards ADD CONSTRAINT referral_rewards_status_check
+    CHECK (status IN ('pending', 'held', 'paid', 'cancelled'));
@@ -0,0 +1,11 @@
+DELETE FROM notifications WHERE notification_type = 'follow_request';
+ALTER TABLE notifications DROP CONSTRAINT notifications_notification_type_check;
+ALTER TABLE notifications ADD CONSTRAINT notifications_notification_type_check
+    CHECK (notification_type IN (
+        'new_follower', 'referral_signup', 'order_filled'
+    ));
+
+DROP TABLE IF EXISTS follow_requests;
+ALTER TABLE users DROP COLUMN IF EXISTS is_private;
+DROP TABLE IF EXISTS user_mutes;
+DROP TABLE IF EXISTS user_blocks;
@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS idx_users_last_active;
+DROP INDEX IF EXISTS idx_users_created_id;
+DROP INDEX IF EXISTS idx_users_bio_trgm;
+DROP INDEX IF EXISTS idx_users_username_trgm;
@@ -0,0 +1,11 @@
+-- Trigram indexes back the ILIKE filters and similarity ranking of user search
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX idx_users_username_trgm ON users USING GIN (username gin_trgm_ops);
+CREATE INDEX idx_users_bio_trgm ON users USING GIN ((COALESCE(bio, '')) gin_trgm_ops);
+
+-- Newest-first discovery pages by (created_at, id); every user needs a created_at
+UPDATE users SET created_at = NOW() WHERE created_at IS NULL;
+CREATE INDEX idx_users_created_id ON users(created_at DESC, id DESC);
+
+CREATE INDEX idx_users_last_active ON users(last_active DESC) WHERE last_active IS NOT NULL;
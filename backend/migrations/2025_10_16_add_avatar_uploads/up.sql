@@ -0,0 +1,18 @@
+-- Uploaded avatar images. Each upload is stored in several thumbnail sizes;
+-- uploads that are no longer a user's avatar are garbage-collected.
+CREATE TABLE avatar_uploads (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    -- Kept when the user is deleted so the files can still be collected
+    user_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    -- URL of the largest thumbnail, as stored in users.avatar_url
+    url TEXT NOT NULL,
+    storage_keys TEXT[] NOT NULL,
+    content_type VARCHAR(32) NOT NULL,
+    original_bytes INT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    -- Set by the garbage collector once the upload stops being the user's avatar
+    replaced_at TIMESTAMPTZ
+);
+
+CREATE INDEX idx_avatar_uploads_user ON avatar_uploads(user_id);
+CREATE INDEX idx_avatar_uploads_replaced ON avatar_uploads(replaced_at) WHERE replaced_at IS NOT NULL;
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS profile_audit_log;
+DROP TABLE IF EXISTS username_history;
@@ -0,0 +1,25 @@
+-- Previous usernames, so links to an old profile or referral code keep working
+CREATE TABLE username_history (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    old_username TEXT NOT NULL,
+    new_username TEXT NOT NULL,
+    changed_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_username_history_user ON username_history(user_id, changed_at DESC);
+CREATE INDEX idx_username_history_old ON username_history(LOWER(old_username), changed_at DESC);
+
+-- One row per changed profile field
+CREATE TABLE profile_audit_log (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- Who made the change
+    actor_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    field VARCHAR(32) NOT NULL CHECK (field IN ('username', 'bio', 'avatar_url')),
+    old_value TEXT,
+    new_value TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_profile_audit_log_user ON profile_audit_log(user_id, created_at DESC);
@@ -0,0 +1,11 @@
+DROP INDEX IF EXISTS idx_users_deletion_scheduled;
+ALTER TABLE users DROP COLUMN IF EXISTS deleted_at;
+ALTER TABLE users DROP COLUMN IF EXISTS deletion_scheduled_at;
+DELETE FROM notifications WHERE notification_type = 'data_export_ready';
+ALTER TABLE notifications DROP CONSTRAINT notifications_notification_type_check;
+ALTER TABLE notifications ADD CONSTRAINT notifications_notification_type_check
+    CHECK (notification_type IN (
+        'new_follower', 'follow_request', 'referral_signup', 'order_filled'
+    ));
+
+DROP TABLE IF EXISTS data_exports;
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS wallet_challenges;
+ALTER TABLE user_wallets DROP COLUMN IF EXISTS verified_at;
@@ -0,0 +1,14 @@
+-- Wallets are verified by signing a challenge; links made before this stay
+-- unverified until their owner signs one
+ALTER TABLE user_wallets ADD COLUMN verified_at TIMESTAMPTZ;
+
+-- Outstanding link challenges, one per user and wallet
+CREATE TABLE wallet_challenges (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    wallet_address TEXT NOT NULL,
+    nonce TEXT NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE(user_id, wallet_address)
+);
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS idx_referral_clicks_dedup;
+ALTER TABLE referral_clicks DROP COLUMN IF EXISTS ip_hash;
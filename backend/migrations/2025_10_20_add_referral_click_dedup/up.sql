@@ -0,0 +1,5 @@
+-- Hash of the visitor's IP, so repeat clicks on a link are only counted once
+-- per window
+ALTER TABLE referral_clicks ADD COLUMN ip_hash VARCHAR(64);
+
+CREATE INDEX idx_referral_clicks_dedup ON referral_clicks(referral_code_id, ip_hash, created_at);
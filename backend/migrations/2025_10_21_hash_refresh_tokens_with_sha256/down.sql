@@ -0,0 +1,2 @@
+-- The ended sessions are not restored
+DROP INDEX IF EXISTS idx_sessions_refresh_token_hash;
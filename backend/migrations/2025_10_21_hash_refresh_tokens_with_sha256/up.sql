@@ -0,0 +1,5 @@
+-- Sessions are now found by a SHA-256 of their refresh token. Those stored
+-- under a salted Argon2 hash can never be refreshed or logged out, so end them.
+DELETE FROM sessions WHERE refresh_token_hash LIKE '$argon2%';
+
+CREATE UNIQUE INDEX idx_sessions_refresh_token_hash ON sessions(refresh_token_hash);
@@ -0,0 +1,6 @@
+DROP INDEX IF EXISTS idx_copy_trades_pending;
+
+UPDATE copy_trades SET status = 'failed' WHERE status IN ('dismissed', 'expired', 'cancelled');
+ALTER TABLE copy_trades DROP CONSTRAINT copy_trades_status_check;
+ALTER TABLE copy_trades ADD CONSTRAINT copy_trades_status_check
+    CHECK (status IN ('executed', 'pending', 'rejected', 'failed'));
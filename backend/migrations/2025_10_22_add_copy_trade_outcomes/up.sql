@@ -0,0 +1,8 @@
+-- Pending live copies end as reported by the follower ('executed', 'failed' or
+-- 'dismissed') or 'expired' when never reported; paper copies are 'cancelled'
+-- along with the leader's order
+ALTER TABLE copy_trades DROP CONSTRAINT copy_trades_status_check;
+ALTER TABLE copy_trades ADD CONSTRAINT copy_trades_status_check
+    CHECK (status IN ('executed', 'pending', 'rejected', 'failed', 'dismissed', 'expired', 'cancelled'));
+
+CREATE INDEX idx_copy_trades_pending ON copy_trades(follower_id, created_at) WHERE status = 'pending';
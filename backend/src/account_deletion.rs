@@ -0,0 +1,260 @@
+use crate::auth::AuthService;
+use crate::config::AppConfig;
+use crate::exports::DataExportService;
+use crate::schema::{
+    activities, activity_privacy_settings, alert_history, alert_rules, copy_subscriptions, equity_snapshots, fills,
+    follow_requests, follows, funding_payments, notifications, paper_accounts, paper_balances, paper_orders,
+    paper_positions, password_reset_tokens, post_likes, posts, profile_audit_log, referral_codes,
+    referral_rewards, sessions, signup_fingerprints, trading_leaderboard, user_blocks, user_mutes, user_wallets,
+    username_history, users, wallet_challenges, wallet_link_history,
+};
+use crate::utils::AppError;
+use crate::DbPool;
+use chrono::{DateTime, Duration, Utc};
+use diesel::prelude::*;
+use uuid::Uuid;
+
+const DELETION_BATCH: i64 = 50;
+
+pub struct AccountDeletionService;
+
+impl AccountDeletionService {
+    // Schedule the account for deletion once the grace period ends. The password
+    // is asked again so a stolen session cannot delete the account.
+    pub async fn request_deletion(
+        pool: &DbPool,
+        user_id: Uuid,
+        password: &str,
+    ) -> Result<DateTime<Utc>, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let (password_hash, scheduled_at) = users::table
+            .find(user_id)
+            .filter(users::deleted_at.is_null())
+            .select((users::password_hash, users::deletion_scheduled_at))
+            .first::<(String, Option<DateTime<Utc>>)>(conn)
+            .optional()?
+            .ok_or_else(|| AppError::NotFoundError("User not found".to_string()))?;
+
+        if !AuthService::verify_password(password, &password_hash)? {
+            return Err(AppError::AuthenticationError("Invalid password".to_string()));
+        }
+        if let Some(scheduled_at) = scheduled_at {
+            return Ok(scheduled_at);
+        }
+
+        let scheduled_at = Utc::now() + deletion_grace_period();
+        diesel::update(users::table.find(user_id))
+            .set((
+                users::deletion_scheduled_at.eq(Some(scheduled_at)),
+                users::updated_at.eq(Utc::now()),
+            ))
+            .execute(conn)?;
+
+        Ok(scheduled_at)
+    }
+
+    pub async fn cancel_deletion(pool: &DbPool, user_id: Uuid) -> Result<(), AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let cancelled = diesel::update(
+            users::table
+                .find(user_id)
+                .filter(users::deletion_scheduled_at.is_not_null())
+                .filter(users::deleted_at.is_null()),
+        )
+        .set((
+            users::deletion_scheduled_at.eq(None::<DateTime<Utc>>),
+            users::updated_at.eq(Utc::now()),
+        ))
+        .execute(conn)?;
+
+        if cancelled == 0 {
+            return Err(AppError::BadRequest("No account deletion is scheduled".to_string()));
+        }
+        Ok(())
+    }
+
+    // Delete the accounts whose grace period has ended. Returns accounts deleted.
+    pub async fn purge_due(pool: &DbPool) -> Result<usize, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let due = users::table
+            .filter(users::deletion_scheduled_at.le(Utc::now()))
+            .filter(users::deleted_at.is_null())
+            .select(users::id)
+            .order(users::deletion_scheduled_at.asc())
+            .limit(DELETION_BATCH)
+            .load::<Uuid>(conn)?;
+
+        let mut deleted = 0;
+        for user_id in due {
+            match conn.transaction(|conn| Self::purge(conn, user_id)) {
+                Ok(true) => deleted += 1,
+                Ok(false) => {}
+                Err(e) => log::error!("Failed to delete account {}: {}", user_id, e),
+            }
+        }
+
+        Ok(deleted)
+    }
+
+    // Delete the user's personal data and anonymize the user row. Comments on
+    // other users' posts and referral records stay, attributed to the anonymized
+    // account. Returns false when the deletion was cancelled in the meantime.
+    fn purge(conn: &mut PgConnection, user_id: Uuid) -> Result<bool, AppError> {
+        let due = users::table
+            .find(user_id)
+            .filter(users::deletion_scheduled_at.le(Utc::now()))
+            .filter(users::deleted_at.is_null())
+            .select(users::id)
+            .for_update()
+            .first::<Uuid>(conn)
+            .optional()?;
+        if due.is_none() {
+            return Ok(false);
+        }
+
+        // Sign-in and account recovery
+        diesel::delete(sessions::table.filter(sessions::user_id.eq(user_id))).execute(conn)?;
+        diesel::delete(password_reset_tokens::table.filter(password_reset_tokens::user_id.eq(user_id)))
+            .execute(conn)?;
+        diesel::delete(signup_fingerprints::table.filter(signup_fingerprints::user_id.eq(user_id)))
+            .execute(conn)?;
+
+        // Trading history; copied trades go with their subscriptions
+        diesel::delete(
+            copy_subscriptions::table.filter(
+                copy_subscriptions::follower_id
+                    .eq(user_id)
+                    .or(copy_subscriptions::leader_id.eq(user_id)),
+            ),
+        )
+        .execute(conn)?;
+        diesel::delete(paper_positions::table.filter(paper_positions::user_id.eq(user_id))).execute(conn)?;
+        diesel::delete(paper_orders::table.filter(paper_orders::user_id.eq(user_id))).execute(conn)?;
+        diesel::delete(paper_balances::table.filter(paper_balances::user_id.eq(user_id))).execute(conn)?;
+        diesel::delete(paper_accounts::table.filter(paper_accounts::user_id.eq(user_id))).execute(conn)?;
+        diesel::delete(fills::table.filter(fills::user_id.eq(user_id))).execute(conn)?;
+        diesel::delete(funding_payments::table.filter(funding_payments::user_id.eq(user_id))).execute(conn)?;
+        diesel::delete(equity_snapshots::table.filter(equity_snapshots::user_id.eq(user_id))).execute(conn)?;
+        diesel::delete(trading_leaderboard::table.filter(trading_leaderboard::user_id.eq(user_id)))
+            .execute(conn)?;
+        diesel::delete(user_wallets::table.filter(user_wallets::user_id.eq(user_id))).execute(conn)?;
+        diesel::delete(wallet_challenges::table.filter(wallet_challenges::user_id.eq(user_id))).execute(conn)?;
+        diesel::delete(wallet_link_history::table.filter(wallet_link_history::user_id.eq(user_id)))
+            .execute(conn)?;
+        diesel::delete(alert_history::table.filter(alert_history::user_id.eq(user_id))).execute(conn)?;
+        diesel::delete(alert_rules::table.filter(alert_rules::user_id.eq(user_id))).execute(conn)?;
+
+        // Social graph
+        diesel::delete(
+            follows::table.filter(follows::follower_id.eq(user_id).or(follows::following_id.eq(user_id))),
+        )
+        .execute(conn)?;
+        diesel::delete(
+            follow_requests::table.filter(
+                follow_requests::requester_id
+                    .eq(user_id)
+                    .or(follow_requests::target_id.eq(user_id)),
+            ),
+        )
+        .execute(conn)?;
+        diesel::delete(
+            user_blocks::table.filter(user_blocks::blocker_id.eq(user_id).or(user_blocks::blocked_id.eq(user_id))),
+        )
+        .execute(conn)?;
+        diesel::delete(
+            user_mutes::table.filter(user_mutes::muter_id.eq(user_id).or(user_mutes::muted_id.eq(user_id))),
+        )
+        .execute(conn)?;
+
+        // Feed and notifications, including those about the user sent to others
+        diesel::delete(
+            activities::table.filter(activities::user_id.eq(user_id).or(activities::subject_user_id.eq(user_id))),
+        )
+        .execute(conn)?;
+        diesel::delete(activity_privacy_settings::table.filter(activity_privacy_settings::user_id.eq(user_id)))
+            .execute(conn)?;
+        diesel::delete(
+            notifications::table.filter(notifications::user_id.eq(user_id).or(notifications::actor_id.eq(user_id))),
+        )
+        .execute(conn)?;
+
+        // Posts go with their comments and likes. Likes on other posts are
+        // removed from the counts; comments on other posts are kept.
+        let liked = diesel::delete(post_likes::table.filter(post_likes::user_id.eq(user_id)))
+            .returning(post_likes::post_id)
+            .get_results::<Uuid>(conn)?;
+        diesel::update(posts::table.filter(posts::id.eq_any(&liked)))
+            .set(posts::like_count.eq(posts::like_count - 1))
+            .execute(conn)?;
+        diesel::delete(posts::table.filter(posts::user_id.eq(user_id))).execute(conn)?;
+
+        // Referred users keep their referrer; unpaid rewards of the user are cancelled
+        diesel::delete(referral_codes::table.filter(referral_codes::user_id.eq(user_id))).execute(conn)?;
+        diesel::update(
+            referral_rewards::table
+                .filter(referral_rewards::referrer_id.eq(user_id))
+                .filter(referral_rewards::status.eq_any(["pending", "held"])),
+        )
+        .set(referral_rewards::status.eq("cancelled"))
+        .execute(conn)?;
+
+        diesel::delete(username_history::table.filter(username_history::user_id.eq(user_id))).execute(conn)?;
+        diesel::delete(profile_audit_log::table.filter(profile_audit_log::user_id.eq(user_id))).execute(conn)?;
+        DataExportService::delete_user_exports(conn, user_id)?;
+
+        // The avatar files are removed by AvatarGcJob once avatar_url is cleared.
+        // The password hash is not a valid hash, so it matches no password.
+        let now = Utc::now();
+        diesel::update(users::table.find(user_id))
+            .set((
+                users::username.eq(format!("deleted-{}", user_id.simple())),
+                users::email.eq(format!("{}@deleted.invalid", user_id.simple())),
+                users::password_hash.eq(""),
+                users::bio.eq(None::<String>),
+                users::avatar_url.eq(None::<String>),
+                users::is_verified.eq(Some(false)),
+                users::last_active.eq(None::<DateTime<Utc>>),
+                users::leaderboard_opt_out.eq(true),
+                users::is_admin.eq(false),
+                users::is_private.eq(true),
+                users::referral_code_id.eq(None::<Uuid>),
+                users::deletion_scheduled_at.eq(None::<DateTime<Utc>>),
+                users::deleted_at.eq(Some(now)),
+                users::updated_at.eq(now),
+            ))
+            .execute(conn)?;
+
+        Ok(true)
+    }
+}
+
+pub struct AccountDeletionJob;
+
+impl AccountDeletionJob {
+    pub fn spawn(pool: DbPool) {
+        let interval_secs = AppConfig::global().account_deletion_interval_secs;
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(std::time::Duration::from_secs(interval_secs));
+
+            loop {
+                ticker.tick().await;
+
+                match AccountDeletionService::purge_due(&pool).await {
+                    Ok(count) => log::debug!("Deleted {} accounts", count),
+                    Err(e) => log::error!("Failed to delete scheduled accounts: {}", e),
+                }
+            }
+        });
+    }
+}
+
+fn deletion_grace_period() -> Duration {
+    Duration::days(AppConfig::global().account_deletion_grace_days)
+}
@@ -0,0 +1,403 @@
+use crate::models::{Activity, ActivityFeedResponse, ActivityResponse, NewActivity, Post};
+use crate::posts::PostService;
+use crate::relationships::RelationshipService;
+use crate::schema::{activities, activity_privacy_settings, follows, posts, users};
+use crate::utils::AppError;
+use crate::DbPool;
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use uuid::Uuid;
+
+pub const ACTIVITY_TYPES: [&str; 6] = [
+    "position_opened",
+    "position_closed",
+    "new_follower",
+    "referral_joined",
+    "profile_updated",
+    "post_created",
+];
+pub const VISIBILITIES: [&str; 3] = ["public", "followers", "private"];
+pub const MAX_FEED_LIMIT: i64 = 100;
+
+// Visibility used until the user picks one for the event type
+fn default_visibility(activity_type: &str) -> &'static str {
+    match activity_type {
+        "position_opened" | "position_closed" | "referral_joined" => "followers",
+        _ => "public",
+    }
+}
+
+// Something a user did that their followers may see
+#[derive(Debug, Clone)]
+pub struct ActivityEvent {
+    pub user_id: Uuid,
+    pub activity_type: &'static str,
+    pub subject_user_id: Option<Uuid>,
+    pub symbol: Option<String>,
+    pub side: Option<String>,
+    pub size: Option<f64>,
+    pub price: Option<f64>,
+    pub pnl: Option<f64>,
+    pub post_id: Option<Uuid>,
+}
+
+impl ActivityEvent {
+    fn new(user_id: Uuid, activity_type: &'static str) -> Self {
+        ActivityEvent {
+            user_id,
+            activity_type,
+            subject_user_id: None,
+            symbol: None,
+            side: None,
+            size: None,
+            price: None,
+            pnl: None,
+            post_id: None,
+        }
+    }
+
+    // `user_id` gained `follower_id` as a follower
+    pub fn new_follower(user_id: Uuid, follower_id: Uuid) -> Self {
+        ActivityEvent { subject_user_id: Some(follower_id), ..Self::new(user_id, "new_follower") }
+    }
+
+    // `referrer_id` brought in `referred_id`
+    pub fn referral_joined(referrer_id: Uuid, referred_id: Uuid) -> Self {
+        ActivityEvent { subject_user_id: Some(referred_id), ..Self::new(referrer_id, "referral_joined") }
+    }
+
+    pub fn profile_updated(user_id: Uuid) -> Self {
+        Self::new(user_id, "profile_updated")
+    }
+
+    pub fn post_created(user_id: Uuid, post_id: Uuid) -> Self {
+        ActivityEvent { post_id: Some(post_id), ..Self::new(user_id, "post_created") }
+    }
+
+    pub fn position_opened(user_id: Uuid, symbol: &str, side: &str, size: f64, price: f64) -> Self {
+        ActivityEvent {
+            symbol: Some(symbol.to_string()),
+            side: Some(side.to_string()),
+            size: Some(size),
+            price: Some(price),
+            ..Self::new(user_id, "position_opened")
+        }
+    }
+
+    pub fn position_closed(
+        user_id: Uuid,
+        symbol: &str,
+        side: &str,
+        size: f64,
+        price: f64,
+        pnl: f64,
+    ) -> Self {
+        ActivityEvent {
+            activity_type: "position_closed",
+            pnl: Some(pnl),
+            ..Self::position_opened(user_id, symbol, side, size, price)
+        }
+    }
+}
+
+pub struct ActivityService;
+
+impl ActivityService {
+    // Record events for the feed. Activities are a side effect of other actions,
+    // so failures are logged and never surface to the caller.
+    pub async fn record(pool: &DbPool, events: Vec<ActivityEvent>) {
+        if events.is_empty() {
+            return;
+        }
+        if let Err(e) = Self::insert(pool, events) {
+            log::warn!("Failed to record activities: {}", e);
+        }
+    }
+
+    fn insert(pool: &DbPool, events: Vec<ActivityEvent>) -> Result<(), AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let mut user_ids: Vec<Uuid> = events.iter().map(|event| event.user_id).collect();
+        user_ids.sort_unstable();
+        user_ids.dedup();
+        let settings: HashMap<(Uuid, String), String> = activity_privacy_settings::table
+            .filter(activity_privacy_settings::user_id.eq_any(&user_ids))
+            .select((
+                activity_privacy_settings::user_id,
+                activity_privacy_settings::activity_type,
+                activity_privacy_settings::visibility,
+            ))
+            .load::<(Uuid, String, String)>(conn)?
+            .into_iter()
+            .map(|(user_id, activity_type, visibility)| ((user_id, activity_type), visibility))
+            .collect();
+
+        let rows: Vec<NewActivity> = events
+            .into_iter()
+            .map(|event| NewActivity {
+                visibility: settings
+                    .get(&(event.user_id, event.activity_type.to_string()))
+                    .cloned()
+                    .unwrap_or_else(|| default_visibility(event.activity_type).to_string()),
+                user_id: event.user_id,
+                activity_type: event.activity_type.to_string(),
+                subject_user_id: event.subject_user_id,
+                symbol: event.symbol,
+                side: event.side,
+                size: event.size,
+                price: event.price,
+                pnl: event.pnl,
+                post_id: event.post_id,
+            })
+            .collect();
+
+        diesel::insert_into(activities::table)
+            .values(&rows)
+            .execute(conn)?;
+        Ok(())
+    }
+
+    // Newest-first activities of the users `user_id` follows. The cursor is the
+    // (created_at, id) of the last item of the previous page, so new activities
+    // never shift later pages.
+    pub async fn get_feed(
+        pool: &DbPool,
+        user_id: Uuid,
+        cursor: Option<&str>,
+        limit: i64,
+    ) -> Result<ActivityFeedResponse, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let following = follows::table
+            .filter(follows::follower_id.eq(user_id))
+            .select(follows::following_id);
+
+        // Muted users drop out of the feed, as does anything about a blocked user
+        let mut excluded = RelationshipService::muted_user_ids(conn, user_id)?;
+        excluded.extend(RelationshipService::hidden_user_ids(conn, Some(user_id))?);
+
+        let mut query = activities::table
+            .inner_join(users::table.on(activities::user_id.eq(users::id)))
+            .filter(activities::user_id.eq_any(following))
+            .filter(activities::user_id.ne_all(&excluded))
+            .filter(activities::subject_user_id.is_null().or(activities::subject_user_id.ne_all(&excluded)))
+            .filter(activities::visibility.ne("private"))
+            .into_boxed();
+        if let Some(cursor) = cursor {
+            let (created_at, id) = parse_cursor(cursor)?;
+            query = query.filter(
+                activities::created_at.lt(created_at).or(activities::created_at
+                    .eq(created_at)
+                    .and(activities::id.lt(id))),
+            );
+        }
+
+        // One extra row tells whether there is a next page
+        let mut rows = query
+            .order((activities::created_at.desc(), activities::id.desc()))
+            .limit(limit + 1)
+            .select((Activity::as_select(), users::username, users::avatar_url))
+            .load::<(Activity, String, Option<String>)>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get feed: {}", e)))?;
+
+        let next_cursor = if rows.len() as i64 > limit {
+            rows.truncate(limit as usize);
+            rows.last().map(|(activity, _, _)| format_cursor(activity))
+        } else {
+            None
+        };
+
+        let subject_ids: Vec<Uuid> = rows.iter().filter_map(|(a, _, _)| a.subject_user_id).collect();
+        let subjects: HashMap<Uuid, String> = users::table
+            .filter(users::id.eq_any(&subject_ids))
+            .select((users::id, users::username))
+            .load::<(Uuid, String)>(conn)?
+            .into_iter()
+            .collect();
+
+        let post_ids: Vec<Uuid> = rows.iter().filter_map(|(a, _, _)| a.post_id).collect();
+        let feed_posts = posts::table
+            .filter(posts::id.eq_any(&post_ids))
+            .filter(posts::hidden_at.is_null())
+            .load::<Post>(conn)?;
+        let mut feed_posts: HashMap<Uuid, _> = PostService::responses(conn, feed_posts, Some(user_id))?
+            .into_iter()
+            .map(|post| (post.id, post))
+            .collect();
+
+        let activities = rows
+            .into_iter()
+            .filter_map(|(activity, username, avatar_url)| {
+                // Skip posts that were hidden by a moderator after they were shared
+                let post = match activity.post_id {
+                    Some(id) => Some(feed_posts.remove(&id)?),
+                    None => None,
+                };
+                Some(ActivityResponse {
+                    id: activity.id,
+                    subject_username: activity.subject_user_id.and_then(|id| subjects.get(&id).cloned()),
+                    activity_type: activity.activity_type,
+                    username,
+                    avatar_url,
+                    symbol: activity.symbol,
+                    side: activity.side,
+                    size: activity.size,
+                    price: activity.price,
+                    pnl: activity.pnl,
+                    post,
+                    created_at: activity.created_at,
+                })
+            })
+            .collect();
+
+        Ok(ActivityFeedResponse { activities, next_cursor })
+    }
+
+    // Visibility for every event type, with defaults filled in
+    pub async fn get_privacy_settings(
+        pool: &DbPool,
+        user_id: Uuid,
+    ) -> Result<BTreeMap<String, String>, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let mut settings: BTreeMap<String, String> = ACTIVITY_TYPES
+            .iter()
+            .map(|activity_type| (activity_type.to_string(), default_visibility(activity_type).to_string()))
+            .collect();
+
+        let saved = activity_privacy_settings::table
+            .filter(activity_privacy_settings::user_id.eq(user_id))
+            .select((activity_privacy_settings::activity_type, activity_privacy_settings::visibility))
+            .load::<(String, String)>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get privacy settings: {}", e)))?;
+        settings.extend(saved);
+
+        Ok(settings)
+    }
+
+    // Change the visibility of event types. Past activities of those types follow
+    // the new setting too, so making something private hides its history.
+    pub async fn set_privacy_settings(
+        pool: &DbPool,
+        user_id: Uuid,
+        changes: BTreeMap<String, String>,
+    ) -> Result<BTreeMap<String, String>, AppError> {
+        for (activity_type, visibility) in &changes {
+            if !ACTIVITY_TYPES.contains(&activity_type.as_str()) {
+                return Err(AppError::ValidationError(format!("Unknown activity type: {}", activity_type)));
+            }
+            if !VISIBILITIES.contains(&visibility.as_str()) {
+                return Err(AppError::ValidationError(
+                    "visibility must be 'public', 'followers' or 'private'".to_string(),
+                ));
+            }
+        }
+
+        {
+            let conn = &mut pool.get()
+                .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+            conn.transaction(|conn| {
+                for (activity_type, visibility) in &changes {
+                    diesel::insert_into(activity_privacy_settings::table)
+                        .values((
+                            activity_privacy_settings::user_id.eq(user_id),
+                            activity_privacy_settings::activity_type.eq(activity_type),
+                            activity_privacy_settings::visibility.eq(visibility),
+                        ))
+                        .on_conflict((activity_privacy_settings::user_id, activity_privacy_settings::activity_type))
+                        .do_update()
+                        .set((
+                            activity_privacy_settings::visibility.eq(visibility),
+                            activity_privacy_settings::updated_at.eq(Utc::now()),
+                        ))
+                        .execute(conn)?;
+
+                    diesel::update(
+                        activities::table
+                            .filter(activities::user_id.eq(user_id))
+                            .filter(activities::activity_type.eq(activity_type)),
+                    )
+                    .set(activities::visibility.eq(visibility))
+                    .execute(conn)?;
+                }
+                Ok::<_, AppError>(())
+            })?;
+        }
+
+        Self::get_privacy_settings(pool, user_id).await
+    }
+}
+
+fn format_cursor(activity: &Activity) -> String {
+    format!("{}_{}", activity.created_at.timestamp_micros(), activity.id)
+}
+
+pub fn parse_cursor(cursor: &str) -> Result<(DateTime<Utc>, Uuid), AppError> {
+    let invalid = || AppError::ValidationError("Invalid cursor".to_string());
+    let (micros, id) = cursor.split_once('_').ok_or_else(invalid)?;
+    let created_at = micros
+        .parse::<i64>()
+        .ok()
+        .and_then(DateTime::from_timestamp_micros)
+        .ok_or_else(invalid)?;
+    let id = Uuid::parse_str(id).map_err(|_| invalid())?;
+    Ok((created_at, id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor_round_trips() {
+        let event = ActivityEvent::new_follower(Uuid::new_v4(), Uuid::new_v4());
+        let activity = Activity {
+            id: Uuid::new_v4(),
+            user_id: event.user_id,
+            activity_type: event.activity_type.to_string(),
+            visibility: "public".to_string(),
+            subject_user_id: event.subject_user_id,
+            symbol: None,
+            side: None,
+            size: None,
+            price: None,
+            pnl: None,
+            created_at: DateTime::from_timestamp_micros(1_760_000_000_123_456).unwrap(),
+            post_id: None,
+        };
+
+        let (created_at, id) = parse_cursor(&format_cursor(&activity)).unwrap();
+        assert_eq!(created_at, activity.created_at);
+        assert_eq!(id, activity.id);
+    }
+
+    #[test]
+    fn test_parse_cursor_rejects_malformed_cursors() {
+        assert!(parse_cursor("").is_err());
+        assert!(parse_cursor("123").is_err());
+        assert!(parse_cursor("abc_7d4a7f3e-4b1c-4a3e-9a55-1f0f5e7c2b11").is_err());
+        assert!(parse_cursor("123_not-a-uuid").is_err());
+    }
+
+    #[test]
+    fn test_position_events_and_default_visibility() {
+        let user_id = Uuid::new_v4();
+        let closed = ActivityEvent::position_closed(user_id, "APT/USDC", "long", 2.0, 10.0, -3.5);
+        assert_eq!(closed.activity_type, "position_closed");
+        assert_eq!(closed.symbol.as_deref(), Some("APT/USDC"));
+        assert_eq!(closed.pnl, Some(-3.5));
+        assert_eq!(ActivityEvent::position_opened(user_id, "APT/USDC", "long", 2.0, 10.0).pnl, None);
+
+        // Trading activity is shown to followers only unless the user opts in
+        assert_eq!(default_visibility("position_closed"), "followers");
+        assert_eq!(default_visibility("post_created"), "public");
+        for activity_type in ACTIVITY_TYPES {
+            assert!(VISIBILITIES.contains(&default_visibility(activity_type)));
+        }
+    }
+}
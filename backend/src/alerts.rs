@@ -0,0 +1,626 @@
+use crate::config::AppConfig;
+use crate::email::{AlertEmail, EmailService};
+use crate::kana_client::KanaClient;
+use crate::markets::MarketRegistry;
+use crate::models::{AlertHistoryEntry, AlertRule, NewAlertHistoryEntry, NewAlertRule, PositionResponse};
+use crate::positions::PositionService;
+use crate::schema::{alert_history, alert_rules, users};
+use crate::utils::AppError;
+use crate::wallets::WalletService;
+use crate::DbPool;
+use chrono::{DateTime, Duration, Utc};
+use diesel::prelude::*;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+pub const MAX_RULES_PER_USER: i64 = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlertType {
+    Price,
+    FundingRate,
+    Pnl,
+    MarginRatio,
+    LiquidationDistance,
+}
+
+impl AlertType {
+    pub fn parse(alert_type: &str) -> Result<Self, AppError> {
+        match alert_type {
+            "price" => Ok(AlertType::Price),
+            "funding_rate" => Ok(AlertType::FundingRate),
+            "pnl" => Ok(AlertType::Pnl),
+            "margin_ratio" => Ok(AlertType::MarginRatio),
+            "liquidation_distance" => Ok(AlertType::LiquidationDistance),
+            other => Err(AppError::ValidationError(format!("Unsupported alert type: {}", other))),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlertType::Price => "price",
+            AlertType::FundingRate => "funding_rate",
+            AlertType::Pnl => "pnl",
+            AlertType::MarginRatio => "margin_ratio",
+            AlertType::LiquidationDistance => "liquidation_distance",
+        }
+    }
+
+    // Market alerts need a symbol; position alerts default to all positions
+    fn requires_symbol(&self) -> bool {
+        matches!(self, AlertType::Price | AlertType::FundingRate)
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            AlertType::Price => "price",
+            AlertType::FundingRate => "funding rate",
+            AlertType::Pnl => "unrealized PnL",
+            AlertType::MarginRatio => "margin ratio",
+            AlertType::LiquidationDistance => "distance to liquidation",
+        }
+    }
+
+    fn format(&self, value: f64) -> String {
+        match self {
+            AlertType::FundingRate => format!("{:.4}%", value * 100.0),
+            AlertType::MarginRatio | AlertType::LiquidationDistance => format!("{:.2}%", value),
+            AlertType::Price | AlertType::Pnl => format!("{:.4}", value),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AlertRuleRequest {
+    pub alert_type: String,
+    pub symbol: Option<String>,
+    pub condition: String,
+    pub threshold: f64,
+    pub repeating: bool,
+    pub cooldown_minutes: i32,
+}
+
+// Fields left as None are unchanged
+#[derive(Debug, Clone, Default)]
+pub struct AlertRuleUpdate {
+    pub condition: Option<String>,
+    pub threshold: Option<f64>,
+    pub repeating: Option<bool>,
+    pub cooldown_minutes: Option<i32>,
+    pub is_active: Option<bool>,
+}
+
+pub struct AlertService;
+
+impl AlertService {
+    pub async fn create_rule(
+        pool: &DbPool,
+        user_id: Uuid,
+        request: AlertRuleRequest,
+    ) -> Result<AlertRule, AppError> {
+        let alert_type = AlertType::parse(&request.alert_type)?;
+        validate_condition(&request.condition)?;
+        let symbol = match request.symbol.as_deref() {
+            Some(symbol) => Some(MarketRegistry::require(symbol)?.symbol.to_string()),
+            None if alert_type.requires_symbol() => {
+                return Err(AppError::ValidationError(format!(
+                    "symbol is required for {} alerts",
+                    alert_type.as_str()
+                )));
+            }
+            None => None,
+        };
+
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let existing = alert_rules::table
+            .filter(alert_rules::user_id.eq(user_id))
+            .count()
+            .get_result::<i64>(conn)?;
+        if existing >= MAX_RULES_PER_USER {
+            return Err(AppError::BadRequest(format!(
+                "You can have at most {} alerts",
+                MAX_RULES_PER_USER
+            )));
+        }
+
+        diesel::insert_into(alert_rules::table)
+            .values(&NewAlertRule {
+                user_id,
+                alert_type: alert_type.as_str().to_string(),
+                symbol,
+                condition: request.condition,
+                threshold: request.threshold,
+                repeating: request.repeating,
+                cooldown_minutes: request.cooldown_minutes,
+            })
+            .get_result::<AlertRule>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to create alert: {}", e)))
+    }
+
+    pub async fn update_rule(
+        pool: &DbPool,
+        user_id: Uuid,
+        rule_id: Uuid,
+        update: AlertRuleUpdate,
+    ) -> Result<AlertRule, AppError> {
+        if let Some(condition) = &update.condition {
+            validate_condition(condition)?;
+        }
+
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let rule = Self::find_rule(conn, user_id, rule_id)?;
+        // A changed or re-enabled rule starts watching for a fresh crossing
+        let rearm = update.condition.is_some() || update.threshold.is_some() || update.is_active == Some(true);
+
+        diesel::update(alert_rules::table.find(rule.id))
+            .set((
+                alert_rules::condition.eq(update.condition.unwrap_or(rule.condition)),
+                alert_rules::threshold.eq(update.threshold.unwrap_or(rule.threshold)),
+                alert_rules::repeating.eq(update.repeating.unwrap_or(rule.repeating)),
+                alert_rules::cooldown_minutes.eq(update.cooldown_minutes.unwrap_or(rule.cooldown_minutes)),
+                alert_rules::is_active.eq(update.is_active.unwrap_or(rule.is_active)),
+                alert_rules::last_value.eq(if rearm { None } else { rule.last_value }),
+            ))
+            .get_result::<AlertRule>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to update alert: {}", e)))
+    }
+
+    pub async fn delete_rule(pool: &DbPool, user_id: Uuid, rule_id: Uuid) -> Result<(), AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let deleted = diesel::delete(
+            alert_rules::table
+                .filter(alert_rules::id.eq(rule_id))
+                .filter(alert_rules::user_id.eq(user_id)),
+        )
+        .execute(conn)
+        .map_err(|e| AppError::InternalServerError(format!("Failed to delete alert: {}", e)))?;
+
+        if deleted == 0 {
+            return Err(AppError::NotFoundError("Alert not found".to_string()));
+        }
+        Ok(())
+    }
+
+    pub async fn get_rules(pool: &DbPool, user_id: Uuid) -> Result<Vec<AlertRule>, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        alert_rules::table
+            .filter(alert_rules::user_id.eq(user_id))
+            .order(alert_rules::created_at.desc())
+            .load::<AlertRule>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get alerts: {}", e)))
+    }
+
+    pub async fn get_history(
+        pool: &DbPool,
+        user_id: Uuid,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(Vec<AlertHistoryEntry>, i64), AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let total = alert_history::table
+            .filter(alert_history::user_id.eq(user_id))
+            .count()
+            .get_result::<i64>(conn)?;
+        let entries = alert_history::table
+            .filter(alert_history::user_id.eq(user_id))
+            .order(alert_history::triggered_at.desc())
+            .limit(limit)
+            .offset(offset)
+            .load::<AlertHistoryEntry>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get alert history: {}", e)))?;
+
+        Ok((entries, total))
+    }
+
+    // Evaluate every active rule against current market data and positions.
+    // Rules fire when their condition becomes true (a crossing), not on every
+    // evaluation while it stays true. Returns the number of rules that fired.
+    pub async fn evaluate_all(pool: &DbPool) -> Result<usize, AppError> {
+        let rules = {
+            let conn = &mut pool.get()
+                .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+            alert_rules::table
+                .filter(alert_rules::is_active.eq(true))
+                .load::<AlertRule>(conn)?
+        };
+        if rules.is_empty() {
+            return Ok(0);
+        }
+
+        let values = Self::current_values(pool, &rules).await;
+        let now = Utc::now();
+
+        let mut fired = Vec::new();
+        {
+            let conn = &mut pool.get()
+                .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+            for rule in &rules {
+                // Missing data leaves the rule untouched until the next run
+                let value = match values.get(&rule.id) {
+                    Some(value) => *value,
+                    None => continue,
+                };
+
+                if should_fire(rule, value, now) {
+                    diesel::update(alert_rules::table.find(rule.id))
+                        .set((
+                            alert_rules::last_value.eq(Some(value)),
+                            alert_rules::last_triggered_at.eq(Some(now)),
+                            alert_rules::is_active.eq(rule.repeating),
+                        ))
+                        .execute(conn)?;
+                    fired.push((rule, value));
+                } else {
+                    diesel::update(alert_rules::table.find(rule.id))
+                        .set(alert_rules::last_value.eq(Some(value)))
+                        .execute(conn)?;
+                }
+            }
+        }
+
+        for (rule, value) in &fired {
+            if let Err(e) = Self::deliver(pool, rule, *value).await {
+                log::error!("Failed to deliver alert {}: {}", rule.id, e);
+            }
+        }
+
+        Ok(fired.len())
+    }
+
+    // Current value of every rule that could be evaluated, by rule id
+    async fn current_values(pool: &DbPool, rules: &[AlertRule]) -> HashMap<Uuid, f64> {
+        let kana_client = KanaClient::new().ok();
+
+        let mut prices: HashMap<String, Option<f64>> = HashMap::new();
+        let mut funding_rates: HashMap<String, f64> = HashMap::new();
+        let mut positions: HashMap<Uuid, Option<Vec<PositionResponse>>> = HashMap::new();
+        let mut values = HashMap::new();
+
+        for rule in rules {
+            let alert_type = match AlertType::parse(&rule.alert_type) {
+                Ok(alert_type) => alert_type,
+                Err(_) => continue,
+            };
+            let symbol = rule.symbol.as_deref();
+
+            let value = match alert_type {
+                AlertType::Price => match symbol.and_then(MarketRegistry::by_symbol) {
+                    Some(market) => {
+                        if !prices.contains_key(market.symbol) {
+                            prices.insert(market.symbol.to_string(), market.mid_price().await);
+                        }
+                        prices.get(market.symbol).copied().flatten()
+                    }
+                    None => None,
+                },
+                AlertType::FundingRate => match (symbol.and_then(MarketRegistry::by_symbol), &kana_client) {
+                    (Some(market), Some(client)) => {
+                        if !funding_rates.contains_key(market.symbol) {
+                            match client.get_funding_rate(market.symbol).await {
+                                Ok(rate) => {
+                                    funding_rates.insert(market.symbol.to_string(), rate);
+                                }
+                                Err(e) => log::warn!("Failed to fetch funding rate for {}: {}", market.symbol, e),
+                            }
+                        }
+                        funding_rates.get(market.symbol).copied()
+                    }
+                    _ => None,
+                },
+                AlertType::Pnl | AlertType::MarginRatio | AlertType::LiquidationDistance => {
+                    if let Entry::Vacant(entry) = positions.entry(rule.user_id) {
+                        entry.insert(Self::user_positions(pool, rule.user_id).await);
+                    }
+                    match positions.get(&rule.user_id) {
+                        Some(Some(user_positions)) => position_value(alert_type, symbol, user_positions),
+                        _ => None,
+                    }
+                }
+            };
+
+            if let Some(value) = value.filter(|v| v.is_finite()) {
+                values.insert(rule.id, value);
+            }
+        }
+
+        values
+    }
+
+    // All positions across the user's wallets; None when any wallet failed to load
+    async fn user_positions(pool: &DbPool, user_id: Uuid) -> Option<Vec<PositionResponse>> {
+        let wallets = WalletService::get_user_wallets(pool, user_id).await.ok()?;
+        let mut all = Vec::new();
+        for wallet in wallets {
+            match PositionService::get_positions(&wallet.wallet_address, None).await {
+                Ok(positions) => all.extend(positions),
+                Err(e) => {
+                    log::warn!("Failed to fetch positions of {}: {}", wallet.wallet_address, e);
+                    return None;
+                }
+            }
+        }
+        Some(all)
+    }
+
+    // Email the user and record the outcome. Users get at most
+    // ALERT_EMAIL_MAX_PER_HOUR emails; alerts over the limit are only logged.
+    async fn deliver(pool: &DbPool, rule: &AlertRule, value: f64) -> Result<(), AppError> {
+        let max_per_hour = AppConfig::global().alert_email_max_per_hour;
+
+        let (email, username, sent_last_hour) = {
+            let conn = &mut pool.get()
+                .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+            let (email, username) = users::table
+                .find(rule.user_id)
+                .select((users::email, users::username))
+                .first::<(String, String)>(conn)?;
+            let sent_last_hour = alert_history::table
+                .filter(alert_history::user_id.eq(rule.user_id))
+                .filter(alert_history::delivery_status.eq("sent"))
+                .filter(alert_history::triggered_at.gt(Utc::now() - Duration::hours(1)))
+                .count()
+                .get_result::<i64>(conn)?;
+            (email, username, sent_last_hour)
+        };
+
+        let (delivery_status, error) = if sent_last_hour >= max_per_hour {
+            ("rate_limited", None)
+        } else {
+            let alert_type = AlertType::parse(&rule.alert_type)?;
+            let subject = match &rule.symbol {
+                Some(symbol) => format!("Aptora alert: {} {}", symbol, alert_type.label()),
+                None => format!("Aptora alert: {}", alert_type.label()),
+            };
+            let message = describe(alert_type, rule, value);
+
+            let sent = match EmailService::new(AppConfig::global()) {
+                Ok(service) => service
+                    .send_alert_email(AlertEmail { to: email, username, subject, message })
+                    .await,
+                Err(e) => Err(e),
+            };
+            match sent {
+                Ok(()) => ("sent", None),
+                Err(e) => {
+                    log::warn!("Failed to send alert {}: {}", rule.id, e);
+                    ("failed", Some(e.to_string()))
+                }
+            }
+        };
+
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+        diesel::insert_into(alert_history::table)
+            .values(&NewAlertHistoryEntry {
+                rule_id: rule.id,
+                user_id: rule.user_id,
+                alert_type: rule.alert_type.clone(),
+                symbol: rule.symbol.clone(),
+                condition: rule.condition.clone(),
+                threshold: rule.threshold,
+                value,
+                delivery_status: delivery_status.to_string(),
+                error,
+            })
+            .execute(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to record alert: {}", e)))?;
+
+        Ok(())
+    }
+
+    fn find_rule(conn: &mut PgConnection, user_id: Uuid, rule_id: Uuid) -> Result<AlertRule, AppError> {
+        alert_rules::table
+            .filter(alert_rules::id.eq(rule_id))
+            .filter(alert_rules::user_id.eq(user_id))
+            .first::<AlertRule>(conn)
+            .optional()?
+            .ok_or_else(|| AppError::NotFoundError("Alert not found".to_string()))
+    }
+}
+
+// Background job that evaluates alert rules
+pub struct AlertWorker;
+
+impl AlertWorker {
+    pub fn spawn(pool: DbPool) {
+        let interval_secs = AppConfig::global().alert_eval_interval_secs;
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(std::time::Duration::from_secs(interval_secs));
+
+            loop {
+                ticker.tick().await;
+
+                match AlertService::evaluate_all(&pool).await {
+                    Ok(count) => log::debug!("Fired {} alerts", count),
+                    Err(e) => log::error!("Failed to evaluate alerts: {}", e),
+                }
+            }
+        });
+    }
+}
+
+fn validate_condition(condition: &str) -> Result<(), AppError> {
+    if condition == "above" || condition == "below" {
+        Ok(())
+    } else {
+        Err(AppError::ValidationError("condition must be 'above' or 'below'".to_string()))
+    }
+}
+
+fn condition_met(condition: &str, value: f64, threshold: f64) -> bool {
+    if condition == "above" {
+        value >= threshold
+    } else {
+        value <= threshold
+    }
+}
+
+// The condition became true since the last evaluation and the cooldown passed
+fn should_fire(rule: &AlertRule, value: f64, now: DateTime<Utc>) -> bool {
+    let met = condition_met(&rule.condition, value, rule.threshold);
+    let was_met = rule
+        .last_value
+        .is_some_and(|last| condition_met(&rule.condition, last, rule.threshold));
+    let cooled_down = rule
+        .last_triggered_at
+        .is_none_or(|at| now - at >= Duration::minutes(rule.cooldown_minutes as i64));
+
+    met && !was_met && cooled_down
+}
+
+// PnL is summed; margin ratio and liquidation distance take the riskiest position.
+// Both are percentages.
+fn position_value(alert_type: AlertType, symbol: Option<&str>, positions: &[PositionResponse]) -> Option<f64> {
+    let matching = positions
+        .iter()
+        .filter(|p| p.size > 0.0 && symbol.is_none_or(|s| p.symbol == s));
+
+    match alert_type {
+        // Positions without a mark price leave the PnL unknown, and so does
+        // having no position at all
+        AlertType::Pnl => {
+            let pnl: Vec<Option<f64>> = matching.map(|p| p.unrealized_pnl).collect();
+            if pnl.is_empty() {
+                return None;
+            }
+            pnl.into_iter().sum()
+        }
+        AlertType::MarginRatio => matching.filter_map(|p| p.margin_ratio).map(|ratio| ratio * 100.0).reduce(f64::max),
+        AlertType::LiquidationDistance => matching
+            .filter_map(|p| {
+                let liquidation_price = p.liquidation_price?;
+                let mark_price = p.mark_price.filter(|mark| *mark > 0.0)?;
+                Some((mark_price - liquidation_price).abs() / mark_price * 100.0)
+            })
+            .reduce(f64::min),
+        AlertType::Price | AlertType::FundingRate => None,
+    }
+}
+
+fn describe(alert_type: AlertType, rule: &AlertRule, value: f64) -> String {
+    let subject = match &rule.symbol {
+        Some(symbol) => format!("{} {}", symbol, alert_type.label()),
+        None => format!("Your {}", alert_type.label()),
+    };
+    format!(
+        "{} is {} {} (now {}).",
+        subject,
+        rule.condition,
+        alert_type.format(rule.threshold),
+        alert_type.format(value)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(condition: &str, threshold: f64, last_value: Option<f64>) -> AlertRule {
+        AlertRule {
+            id: Uuid::new_v4(),
+            user_id: Uuid::new_v4(),
+            alert_type: "price".to_string(),
+            symbol: Some("APT/USDC".to_string()),
+            condition: condition.to_string(),
+            threshold,
+            repeating: true,
+            cooldown_minutes: 60,
+            is_active: true,
+            last_value,
+            last_triggered_at: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    fn position(symbol: &str, size: f64, unrealized_pnl: Option<f64>) -> PositionResponse {
+        PositionResponse {
+            market_id: "501".to_string(),
+            symbol: symbol.to_string(),
+            side: "long".to_string(),
+            size,
+            entry_price: 10.0,
+            mark_price: unrealized_pnl.map(|_| 10.0),
+            notional: None,
+            unrealized_pnl,
+            realized_pnl: 0.0,
+            roe: None,
+            margin: 1.0,
+            margin_type: "cross".to_string(),
+            leverage: 10.0,
+            maintenance_margin: None,
+            margin_ratio: Some(0.2),
+            liquidation_price: Some(9.0),
+        }
+    }
+
+    #[test]
+    fn test_condition_met_includes_the_threshold() {
+        assert!(condition_met("above", 10.0, 10.0));
+        assert!(condition_met("above", 11.0, 10.0));
+        assert!(!condition_met("above", 9.0, 10.0));
+        assert!(condition_met("below", 10.0, 10.0));
+        assert!(!condition_met("below", 11.0, 10.0));
+        assert!(validate_condition("sideways").is_err());
+    }
+
+    #[test]
+    fn test_fires_on_crossing_only() {
+        let now = Utc::now();
+        // First evaluation with the condition already true
+        assert!(should_fire(&rule("above", 10.0, None), 12.0, now));
+        // Crossing from below
+        assert!(should_fire(&rule("above", 10.0, Some(9.0)), 10.5, now));
+        // Staying above does not fire again
+        assert!(!should_fire(&rule("above", 10.0, Some(11.0)), 12.0, now));
+        assert!(!should_fire(&rule("below", 10.0, Some(9.0)), 12.0, now));
+    }
+
+    #[test]
+    fn test_cooldown_delays_the_next_crossing() {
+        let now = Utc::now();
+        let mut recent = rule("above", 10.0, Some(9.0));
+        recent.last_triggered_at = Some(now - Duration::minutes(30));
+        assert!(!should_fire(&recent, 11.0, now));
+
+        recent.last_triggered_at = Some(now - Duration::minutes(60));
+        assert!(should_fire(&recent, 11.0, now));
+    }
+
+    #[test]
+    fn test_pnl_needs_positions_with_a_mark() {
+        assert_eq!(position_value(AlertType::Pnl, None, &[]), None);
+        // Closed positions do not count
+        assert_eq!(position_value(AlertType::Pnl, None, &[position("APT/USDC", 0.0, Some(1.0))]), None);
+
+        let positions = vec![position("APT/USDC", 1.0, Some(2.0)), position("BTC/USDC", 1.0, Some(-0.5))];
+        assert_eq!(position_value(AlertType::Pnl, None, &positions), Some(1.5));
+        assert_eq!(position_value(AlertType::Pnl, Some("BTC/USDC"), &positions), Some(-0.5));
+        assert_eq!(position_value(AlertType::Pnl, Some("ETH/USDC"), &positions), None);
+
+        let unpriced = vec![position("APT/USDC", 1.0, Some(2.0)), position("XYZ/USDC", 1.0, None)];
+        assert_eq!(position_value(AlertType::Pnl, None, &unpriced), None);
+    }
+
+    #[test]
+    fn test_risk_values_take_the_riskiest_position() {
+        let positions = vec![position("APT/USDC", 1.0, Some(0.0))];
+        assert!((position_value(AlertType::MarginRatio, None, &positions).unwrap() - 20.0).abs() < 1e-9);
+        assert!((position_value(AlertType::LiquidationDistance, None, &positions).unwrap() - 10.0).abs() < 1e-9);
+        assert_eq!(position_value(AlertType::MarginRatio, None, &[]), None);
+    }
+}
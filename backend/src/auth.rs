@@ -1,3 +1,10 @@
+use crate::activity::{ActivityEvent, ActivityService};
+use crate::config::AppConfig;
+use crate::jwt_keys::JwtKeys;
+use crate::notifications::NotificationService;
+use crate::referral_abuse::{ReferralAbuseService, SignupContext};
+use crate::referral_codes::ReferralCodeService;
+use crate::referrals::ReferralRewardService;
 use crate::models::{User, NewUser, Session, NewSession, UserProfile, AuthUser};
 use crate::schema::{users, sessions};
 use crate::DbPool;
@@ -5,18 +12,32 @@ use crate::utils::AppError;
 use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier, password_hash::SaltString};
 use chrono::{Duration, Utc};
 use diesel::prelude::*;
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation, Algorithm};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use uuid::Uuid;
-use std::env;
+
+pub const ROLE_USER: &str = "user";
+pub const ROLE_ADMIN: &str = "admin";
+// Scopes of tokens issued at sign-in, which may use the whole API
+pub const SIGN_IN_SCOPES: [&str; 2] = ["read", "write"];
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String, // user_id
     pub exp: i64,    // expiration time
     pub iat: i64,    // issued at
+    pub iss: String,
+    pub aud: String,
     pub email: String,
     pub username: String,
+    // Session the token was issued for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<Uuid>,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    // Space-separated, as in OAuth
+    #[serde(default)]
+    pub scope: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -31,6 +52,9 @@ pub struct RegisterRequest {
     pub username: String,
     pub password: String,
     pub referral_code: Option<String>,
+    // Filled in from the request, not the body
+    #[serde(skip)]
+    pub signup: SignupContext,
 }
 
 #[derive(Debug, Serialize)]
@@ -73,46 +97,54 @@ impl AuthService {
             .is_ok())
     }
 
-    // Generate JWT access token (15 minutes)
-    pub fn generate_access_token(user: &AuthUser) -> Result<String, AppError> {
-        let secret = env::var("JWT_SECRET")
-            .map_err(|_| AppError::ConfigurationError("JWT_SECRET not set".to_string()))?;
+    // Generate JWT access token (ACCESS_TOKEN_TTL_SECS) for a session, signed
+    // with the current signing key
+    pub fn generate_access_token(user: &AuthUser, is_admin: bool, session_id: Uuid) -> Result<String, AppError> {
+        let config = AppConfig::global();
+        let keys = JwtKeys::global();
         
         let now = Utc::now();
-        let expires_at = now + Duration::minutes(15);
+        let expires_at = now + Duration::seconds(config.access_token_ttl_secs);
+
+        let mut roles = vec![ROLE_USER.to_string()];
+        if is_admin {
+            roles.push(ROLE_ADMIN.to_string());
+        }
         
         let claims = Claims {
             sub: user.id.to_string(),
             exp: expires_at.timestamp(),
             iat: now.timestamp(),
+            iss: keys.issuer().to_string(),
+            aud: keys.audience().to_string(),
             email: user.email.clone(),
             username: user.username.clone(),
+            sid: Some(session_id),
+            roles,
+            scope: SIGN_IN_SCOPES.join(" "),
         };
         
-        encode(
-            &Header::default(),
-            &claims,
-            &EncodingKey::from_secret(secret.as_ref()),
-        )
-        .map_err(|e| AppError::InternalServerError(format!("Token generation failed: {}", e)))
+        keys.sign(&claims)
+            .map_err(|e| AppError::InternalServerError(format!("Token generation failed: {}", e)))
     }
 
-    // Generate refresh token (30 days)
+    // Generate refresh token (REFRESH_TOKEN_TTL_DAYS)
     pub fn generate_refresh_token() -> String {
         Uuid::new_v4().to_string()
     }
 
-    // Verify JWT access token
+    // Sessions are found by their refresh token, so its hash has to be
+    // deterministic. The token is random, so an unsalted SHA-256 is enough.
+    pub fn hash_refresh_token(refresh_token: &str) -> String {
+        format!("{:x}", Sha256::digest(refresh_token.as_bytes()))
+    }
+
+    // Verify JWT access token: signature by the key in its kid header, expiry,
+    // issuer and audience
     pub fn verify_access_token(token: &str) -> Result<Claims, AppError> {
-        let secret = env::var("JWT_SECRET")
-            .map_err(|_| AppError::ConfigurationError("JWT_SECRET not set".to_string()))?;
-        
-        let token_data = decode::<Claims>(
-            token,
-            &DecodingKey::from_secret(secret.as_ref()),
-            &Validation::new(Algorithm::HS256),
-        )
-        .map_err(|e| AppError::Unauthorized(format!("Invalid token: {}", e)))?;
+        let token_data = JwtKeys::global()
+            .verify::<Claims>(token)
+            .map_err(|e| AppError::Unauthorized(format!("Invalid token: {}", e)))?;
         
         Ok(token_data.claims)
     }
@@ -149,45 +181,58 @@ impl AuthService {
             return Err(AppError::BadRequest("Username already exists".to_string()));
         }
 
-        // Handle referral if provided
-        let mut referred_by = None;
-        if let Some(ref referral_code) = request.referral_code {
-            // Find referrer by username
-            let referrer = users::table
-                .filter(users::username.eq(referral_code))
-                .select((users::id, users::email, users::password_hash, users::username))
-                .first::<AuthUser>(conn)
-                .optional()
-                .map_err(|e| AppError::InternalServerError(format!("Database error: {}", e)))?;
-
-            if let Some(referrer_user) = referrer {
-                referred_by = Some(referrer_user.id);
-            } else {
-                return Err(AppError::BadRequest("Invalid referral code".to_string()));
-            }
-        }
-
         // Hash password
         let password_hash = Self::hash_password(&request.password)?;
 
-        // Create new user with referral info
-        let new_user = NewUser {
-            email: request.email,
-            username: request.username,
-            password_hash,
-            referred_by,
-            referral_count: Some(0),
-            total_referral_rewards: None,
-            bio: None,
-            avatar_url: None,
-            is_verified: Some(false),
-            last_active: Some(Utc::now()),
-        };
-
-        let user: User = diesel::insert_into(users::table)
-            .values(&new_user)
-            .get_result(conn)
-            .map_err(|e| AppError::InternalServerError(format!("Failed to create user: {}", e)))?;
+        // Claim the referral code and create the user together, so a failed
+        // signup does not use up the code
+        let user: User = conn.transaction(|conn| {
+            let referral_code = request
+                .referral_code
+                .as_deref()
+                .map(|code| ReferralCodeService::claim(conn, code))
+                .transpose()?;
+
+            // Create new user with referral info
+            let new_user = NewUser {
+                email: request.email,
+                username: request.username,
+                password_hash,
+                referred_by: referral_code.as_ref().map(|code| code.user_id),
+                referral_count: Some(0),
+                total_referral_rewards: None,
+                bio: None,
+                avatar_url: None,
+                is_verified: Some(false),
+                last_active: Some(Utc::now()),
+                referral_code_id: referral_code.as_ref().map(|code| code.id),
+            };
+
+            let user: User = diesel::insert_into(users::table)
+                .values(&new_user)
+                .get_result(conn)
+                .map_err(|e| AppError::InternalServerError(format!("Failed to create user: {}", e)))?;
+
+            ReferralCodeService::create_default_code(conn, user.id, &user.username)?;
+            ReferralAbuseService::record_signup(conn, user.id, request.signup)?;
+            Ok::<_, AppError>(user)
+        })?;
+
+        if let Some(referrer_id) = user.referred_by {
+            ActivityService::record(pool, vec![ActivityEvent::referral_joined(referrer_id, user.id)]).await;
+            NotificationService::notify(
+                pool,
+                vec![NotificationService::referral_signup(referrer_id, user.id, &user.username)],
+            )
+            .await;
+            // Checked first so the signup reward of a flagged referral is held
+            if let Err(e) = ReferralAbuseService::check_signup(pool, user.id).await {
+                log::warn!("Failed to run referral abuse checks for {}: {}", user.id, e);
+            }
+            if let Err(e) = ReferralRewardService::reward_signup(pool, user.id).await {
+                log::warn!("Failed to grant signup referral reward for {}: {}", user.id, e);
+            }
+        }
 
         // Generate tokens (using minimal user data for token)
         let auth_user = AuthUser {
@@ -196,21 +241,22 @@ impl AuthService {
             password_hash: user.password_hash.clone(),
             username: user.username.clone(),
         };
-        let access_token = Self::generate_access_token(&auth_user)?;
         let refresh_token = Self::generate_refresh_token();
-        let refresh_token_hash = Self::hash_password(&refresh_token)?;
+        let refresh_token_hash = Self::hash_refresh_token(&refresh_token);
 
         // Store refresh token in sessions table
         let session = NewSession {
             user_id: auth_user.id,
             refresh_token_hash,
-            expires_at: Utc::now() + Duration::days(30),
+            expires_at: Utc::now() + Duration::days(AppConfig::global().refresh_token_ttl_days),
         };
 
-        diesel::insert_into(sessions::table)
+        let session_id = diesel::insert_into(sessions::table)
             .values(&session)
-            .execute(conn)
+            .returning(sessions::id)
+            .get_result::<Uuid>(conn)
             .map_err(|e| AppError::InternalServerError(format!("Failed to create session: {}", e)))?;
+        let access_token = Self::generate_access_token(&auth_user, user.is_admin, session_id)?;
 
         // Create user profile
         let user_profile = UserProfile {
@@ -231,7 +277,7 @@ impl AuthService {
             access_token,
             refresh_token,
             token_type: "Bearer".to_string(),
-            expires_in: 900, // 15 minutes in seconds
+            expires_in: AppConfig::global().access_token_ttl_secs,
             user: user_profile,
         })
     }
@@ -265,21 +311,22 @@ impl AuthService {
             .map_err(|e| AppError::InternalServerError(format!("Failed to get user profile: {}", e)))?;
 
         // Generate tokens
-        let access_token = Self::generate_access_token(&user)?;
         let refresh_token = Self::generate_refresh_token();
-        let refresh_token_hash = Self::hash_password(&refresh_token)?;
+        let refresh_token_hash = Self::hash_refresh_token(&refresh_token);
 
         // Store refresh token in sessions table
         let session = NewSession {
             user_id: user.id,
             refresh_token_hash,
-            expires_at: Utc::now() + Duration::days(30),
+            expires_at: Utc::now() + Duration::days(AppConfig::global().refresh_token_ttl_days),
         };
 
-        diesel::insert_into(sessions::table)
+        let session_id = diesel::insert_into(sessions::table)
             .values(&session)
-            .execute(conn)
+            .returning(sessions::id)
+            .get_result::<Uuid>(conn)
             .map_err(|e| AppError::InternalServerError(format!("Failed to create session: {}", e)))?;
+        let access_token = Self::generate_access_token(&user, full_user.is_admin, session_id)?;
 
         // Create user profile
         let user_profile = UserProfile {
@@ -300,7 +347,7 @@ impl AuthService {
             access_token,
             refresh_token,
             token_type: "Bearer".to_string(),
-            expires_in: 900, // 15 minutes in seconds
+            expires_in: AppConfig::global().access_token_ttl_secs,
             user: user_profile,
         })
     }
@@ -315,7 +362,7 @@ impl AuthService {
 
         // Find session by refresh token hash
         let session = sessions::table
-            .filter(sessions::refresh_token_hash.eq(&Self::hash_password(refresh_token)?))
+            .filter(sessions::refresh_token_hash.eq(Self::hash_refresh_token(refresh_token)))
             .filter(sessions::expires_at.gt(Utc::now()))
             .first::<Session>(conn)
             .optional()
@@ -324,19 +371,19 @@ impl AuthService {
 
         // Get user
         let user_id = session.user_id.ok_or_else(|| AppError::InternalServerError("Session has no user_id".to_string()))?;
-        let user = users::table
+        let (user, is_admin) = users::table
             .find(user_id)
-            .select((users::id, users::email, users::password_hash, users::username))
-            .first::<AuthUser>(conn)
+            .select(((users::id, users::email, users::password_hash, users::username), users::is_admin))
+            .first::<(AuthUser, bool)>(conn)
             .map_err(|e| AppError::InternalServerError(format!("Failed to get user: {}", e)))?;
 
         // Generate new access token
-        let access_token = Self::generate_access_token(&user)?;
+        let access_token = Self::generate_access_token(&user, is_admin, session.id)?;
 
         Ok(RefreshResponse {
             access_token,
             token_type: "Bearer".to_string(),
-            expires_in: 900, // 15 minutes in seconds
+            expires_in: AppConfig::global().access_token_ttl_secs,
         })
     }
 
@@ -348,7 +395,7 @@ impl AuthService {
         let conn = &mut pool.get()
             .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
 
-        let refresh_token_hash = Self::hash_password(refresh_token)?;
+        let refresh_token_hash = Self::hash_refresh_token(refresh_token);
 
         // Delete session
         diesel::delete(sessions::table)
@@ -359,6 +406,26 @@ impl AuthService {
         Ok(())
     }
 
+    // Whether the session an access token names is still open. Logout, password
+    // resets and account deletion remove the row.
+    pub async fn session_active(
+        pool: &DbPool,
+        user_id: Uuid,
+        session_id: Uuid,
+    ) -> Result<bool, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        diesel::select(diesel::dsl::exists(
+            sessions::table
+                .filter(sessions::id.eq(session_id))
+                .filter(sessions::user_id.eq(user_id))
+                .filter(sessions::expires_at.gt(Utc::now())),
+        ))
+        .get_result::<bool>(conn)
+        .map_err(|e| AppError::InternalServerError(format!("Failed to check session: {}", e)))
+    }
+
     // Get user profile by ID
     pub async fn get_user_profile(
         pool: &DbPool,
@@ -372,19 +439,7 @@ impl AuthService {
             .first::<User>(conn)
             .map_err(|e| AppError::InternalServerError(format!("Failed to get user: {}", e)))?;
 
-        Ok(UserProfile {
-            id: user.id,
-            email: user.email,
-            username: user.username,
-            bio: user.bio,
-            avatar_url: user.avatar_url,
-            is_verified: user.is_verified,
-            referral_count: user.referral_count,
-            total_referral_rewards: user.total_referral_rewards,
-            last_active: user.last_active,
-            created_at: user.created_at,
-            updated_at: user.updated_at,
-        })
+        Ok(user.into())
     }
 
     // Clean up expired sessions
@@ -0,0 +1,392 @@
+use crate::activity::{ActivityEvent, ActivityService};
+use crate::config::{AppConfig, AvatarStorageKind};
+use crate::models::{AvatarResponse, AvatarUpload, NewAvatarUpload};
+use crate::profiles::ProfileService;
+use crate::schema::{avatar_uploads, users};
+use crate::utils::AppError;
+use crate::DbPool;
+use actix_web::web;
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::io::{Limits, Reader};
+use image::{DynamicImage, ImageFormat, Rgb, RgbImage};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Cursor, ErrorKind};
+use std::path::PathBuf;
+use uuid::Uuid;
+
+// Replaced avatars are kept this long so cached pages and feeds don't break
+const AVATAR_GC_GRACE_HOURS: i64 = 24;
+const AVATAR_GC_BATCH: i64 = 500;
+// Larger source images are rejected from their header, before they are decoded
+const MAX_SOURCE_DIMENSION: u32 = 4096;
+// Cap on the decoder's memory: a 4096x4096 RGBA image at 8 bits per channel
+const MAX_DECODE_ALLOC: u64 = 64 * 1024 * 1024;
+// Square thumbnail edge lengths; avatar_url points at the largest
+pub const AVATAR_SIZES: [u32; 3] = [256, 128, 64];
+const JPEG_QUALITY: u8 = 85;
+
+// Where avatar files are kept. Keys look like "{user_id}/{upload_id}_256.jpg".
+pub trait AvatarStorage: Send + Sync {
+    fn put(&self, key: &str, bytes: &[u8]) -> Result<(), AppError>;
+    // Deleting a missing file is not an error
+    fn delete(&self, key: &str) -> Result<(), AppError>;
+    // URL clients load the file from
+    fn url(&self, key: &str) -> String;
+}
+
+// Files on local disk, served by the backend itself
+pub struct LocalAvatarStorage {
+    root: PathBuf,
+    public_path: String,
+}
+
+impl LocalAvatarStorage {
+    pub fn from_config(config: &AppConfig) -> Self {
+        LocalAvatarStorage {
+            root: config.avatar_storage_dir.clone().into(),
+            public_path: config.avatar_public_path.clone(),
+        }
+    }
+
+    // Serve stored files; must be registered before the /api scope
+    pub fn configure(cfg: &mut web::ServiceConfig) {
+        let config = AppConfig::global();
+        if config.avatar_storage != AvatarStorageKind::Local {
+            return;
+        }
+        let storage = Self::from_config(config);
+        if let Err(e) = fs::create_dir_all(&storage.root) {
+            log::error!("Failed to create avatar directory {}: {}", storage.root.display(), e);
+            return;
+        }
+        cfg.service(actix_files::Files::new(&storage.public_path, &storage.root));
+    }
+}
+
+impl AvatarStorage for LocalAvatarStorage {
+    fn put(&self, key: &str, bytes: &[u8]) -> Result<(), AppError> {
+        let path = self.root.join(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| AppError::InternalServerError(format!("Failed to create avatar directory: {}", e)))?;
+        }
+        // Write then rename, so a file is never served half-written
+        let partial = path.with_extension("partial");
+        fs::write(&partial, bytes)
+            .and_then(|_| fs::rename(&partial, &path))
+            .map_err(|e| AppError::InternalServerError(format!("Failed to store avatar: {}", e)))
+    }
+
+    fn delete(&self, key: &str) -> Result<(), AppError> {
+        match fs::remove_file(self.root.join(key)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                Err(AppError::InternalServerError(format!("Failed to delete avatar: {}", e)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn url(&self, key: &str) -> String {
+        format!("{}/{}", self.public_path, key)
+    }
+}
+
+// The backend selected by AVATAR_STORAGE. Only "local" exists so far; an
+// S3-compatible backend plugs in here by implementing AvatarStorage and
+// adding an AvatarStorageKind.
+pub fn avatar_storage() -> Result<Box<dyn AvatarStorage>, AppError> {
+    let config = AppConfig::global();
+    match config.avatar_storage {
+        AvatarStorageKind::Local => Ok(Box::new(LocalAvatarStorage::from_config(config))),
+    }
+}
+
+pub fn max_avatar_bytes() -> usize {
+    AppConfig::global().avatar_max_bytes
+}
+
+pub fn image_format(content_type: &str) -> Result<ImageFormat, AppError> {
+    match content_type {
+        "image/jpeg" => Ok(ImageFormat::Jpeg),
+        "image/png" => Ok(ImageFormat::Png),
+        "image/webp" => Ok(ImageFormat::WebP),
+        _ => Err(AppError::ValidationError(
+            "Avatar must be a JPEG, PNG or WebP image".to_string(),
+        )),
+    }
+}
+
+pub struct AvatarService;
+
+impl AvatarService {
+    // Resize the image to every thumbnail size, store them and make the largest
+    // the user's avatar. The previous avatar is collected later by AvatarGcJob.
+    pub async fn upload(
+        pool: &DbPool,
+        user_id: Uuid,
+        content_type: &str,
+        bytes: Vec<u8>,
+    ) -> Result<AvatarResponse, AppError> {
+        let format = image_format(content_type)?;
+        let original_bytes = bytes.len() as i32;
+
+        let thumbnails = web::block(move || process_image(&bytes, format))
+            .await
+            .map_err(|e| AppError::InternalServerError(format!("Failed to process avatar: {}", e)))??;
+
+        let storage = avatar_storage()?;
+        let upload_id = Uuid::new_v4();
+        let mut storage_keys = Vec::new();
+        let mut urls = BTreeMap::new();
+        for (size, jpeg) in &thumbnails {
+            let key = format!("{}/{}_{}.jpg", user_id, upload_id, size);
+            if let Err(e) = storage.put(&key, jpeg) {
+                remove_files(storage.as_ref(), &storage_keys);
+                return Err(e);
+            }
+            urls.insert(*size, storage.url(&key));
+            storage_keys.push(key);
+        }
+        let avatar_url = urls[&AVATAR_SIZES[0]].clone();
+
+        let saved = pool
+            .get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))
+            .and_then(|mut conn| {
+                conn.transaction(|conn| {
+                    diesel::insert_into(avatar_uploads::table)
+                        .values(&NewAvatarUpload {
+                            id: upload_id,
+                            user_id: Some(user_id),
+                            url: avatar_url.clone(),
+                            storage_keys: storage_keys.clone(),
+                            content_type: content_type.to_string(),
+                            original_bytes,
+                        })
+                        .execute(conn)?;
+                    ProfileService::set_avatar_url(conn, user_id, Some(&avatar_url))
+                })
+            });
+        if let Err(e) = saved {
+            remove_files(storage.as_ref(), &storage_keys);
+            return Err(e);
+        }
+
+        ActivityService::record(pool, vec![ActivityEvent::profile_updated(user_id)]).await;
+
+        Ok(AvatarResponse {
+            avatar_url,
+            thumbnails: urls,
+        })
+    }
+
+    // Clear the avatar; the files are collected later
+    pub async fn remove(pool: &DbPool, user_id: Uuid) -> Result<(), AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        conn.transaction(|conn| ProfileService::set_avatar_url(conn, user_id, None))
+    }
+
+    // Mark uploads that are no longer their user's avatar, then delete the files
+    // of those replaced more than AVATAR_GC_GRACE_HOURS ago. Returns uploads deleted.
+    pub async fn collect_garbage(pool: &DbPool) -> Result<usize, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let now = Utc::now();
+        let live = avatar_uploads::table
+            .left_join(users::table)
+            .filter(avatar_uploads::replaced_at.is_null())
+            .select((avatar_uploads::id, avatar_uploads::url, users::avatar_url.nullable()))
+            .load::<(Uuid, String, Option<String>)>(conn)?;
+        let replaced: Vec<Uuid> = live
+            .into_iter()
+            .filter(|(_, url, current)| current.as_ref() != Some(url))
+            .map(|(id, _, _)| id)
+            .collect();
+        if !replaced.is_empty() {
+            diesel::update(avatar_uploads::table.filter(avatar_uploads::id.eq_any(&replaced)))
+                .set(avatar_uploads::replaced_at.eq(Some(now)))
+                .execute(conn)?;
+        }
+
+        let expired = avatar_uploads::table
+            .filter(avatar_uploads::replaced_at.lt(now - Duration::hours(AVATAR_GC_GRACE_HOURS)))
+            .select(AvatarUpload::as_select())
+            .limit(AVATAR_GC_BATCH)
+            .load::<AvatarUpload>(conn)?;
+        if expired.is_empty() {
+            return Ok(0);
+        }
+
+        // Rows whose files could not be deleted are retried on the next run
+        let storage = avatar_storage()?;
+        let deleted: Vec<Uuid> = expired
+            .into_iter()
+            .filter(|upload| remove_files(storage.as_ref(), &upload.storage_keys))
+            .map(|upload| upload.id)
+            .collect();
+        diesel::delete(avatar_uploads::table.filter(avatar_uploads::id.eq_any(&deleted)))
+            .execute(conn)?;
+
+        Ok(deleted.len())
+    }
+}
+
+pub struct AvatarGcJob;
+
+impl AvatarGcJob {
+    pub fn spawn(pool: DbPool) {
+        let interval_secs = AppConfig::global().avatar_gc_interval_secs;
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(std::time::Duration::from_secs(interval_secs));
+
+            loop {
+                ticker.tick().await;
+
+                match AvatarService::collect_garbage(&pool).await {
+                    Ok(count) => log::debug!("Deleted {} replaced avatars", count),
+                    Err(e) => log::error!("Failed to collect replaced avatars: {}", e),
+                }
+            }
+        });
+    }
+}
+
+// Returns false if any file could not be deleted
+fn remove_files(storage: &dyn AvatarStorage, keys: &[String]) -> bool {
+    keys.iter().fold(true, |ok, key| match storage.delete(key) {
+        Ok(()) => ok,
+        Err(e) => {
+            log::warn!("{}", e);
+            false
+        }
+    })
+}
+
+// Decode, check the content really is `format`, and encode one JPEG per
+// thumbnail size. Re-encoding drops EXIF and all other metadata.
+fn process_image(bytes: &[u8], format: ImageFormat) -> Result<Vec<(u32, Vec<u8>)>, AppError> {
+    let invalid = |message: &str| AppError::ValidationError(message.to_string());
+
+    if image::guess_format(bytes).ok() != Some(format) {
+        return Err(invalid("File content does not match its content type"));
+    }
+
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_SOURCE_DIMENSION);
+    limits.max_image_height = Some(MAX_SOURCE_DIMENSION);
+    limits.max_alloc = Some(MAX_DECODE_ALLOC);
+    let reader = || {
+        let mut reader = Reader::with_format(Cursor::new(bytes), format);
+        reader.limits(limits.clone());
+        reader
+    };
+
+    let (width, height) = reader().into_dimensions().map_err(|_| invalid("Could not read image"))?;
+    if width > MAX_SOURCE_DIMENSION || height > MAX_SOURCE_DIMENSION {
+        return Err(AppError::ValidationError(format!(
+            "Images must be at most {} pixels per side",
+            MAX_SOURCE_DIMENSION
+        )));
+    }
+    let image = reader().decode().map_err(|_| invalid("Could not read image"))?;
+
+    // The orientation tag is lost with the rest of the metadata, so apply it first
+    let image = apply_orientation(image, exif_orientation(bytes));
+
+    AVATAR_SIZES
+        .iter()
+        .map(|&size| {
+            let thumbnail = flatten(&image.resize_to_fill(size, size, FilterType::Lanczos3));
+            let mut jpeg = Vec::new();
+            JpegEncoder::new_with_quality(&mut jpeg, JPEG_QUALITY)
+                .encode_image(&thumbnail)
+                .map_err(|e| AppError::InternalServerError(format!("Failed to encode avatar: {}", e)))?;
+            Ok((size, jpeg))
+        })
+        .collect()
+}
+
+fn exif_orientation(bytes: &[u8]) -> u32 {
+    exif::Reader::new()
+        .read_from_container(&mut Cursor::new(bytes))
+        .ok()
+        .and_then(|exif| {
+            exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)
+                .and_then(|field| field.value.get_uint(0))
+        })
+        .unwrap_or(1)
+}
+
+fn apply_orientation(image: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+// JPEG has no alpha channel; transparent areas become white
+fn flatten(image: &DynamicImage) -> RgbImage {
+    let rgba = image.to_rgba8();
+    RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let blend = |c: u8| ((c as u16 * a as u16 + 255 * (255 - a as u16)) / 255) as u8;
+        Rgb([blend(r), blend(g), blend(b)])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let image = RgbaImage::from_pixel(width, height, Rgba([10, 20, 30, 255]));
+        let mut bytes = Vec::new();
+        DynamicImage::ImageRgba8(image)
+            .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_process_image_makes_every_thumbnail() {
+        let thumbnails = process_image(&png(300, 200), ImageFormat::Png).unwrap();
+        let sizes: Vec<u32> = thumbnails.iter().map(|(size, _)| *size).collect();
+        assert_eq!(sizes, AVATAR_SIZES);
+
+        let decoded = image::load_from_memory(&thumbnails[2].1).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (64, 64));
+    }
+
+    #[test]
+    fn test_rejects_oversized_and_mislabelled_images() {
+        assert!(process_image(&png(MAX_SOURCE_DIMENSION + 1, 1), ImageFormat::Png).is_err());
+        assert!(process_image(&png(1, MAX_SOURCE_DIMENSION + 1), ImageFormat::Png).is_err());
+        assert!(process_image(&png(10, 10), ImageFormat::Jpeg).is_err());
+        assert!(process_image(b"not an image", ImageFormat::Png).is_err());
+    }
+
+    #[test]
+    fn test_orientation_and_transparency() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(4, 2, Rgba([0, 0, 0, 0])));
+        let rotated = apply_orientation(image.clone(), 6);
+        assert_eq!((rotated.width(), rotated.height()), (2, 4));
+        assert_eq!(apply_orientation(image.clone(), 1).width(), 4);
+
+        assert_eq!(flatten(&image).get_pixel(0, 0).0, [255, 255, 255]);
+    }
+}
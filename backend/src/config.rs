@@ -0,0 +1,453 @@
+use crate::rate_limit::Quota;
+use crate::referrals::{ReferralRewardConfig, RebateTier};
+use ::config::builder::DefaultState;
+use ::config::{Config, ConfigBuilder, ConfigError, Environment, File};
+use serde::{Deserialize, Deserializer};
+use std::env;
+use std::sync::OnceLock;
+
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+const PRODUCTION_HSTS_MAX_AGE_SECS: u64 = 31_536_000;
+
+// Vite and CRA dev servers
+const DEVELOPMENT_CORS_ORIGINS: &[&str] = &[
+    "http://localhost:5173",
+    "http://127.0.0.1:5173",
+    "http://localhost:3000",
+];
+
+static CONFIG: OnceLock<AppConfig> = OnceLock::new();
+
+// Settings loaded once at startup. Later sources win: the defaults below, the
+// optional config file (APP_CONFIG_FILE, default config.toml), then environment
+// variables named like the fields in upper case, e.g. DATABASE_URL.
+#[derive(Clone, Deserialize)]
+pub struct AppConfig {
+    // Server
+    pub app_env: AppEnv,
+    pub host: String,
+    pub port: u16,
+
+    // Database pool
+    pub database_url: String,
+    pub database_max_connections: u32,
+    pub database_min_idle: u32,
+    pub database_connect_timeout_secs: u64,
+    pub database_idle_timeout_secs: u64,
+    pub database_max_lifetime_secs: u64,
+
+    // Tokens. JWT_KEYS_DIR holds the signing keys as <kid>.pem; unset means a
+    // temporary key, allowed in development only.
+    pub jwt_keys_dir: Option<String>,
+    pub jwt_signing_kid: Option<String>,
+    pub jwt_issuer: String,
+    pub jwt_audience: String,
+    pub access_token_ttl_secs: i64,
+    pub refresh_token_ttl_days: i64,
+
+    // Origins allowed to call the API from a browser; "*" allows any. Unset
+    // means the preset of the environment.
+    #[serde(default, deserialize_with = "string_or_list")]
+    pub cors_allowed_origins: Vec<String>,
+    // Strict-Transport-Security max-age; 0 leaves the header out. Unset means
+    // one year in production and staging, 0 in development.
+    pub hsts_max_age_secs: Option<u64>,
+
+    // Rate limiting. Quotas are "<requests>/<seconds>"; the bucket refills
+    // evenly over the period.
+    pub rate_limit_enabled: bool,
+    pub rate_limit_store: RateLimitStoreKind,
+    // Needed for the redis store
+    pub redis_url: Option<String>,
+    // Take the client IP from Forwarded / X-Forwarded-For; only behind a proxy
+    // that sets them
+    pub rate_limit_trust_proxy: bool,
+    // SHA-256 hex digests of the issued API keys. Only these get a bucket of
+    // their own; any other X-API-Key is ignored and counted by IP.
+    #[serde(default, deserialize_with = "string_or_list")]
+    pub rate_limit_api_keys: Vec<String>,
+    pub rate_limit_auth: Quota,
+    pub rate_limit_trading_writes: Quota,
+    pub rate_limit_market_data: Quota,
+    pub rate_limit_default: Quota,
+
+    // Kana Labs
+    pub kana_api_key: Option<String>,
+    pub kana_api_base_url: String,
+    pub kana_request_timeout_secs: u64,
+
+    // Email; sending is disabled without an API key
+    pub resend_api_key: Option<String>,
+    pub from_email: String,
+    // Links in emails point here
+    pub frontend_url: String,
+
+    // How often the background jobs run
+    pub funding_collector_interval_secs: u64,
+    pub fill_reconcile_interval_secs: u64,
+    pub equity_snapshot_interval_secs: u64,
+    pub leaderboard_refresh_interval_secs: u64,
+    pub alert_eval_interval_secs: u64,
+    pub avatar_gc_interval_secs: u64,
+    pub data_export_interval_secs: u64,
+    pub account_deletion_interval_secs: u64,
+
+    // Alert emails per user per hour; alerts over the limit are only logged
+    pub alert_email_max_per_hour: i64,
+
+    // Referral rewards. Tiers are "<minimum referees>:<fee rebate %>" pairs.
+    pub referral_signup_bonus: f64,
+    pub referral_deposit_bonus_pct: f64,
+    pub referral_deposit_bonus_cap: f64,
+    pub referral_min_deposit: f64,
+    #[serde(deserialize_with = "rebate_tiers")]
+    pub referral_rebate_tiers: Vec<RebateTier>,
+
+    // Avatars; files are served from AVATAR_PUBLIC_PATH
+    pub avatar_storage: AvatarStorageKind,
+    pub avatar_storage_dir: String,
+    pub avatar_public_path: String,
+    pub avatar_max_bytes: usize,
+
+    // Accounts
+    pub username_change_cooldown_days: i64,
+    pub account_deletion_grace_days: i64,
+    pub data_export_dir: String,
+    pub data_export_retention_hours: i64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AppEnv {
+    Development,
+    Staging,
+    Production,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitStoreKind {
+    Memory,
+    Redis,
+}
+
+// Only local disk so far; an S3-compatible backend would be added here
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AvatarStorageKind {
+    Local,
+}
+
+impl AppConfig {
+    pub fn load() -> Result<Self, ConfigError> {
+        let file = env::var("APP_CONFIG_FILE").unwrap_or_else(|_| DEFAULT_CONFIG_FILE.to_string());
+
+        Self::build(
+            Self::defaults()?
+                .add_source(File::with_name(&file).required(false))
+                .add_source(Environment::default().ignore_empty(true)),
+        )
+    }
+
+    fn defaults() -> Result<ConfigBuilder<DefaultState>, ConfigError> {
+        Config::builder()
+            .set_default("app_env", "development")?
+            .set_default("host", "0.0.0.0")?
+            .set_default("port", 8081)?
+            .set_default("database_url", "")?
+            .set_default("database_max_connections", 10)?
+            .set_default("database_min_idle", 1)?
+            .set_default("database_connect_timeout_secs", 30)?
+            .set_default("database_idle_timeout_secs", 600)?
+            .set_default("database_max_lifetime_secs", 1800)?
+            .set_default("jwt_issuer", "aptora")?
+            .set_default("jwt_audience", "aptora-api")?
+            .set_default("access_token_ttl_secs", 900)?
+            .set_default("refresh_token_ttl_days", 30)?
+            .set_default("rate_limit_enabled", true)?
+            .set_default("rate_limit_store", "memory")?
+            .set_default("rate_limit_trust_proxy", false)?
+            .set_default("rate_limit_auth", "10/60")?
+            .set_default("rate_limit_trading_writes", "60/60")?
+            .set_default("rate_limit_market_data", "300/60")?
+            .set_default("rate_limit_default", "120/60")?
+            .set_default("kana_api_base_url", "https://perps-tradeapi.kanalabs.io")?
+            .set_default("kana_request_timeout_secs", 10)?
+            .set_default("from_email", "noreply@aptora.com")?
+            .set_default("frontend_url", "https://aptora-kana.netlify.app")?
+            .set_default("funding_collector_interval_secs", 900)?
+            .set_default("fill_reconcile_interval_secs", 300)?
+            .set_default("equity_snapshot_interval_secs", 300)?
+            .set_default("leaderboard_refresh_interval_secs", 600)?
+            .set_default("alert_eval_interval_secs", 60)?
+            .set_default("avatar_gc_interval_secs", 3600)?
+            .set_default("data_export_interval_secs", 30)?
+            .set_default("account_deletion_interval_secs", 3600)?
+            .set_default("alert_email_max_per_hour", 10)?
+            .set_default("referral_signup_bonus", 5.0)?
+            .set_default("referral_deposit_bonus_pct", 5.0)?
+            .set_default("referral_deposit_bonus_cap", 50.0)?
+            .set_default("referral_min_deposit", 10.0)?
+            .set_default("referral_rebate_tiers", "0:10,10:15,50:20,100:25")?
+            .set_default("avatar_storage", "local")?
+            .set_default("avatar_storage_dir", "uploads/avatars")?
+            // Under /api so the reverse proxy forwards it to the backend
+            .set_default("avatar_public_path", "/api/avatars")?
+            .set_default("avatar_max_bytes", 5 * 1024 * 1024)?
+            .set_default("username_change_cooldown_days", 30)?
+            .set_default("account_deletion_grace_days", 30)?
+            .set_default("data_export_dir", "exports")?
+            .set_default("data_export_retention_hours", 48)
+    }
+
+    fn build(builder: ConfigBuilder<DefaultState>) -> Result<Self, ConfigError> {
+        let mut config: AppConfig = builder.build()?.try_deserialize()?;
+
+        config.apply_presets();
+        config.validate()?;
+        Ok(config)
+    }
+
+    // The defaults, for tests of code that reads the global config
+    #[cfg(test)]
+    pub fn for_tests() -> &'static AppConfig {
+        CONFIG.get_or_init(|| {
+            Self::build(Self::defaults().unwrap().set_override("database_url", "postgres://localhost/test").unwrap())
+                .unwrap()
+        })
+    }
+
+    // Fill in the settings whose default depends on APP_ENV
+    fn apply_presets(&mut self) {
+        self.avatar_public_path = self.avatar_public_path.trim_end_matches('/').to_string();
+        if self.cors_allowed_origins.is_empty() {
+            self.cors_allowed_origins = match self.app_env {
+                AppEnv::Development => DEVELOPMENT_CORS_ORIGINS.iter().map(|o| o.to_string()).collect(),
+                AppEnv::Staging | AppEnv::Production => {
+                    vec![self.frontend_url.trim_end_matches('/').to_string()]
+                }
+            };
+        }
+        if self.hsts_max_age_secs.is_none() {
+            self.hsts_max_age_secs = Some(match self.app_env {
+                AppEnv::Development => 0,
+                AppEnv::Staging | AppEnv::Production => PRODUCTION_HSTS_MAX_AGE_SECS,
+            });
+        }
+    }
+
+    // Make the config available where no app data is at hand, such as background
+    // jobs and token verification
+    pub fn init(config: AppConfig) -> &'static AppConfig {
+        CONFIG.get_or_init(|| config)
+    }
+
+    pub fn global() -> &'static AppConfig {
+        CONFIG.get().expect("AppConfig::init must be called at startup")
+    }
+
+    // Report every invalid setting at once, by its environment variable name
+    fn validate(&self) -> Result<(), ConfigError> {
+        let mut errors = Vec::new();
+
+        if self.database_url.trim().is_empty() {
+            errors.push("DATABASE_URL must be set".to_string());
+        }
+        if self.port == 0 {
+            errors.push("PORT must not be 0".to_string());
+        }
+        if self.database_max_connections == 0 {
+            errors.push("DATABASE_MAX_CONNECTIONS must be at least 1".to_string());
+        }
+        if self.database_min_idle > self.database_max_connections {
+            errors.push("DATABASE_MIN_IDLE must not exceed DATABASE_MAX_CONNECTIONS".to_string());
+        }
+        match (&self.jwt_keys_dir, &self.jwt_signing_kid) {
+            (None, _) if self.app_env != AppEnv::Development => {
+                errors.push("JWT_KEYS_DIR must be set in staging and production".to_string());
+            }
+            (Some(_), None) => errors.push("JWT_SIGNING_KID must be set with JWT_KEYS_DIR".to_string()),
+            _ => {}
+        }
+        if self.jwt_issuer.trim().is_empty() {
+            errors.push("JWT_ISSUER must not be empty".to_string());
+        }
+        if self.jwt_audience.trim().is_empty() {
+            errors.push("JWT_AUDIENCE must not be empty".to_string());
+        }
+        if self.access_token_ttl_secs <= 0 {
+            errors.push("ACCESS_TOKEN_TTL_SECS must be positive".to_string());
+        }
+        if self.refresh_token_ttl_days <= 0 {
+            errors.push("REFRESH_TOKEN_TTL_DAYS must be positive".to_string());
+        }
+        for origin in &self.cors_allowed_origins {
+            if origin == "*" {
+                if self.app_env == AppEnv::Production {
+                    errors.push("CORS_ALLOWED_ORIGINS must list origins in production, not *".to_string());
+                }
+            } else if !is_origin(origin) {
+                errors.push(format!("CORS_ALLOWED_ORIGINS has an invalid origin: {}", origin));
+            }
+        }
+        if self.rate_limit_enabled
+            && self.rate_limit_store == RateLimitStoreKind::Redis
+            && self.redis_url.is_none()
+        {
+            errors.push("REDIS_URL must be set when RATE_LIMIT_STORE is redis".to_string());
+        }
+        if self
+            .rate_limit_api_keys
+            .iter()
+            .any(|digest| digest.len() != 64 || !digest.chars().all(|c| c.is_ascii_hexdigit()))
+        {
+            errors.push("RATE_LIMIT_API_KEYS must list SHA-256 hex digests".to_string());
+        }
+        if !is_http_url(&self.kana_api_base_url) {
+            errors.push("KANA_API_BASE_URL must be an http or https URL".to_string());
+        }
+        if !is_http_url(&self.frontend_url) {
+            errors.push("FRONTEND_URL must be an http or https URL".to_string());
+        }
+        if !self.from_email.contains('@') {
+            errors.push("FROM_EMAIL must be an email address".to_string());
+        }
+        for (name, secs) in [
+            ("FUNDING_COLLECTOR_INTERVAL_SECS", self.funding_collector_interval_secs),
+            ("FILL_RECONCILE_INTERVAL_SECS", self.fill_reconcile_interval_secs),
+            ("EQUITY_SNAPSHOT_INTERVAL_SECS", self.equity_snapshot_interval_secs),
+            ("LEADERBOARD_REFRESH_INTERVAL_SECS", self.leaderboard_refresh_interval_secs),
+            ("ALERT_EVAL_INTERVAL_SECS", self.alert_eval_interval_secs),
+            ("AVATAR_GC_INTERVAL_SECS", self.avatar_gc_interval_secs),
+            ("DATA_EXPORT_INTERVAL_SECS", self.data_export_interval_secs),
+            ("ACCOUNT_DELETION_INTERVAL_SECS", self.account_deletion_interval_secs),
+        ] {
+            if secs == 0 {
+                errors.push(format!("{} must be positive", name));
+            }
+        }
+        if self.alert_email_max_per_hour < 0 {
+            errors.push("ALERT_EMAIL_MAX_PER_HOUR must not be negative".to_string());
+        }
+        for (name, amount) in [
+            ("REFERRAL_SIGNUP_BONUS", self.referral_signup_bonus),
+            ("REFERRAL_DEPOSIT_BONUS_PCT", self.referral_deposit_bonus_pct),
+            ("REFERRAL_DEPOSIT_BONUS_CAP", self.referral_deposit_bonus_cap),
+            ("REFERRAL_MIN_DEPOSIT", self.referral_min_deposit),
+        ] {
+            if !amount.is_finite() || amount < 0.0 {
+                errors.push(format!("{} must be a non-negative number", name));
+            }
+        }
+        if self.avatar_storage_dir.trim().is_empty() {
+            errors.push("AVATAR_STORAGE_DIR must not be empty".to_string());
+        }
+        if !self.avatar_public_path.starts_with('/') {
+            errors.push("AVATAR_PUBLIC_PATH must start with /".to_string());
+        }
+        if self.avatar_max_bytes == 0 {
+            errors.push("AVATAR_MAX_BYTES must be positive".to_string());
+        }
+        if self.username_change_cooldown_days < 0 {
+            errors.push("USERNAME_CHANGE_COOLDOWN_DAYS must not be negative".to_string());
+        }
+        if self.account_deletion_grace_days < 0 {
+            errors.push("ACCOUNT_DELETION_GRACE_DAYS must not be negative".to_string());
+        }
+        if self.data_export_dir.trim().is_empty() {
+            errors.push("DATA_EXPORT_DIR must not be empty".to_string());
+        }
+        if self.data_export_retention_hours < 1 {
+            errors.push("DATA_EXPORT_RETENTION_HOURS must be at least 1".to_string());
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Message(format!("Invalid configuration: {}", errors.join("; "))))
+        }
+    }
+}
+
+fn is_http_url(value: &str) -> bool {
+    url::Url::parse(value)
+        .is_ok_and(|url| matches!(url.scheme(), "http" | "https") && url.host().is_some())
+}
+
+// Scheme, host and optional port, as browsers send in the Origin header
+fn is_origin(value: &str) -> bool {
+    is_http_url(value) && url::Url::parse(value).is_ok_and(|url| url.origin().ascii_serialization() == value)
+}
+
+// Lists come as arrays from the config file and comma-separated from the environment
+fn string_or_list<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrList {
+        List(Vec<String>),
+        String(String),
+    }
+
+    let items = match StringOrList::deserialize(deserializer)? {
+        StringOrList::List(items) => items,
+        StringOrList::String(value) => value.split(',').map(str::to_string).collect(),
+    };
+    Ok(items
+        .into_iter()
+        .map(|item| item.trim().trim_end_matches('/').to_string())
+        .filter(|item| !item.is_empty())
+        .collect())
+}
+
+fn rebate_tiers<'de, D>(deserializer: D) -> Result<Vec<RebateTier>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let spec = String::deserialize(deserializer)?;
+    ReferralRewardConfig::parse_tiers(&spec).ok_or_else(|| {
+        serde::de::Error::custom(
+            "REFERRAL_REBATE_TIERS must be <minimum referees>:<rebate %> pairs with rebates of 0 to 100",
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(overrides: &[(&str, &str)]) -> Result<AppConfig, ConfigError> {
+        let mut builder = AppConfig::defaults()?.set_override("database_url", "postgres://localhost/test")?;
+        for (key, value) in overrides {
+            builder = builder.set_override(*key, *value)?;
+        }
+        AppConfig::build(builder)
+    }
+
+    #[test]
+    fn test_defaults_are_valid() {
+        let config = build(&[]).unwrap();
+        assert_eq!(config.referral_rebate_tiers.len(), 4);
+        assert_eq!(config.avatar_storage, AvatarStorageKind::Local);
+        assert_eq!(config.avatar_max_bytes, 5 * 1024 * 1024);
+    }
+
+    #[test]
+    fn test_invalid_job_and_feature_settings_fail_at_boot() {
+        assert!(build(&[("funding_collector_interval_secs", "0")]).is_err());
+        assert!(build(&[("referral_rebate_tiers", "0:10,10")]).is_err());
+        assert!(build(&[("referral_signup_bonus", "-5")]).is_err());
+        assert!(build(&[("avatar_storage", "s3")]).is_err());
+        assert!(build(&[("avatar_public_path", "avatars")]).is_err());
+        assert!(build(&[("data_export_retention_hours", "0")]).is_err());
+        assert!(build(&[("account_deletion_grace_days", "-1")]).is_err());
+    }
+
+    #[test]
+    fn test_avatar_public_path_loses_its_trailing_slash() {
+        let config = build(&[("avatar_public_path", "/media/avatars/")]).unwrap();
+        assert_eq!(config.avatar_public_path, "/media/avatars");
+    }
+}
@@ -0,0 +1,635 @@
+use crate::equity::EquityService;
+use crate::markets::{MarketRegistry, MarketSpec};
+use crate::models::{
+    CopySubscription, CopyTrade, Fill, NewCopySubscription, NewCopyTrade, PaperOrder,
+};
+use crate::paper_trading::{PaperOrderRequest, PaperTradingService};
+use crate::relationships::RelationshipService;
+use crate::risk::{RiskEngine, RiskLimits};
+use crate::schema::{copy_subscriptions, copy_trades, paper_orders, users};
+use crate::utils::AppError;
+use crate::wallets::WalletService;
+use crate::DbPool;
+use chrono::{DateTime, Duration, Utc};
+use diesel::prelude::*;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+// Fills older than this when they reach the ledger are history, not signals
+const MAX_SOURCE_AGE_MINUTES: i64 = 15;
+// Pending live copies the follower has not reported on by then expire and
+// stop holding margin
+const PENDING_COPY_TTL_MINUTES: i64 = 30;
+// What a follower can report for a pending live copy
+const COPY_RESULTS: [&str; 3] = ["executed", "failed", "dismissed"];
+
+#[derive(Debug, Clone)]
+pub struct CopySubscriptionRequest {
+    pub leader_username: String,
+    pub allocation: f64,
+    pub max_leverage: f64,
+    pub max_trade_notional: f64,
+    pub mode: String,
+}
+
+// A leader trade that subscribers should copy
+#[derive(Debug, Clone)]
+pub struct SourceTrade {
+    pub leader_id: Uuid,
+    pub source_type: &'static str, // "fill" or "paper_order"
+    pub source_id: Uuid,
+    pub symbol: String,
+    pub side: String,
+    pub order_type: String,
+    pub size: f64,
+    pub price: f64,
+    pub leverage: Option<f64>,
+    pub executed_at: DateTime<Utc>,
+}
+
+pub struct CopyTradingService;
+
+impl CopyTradingService {
+    // Start copying a leader, or update the settings of an existing subscription
+    pub async fn subscribe(
+        pool: &DbPool,
+        follower_id: Uuid,
+        request: CopySubscriptionRequest,
+    ) -> Result<CopySubscription, AppError> {
+        if request.mode != "paper" && request.mode != "live" {
+            return Err(AppError::ValidationError("mode must be 'paper' or 'live'".to_string()));
+        }
+
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let leader_id = users::table
+            .filter(users::username.eq(&request.leader_username))
+            .filter(users::deleted_at.is_null())
+            .select(users::id)
+            .first::<Uuid>(conn)
+            .optional()
+            .map_err(|e| AppError::InternalServerError(format!("Database error: {}", e)))?
+            .ok_or_else(|| AppError::NotFoundError("User not found".to_string()))?;
+
+        if leader_id == follower_id {
+            return Err(AppError::BadRequest("Cannot copy yourself".to_string()));
+        }
+        if RelationshipService::is_blocked_between(conn, follower_id, leader_id)? {
+            return Err(AppError::NotFoundError("User not found".to_string()));
+        }
+        // Private accounts can only be copied by their approved followers
+        if !RelationshipService::content_visible(conn, leader_id, Some(follower_id))? {
+            return Err(AppError::AuthorizationError("Follow this account before copying it".to_string()));
+        }
+        // Live copies are submitted from the follower's wallet, so it must be verified
+        if request.mode == "live" && !WalletService::has_verified_wallet(conn, follower_id)? {
+            return Err(AppError::BadRequest("Link and verify a wallet before copying live".to_string()));
+        }
+
+        diesel::insert_into(copy_subscriptions::table)
+            .values(&NewCopySubscription {
+                follower_id,
+                leader_id,
+                allocation: request.allocation,
+                max_leverage: request.max_leverage,
+                max_trade_notional: request.max_trade_notional,
+                mode: request.mode.clone(),
+            })
+            .on_conflict((copy_subscriptions::follower_id, copy_subscriptions::leader_id))
+            .do_update()
+            .set((
+                copy_subscriptions::allocation.eq(request.allocation),
+                copy_subscriptions::max_leverage.eq(request.max_leverage),
+                copy_subscriptions::max_trade_notional.eq(request.max_trade_notional),
+                copy_subscriptions::mode.eq(&request.mode),
+                copy_subscriptions::status.eq("active"),
+                copy_subscriptions::stopped_at.eq(None::<DateTime<Utc>>),
+            ))
+            .get_result::<CopySubscription>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to save subscription: {}", e)))
+    }
+
+    // Stop copying; past copied trades are kept
+    pub async fn stop_copying(
+        pool: &DbPool,
+        follower_id: Uuid,
+        subscription_id: Uuid,
+    ) -> Result<CopySubscription, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        diesel::update(
+            copy_subscriptions::table
+                .filter(copy_subscriptions::id.eq(subscription_id))
+                .filter(copy_subscriptions::follower_id.eq(follower_id)),
+        )
+        .set((
+            copy_subscriptions::status.eq("stopped"),
+            copy_subscriptions::stopped_at.eq(Some(Utc::now())),
+        ))
+        .get_result::<CopySubscription>(conn)
+        .optional()
+        .map_err(|e| AppError::InternalServerError(format!("Failed to stop subscription: {}", e)))?
+        .ok_or_else(|| AppError::NotFoundError("Subscription not found".to_string()))
+    }
+
+    pub async fn get_subscriptions(
+        pool: &DbPool,
+        follower_id: Uuid,
+    ) -> Result<Vec<CopySubscription>, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        copy_subscriptions::table
+            .filter(copy_subscriptions::follower_id.eq(follower_id))
+            .order(copy_subscriptions::created_at.desc())
+            .load::<CopySubscription>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get subscriptions: {}", e)))
+    }
+
+    pub async fn get_copy_trades(
+        pool: &DbPool,
+        follower_id: Uuid,
+        subscription_id: Option<Uuid>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(Vec<CopyTrade>, i64), AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+        Self::expire_pending(conn, follower_id)?;
+
+        let filtered = || {
+            let mut query = copy_trades::table
+                .filter(copy_trades::follower_id.eq(follower_id))
+                .into_boxed();
+            if let Some(subscription_id) = subscription_id {
+                query = query.filter(copy_trades::subscription_id.eq(subscription_id));
+            }
+            query
+        };
+
+        let total = filtered().count().get_result::<i64>(conn)?;
+        let trades = filtered()
+            .order(copy_trades::created_at.desc())
+            .limit(limit)
+            .offset(offset)
+            .load::<CopyTrade>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get copy trades: {}", e)))?;
+
+        Ok((trades, total))
+    }
+
+    // Record what became of a pending live copy: the follower's wallet
+    // submitted it, the submission failed, or the follower passed on it
+    pub async fn report_result(
+        pool: &DbPool,
+        follower_id: Uuid,
+        trade_id: Uuid,
+        status: &str,
+        reason: Option<String>,
+    ) -> Result<CopyTrade, AppError> {
+        if !COPY_RESULTS.contains(&status) {
+            return Err(AppError::ValidationError(
+                "status must be 'executed', 'failed' or 'dismissed'".to_string(),
+            ));
+        }
+
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+        Self::expire_pending(conn, follower_id)?;
+
+        let owned = copy_trades::table
+            .filter(copy_trades::id.eq(trade_id))
+            .filter(copy_trades::follower_id.eq(follower_id));
+
+        // Only a pending copy can change, so two reports cannot both land
+        let updated = diesel::update(owned.filter(copy_trades::status.eq("pending")))
+            .set((copy_trades::status.eq(status), copy_trades::reason.eq(reason)))
+            .get_result::<CopyTrade>(conn)
+            .optional()?;
+        if let Some(trade) = updated {
+            return Ok(trade);
+        }
+
+        let current = owned
+            .select(copy_trades::status)
+            .first::<String>(conn)
+            .optional()?
+            .ok_or_else(|| AppError::NotFoundError("Copy trade not found".to_string()))?;
+        Err(AppError::BadRequest(format!("Copy trade is already {}", current)))
+    }
+
+    // Cancel the followers' copies of a paper order the leader cancelled. Copies
+    // that already filled, or that the follower cancelled, are left alone.
+    pub async fn cancel_paper_copies(pool: &DbPool, order: &PaperOrder) -> Result<usize, AppError> {
+        let copies = {
+            let conn = &mut pool.get()
+                .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+            copy_trades::table
+                .inner_join(paper_orders::table)
+                .filter(copy_trades::source_type.eq("paper_order"))
+                .filter(copy_trades::source_id.eq(order.id))
+                .filter(copy_trades::status.eq("executed"))
+                .filter(paper_orders::status.eq("open"))
+                .select((copy_trades::id, copy_trades::follower_id, paper_orders::id))
+                .load::<(Uuid, Uuid, Uuid)>(conn)?
+        };
+
+        let mut cancelled = 0;
+        for (trade_id, follower_id, paper_order_id) in copies {
+            match PaperTradingService::cancel_order(pool, follower_id, paper_order_id).await {
+                Ok(_) => {}
+                // Filled or cancelled since it was loaded
+                Err(AppError::BadRequest(_)) => continue,
+                Err(e) => return Err(e),
+            }
+
+            let conn = &mut pool.get()
+                .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+            diesel::update(copy_trades::table.find(trade_id))
+                .set((
+                    copy_trades::status.eq("cancelled"),
+                    copy_trades::reason.eq("Leader cancelled the order"),
+                ))
+                .execute(conn)
+                .map_err(|e| AppError::InternalServerError(format!("Failed to log copy trade: {}", e)))?;
+            cancelled += 1;
+        }
+
+        Ok(cancelled)
+    }
+
+    // Give up on pending live copies the follower never reported on, which
+    // returns their margin to the allocation
+    fn expire_pending(conn: &mut PgConnection, follower_id: Uuid) -> Result<usize, AppError> {
+        diesel::update(
+            copy_trades::table
+                .filter(copy_trades::follower_id.eq(follower_id))
+                .filter(copy_trades::status.eq("pending"))
+                .filter(copy_trades::created_at.lt(Utc::now() - Duration::minutes(PENDING_COPY_TTL_MINUTES))),
+        )
+        .set((
+            copy_trades::status.eq("expired"),
+            copy_trades::reason.eq("Not submitted in time"),
+        ))
+        .execute(conn)
+        .map_err(|e| AppError::InternalServerError(format!("Failed to expire copy trades: {}", e)))
+    }
+
+    // Copy freshly reconciled live fills of leaders
+    pub async fn copy_fills(pool: &DbPool, fills: &[Fill]) -> Result<usize, AppError> {
+        let cutoff = Utc::now() - Duration::minutes(MAX_SOURCE_AGE_MINUTES);
+
+        let mut copied = 0;
+        for fill in fills.iter().filter(|fill| fill.executed_at >= cutoff) {
+            copied += Self::replicate(
+                pool,
+                SourceTrade {
+                    leader_id: fill.user_id,
+                    source_type: "fill",
+                    source_id: fill.id,
+                    symbol: fill.symbol.clone(),
+                    side: fill.side.clone(),
+                    order_type: "market".to_string(),
+                    size: fill.size,
+                    price: fill.price,
+                    leverage: None,
+                    executed_at: fill.executed_at,
+                },
+            )
+            .await?;
+        }
+        Ok(copied)
+    }
+
+    // Copy a paper order a leader just placed. The copy has the same type and
+    // limit price, so a resting copy fills when the market crosses that price,
+    // as the leader's does; cancel_paper_copies follows the leader's cancels.
+    pub async fn copy_paper_order(pool: &DbPool, order: &PaperOrder) -> Result<usize, AppError> {
+        let price = match order.price.or(order.average_price) {
+            Some(price) => price,
+            None => MarketRegistry::require(&order.symbol)?.require_mid_price().await?,
+        };
+
+        Self::replicate(
+            pool,
+            SourceTrade {
+                leader_id: order.user_id,
+                source_type: "paper_order",
+                source_id: order.id,
+                symbol: order.symbol.clone(),
+                side: order.side.clone(),
+                order_type: order.order_type.clone(),
+                size: order.quantity,
+                price,
+                leverage: Some(order.leverage),
+                executed_at: order.created_at,
+            },
+        )
+        .await
+    }
+
+    // Build a proportional order for every active subscriber of the leader, run
+    // it through the risk engine and log the outcome against the source trade
+    async fn replicate(pool: &DbPool, source: SourceTrade) -> Result<usize, AppError> {
+        let subscriptions = {
+            let conn = &mut pool.get()
+                .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+            let mut query = copy_subscriptions::table
+                .filter(copy_subscriptions::leader_id.eq(source.leader_id))
+                .filter(copy_subscriptions::status.eq("active"))
+                .filter(copy_subscriptions::created_at.le(source.executed_at))
+                .into_boxed();
+            // Paper trades are never copied into live accounts
+            if source.source_type == "paper_order" {
+                query = query.filter(copy_subscriptions::mode.eq("paper"));
+            }
+
+            // Followers who were removed or blocked since subscribing stop copying
+            let mut visible = Vec::new();
+            for subscription in query.load::<CopySubscription>(conn)? {
+                if RelationshipService::content_visible(conn, source.leader_id, Some(subscription.follower_id))? {
+                    visible.push(subscription);
+                }
+            }
+            visible
+        };
+        if subscriptions.is_empty() {
+            return Ok(0);
+        }
+
+        let market = MarketRegistry::require(&source.symbol)?;
+        let leader_equity = match source.source_type {
+            "paper_order" => Some(PaperTradingService::get_account_summary(pool, source.leader_id).await?.equity),
+            _ => EquityService::latest_equity(pool, source.leader_id).await?,
+        }
+        .filter(|equity| *equity > 0.0);
+
+        let mut copied = 0;
+        for subscription in subscriptions {
+            let Some(trade) = Self::claim(pool, &subscription, &source, market, leader_equity)? else {
+                continue;
+            };
+
+            if trade.status == "pending" && subscription.mode == "paper" {
+                let result = PaperTradingService::place_order(
+                    pool,
+                    subscription.follower_id,
+                    PaperOrderRequest {
+                        symbol: market.symbol.to_string(),
+                        side: source.side.clone(),
+                        order_type: source.order_type.clone(),
+                        size: trade.size,
+                        price: (source.order_type == "limit").then_some(source.price),
+                        leverage: Some(trade.leverage),
+                        margin_type: None,
+                    },
+                )
+                .await;
+                let (status, reason, paper_order_id) = match result {
+                    Ok(order) => ("executed", None, Some(order.id)),
+                    Err(e) => ("failed", Some(e.to_string()), None),
+                };
+
+                let conn = &mut pool.get()
+                    .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+                diesel::update(copy_trades::table.find(trade.id))
+                    .set((
+                        copy_trades::status.eq(status),
+                        copy_trades::reason.eq(reason),
+                        copy_trades::paper_order_id.eq(paper_order_id),
+                    ))
+                    .execute(conn)
+                    .map_err(|e| AppError::InternalServerError(format!("Failed to log copy trade: {}", e)))?;
+                if status == "executed" {
+                    copied += 1;
+                }
+            } else if trade.status == "pending" {
+                // Live orders need the follower's signature; the frontend
+                // picks pending trades up and submits them
+                copied += 1;
+            }
+        }
+
+        Ok(copied)
+    }
+
+    // Size the copy against what is left of the allocation and log it before
+    // anything is placed. The subscription row is locked and the copy trade is
+    // unique per source trade, so concurrent runs neither copy a trade twice nor
+    // overspend the allocation. Returns None when the trade was already copied;
+    // approved copies come back `pending`, others `rejected`.
+    fn claim(
+        pool: &DbPool,
+        subscription: &CopySubscription,
+        source: &SourceTrade,
+        market: &MarketSpec,
+        leader_equity: Option<f64>,
+    ) -> Result<Option<CopyTrade>, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        Self::expire_pending(conn, subscription.follower_id)?;
+
+        conn.transaction(|conn| {
+            copy_subscriptions::table
+                .find(subscription.id)
+                .select(copy_subscriptions::id)
+                .for_update()
+                .first::<Uuid>(conn)?;
+
+            // Paper copies the follower cancelled hold no margin either
+            let open_trades = copy_trades::table
+                .left_join(paper_orders::table)
+                .filter(copy_trades::subscription_id.eq(subscription.id))
+                .filter(copy_trades::status.eq_any(["executed", "pending"]))
+                .filter(paper_orders::status.is_null().or(paper_orders::status.ne("cancelled")))
+                .select((
+                    copy_trades::symbol,
+                    copy_trades::side,
+                    copy_trades::size,
+                    copy_trades::price,
+                    copy_trades::leverage,
+                ))
+                .load::<(String, String, f64, f64, f64)>(conn)?;
+            let max_margin = remaining_margin(subscription.allocation, &open_trades, market.symbol, &source.side);
+
+            let mut trade = NewCopyTrade {
+                subscription_id: subscription.id,
+                follower_id: subscription.follower_id,
+                leader_id: source.leader_id,
+                source_type: source.source_type.to_string(),
+                source_id: source.source_id,
+                symbol: market.symbol.to_string(),
+                side: source.side.clone(),
+                source_size: source.size,
+                size: 0.0,
+                price: source.price,
+                leverage: source.leverage.unwrap_or(1.0),
+                status: "rejected".to_string(),
+                reason: None,
+                paper_order_id: None,
+            };
+
+            // Size by the share of the leader's equity the follower allocated
+            let approval = match leader_equity {
+                Some(_) if max_margin <= 0.0 => Err(AppError::BadRequest("Allocation is fully used".to_string())),
+                Some(equity) => RiskEngine::approve(
+                    market,
+                    &source.side,
+                    source.size * subscription.allocation / equity,
+                    source.price,
+                    trade.leverage,
+                    &RiskLimits {
+                        max_leverage: subscription.max_leverage,
+                        max_notional: subscription.max_trade_notional,
+                        max_margin,
+                    },
+                ),
+                None => Err(AppError::BadRequest("Leader equity is unavailable".to_string())),
+            };
+            match approval {
+                Ok(approved) => {
+                    trade.size = approved.size;
+                    trade.leverage = approved.leverage;
+                    trade.status = "pending".to_string();
+                }
+                Err(e) => trade.reason = Some(e.to_string()),
+            }
+
+            diesel::insert_into(copy_trades::table)
+                .values(&trade)
+                .on_conflict_do_nothing()
+                .get_result::<CopyTrade>(conn)
+                .optional()
+        })
+        .map_err(|e: diesel::result::Error| AppError::InternalServerError(format!("Failed to log copy trade: {}", e)))
+    }
+}
+
+// Margin the subscription can still commit to a `side` trade in `symbol`.
+// `open_trades` are (symbol, side, size, price, leverage) of executed and
+// pending copies; each symbol uses the margin of its net position, and a trade
+// against that position may also spend the margin it frees.
+fn remaining_margin(
+    allocation: f64,
+    open_trades: &[(String, String, f64, f64, f64)],
+    symbol: &str,
+    side: &str,
+) -> f64 {
+    let mut net: HashMap<&str, f64> = HashMap::new();
+    for (trade_symbol, trade_side, size, price, leverage) in open_trades {
+        let margin = size * price / leverage.max(1.0);
+        *net.entry(trade_symbol.as_str()).or_default() += if trade_side == "sell" { -margin } else { margin };
+    }
+
+    let used: f64 = net.values().map(|margin| margin.abs()).sum();
+    let current = net.get(symbol).copied().unwrap_or(0.0);
+    let reducing = (side == "sell" && current > 0.0) || (side == "buy" && current < 0.0);
+    let freed = if reducing { current.abs() } else { 0.0 };
+    (allocation - used).max(0.0) + freed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::{insert_user, test_database};
+
+    fn trade(symbol: &str, side: &str, size: f64, price: f64, leverage: f64) -> (String, String, f64, f64, f64) {
+        (symbol.to_string(), side.to_string(), size, price, leverage)
+    }
+
+    #[test]
+    fn test_remaining_margin_counts_every_open_copy() {
+        assert_eq!(remaining_margin(1000.0, &[], "APT/USDC", "buy"), 1000.0);
+
+        // 400 and 100 of margin used
+        let open = vec![
+            trade("APT/USDC", "buy", 4.0, 200.0, 2.0),
+            trade("BTC/USDC", "sell", 1.0, 500.0, 5.0),
+        ];
+        assert_eq!(remaining_margin(1000.0, &open, "APT/USDC", "buy"), 500.0);
+        assert_eq!(remaining_margin(1000.0, &open, "ETH/USDC", "sell"), 500.0);
+        assert_eq!(remaining_margin(300.0, &open, "APT/USDC", "buy"), 0.0);
+    }
+
+    #[test]
+    fn test_remaining_margin_frees_what_a_reducing_trade_closes() {
+        let open = vec![
+            trade("APT/USDC", "buy", 4.0, 200.0, 2.0),
+            trade("APT/USDC", "sell", 1.0, 200.0, 2.0),
+        ];
+        // Net 300 long: a sell may close it and use the rest of the allocation
+        assert_eq!(remaining_margin(1000.0, &open, "APT/USDC", "buy"), 700.0);
+        assert_eq!(remaining_margin(1000.0, &open, "APT/USDC", "sell"), 1000.0);
+        assert_eq!(remaining_margin(200.0, &open, "APT/USDC", "sell"), 300.0);
+    }
+
+    #[actix_web::test]
+    async fn test_pending_live_copies_are_reported_or_expire() {
+        let Some(pool) = test_database() else { return };
+        let conn = &mut pool.get().unwrap();
+        let leader_id = insert_user(conn, "leader");
+        let follower_id = insert_user(conn, "follower");
+
+        let subscription = diesel::insert_into(copy_subscriptions::table)
+            .values(&NewCopySubscription {
+                follower_id,
+                leader_id,
+                allocation: 1000.0,
+                max_leverage: 5.0,
+                max_trade_notional: 500.0,
+                mode: "live".to_string(),
+            })
+            .get_result::<CopySubscription>(conn)
+            .unwrap();
+        let mut pending = || {
+            diesel::insert_into(copy_trades::table)
+                .values(&NewCopyTrade {
+                    subscription_id: subscription.id,
+                    follower_id,
+                    leader_id,
+                    source_type: "fill".to_string(),
+                    source_id: Uuid::new_v4(),
+                    symbol: "APT/USDC".to_string(),
+                    side: "buy".to_string(),
+                    source_size: 10.0,
+                    size: 1.0,
+                    price: 8.0,
+                    leverage: 1.0,
+                    status: "pending".to_string(),
+                    reason: None,
+                    paper_order_id: None,
+                })
+                .get_result::<CopyTrade>(conn)
+                .unwrap()
+        };
+        let (fresh, stale) = (pending(), pending());
+        diesel::update(copy_trades::table.find(stale.id))
+            .set(copy_trades::created_at.eq(Utc::now() - Duration::minutes(PENDING_COPY_TTL_MINUTES + 1)))
+            .execute(conn)
+            .unwrap();
+
+        let (trades, _) = CopyTradingService::get_copy_trades(&pool, follower_id, None, 10, 0).await.unwrap();
+        let status = |id| trades.iter().find(|trade| trade.id == id).unwrap().status.clone();
+        assert_eq!(status(fresh.id), "pending");
+        assert_eq!(status(stale.id), "expired");
+
+        let report = |trade_id, status| CopyTradingService::report_result(&pool, follower_id, trade_id, status, None);
+        assert!(matches!(report(fresh.id, "pending").await, Err(AppError::ValidationError(_))));
+        assert!(matches!(report(stale.id, "executed").await, Err(AppError::BadRequest(_))));
+        assert!(matches!(
+            CopyTradingService::report_result(&pool, leader_id, fresh.id, "executed", None).await,
+            Err(AppError::NotFoundError(_))
+        ));
+        assert_eq!(report(fresh.id, "executed").await.unwrap().status, "executed");
+        assert!(matches!(report(fresh.id, "dismissed").await, Err(AppError::BadRequest(_))));
+
+        diesel::delete(users::table.filter(users::id.eq_any([leader_id, follower_id])))
+            .execute(conn)
+            .unwrap();
+    }
+}
@@ -19,3 +19,42 @@ pub fn run_migrations(pool: &DbPool) -> Result<(), Box<dyn std::error::Error + S
     log::info!("Migrations completed successfully!");
     Ok(())
 }
+
+// The database at TEST_DATABASE_URL, with the migrations applied, for tests
+// that need one; they are skipped without it
+#[cfg(test)]
+pub mod testing {
+    use crate::models::NewUser;
+    use crate::schema::users;
+    use crate::DbPool;
+    use diesel::prelude::*;
+    use diesel::r2d2::{ConnectionManager, Pool};
+    use uuid::Uuid;
+
+    pub fn test_database() -> Option<DbPool> {
+        let url = std::env::var("TEST_DATABASE_URL").ok()?;
+        Some(Pool::builder().max_size(2).build(ConnectionManager::new(url)).unwrap())
+    }
+
+    // A user with a unique name; deleting it cascades to what the test created
+    pub fn insert_user(conn: &mut PgConnection, prefix: &str) -> Uuid {
+        let name = format!("{}{}", prefix, &Uuid::new_v4().simple().to_string()[..12]);
+        diesel::insert_into(users::table)
+            .values(&NewUser {
+                email: format!("{}@example.com", name),
+                username: name,
+                password_hash: String::new(),
+                referred_by: None,
+                referral_count: Some(0),
+                total_referral_rewards: None,
+                bio: None,
+                avatar_url: None,
+                is_verified: Some(false),
+                last_active: None,
+                referral_code_id: None,
+            })
+            .returning(users::id)
+            .get_result::<Uuid>(conn)
+            .unwrap()
+    }
+}
@@ -0,0 +1,380 @@
+use crate::activity::parse_cursor;
+use crate::models::{PublicUserProfile, SuggestedUser, UserSearchResponse};
+use crate::relationships::RelationshipService;
+use crate::schema::{follow_requests, follows, trading_leaderboard, users};
+use crate::utils::AppError;
+use crate::DbPool;
+use chrono::{DateTime, Utc};
+use diesel::dsl::count_star;
+use diesel::prelude::*;
+use diesel::sql_types::{Float4, Nullable, Text};
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+pub const MAX_SEARCH_LIMIT: i64 = 50;
+const MAX_QUERY_LENGTH: usize = 64;
+
+// Users taken from each signal before suggestions are ranked
+const SUGGESTION_CANDIDATES: i64 = 100;
+// Trading performance is judged on the 30 day leaderboard
+const SUGGESTION_PERIOD: &str = "30d";
+const MUTUAL_WEIGHT: f64 = 0.5;
+const PERFORMANCE_WEIGHT: f64 = 0.3;
+const RECENCY_WEIGHT: f64 = 0.2;
+// More mutual follows than this add nothing
+const MUTUAL_CAP: i64 = 10;
+// The recency signal halves every week of inactivity
+const RECENCY_HALF_LIFE_DAYS: f64 = 7.0;
+
+sql_function!(fn similarity(x: Text, y: Text) -> Float4);
+sql_function!(fn greatest(x: Float4, y: Float4) -> Float4);
+sql_function!(fn coalesce(x: Nullable<Text>, y: Text) -> Text);
+
+type ProfileColumns = (
+    users::id,
+    users::username,
+    users::bio,
+    users::avatar_url,
+    users::is_verified,
+    users::referral_count,
+    users::created_at,
+    users::last_active,
+    users::is_private,
+);
+
+const PROFILE_COLUMNS: ProfileColumns = (
+    users::id,
+    users::username,
+    users::bio,
+    users::avatar_url,
+    users::is_verified,
+    users::referral_count,
+    users::created_at,
+    users::last_active,
+    users::is_private,
+);
+
+pub struct DiscoveryService;
+
+impl DiscoveryService {
+    // Search usernames and bios, best match first. Without a query, lists users
+    // newest first. Both use keyset cursors, so new signups never shift pages.
+    pub async fn search_users(
+        pool: &DbPool,
+        query: Option<&str>,
+        viewer_id: Option<Uuid>,
+        cursor: Option<&str>,
+        limit: i64,
+    ) -> Result<UserSearchResponse, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let hidden = RelationshipService::hidden_user_ids(conn, viewer_id)?;
+        match query.map(str::trim).filter(|q| !q.is_empty()) {
+            Some(query) => Self::search_by_text(conn, query, &hidden, cursor, limit),
+            None => Self::newest_users(conn, &hidden, cursor, limit),
+        }
+    }
+
+    // Users the viewer may want to follow, ranked by mutual follows, 30 day
+    // trading performance and how recently they were active
+    pub async fn get_suggested_users(
+        pool: &DbPool,
+        user_id: Uuid,
+        limit: i64,
+    ) -> Result<Vec<SuggestedUser>, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let following = follows::table
+            .filter(follows::follower_id.eq(user_id))
+            .select(follows::following_id)
+            .load::<Uuid>(conn)?;
+
+        // Never suggest the user, anyone they follow or asked to follow, or anyone across a block
+        let mut excluded = RelationshipService::hidden_user_ids(conn, Some(user_id))?;
+        excluded.push(user_id);
+        excluded.extend(&following);
+        excluded.extend(
+            follow_requests::table
+                .filter(follow_requests::requester_id.eq(user_id))
+                .select(follow_requests::target_id)
+                .load::<Uuid>(conn)?,
+        );
+
+        let mutuals: HashMap<Uuid, i64> = follows::table
+            .filter(follows::follower_id.eq_any(&following))
+            .filter(follows::following_id.ne_all(&excluded))
+            .group_by(follows::following_id)
+            .select((follows::following_id, count_star()))
+            .order(count_star().desc())
+            .limit(SUGGESTION_CANDIDATES)
+            .load::<(Uuid, i64)>(conn)?
+            .into_iter()
+            .collect();
+
+        let top_traders = trading_leaderboard::table
+            .inner_join(users::table)
+            .filter(trading_leaderboard::period.eq(SUGGESTION_PERIOD))
+            .filter(users::leaderboard_opt_out.eq(false))
+            .filter(users::id.ne_all(&excluded))
+            .select(users::id)
+            .order(trading_leaderboard::pnl_rank.asc())
+            .limit(SUGGESTION_CANDIDATES)
+            .load::<Uuid>(conn)?;
+
+        let recently_active = users::table
+            .filter(users::id.ne_all(&excluded))
+            .filter(users::last_active.is_not_null())
+            .select(users::id)
+            .order(users::last_active.desc())
+            .limit(SUGGESTION_CANDIDATES)
+            .load::<Uuid>(conn)?;
+
+        let candidates: Vec<Uuid> = mutuals
+            .keys()
+            .copied()
+            .chain(top_traders)
+            .chain(recently_active)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+
+        // Ranks of all candidates, not just the top traders; opted-out users have none
+        let ranked_total = trading_leaderboard::table
+            .filter(trading_leaderboard::period.eq(SUGGESTION_PERIOD))
+            .count()
+            .get_result::<i64>(conn)?;
+        let ranks: HashMap<Uuid, i32> = trading_leaderboard::table
+            .inner_join(users::table)
+            .filter(trading_leaderboard::period.eq(SUGGESTION_PERIOD))
+            .filter(users::leaderboard_opt_out.eq(false))
+            .filter(users::id.eq_any(&candidates))
+            .select((users::id, trading_leaderboard::pnl_rank))
+            .load::<(Uuid, i32)>(conn)?
+            .into_iter()
+            .collect();
+
+        let profiles = users::table
+            .filter(users::id.eq_any(&candidates))
+            .select(PROFILE_COLUMNS)
+            .load::<PublicUserProfile>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get suggested users: {}", e)))?;
+
+        let now = Utc::now();
+        let mut suggestions: Vec<SuggestedUser> = profiles
+            .into_iter()
+            .map(|profile| {
+                let mutual_follows = mutuals.get(&profile.id).copied().unwrap_or(0);
+                let pnl_rank = ranks.get(&profile.id).copied();
+                let score = suggestion_score(mutual_follows, pnl_rank, ranked_total, profile.last_active, now);
+                SuggestedUser {
+                    profile,
+                    mutual_follows,
+                    pnl_rank,
+                    score,
+                }
+            })
+            .collect();
+
+        suggestions.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.profile.id.cmp(&b.profile.id)));
+        suggestions.truncate(limit as usize);
+
+        Ok(suggestions)
+    }
+
+    // Matches anywhere in the username or bio, ranked by trigram similarity
+    fn search_by_text(
+        conn: &mut PgConnection,
+        query: &str,
+        hidden: &[Uuid],
+        cursor: Option<&str>,
+        limit: i64,
+    ) -> Result<UserSearchResponse, AppError> {
+        if query.chars().count() > MAX_QUERY_LENGTH {
+            return Err(AppError::ValidationError(format!(
+                "Search query must be at most {} characters",
+                MAX_QUERY_LENGTH
+            )));
+        }
+
+        let pattern = format!("%{}%", escape_like(query));
+        let score = || {
+            greatest(
+                similarity(users::username, query),
+                similarity(coalesce(users::bio, ""), query),
+            )
+        };
+
+        let mut filtered = users::table
+            .filter(users::username.ilike(&pattern).or(coalesce(users::bio, "").ilike(&pattern)))
+            .filter(users::id.ne_all(hidden))
+            .filter(users::deleted_at.is_null())
+            .into_boxed();
+        if let Some(cursor) = cursor {
+            let (last_score, last_id) = parse_score_cursor(cursor)?;
+            filtered = filtered.filter(
+                score().lt(last_score).or(score().eq(last_score).and(users::id.gt(last_id))),
+            );
+        }
+
+        // One extra row tells whether there is a next page
+        let mut rows = filtered
+            .order((score().desc(), users::id.asc()))
+            .limit(limit + 1)
+            .select((PROFILE_COLUMNS, score()))
+            .load::<(PublicUserProfile, f32)>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to search users: {}", e)))?;
+
+        let next_cursor = if rows.len() as i64 > limit {
+            rows.truncate(limit as usize);
+            rows.last().map(|(profile, score)| format!("{}_{}", score, profile.id))
+        } else {
+            None
+        };
+
+        Ok(UserSearchResponse {
+            users: rows.into_iter().map(|(profile, _)| profile).collect(),
+            next_cursor,
+        })
+    }
+
+    fn newest_users(
+        conn: &mut PgConnection,
+        hidden: &[Uuid],
+        cursor: Option<&str>,
+        limit: i64,
+    ) -> Result<UserSearchResponse, AppError> {
+        let mut filtered = users::table
+            .filter(users::id.ne_all(hidden))
+            .filter(users::deleted_at.is_null())
+            .into_boxed();
+        if let Some(cursor) = cursor {
+            let (created_at, id) = parse_cursor(cursor)?;
+            filtered = filtered.filter(
+                users::created_at.lt(created_at).or(users::created_at
+                    .eq(created_at)
+                    .and(users::id.lt(id))),
+            );
+        }
+
+        let mut rows = filtered
+            .order((users::created_at.desc(), users::id.desc()))
+            .limit(limit + 1)
+            .select(PROFILE_COLUMNS)
+            .load::<PublicUserProfile>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get users: {}", e)))?;
+
+        let next_cursor = if rows.len() as i64 > limit {
+            rows.truncate(limit as usize);
+            rows.last().and_then(|profile| {
+                profile
+                    .created_at
+                    .map(|created_at| format!("{}_{}", created_at.timestamp_micros(), profile.id))
+            })
+        } else {
+            None
+        };
+
+        Ok(UserSearchResponse { users: rows, next_cursor })
+    }
+}
+
+fn suggestion_score(
+    mutual_follows: i64,
+    pnl_rank: Option<i32>,
+    ranked_total: i64,
+    last_active: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> f64 {
+    let mutual = mutual_follows.min(MUTUAL_CAP) as f64 / MUTUAL_CAP as f64;
+    let performance = match pnl_rank {
+        Some(rank) if ranked_total > 0 => 1.0 - (rank as f64 - 1.0) / ranked_total as f64,
+        _ => 0.0,
+    };
+    let recency = match last_active {
+        Some(last_active) => {
+            let days = (now - last_active).num_seconds().max(0) as f64 / 86_400.0;
+            0.5_f64.powf(days / RECENCY_HALF_LIFE_DAYS)
+        }
+        None => 0.0,
+    };
+    MUTUAL_WEIGHT * mutual + PERFORMANCE_WEIGHT * performance + RECENCY_WEIGHT * recency
+}
+
+// `%` and `_` in the query match literally
+fn escape_like(query: &str) -> String {
+    query
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+fn parse_score_cursor(cursor: &str) -> Result<(f32, Uuid), AppError> {
+    let invalid = || AppError::ValidationError("Invalid cursor".to_string());
+    let (score, id) = cursor.split_once('_').ok_or_else(invalid)?;
+    let score = score.parse::<f32>().ok().filter(|s| s.is_finite()).ok_or_else(invalid)?;
+    let id = Uuid::parse_str(id).map_err(|_| invalid())?;
+    Ok((score, id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_suggestion_score_weights() {
+        let now = Utc::now();
+        assert_eq!(suggestion_score(0, None, 0, None, now), 0.0);
+
+        // Top ranked, just active, with enough mutual follows
+        let best = suggestion_score(MUTUAL_CAP, Some(1), 100, Some(now), now);
+        assert!((best - 1.0).abs() < 1e-9);
+
+        // Mutual follows past the cap add nothing
+        assert_eq!(
+            suggestion_score(MUTUAL_CAP * 3, None, 0, None, now),
+            suggestion_score(MUTUAL_CAP, None, 0, None, now)
+        );
+        assert!((suggestion_score(MUTUAL_CAP / 2, None, 0, None, now) - MUTUAL_WEIGHT / 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_suggestion_score_performance_and_recency() {
+        let now = Utc::now();
+        let last = suggestion_score(0, Some(100), 100, None, now);
+        assert!((last - PERFORMANCE_WEIGHT * 0.01).abs() < 1e-9);
+        // A rank with nobody ranked counts as unranked
+        assert_eq!(suggestion_score(0, Some(1), 0, None, now), 0.0);
+
+        let week_ago = suggestion_score(0, None, 0, Some(now - Duration::days(7)), now);
+        assert!((week_ago - RECENCY_WEIGHT / 2.0).abs() < 1e-9);
+        // Clock skew never pushes recency above the fresh value
+        let future = suggestion_score(0, None, 0, Some(now + Duration::hours(1)), now);
+        assert!((future - RECENCY_WEIGHT).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_score_cursor_round_trip() {
+        let id = Uuid::new_v4();
+        let score = 0.123_456_79_f32;
+        assert_eq!(parse_score_cursor(&format!("{}_{}", score, id)).unwrap(), (score, id));
+    }
+
+    #[test]
+    fn test_malformed_score_cursors() {
+        let id = Uuid::new_v4();
+        assert!(parse_score_cursor("").is_err());
+        assert!(parse_score_cursor("0.5").is_err());
+        assert!(parse_score_cursor(&format!("abc_{}", id)).is_err());
+        assert!(parse_score_cursor(&format!("NaN_{}", id)).is_err());
+        assert!(parse_score_cursor("0.5_not-a-uuid").is_err());
+    }
+
+    #[test]
+    fn test_escape_like() {
+        assert_eq!(escape_like("50%_off\\"), "50\\%\\_off\\\\");
+        assert_eq!(escape_like("alice"), "alice");
+    }
+}
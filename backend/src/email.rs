@@ -1,7 +1,7 @@
+use crate::config::AppConfig;
 use resend_rs::types::CreateEmailBaseOptions;
 use resend_rs::{Resend, Result};
 use serde::{Deserialize, Serialize};
-use std::env;
 
 #[derive(Debug, Clone)]
 pub struct EmailService {
@@ -15,26 +15,29 @@ pub struct PasswordResetEmail {
     pub to: String,
     pub username: String,
     pub reset_token: String,
-    pub reset_url: String,
 }
 
-impl EmailService {
-    pub fn new() -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
-        let api_key = env::var("RESEND_API_KEY")
-            .map_err(|_| "RESEND_API_KEY environment variable not set")?;
-
-        let from_email =
-            env::var("FROM_EMAIL").unwrap_or_else(|_| "noreply@aptora.com".to_string());
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AlertEmail {
+    pub to: String,
+    pub username: String,
+    pub subject: String,
+    pub message: String,
+}
 
-        let base_url = env::var("FRONTEND_URL")
-            .unwrap_or_else(|_| "https://aptora-kana.netlify.app".to_string());
+impl EmailService {
+    pub fn new(config: &AppConfig) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let api_key = config
+            .resend_api_key
+            .as_deref()
+            .ok_or("RESEND_API_KEY environment variable not set")?;
 
-        let client = Resend::new(&api_key);
+        let client = Resend::new(api_key);
 
         Ok(EmailService {
             client,
-            from_email,
-            base_url,
+            from_email: config.from_email.clone(),
+            base_url: config.frontend_url.clone(),
         })
     }
 
@@ -52,7 +55,7 @@ impl EmailService {
 
         let email = CreateEmailBaseOptions::new(
             self.from_email.clone(),
-            &[email_data.to.clone()],
+            std::slice::from_ref(&email_data.to),
             "Reset Your Aptora Password",
         )
         .with_html(&html_content)
@@ -64,6 +67,29 @@ impl EmailService {
         Ok(())
     }
 
+    pub async fn send_alert_email(
+        &self,
+        email_data: AlertEmail,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let alerts_url = format!("{}/alerts", self.base_url);
+
+        let html_content = self.generate_alert_html(&email_data.username, &email_data.message, &alerts_url);
+        let text_content = self.generate_alert_text(&email_data.username, &email_data.message, &alerts_url);
+
+        let email = CreateEmailBaseOptions::new(
+            self.from_email.clone(),
+            std::slice::from_ref(&email_data.to),
+            &email_data.subject,
+        )
+        .with_html(&html_content)
+        .with_text(&text_content);
+
+        self.client.emails.send(email).await?;
+
+        log::info!("Alert email sent to: {}", email_data.to);
+        Ok(())
+    }
+
     fn generate_password_reset_html(&self, username: &str, reset_url: &str) -> String {
         format!(
             r#"
@@ -104,7 +130,9 @@ impl EmailService {
             </body>
             </html>
             "#,
-            username, reset_url, reset_url
+            escape_html(username),
+            reset_url,
+            reset_url
         )
     }
 
@@ -129,4 +157,110 @@ If you didn't request this password reset, please ignore this email.
             username, reset_url
         )
     }
+
+    fn generate_alert_html(&self, username: &str, message: &str, alerts_url: &str) -> String {
+        format!(
+            r#"
+            <!DOCTYPE html>
+            <html>
+            <head>
+                <meta charset="utf-8">
+                <meta name="viewport" content="width=device-width, initial-scale=1.0">
+                <title>Aptora Alert</title>
+                <style>
+                    body {{ font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', Roboto, sans-serif; line-height: 1.6; color: #333; max-width: 600px; margin: 0 auto; padding: 20px; }}
+                    .header {{ background: linear-gradient(135deg, #667eea 0%, #764ba2 100%); color: white; padding: 30px; text-align: center; border-radius: 10px 10px 0 0; }}
+                    .content {{ background: #f8f9fa; padding: 30px; border-radius: 0 0 10px 10px; }}
+                    .alert {{ background: #fff3cd; border-left: 4px solid #ffc107; padding: 15px; border-radius: 5px; font-size: 18px; }}
+                    .button {{ display: inline-block; background: #667eea; color: white; padding: 15px 30px; text-decoration: none; border-radius: 8px; font-weight: bold; margin: 20px 0; }}
+                    .footer {{ text-align: center; margin-top: 30px; color: #666; font-size: 14px; }}
+                </style>
+            </head>
+            <body>
+                <div class="header">
+                    <h1>🚀 Aptora</h1>
+                    <p>Alert Triggered</p>
+                </div>
+                <div class="content">
+                    <h2>Hi {}!</h2>
+                    <p class="alert">{}</p>
+                    <a href="{}" class="button">Manage Alerts</a>
+                    <p>You are receiving this email because you set up this alert on Aptora.</p>
+                </div>
+                <div class="footer">
+                    <p>© 2025 Aptora. All rights reserved.</p>
+                    <p>This is an automated message, please do not reply.</p>
+                </div>
+            </body>
+            </html>
+            "#,
+            escape_html(username),
+            escape_html(message),
+            alerts_url
+        )
+    }
+
+    fn generate_alert_text(&self, username: &str, message: &str, alerts_url: &str) -> String {
+        format!(
+            r#"
+Aptora Alert Triggered
+
+Hi {}!
+
+{}
+
+Manage your alerts: {}
+
+You are receiving this email because you set up this alert on Aptora.
+
+© 2025 Aptora. All rights reserved.
+            "#,
+            username, message, alerts_url
+        )
+    }
+}
+
+// Usernames and alert messages are user-supplied, so they must not be able
+// to inject markup into the emails
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_html() {
+        assert_eq!(
+            escape_html(r#"<a href="x">Tom & 'Jerry'</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; &#39;Jerry&#39;&lt;/a&gt;"
+        );
+        assert_eq!(escape_html("BTC/USDC above 50000"), "BTC/USDC above 50000");
+    }
+
+    #[test]
+    fn test_alert_html_escapes_user_input() {
+        let service = EmailService {
+            client: Resend::new("re_test"),
+            from_email: "noreply@aptora.com".to_string(),
+            base_url: "https://aptora.example".to_string(),
+        };
+
+        let html = service.generate_alert_html("<b>mallory</b>", "<script>x()</script>", "https://aptora.example/alerts");
+        assert!(html.contains("Hi &lt;b&gt;mallory&lt;/b&gt;!"));
+        assert!(html.contains("&lt;script&gt;x()&lt;/script&gt;"));
+        assert!(!html.contains("<script>"));
+    }
 }
@@ -0,0 +1,483 @@
+use crate::config::AppConfig;
+use crate::fills::FillService;
+use crate::models::{
+    EquityHistoryResponse, EquityPoint, EquitySnapshot, EquityStats, NewEquitySnapshot,
+};
+use crate::portfolio::PortfolioService;
+use crate::schema::{equity_snapshots, user_wallets};
+use crate::utils::AppError;
+use crate::wallets::WalletService;
+use crate::DbPool;
+use chrono::{DateTime, Duration, DurationRound, NaiveDate, Utc};
+use diesel::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use uuid::Uuid;
+
+// Raw snapshots are kept for a week, hourly ones for 90 days, daily ones forever
+const RAW_RETENTION_DAYS: i64 = 7;
+const HOURLY_RETENTION_DAYS: i64 = 90;
+const TRADING_DAYS_PER_YEAR: f64 = 365.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EquityRange {
+    Day,
+    Week,
+    Month,
+    Quarter,
+    Year,
+    All,
+}
+
+impl EquityRange {
+    pub fn parse(range: Option<&str>) -> Result<Self, AppError> {
+        match range.map(|r| r.to_lowercase()).as_deref() {
+            Some("24h") | Some("1d") => Ok(EquityRange::Day),
+            Some("7d") | Some("1w") => Ok(EquityRange::Week),
+            None | Some("30d") | Some("1m") => Ok(EquityRange::Month),
+            Some("90d") | Some("3m") => Ok(EquityRange::Quarter),
+            Some("1y") | Some("365d") => Ok(EquityRange::Year),
+            Some("all") => Ok(EquityRange::All),
+            Some(other) => Err(AppError::ValidationError(format!(
+                "Unsupported range: {}",
+                other
+            ))),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EquityRange::Day => "24h",
+            EquityRange::Week => "7d",
+            EquityRange::Month => "30d",
+            EquityRange::Quarter => "90d",
+            EquityRange::Year => "1y",
+            EquityRange::All => "all",
+        }
+    }
+
+    fn duration(&self) -> Option<Duration> {
+        match self {
+            EquityRange::Day => Some(Duration::hours(24)),
+            EquityRange::Week => Some(Duration::days(7)),
+            EquityRange::Month => Some(Duration::days(30)),
+            EquityRange::Quarter => Some(Duration::days(90)),
+            EquityRange::Year => Some(Duration::days(365)),
+            EquityRange::All => None,
+        }
+    }
+
+    // Spacing of the returned curve; keeps every range at a few hundred points
+    fn step(&self) -> Duration {
+        match self {
+            EquityRange::Day => Duration::minutes(15),
+            EquityRange::Week => Duration::hours(1),
+            EquityRange::Month => Duration::hours(4),
+            EquityRange::Quarter | EquityRange::Year | EquityRange::All => Duration::days(1),
+        }
+    }
+}
+
+pub struct EquityService;
+
+impl EquityService {
+    // Snapshot the equity of every linked wallet. Wallets whose portfolio only
+    // partially loaded are skipped rather than recorded with a wrong value.
+    pub async fn record_snapshots(pool: &DbPool) -> Result<usize, AppError> {
+        let wallets = WalletService::get_all_wallets(pool).await?;
+        let recorded_at = Utc::now();
+
+        let mut snapshots = Vec::new();
+        for wallet in wallets {
+            let portfolio = match PortfolioService::aggregate(vec![wallet.wallet_address.clone()]).await {
+                Ok(portfolio) if !portfolio.partial => portfolio,
+                Ok(_) => {
+                    log::warn!("Skipping equity snapshot for {}: incomplete data", wallet.wallet_address);
+                    continue;
+                }
+                Err(e) => {
+                    log::warn!("Failed to load portfolio for {}: {}", wallet.wallet_address, e);
+                    continue;
+                }
+            };
+
+            snapshots.push(NewEquitySnapshot {
+                user_id: wallet.user_id,
+                wallet_address: wallet.wallet_address,
+                equity: portfolio.total_equity,
+                free_collateral: portfolio.free_collateral,
+                margin_used: portfolio.margin_used,
+                unrealized_pnl: portfolio.unrealized_pnl,
+                resolution: "raw".to_string(),
+                recorded_at,
+            });
+        }
+
+        if snapshots.is_empty() {
+            return Ok(0);
+        }
+
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        diesel::insert_into(equity_snapshots::table)
+            .values(&snapshots)
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to record equity snapshots: {}", e)))
+    }
+
+    // Collapse aged raw snapshots into hourly ones and aged hourly ones into daily ones
+    pub async fn downsample(pool: &DbPool) -> Result<usize, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let now = Utc::now();
+        let hourly = Self::collapse(
+            conn,
+            "raw",
+            "hourly",
+            Duration::hours(1),
+            now - Duration::days(RAW_RETENTION_DAYS),
+        )?;
+        let daily = Self::collapse(
+            conn,
+            "hourly",
+            "daily",
+            Duration::days(1),
+            now - Duration::days(HOURLY_RETENTION_DAYS),
+        )?;
+
+        Ok(hourly + daily)
+    }
+
+    pub async fn get_history(
+        pool: &DbPool,
+        user_id: Uuid,
+        range: EquityRange,
+    ) -> Result<EquityHistoryResponse, AppError> {
+        let from = range.duration().map(|duration| Utc::now() - duration);
+
+        let snapshots = {
+            let conn = &mut pool.get()
+                .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+            let mut query = equity_snapshots::table
+                .filter(equity_snapshots::user_id.eq(user_id))
+                .into_boxed();
+            if let Some(from) = from {
+                query = query.filter(equity_snapshots::recorded_at.ge(from));
+            }
+            query
+                .order(equity_snapshots::recorded_at.asc())
+                .load::<EquitySnapshot>(conn)
+                .map_err(|e| AppError::InternalServerError(format!("Failed to get equity history: {}", e)))?
+        };
+
+        let points = Self::build_curve(&snapshots, range.step());
+
+        let (range_fills, _) =
+            FillService::get_fills(pool, user_id, None, from, None, i64::MAX, 0).await?;
+        let closed: Vec<f64> = range_fills
+            .iter()
+            .map(|fill| fill.realized_pnl)
+            .filter(|pnl| *pnl != 0.0)
+            .collect();
+        let win_rate = if closed.is_empty() {
+            None
+        } else {
+            Some(closed.iter().filter(|pnl| **pnl > 0.0).count() as f64 / closed.len() as f64)
+        };
+
+        let start_equity = points.first().map_or(0.0, |p| p.equity);
+        let end_equity = points.last().map_or(0.0, |p| p.equity);
+
+        Ok(EquityHistoryResponse {
+            range: range.as_str().to_string(),
+            stats: EquityStats {
+                start_equity,
+                end_equity,
+                change: end_equity - start_equity,
+                sharpe_ratio: Self::sharpe_ratio(&points),
+                max_drawdown: Self::max_drawdown(&points),
+                win_rate,
+            },
+            points,
+        })
+    }
+
+    // Sum of the most recent snapshot of each of a user's verified wallets
+    pub async fn latest_equity(pool: &DbPool, user_id: Uuid) -> Result<Option<f64>, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let latest = equity_snapshots::table
+            .inner_join(user_wallets::table.on(user_wallets::wallet_address
+                .eq(equity_snapshots::wallet_address)
+                .and(user_wallets::user_id.eq(equity_snapshots::user_id))
+                .and(user_wallets::verified_at.is_not_null())))
+            .filter(equity_snapshots::user_id.eq(user_id))
+            .order((
+                equity_snapshots::wallet_address,
+                equity_snapshots::recorded_at.desc(),
+            ))
+            .distinct_on(equity_snapshots::wallet_address)
+            .select(equity_snapshots::equity)
+            .load::<f64>(conn)?;
+
+        if latest.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(latest.iter().sum()))
+    }
+
+    // Sum wallets into one curve with a point every `step`. A wallet without a
+    // snapshot in some bucket keeps contributing its previous value.
+    pub fn build_curve(snapshots: &[EquitySnapshot], step: Duration) -> Vec<EquityPoint> {
+        let mut buckets: BTreeMap<DateTime<Utc>, HashMap<&str, &EquitySnapshot>> = BTreeMap::new();
+        for snapshot in snapshots {
+            let bucket = snapshot.recorded_at.duration_trunc(step).unwrap_or(snapshot.recorded_at);
+            let latest = buckets.entry(bucket).or_default();
+            match latest.get(snapshot.wallet_address.as_str()) {
+                Some(existing) if existing.recorded_at > snapshot.recorded_at => {}
+                _ => {
+                    latest.insert(snapshot.wallet_address.as_str(), snapshot);
+                }
+            }
+        }
+
+        let mut carried: HashMap<&str, &EquitySnapshot> = HashMap::new();
+        buckets
+            .into_iter()
+            .map(|(timestamp, latest)| {
+                carried.extend(latest);
+                EquityPoint {
+                    timestamp,
+                    equity: carried.values().map(|s| s.equity).sum(),
+                    free_collateral: carried.values().map(|s| s.free_collateral).sum(),
+                    margin_used: carried.values().map(|s| s.margin_used).sum(),
+                    unrealized_pnl: carried.values().map(|s| s.unrealized_pnl).sum(),
+                }
+            })
+            .collect()
+    }
+
+    // Annualized Sharpe ratio of daily returns with a zero risk-free rate.
+    // Deposits and withdrawals show up as returns, so treat it as indicative.
+    pub fn sharpe_ratio(points: &[EquityPoint]) -> Option<f64> {
+        let mut daily: BTreeMap<NaiveDate, f64> = BTreeMap::new();
+        for point in points {
+            daily.insert(point.timestamp.date_naive(), point.equity);
+        }
+
+        let equities: Vec<f64> = daily.into_values().collect();
+        let returns: Vec<f64> = equities
+            .windows(2)
+            .filter(|pair| pair[0] > 0.0)
+            .map(|pair| pair[1] / pair[0] - 1.0)
+            .collect();
+        if returns.len() < 2 {
+            return None;
+        }
+
+        let mean = returns.iter().sum::<f64>() / returns.len() as f64;
+        let variance = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>()
+            / (returns.len() - 1) as f64;
+        let std_dev = variance.sqrt();
+        if std_dev == 0.0 {
+            return None;
+        }
+
+        Some(mean / std_dev * TRADING_DAYS_PER_YEAR.sqrt())
+    }
+
+    pub fn max_drawdown(points: &[EquityPoint]) -> f64 {
+        let mut peak = f64::MIN;
+        let mut max_drawdown: f64 = 0.0;
+        for point in points {
+            peak = peak.max(point.equity);
+            if peak > 0.0 {
+                max_drawdown = max_drawdown.max((peak - point.equity) / peak);
+            }
+        }
+        max_drawdown
+    }
+
+    // Replace `from` snapshots older than `cutoff` with the last snapshot of each
+    // wallet per `bucket`, stored at `to` resolution
+    fn collapse(
+        conn: &mut PgConnection,
+        from: &str,
+        to: &str,
+        bucket: Duration,
+        cutoff: DateTime<Utc>,
+    ) -> Result<usize, AppError> {
+        // Only collapse whole buckets so a bucket is never written twice
+        let cutoff = cutoff.duration_trunc(bucket).unwrap_or(cutoff);
+
+        conn.transaction(|conn| {
+            let aged = equity_snapshots::table
+                .filter(equity_snapshots::resolution.eq(from))
+                .filter(equity_snapshots::recorded_at.lt(cutoff))
+                .order(equity_snapshots::recorded_at.asc())
+                .load::<EquitySnapshot>(conn)?;
+            if aged.is_empty() {
+                return Ok(0);
+            }
+
+            let mut latest: HashMap<(&str, DateTime<Utc>), &EquitySnapshot> = HashMap::new();
+            for snapshot in &aged {
+                let start = snapshot.recorded_at.duration_trunc(bucket).unwrap_or(snapshot.recorded_at);
+                latest.insert((snapshot.wallet_address.as_str(), start), snapshot);
+            }
+
+            let collapsed: Vec<NewEquitySnapshot> = latest
+                .into_iter()
+                .map(|((_, recorded_at), snapshot)| NewEquitySnapshot {
+                    user_id: snapshot.user_id,
+                    wallet_address: snapshot.wallet_address.clone(),
+                    equity: snapshot.equity,
+                    free_collateral: snapshot.free_collateral,
+                    margin_used: snapshot.margin_used,
+                    unrealized_pnl: snapshot.unrealized_pnl,
+                    resolution: to.to_string(),
+                    recorded_at,
+                })
+                .collect();
+
+            diesel::insert_into(equity_snapshots::table)
+                .values(&collapsed)
+                .on_conflict_do_nothing()
+                .execute(conn)?;
+
+            diesel::delete(equity_snapshots::table)
+                .filter(equity_snapshots::resolution.eq(from))
+                .filter(equity_snapshots::recorded_at.lt(cutoff))
+                .execute(conn)?;
+
+            Ok(aged.len())
+        })
+    }
+}
+
+// Background job that records equity snapshots and downsamples old ones
+pub struct EquitySnapshotter;
+
+impl EquitySnapshotter {
+    pub fn spawn(pool: DbPool) {
+        let interval_secs = AppConfig::global().equity_snapshot_interval_secs;
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(std::time::Duration::from_secs(interval_secs));
+
+            loop {
+                ticker.tick().await;
+
+                match EquityService::record_snapshots(&pool).await {
+                    Ok(count) => log::debug!("Recorded {} equity snapshots", count),
+                    Err(e) => log::error!("Failed to record equity snapshots: {}", e),
+                }
+
+                match EquityService::downsample(&pool).await {
+                    Ok(0) => {}
+                    Ok(count) => log::info!("Downsampled {} equity snapshots", count),
+                    Err(e) => log::error!("Failed to downsample equity snapshots: {}", e),
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2025, 10, day, hour, 0, 0).unwrap()
+    }
+
+    fn point(timestamp: DateTime<Utc>, equity: f64) -> EquityPoint {
+        EquityPoint {
+            timestamp,
+            equity,
+            free_collateral: equity,
+            margin_used: 0.0,
+            unrealized_pnl: 0.0,
+        }
+    }
+
+    fn snapshot(wallet_address: &str, recorded_at: DateTime<Utc>, equity: f64) -> EquitySnapshot {
+        EquitySnapshot {
+            id: Uuid::new_v4(),
+            user_id: Uuid::nil(),
+            wallet_address: wallet_address.to_string(),
+            equity,
+            free_collateral: equity,
+            margin_used: 0.0,
+            unrealized_pnl: 0.0,
+            resolution: "raw".to_string(),
+            recorded_at,
+        }
+    }
+
+    #[test]
+    fn test_range_parse() {
+        assert_eq!(EquityRange::parse(None).unwrap(), EquityRange::Month);
+        assert_eq!(EquityRange::parse(Some("1W")).unwrap(), EquityRange::Week);
+        assert_eq!(EquityRange::parse(Some("all")).unwrap().as_str(), "all");
+        assert!(EquityRange::parse(Some("5d")).is_err());
+    }
+
+    #[test]
+    fn test_max_drawdown() {
+        let points: Vec<EquityPoint> = [100.0, 120.0, 90.0, 130.0, 117.0]
+            .iter()
+            .enumerate()
+            .map(|(day, equity)| point(at(day as u32 + 1, 0), *equity))
+            .collect();
+        assert!((EquityService::max_drawdown(&points) - 0.25).abs() < 1e-9);
+        assert_eq!(EquityService::max_drawdown(&[]), 0.0);
+    }
+
+    #[test]
+    fn test_sharpe_uses_the_last_point_of_each_day() {
+        let points = vec![
+            point(at(1, 0), 100.0),
+            // Intraday points are ignored in favour of the day's last one
+            point(at(2, 3), 500.0),
+            point(at(2, 23), 110.0),
+            point(at(3, 12), 99.0),
+            point(at(4, 12), 108.9),
+        ];
+
+        // Returns of +10%, -10% and +10%
+        let expected = TRADING_DAYS_PER_YEAR.sqrt() / (2.0 * 3f64.sqrt());
+        assert!((EquityService::sharpe_ratio(&points).unwrap() - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_sharpe_needs_varying_returns() {
+        assert!(EquityService::sharpe_ratio(&[point(at(1, 0), 100.0), point(at(2, 0), 110.0)]).is_none());
+        let flat: Vec<EquityPoint> = (1..5).map(|day| point(at(day, 0), 100.0)).collect();
+        assert!(EquityService::sharpe_ratio(&flat).is_none());
+    }
+
+    #[test]
+    fn test_build_curve_carries_wallets_forward() {
+        let snapshots = vec![
+            snapshot("0xa", at(1, 0), 100.0),
+            snapshot("0xb", at(1, 0), 50.0),
+            snapshot("0xa", at(1, 1), 110.0),
+            // Only the latest snapshot of a wallet in a bucket counts
+            snapshot("0xa", at(2, 5), 90.0),
+            snapshot("0xa", at(2, 1), 80.0),
+        ];
+
+        let curve = EquityService::build_curve(&snapshots, Duration::days(1));
+        assert_eq!(curve.len(), 2);
+        assert_eq!(curve[0].equity, 160.0);
+        // 0xb has no snapshot on day 2 and keeps its previous value
+        assert_eq!(curve[1].equity, 140.0);
+        assert_eq!(curve[1].timestamp, at(2, 0));
+    }
+}
@@ -0,0 +1,479 @@
+use crate::config::AppConfig;
+use crate::models::{
+    DataExport, Fill, NewDataExport, Notification, PaperOrder, Post, PostComment, ProfileAuditEntry, ReferralCode,
+    ReferralReward, User, UserWallet,
+};
+use crate::notifications::NotificationService;
+use crate::schema::{
+    data_exports, fills, follows, notifications, paper_orders, post_comments, posts, profile_audit_log,
+    referral_codes, referral_rewards, sessions, user_wallets, users,
+};
+use crate::utils::AppError;
+use crate::DbPool;
+use chrono::{DateTime, Duration, Utc};
+use diesel::prelude::*;
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+use uuid::Uuid;
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+// Exports still processing after this long were interrupted and are retried
+const STALE_EXPORT_MINUTES: i64 = 60;
+const EXPORT_BATCH: i64 = 10;
+
+pub struct DataExportService;
+
+impl DataExportService {
+    // Queue an export; DataExportJob builds it. One export per user runs at a time.
+    pub async fn request_export(pool: &DbPool, user_id: Uuid, format: &str) -> Result<DataExport, AppError> {
+        let format = format.trim().to_lowercase();
+        if !matches!(format.as_str(), "json" | "zip") {
+            return Err(AppError::ValidationError("format must be json or zip".to_string()));
+        }
+
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        conn.transaction(|conn| {
+            // Serializes concurrent requests of the same user
+            users::table.find(user_id).select(users::id).for_update().first::<Uuid>(conn)?;
+
+            let in_progress = data_exports::table
+                .filter(data_exports::user_id.eq(user_id))
+                .filter(data_exports::status.eq_any(["pending", "processing"]))
+                .count()
+                .get_result::<i64>(conn)?;
+            if in_progress > 0 {
+                return Err(AppError::ValidationError(
+                    "A data export is already in progress".to_string(),
+                ));
+            }
+
+            diesel::insert_into(data_exports::table)
+                .values(&NewDataExport { user_id, format })
+                .get_result::<DataExport>(conn)
+                .map_err(AppError::from)
+        })
+    }
+
+    pub async fn get_export(pool: &DbPool, user_id: Uuid, export_id: Uuid) -> Result<DataExport, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        data_exports::table
+            .find(export_id)
+            .filter(data_exports::user_id.eq(user_id))
+            .first::<DataExport>(conn)
+            .optional()?
+            .ok_or_else(|| AppError::NotFoundError("Export not found".to_string()))
+    }
+
+    // Path of a finished export and the file name to download it as
+    pub async fn export_file(pool: &DbPool, user_id: Uuid, export_id: Uuid) -> Result<(PathBuf, String), AppError> {
+        let export = Self::get_export(pool, user_id, export_id).await?;
+        let key = match (export.status.as_str(), export.file_path) {
+            ("completed", Some(key)) => key,
+            ("expired", _) => return Err(AppError::NotFoundError("Export has expired".to_string())),
+            _ => return Err(AppError::BadRequest("Export is not ready yet".to_string())),
+        };
+
+        let file_name = format!("aptora-export-{}.{}", export.created_at.format("%Y%m%d"), export.format);
+        Ok((export_dir().join(key), file_name))
+    }
+
+    // Build the pending exports. Returns how many were completed.
+    pub async fn process_pending(pool: &DbPool) -> Result<usize, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        diesel::update(
+            data_exports::table
+                .filter(data_exports::status.eq("processing"))
+                .filter(data_exports::started_at.lt(Utc::now() - Duration::minutes(STALE_EXPORT_MINUTES))),
+        )
+        .set(data_exports::status.eq("pending"))
+        .execute(conn)?;
+
+        let mut completed = 0;
+        for _ in 0..EXPORT_BATCH {
+            // Claimed rows are skipped by other instances running the job
+            let export = conn.transaction(|conn| {
+                let next = data_exports::table
+                    .filter(data_exports::status.eq("pending"))
+                    .order(data_exports::created_at.asc())
+                    .select(data_exports::id)
+                    .for_update()
+                    .skip_locked()
+                    .first::<Uuid>(conn)
+                    .optional()?;
+                match next {
+                    Some(id) => diesel::update(data_exports::table.find(id))
+                        .set((
+                            data_exports::status.eq("processing"),
+                            data_exports::started_at.eq(Some(Utc::now())),
+                        ))
+                        .get_result::<DataExport>(conn)
+                        .map(Some),
+                    None => Ok(None),
+                }
+            })?;
+            let Some(export) = export else {
+                break;
+            };
+
+            match build_export(conn, &export) {
+                Ok((key, bytes)) => {
+                    diesel::update(data_exports::table.find(export.id))
+                        .set((
+                            data_exports::status.eq("completed"),
+                            data_exports::file_path.eq(Some(&key)),
+                            data_exports::file_bytes.eq(Some(bytes as i64)),
+                            data_exports::completed_at.eq(Some(Utc::now())),
+                            data_exports::expires_at.eq(Some(Utc::now() + export_retention())),
+                        ))
+                        .execute(conn)?;
+                    NotificationService::notify(pool, vec![NotificationService::data_export_ready(
+                        export.user_id,
+                        export.id,
+                    )])
+                    .await;
+                    completed += 1;
+                }
+                Err(e) => {
+                    log::error!("Failed to build data export {}: {}", export.id, e);
+                    diesel::update(data_exports::table.find(export.id))
+                        .set((
+                            data_exports::status.eq("failed"),
+                            data_exports::error.eq(Some("Export could not be built")),
+                            data_exports::completed_at.eq(Some(Utc::now())),
+                        ))
+                        .execute(conn)?;
+                }
+            }
+        }
+
+        Ok(completed)
+    }
+
+    // Delete the files of exports past their retention. Returns how many expired.
+    pub async fn purge_expired(pool: &DbPool) -> Result<usize, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let expired = data_exports::table
+            .filter(data_exports::status.eq("completed"))
+            .filter(data_exports::expires_at.lt(Utc::now()))
+            .select((data_exports::id, data_exports::file_path))
+            .load::<(Uuid, Option<String>)>(conn)?;
+
+        // Rows whose file could not be deleted are retried on the next run
+        let removed: Vec<Uuid> = expired
+            .into_iter()
+            .filter(|(_, key)| key.as_deref().is_none_or(remove_file))
+            .map(|(id, _)| id)
+            .collect();
+        diesel::update(data_exports::table.filter(data_exports::id.eq_any(&removed)))
+            .set((data_exports::status.eq("expired"), data_exports::file_path.eq(None::<String>)))
+            .execute(conn)?;
+
+        Ok(removed.len())
+    }
+
+    // Remove every export of a user, files included
+    pub fn delete_user_exports(conn: &mut PgConnection, user_id: Uuid) -> Result<(), AppError> {
+        let keys = data_exports::table
+            .filter(data_exports::user_id.eq(user_id))
+            .filter(data_exports::file_path.is_not_null())
+            .select(data_exports::file_path)
+            .load::<Option<String>>(conn)?;
+        for key in keys.iter().flatten() {
+            remove_file(key);
+        }
+
+        diesel::delete(data_exports::table.filter(data_exports::user_id.eq(user_id))).execute(conn)?;
+        Ok(())
+    }
+}
+
+pub struct DataExportJob;
+
+impl DataExportJob {
+    pub fn spawn(pool: DbPool) {
+        let interval_secs = AppConfig::global().data_export_interval_secs;
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(std::time::Duration::from_secs(interval_secs));
+
+            loop {
+                ticker.tick().await;
+
+                match DataExportService::process_pending(&pool).await {
+                    Ok(count) => log::debug!("Built {} data exports", count),
+                    Err(e) => log::error!("Failed to build data exports: {}", e),
+                }
+                match DataExportService::purge_expired(&pool).await {
+                    Ok(count) => log::debug!("Expired {} data exports", count),
+                    Err(e) => log::error!("Failed to expire data exports: {}", e),
+                }
+            }
+        });
+    }
+}
+
+fn export_dir() -> PathBuf {
+    PathBuf::from(&AppConfig::global().data_export_dir)
+}
+
+fn export_retention() -> Duration {
+    Duration::hours(AppConfig::global().data_export_retention_hours)
+}
+
+// Returns false if the file exists but could not be deleted
+fn remove_file(key: &str) -> bool {
+    match fs::remove_file(export_dir().join(key)) {
+        Ok(()) => true,
+        Err(e) if e.kind() == ErrorKind::NotFound => true,
+        Err(e) => {
+            log::warn!("Failed to delete data export {}: {}", key, e);
+            false
+        }
+    }
+}
+
+// Write the export file and return its key under the export directory and its size
+fn build_export(conn: &mut PgConnection, export: &DataExport) -> Result<(String, usize), AppError> {
+    let sections = collect_sections(conn, export.user_id)?;
+    let bytes = encode_export(&export.format, export.user_id, Utc::now(), sections)?;
+
+    let key = format!("{}/{}.{}", export.user_id, export.id, export.format);
+    let path = export_dir().join(&key);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to create export directory: {}", e)))?;
+    }
+    fs::write(&path, &bytes)
+        .map_err(|e| AppError::InternalServerError(format!("Failed to write export: {}", e)))?;
+
+    Ok((key, bytes.len()))
+}
+
+// A zip with a manifest and one JSON file per section, or a single JSON document
+fn encode_export(
+    format: &str,
+    user_id: Uuid,
+    exported_at: DateTime<Utc>,
+    sections: Vec<(&'static str, Value)>,
+) -> Result<Vec<u8>, AppError> {
+    let bytes = match format {
+        "zip" => {
+            let mut zip = ZipWriter::new(std::io::Cursor::new(Vec::new()));
+            let options = SimpleFileOptions::default();
+            let manifest = json!({
+                "user_id": user_id,
+                "exported_at": exported_at,
+                "files": sections.iter().map(|(name, _)| format!("{}.json", name)).collect::<Vec<_>>(),
+            });
+            for (name, value) in std::iter::once(("manifest", manifest)).chain(sections) {
+                let contents = serde_json::to_vec_pretty(&value).map_err(archive_error)?;
+                zip.start_file(format!("{}.json", name), options).map_err(archive_error)?;
+                zip.write_all(&contents).map_err(archive_error)?;
+            }
+            zip.finish().map_err(archive_error)?.into_inner()
+        }
+        _ => {
+            let mut document = serde_json::Map::new();
+            document.insert("user_id".to_string(), json!(user_id));
+            document.insert("exported_at".to_string(), json!(exported_at));
+            document.extend(sections.into_iter().map(|(name, value)| (name.to_string(), value)));
+            serde_json::to_vec_pretty(&document)
+                .map_err(|e| AppError::InternalServerError(format!("Failed to serialize export: {}", e)))?
+        }
+    };
+    Ok(bytes)
+}
+
+// The whole users row except the password hash
+fn profile_section(user: &User) -> Result<Value, serde_json::Error> {
+    let mut profile = serde_json::to_value(user)?;
+    if let Some(profile) = profile.as_object_mut() {
+        profile.remove("password_hash");
+    }
+    Ok(profile)
+}
+
+fn archive_error(e: impl std::fmt::Display) -> AppError {
+    AppError::InternalServerError(format!("Failed to write export archive: {}", e))
+}
+
+// Everything stored about the user, by section
+fn collect_sections(conn: &mut PgConnection, user_id: Uuid) -> Result<Vec<(&'static str, Value)>, AppError> {
+    let to_json = |value: Result<Value, serde_json::Error>| {
+        value.map_err(|e| AppError::InternalServerError(format!("Failed to serialize export: {}", e)))
+    };
+
+    let user = users::table.find(user_id).first::<User>(conn)?;
+    let profile = to_json(profile_section(&user))?;
+
+    let sessions = sessions::table
+        .filter(sessions::user_id.eq(user_id))
+        .order(sessions::created_at.desc())
+        .select((sessions::id, sessions::created_at, sessions::expires_at))
+        .load::<(Uuid, Option<DateTime<Utc>>, DateTime<Utc>)>(conn)?
+        .into_iter()
+        .map(|(id, created_at, expires_at)| json!({ "id": id, "created_at": created_at, "expires_at": expires_at }))
+        .collect::<Vec<_>>();
+
+    let related = |rows: Vec<(String, Option<DateTime<Utc>>)>| {
+        rows.into_iter()
+            .map(|(username, since)| json!({ "username": username, "since": since }))
+            .collect::<Vec<_>>()
+    };
+    let following = follows::table
+        .inner_join(users::table.on(users::id.eq(follows::following_id)))
+        .filter(follows::follower_id.eq(user_id))
+        .select((users::username, follows::created_at))
+        .load::<(String, Option<DateTime<Utc>>)>(conn)?;
+    let followers = follows::table
+        .inner_join(users::table.on(users::id.eq(follows::follower_id)))
+        .filter(follows::following_id.eq(user_id))
+        .select((users::username, follows::created_at))
+        .load::<(String, Option<DateTime<Utc>>)>(conn)?;
+
+    let referred_by = match user.referred_by {
+        Some(referrer_id) => users::table
+            .find(referrer_id)
+            .select(users::username)
+            .first::<String>(conn)
+            .optional()?,
+        None => None,
+    };
+    let referred_users = users::table
+        .filter(users::referred_by.eq(user_id))
+        .select((users::username, users::created_at))
+        .load::<(String, Option<DateTime<Utc>>)>(conn)?;
+    let codes = referral_codes::table
+        .filter(referral_codes::user_id.eq(user_id))
+        .load::<ReferralCode>(conn)?;
+    let rewards = referral_rewards::table
+        .filter(referral_rewards::referrer_id.eq(user_id).or(referral_rewards::referred_user_id.eq(user_id)))
+        .load::<ReferralReward>(conn)?;
+
+    let orders = paper_orders::table
+        .filter(paper_orders::user_id.eq(user_id))
+        .order(paper_orders::created_at.desc())
+        .load::<PaperOrder>(conn)?;
+    let fills = fills::table
+        .filter(fills::user_id.eq(user_id))
+        .order(fills::executed_at.desc())
+        .load::<Fill>(conn)?;
+    let notifications = notifications::table
+        .filter(notifications::user_id.eq(user_id))
+        .order(notifications::created_at.desc())
+        .load::<Notification>(conn)?;
+    let posts = posts::table
+        .filter(posts::user_id.eq(user_id))
+        .order(posts::created_at.desc())
+        .load::<Post>(conn)?;
+    let comments = post_comments::table
+        .filter(post_comments::user_id.eq(user_id))
+        .order(post_comments::created_at.desc())
+        .load::<PostComment>(conn)?;
+    let wallets = user_wallets::table
+        .filter(user_wallets::user_id.eq(user_id))
+        .load::<UserWallet>(conn)?;
+    let profile_history = profile_audit_log::table
+        .filter(profile_audit_log::user_id.eq(user_id))
+        .order(profile_audit_log::created_at.desc())
+        .load::<ProfileAuditEntry>(conn)?;
+
+    Ok(vec![
+        ("profile", profile),
+        ("profile_history", to_json(serde_json::to_value(profile_history))?),
+        ("sessions", json!(sessions)),
+        ("follows", json!({ "following": related(following), "followers": related(followers) })),
+        (
+            "referrals",
+            json!({
+                "referred_by": referred_by,
+                "referred_users": related(referred_users),
+                "codes": to_json(serde_json::to_value(codes))?,
+                "rewards": to_json(serde_json::to_value(rewards))?,
+            }),
+        ),
+        ("orders", to_json(serde_json::to_value(orders))?),
+        ("fills", to_json(serde_json::to_value(fills))?),
+        ("notifications", to_json(serde_json::to_value(notifications))?),
+        ("posts", to_json(serde_json::to_value(posts))?),
+        ("comments", to_json(serde_json::to_value(comments))?),
+        ("wallets", to_json(serde_json::to_value(wallets))?),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn sections() -> Vec<(&'static str, Value)> {
+        vec![("profile", json!({ "username": "alice" })), ("fills", json!([{ "symbol": "APT/USDC" }]))]
+    }
+
+    #[test]
+    fn test_json_export_is_one_document() {
+        let user_id = Uuid::new_v4();
+        let bytes = encode_export("json", user_id, Utc::now(), sections()).unwrap();
+        let document: Value = serde_json::from_slice(&bytes).unwrap();
+
+        assert_eq!(document["user_id"], json!(user_id));
+        assert_eq!(document["profile"]["username"], "alice");
+        assert_eq!(document["fills"][0]["symbol"], "APT/USDC");
+    }
+
+    #[test]
+    fn test_zip_export_has_a_manifest_and_a_file_per_section() {
+        let bytes = encode_export("zip", Uuid::new_v4(), Utc::now(), sections()).unwrap();
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes)).unwrap();
+
+        let mut names: Vec<&str> = archive.file_names().collect();
+        names.sort();
+        assert_eq!(names, ["fills.json", "manifest.json", "profile.json"]);
+
+        let mut manifest = String::new();
+        archive.by_name("manifest.json").unwrap().read_to_string(&mut manifest).unwrap();
+        let manifest: Value = serde_json::from_str(&manifest).unwrap();
+        assert_eq!(manifest["files"], json!(["profile.json", "fills.json"]));
+    }
+
+    #[test]
+    fn test_profile_section_drops_the_password_hash() {
+        let user: User = serde_json::from_value(json!({
+            "id": Uuid::new_v4(),
+            "email": "alice@example.com",
+            "password_hash": "$argon2id$secret",
+            "username": "alice",
+            "created_at": null,
+            "updated_at": null,
+            "referred_by": null,
+            "referral_count": 0,
+            "total_referral_rewards": 0.0,
+            "bio": null,
+            "avatar_url": null,
+            "is_verified": false,
+            "last_active": null,
+            "leaderboard_opt_out": false,
+            "is_admin": false,
+            "referral_code_id": null,
+            "is_private": false,
+            "deletion_scheduled_at": null,
+            "deleted_at": null
+        }))
+        .unwrap();
+
+        let profile = profile_section(&user).unwrap();
+        assert!(profile.get("password_hash").is_none());
+        assert_eq!(profile["email"], "alice@example.com");
+    }
+}
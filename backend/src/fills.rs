@@ -0,0 +1,906 @@
+use crate::activity::{ActivityEvent, ActivityService};
+use crate::config::AppConfig;
+use crate::copy_trading::CopyTradingService;
+use crate::kana_client::KanaClient;
+use crate::markets::MarketRegistry;
+use crate::models::{Fill, NewFill, PnlSummary, UserWallet};
+use crate::notifications::NotificationService;
+use crate::positions::number;
+use crate::referrals::ReferralRewardService;
+use crate::schema::{fills, funding_payments};
+use crate::utils::AppError;
+use crate::wallets::WalletService;
+use crate::DbPool;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Utc};
+use diesel::prelude::*;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+use uuid::Uuid;
+
+const SIZE_EPSILON: f64 = 1e-9;
+// Backfilled fills older than this do not produce feed activities or notifications
+const RECENT_FILL_MAX_AGE_HOURS: i64 = 24;
+// Trade id of the fill standing for the part of an order no trade accounts for
+const SYNTHETIC_TRADE_PREFIX: &str = "order-";
+// Manual syncs of one user are spaced at least this far apart
+const MIN_SYNC_INTERVAL_SECS: u64 = 30;
+
+// How closing fills are matched against the open position
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CostBasis {
+    Fifo,
+    AverageCost,
+}
+
+impl CostBasis {
+    pub fn parse(method: Option<&str>) -> Result<Self, AppError> {
+        match method.map(|m| m.to_lowercase()).as_deref() {
+            None | Some("fifo") => Ok(CostBasis::Fifo),
+            Some("average") | Some("avg") | Some("average_cost") => Ok(CostBasis::AverageCost),
+            Some(other) => Err(AppError::ValidationError(format!(
+                "Unsupported cost basis method: {}",
+                other
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PnlPeriod {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+impl PnlPeriod {
+    pub fn parse(period: Option<&str>) -> Result<Self, AppError> {
+        match period.map(|p| p.to_lowercase()).as_deref() {
+            None | Some("daily") | Some("day") => Ok(PnlPeriod::Daily),
+            Some("weekly") | Some("week") => Ok(PnlPeriod::Weekly),
+            Some("monthly") | Some("month") => Ok(PnlPeriod::Monthly),
+            Some(other) => Err(AppError::ValidationError(format!(
+                "Unsupported period: {}",
+                other
+            ))),
+        }
+    }
+
+    // First day of the period containing `date` (weeks start on Monday)
+    pub fn start_of(&self, date: NaiveDate) -> NaiveDate {
+        match self {
+            PnlPeriod::Daily => date,
+            PnlPeriod::Weekly => date - Duration::days(date.weekday().num_days_from_monday() as i64),
+            PnlPeriod::Monthly => date.with_day(1).unwrap_or(date),
+        }
+    }
+}
+
+// Open lots of one wallet and market, replayed in execution order.
+// Sizes are signed: positive for long lots, negative for short lots.
+#[derive(Debug, Default)]
+pub struct LotLedger {
+    lots: VecDeque<(f64, f64)>,
+    position: f64,
+    average_price: f64,
+}
+
+impl LotLedger {
+    // Apply a fill and return its realized PnL as (FIFO, average cost)
+    pub fn apply(&mut self, side: &str, size: f64, price: f64) -> (f64, f64) {
+        let signed = if side == "sell" { -size } else { size };
+        (self.apply_fifo(signed, price), self.apply_average(signed, price))
+    }
+
+    // Net signed position after the fills applied so far
+    pub fn position(&self) -> f64 {
+        self.position
+    }
+
+    fn apply_fifo(&mut self, signed: f64, price: f64) -> f64 {
+        let mut remaining = signed.abs();
+        let mut realized = 0.0;
+
+        while remaining > SIZE_EPSILON {
+            let lot = match self.lots.front_mut() {
+                Some(lot) if lot.0.signum() != signed.signum() => lot,
+                _ => break,
+            };
+
+            let matched = remaining.min(lot.0.abs());
+            realized += (price - lot.1) * matched * lot.0.signum();
+            lot.0 -= matched * lot.0.signum();
+            remaining -= matched;
+
+            if lot.0.abs() <= SIZE_EPSILON {
+                self.lots.pop_front();
+            }
+        }
+
+        if remaining > SIZE_EPSILON {
+            self.lots.push_back((remaining * signed.signum(), price));
+        }
+
+        realized
+    }
+
+    fn apply_average(&mut self, signed: f64, price: f64) -> f64 {
+        if self.position.abs() <= SIZE_EPSILON || self.position.signum() == signed.signum() {
+            let size = self.position.abs() + signed.abs();
+            self.average_price =
+                (self.position.abs() * self.average_price + signed.abs() * price) / size;
+            self.position += signed;
+            return 0.0;
+        }
+
+        let closed = signed.abs().min(self.position.abs());
+        let realized = (price - self.average_price) * closed * self.position.signum();
+        self.position += signed;
+
+        if self.position.abs() <= SIZE_EPSILON {
+            self.position = 0.0;
+            self.average_price = 0.0;
+        } else if self.position.signum() == signed.signum() {
+            // The fill flipped the position; the remainder opens at the fill price
+            self.average_price = price;
+        }
+
+        realized
+    }
+}
+
+// A fill as parsed from a Kana Labs response, before it is attributed to a user
+#[derive(Debug, Clone)]
+pub struct ParsedFill {
+    pub market_id: String,
+    pub order_id: Option<String>,
+    pub trade_id: String,
+    pub side: String,
+    pub size: f64,
+    pub price: f64,
+    pub fee: Option<f64>,
+    pub executed_at: DateTime<Utc>,
+}
+
+pub struct FillService;
+
+impl FillService {
+    pub async fn reconcile_user(pool: &DbPool, user_id: Uuid) -> Result<usize, AppError> {
+        static LAST_SYNC: OnceLock<Mutex<HashMap<Uuid, Instant>>> = OnceLock::new();
+        {
+            let mut last_sync = LAST_SYNC.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner());
+            if !claim_sync(&mut last_sync, user_id, Instant::now()) {
+                return Err(AppError::RateLimited(format!(
+                    "Fills can be synced once every {} seconds",
+                    MIN_SYNC_INTERVAL_SECS
+                )));
+            }
+        }
+
+        let wallets = WalletService::get_user_wallets(pool, user_id).await?;
+        Self::reconcile_wallets(pool, &wallets).await
+    }
+
+    pub async fn reconcile_all(pool: &DbPool) -> Result<usize, AppError> {
+        let wallets = WalletService::get_all_wallets(pool).await?;
+        Self::reconcile_wallets(pool, &wallets).await
+    }
+
+    // Pull order history and recent trades for each wallet, store new fills and
+    // recompute realized PnL for every market that received one
+    pub async fn reconcile_wallets(
+        pool: &DbPool,
+        wallets: &[UserWallet],
+    ) -> Result<usize, AppError> {
+        if wallets.is_empty() {
+            return Ok(0);
+        }
+
+        let kana_client = KanaClient::new()?;
+
+        // getAllTrades is per market and not per wallet, so fetch it once
+        let mut market_trades = Vec::new();
+        for market in MarketRegistry::all() {
+            match kana_client.get_all_trades(market.market_id).await {
+                Ok(raw) => market_trades.push(raw),
+                Err(e) => log::warn!("Failed to fetch trades for {}: {}", market.symbol, e),
+            }
+        }
+
+        let mut inserted = 0;
+        for wallet in wallets {
+            let history = match kana_client.get_order_history(&wallet.wallet_address, None).await {
+                Ok(history) => history,
+                Err(e) => {
+                    log::warn!("Failed to fetch order history for {}: {}", wallet.wallet_address, e);
+                    continue;
+                }
+            };
+
+            let trades = market_trades
+                .iter()
+                .flat_map(|raw| Self::parse_trades(raw, &wallet.wallet_address))
+                .collect();
+            let orders = Self::parse_order_history(&history);
+
+            let (new_fills, activities) = Self::store_fills(pool, wallet, trades, orders)?;
+            inserted += new_fills.len();
+            ActivityService::record(pool, activities).await;
+
+            let recent = Utc::now() - Duration::hours(RECENT_FILL_MAX_AGE_HOURS);
+            let notifications = new_fills
+                .iter()
+                .filter(|fill| fill.executed_at >= recent)
+                .map(|fill| {
+                    NotificationService::order_filled(
+                        fill.user_id,
+                        fill.id,
+                        &fill.symbol,
+                        &fill.side,
+                        fill.size,
+                        fill.price,
+                        false,
+                    )
+                })
+                .collect();
+            NotificationService::notify(pool, notifications).await;
+
+            // Fills of a wallet the user never proved to own stay in their own
+            // ledger but are not copied or rewarded
+            if wallet.verified_at.is_none() {
+                continue;
+            }
+            if let Err(e) = CopyTradingService::copy_fills(pool, &new_fills).await {
+                log::warn!("Failed to copy fills of {}: {}", wallet.wallet_address, e);
+            }
+            if let Err(e) = ReferralRewardService::reward_fees(pool, &new_fills).await {
+                log::warn!("Failed to grant fee rebates for {}: {}", wallet.wallet_address, e);
+            }
+        }
+
+        Ok(inserted)
+    }
+
+    pub async fn get_fills(
+        pool: &DbPool,
+        user_id: Uuid,
+        symbol: Option<&str>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(Vec<Fill>, i64), AppError> {
+        let symbol = symbol
+            .map(|s| MarketRegistry::require(s).map(|m| m.symbol))
+            .transpose()?;
+
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let filtered = || {
+            let mut query = fills::table.filter(fills::user_id.eq(user_id)).into_boxed();
+            if let Some(symbol) = symbol {
+                query = query.filter(fills::symbol.eq(symbol));
+            }
+            if let Some(from) = from {
+                query = query.filter(fills::executed_at.ge(from));
+            }
+            if let Some(to) = to {
+                query = query.filter(fills::executed_at.lt(to));
+            }
+            query
+        };
+
+        let total = filtered().count().get_result::<i64>(conn)?;
+        let results = filtered()
+            .order((fills::executed_at.desc(), fills::trade_id.desc()))
+            .limit(limit)
+            .offset(offset)
+            .load::<Fill>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get fills: {}", e)))?;
+
+        Ok((results, total))
+    }
+
+    // Realized PnL, fees and funding bucketed by day, week or month, oldest first
+    pub async fn get_pnl_summary(
+        pool: &DbPool,
+        user_id: Uuid,
+        period: PnlPeriod,
+        basis: CostBasis,
+        symbol: Option<&str>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Result<Vec<PnlSummary>, AppError> {
+        let (user_fills, _) =
+            Self::get_fills(pool, user_id, symbol, from, to, i64::MAX, 0).await?;
+
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let mut funding_query = funding_payments::table
+            .filter(funding_payments::user_id.eq(user_id))
+            .select((funding_payments::funding_time, funding_payments::payment))
+            .into_boxed();
+        if let Some(symbol) = symbol {
+            funding_query =
+                funding_query.filter(funding_payments::symbol.eq(MarketRegistry::require(symbol)?.symbol));
+        }
+        if let Some(from) = from {
+            funding_query = funding_query.filter(funding_payments::funding_time.ge(from));
+        }
+        if let Some(to) = to {
+            funding_query = funding_query.filter(funding_payments::funding_time.lt(to));
+        }
+        let funding = funding_query
+            .load::<(DateTime<Utc>, f64)>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get funding payments: {}", e)))?;
+
+        let mut buckets: BTreeMap<NaiveDate, PnlSummary> = BTreeMap::new();
+        for fill in &user_fills {
+            let summary = bucket(&mut buckets, period, fill.executed_at);
+            summary.realized_pnl += match basis {
+                CostBasis::Fifo => fill.realized_pnl,
+                CostBasis::AverageCost => fill.realized_pnl_avg_cost,
+            };
+            summary.fees += fill.fee;
+            summary.volume += fill.size * fill.price;
+            summary.fill_count += 1;
+        }
+        for (funding_time, payment) in funding {
+            bucket(&mut buckets, period, funding_time).funding += payment;
+        }
+
+        Ok(buckets
+            .into_values()
+            .map(|mut summary| {
+                // Funding payments are positive when paid
+                summary.net_pnl = summary.realized_pnl - summary.fees - summary.funding;
+                summary
+            })
+            .collect())
+    }
+
+    // Parse filled orders from a Kana Labs getOrderHistory response
+    pub fn parse_order_history(raw: &Value) -> Vec<ParsedFill> {
+        data(raw)
+            .iter()
+            .filter_map(|entry| {
+                let order_id = string(entry, &["order_id", "orderId", "id"])?;
+                let size = number(entry, &["size", "quantity"])?;
+                let filled = number(entry, &["filled_size", "filledSize", "filled_quantity"])
+                    .or_else(|| number(entry, &["remaining_size", "remainingSize"]).map(|r| size - r))
+                    .unwrap_or(0.0);
+                if filled <= SIZE_EPSILON {
+                    return None;
+                }
+
+                Some(ParsedFill {
+                    market_id: string(entry, &["market_id", "marketId"])?,
+                    trade_id: format!("{}{}", SYNTHETIC_TRADE_PREFIX, order_id),
+                    order_id: Some(order_id),
+                    side: side(entry)?,
+                    size: filled,
+                    price: number(entry, &["average_price", "avg_price", "averagePrice", "price"])?,
+                    fee: number(entry, &["fee", "fees", "trading_fee"]),
+                    executed_at: timestamp(entry, &["last_updated", "timestamp", "created_at"])?,
+                })
+            })
+            .collect()
+    }
+
+    // Parse the trades of `wallet_address` from a Kana Labs getAllTrades response
+    pub fn parse_trades(raw: &Value, wallet_address: &str) -> Vec<ParsedFill> {
+        let wallet_address = wallet_address.to_lowercase();
+        let matches = |entry: &Value, keys: &[&str]| {
+            string(entry, keys).is_some_and(|address| address.to_lowercase() == wallet_address)
+        };
+
+        data(raw)
+            .iter()
+            .filter_map(|entry| {
+                let is_taker = matches(entry, &["address", "user_address", "userAddress", "taker", "taker_address"]);
+                let is_maker = matches(entry, &["maker", "maker_address"]);
+                if !is_taker && !is_maker {
+                    return None;
+                }
+
+                // The reported side is the taker's; makers traded the other way
+                let mut side = side(entry)?;
+                if is_maker && !is_taker {
+                    side = if side == "buy" { "sell" } else { "buy" }.to_string();
+                }
+
+                Some(ParsedFill {
+                    market_id: string(entry, &["market_id", "marketId"])?,
+                    order_id: string(entry, &["order_id", "orderId"]),
+                    trade_id: string(entry, &["trade_id", "tradeId", "txn_version", "id"])?,
+                    side,
+                    size: number(entry, &["size", "quantity"])?,
+                    price: number(entry, &["price"])?,
+                    fee: number(entry, &["fee", "fees"]),
+                    executed_at: timestamp(entry, &["timestamp", "time", "created_at"])?,
+                })
+            })
+            .collect()
+    }
+
+    // Trades are the most granular record, but getAllTrades only covers recent
+    // activity. Order history fills in whatever the trades do not account for,
+    // and order-level fees are spread over the order's trades by size.
+    // `stored` holds (order_id, trade_id, size) of fills already in the ledger,
+    // so trades that fell out of the window still count against their order.
+    // Every order yields its synthetic remainder fill, with size 0 once the
+    // trades cover it.
+    pub fn merge(
+        trades: Vec<ParsedFill>,
+        orders: Vec<ParsedFill>,
+        stored: &[(String, String, f64)],
+    ) -> Vec<ParsedFill> {
+        let mut merged = trades;
+        let fetched = merged.len();
+        for order in orders {
+            let order_fee = order.fee;
+            let mut covered = 0.0;
+            for trade in merged[..fetched]
+                .iter_mut()
+                .filter(|trade| trade.order_id.is_some() && trade.order_id == order.order_id)
+            {
+                covered += trade.size;
+                if trade.fee.is_none() {
+                    trade.fee = order_fee.map(|fee| fee * trade.size / order.size);
+                }
+            }
+            covered += stored
+                .iter()
+                .filter(|(order_id, trade_id, _)| {
+                    order.order_id.as_ref() == Some(order_id)
+                        && !is_synthetic(trade_id)
+                        && !merged[..fetched].iter().any(|trade| &trade.trade_id == trade_id)
+                })
+                .map(|(_, _, size)| size)
+                .sum::<f64>();
+
+            let remainder = (order.size - covered).max(0.0);
+            merged.push(ParsedFill {
+                size: remainder,
+                fee: order_fee.map(|fee| fee * remainder / order.size),
+                ..order
+            });
+        }
+        merged
+    }
+
+    fn store_fills(
+        pool: &DbPool,
+        wallet: &UserWallet,
+        trades: Vec<ParsedFill>,
+        orders: Vec<ParsedFill>,
+    ) -> Result<(Vec<Fill>, Vec<ActivityEvent>), AppError> {
+        if trades.is_empty() && orders.is_empty() {
+            return Ok((vec![], vec![]));
+        }
+
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        conn.transaction(|conn| {
+            let order_ids: Vec<&str> = orders.iter().filter_map(|order| order.order_id.as_deref()).collect();
+            let stored: Vec<(String, String, f64)> = fills::table
+                .filter(fills::wallet_address.eq(&wallet.wallet_address))
+                .filter(fills::order_id.eq_any(&order_ids))
+                .select((fills::order_id, fills::trade_id, fills::size))
+                .load::<(Option<String>, String, f64)>(conn)?
+                .into_iter()
+                .filter_map(|(order_id, trade_id, size)| Some((order_id?, trade_id, size)))
+                .collect();
+            let stored_remainders: HashMap<&str, f64> = stored
+                .iter()
+                .filter(|(_, trade_id, _)| is_synthetic(trade_id))
+                .map(|(_, trade_id, size)| (trade_id.as_str(), *size))
+                .collect();
+
+            let mut changed_markets: Vec<String> = Vec::new();
+            let mut new_fills: Vec<NewFill> = Vec::new();
+            for fill in Self::merge(trades, orders, &stored) {
+                let Some(market) = MarketRegistry::by_market_id(&fill.market_id) else {
+                    continue;
+                };
+
+                let stored_remainder = stored_remainders.get(fill.trade_id.as_str()).copied();
+                if fill.size <= SIZE_EPSILON {
+                    // The trades now cover the order; its remainder would count twice
+                    if stored_remainder.is_some() {
+                        diesel::delete(fills::table)
+                            .filter(fills::wallet_address.eq(&wallet.wallet_address))
+                            .filter(fills::market_id.eq(&fill.market_id))
+                            .filter(fills::trade_id.eq(&fill.trade_id))
+                            .execute(conn)?;
+                        changed_markets.push(fill.market_id);
+                    }
+                    continue;
+                }
+
+                let new_fill = NewFill {
+                    user_id: wallet.user_id,
+                    wallet_address: wallet.wallet_address.clone(),
+                    symbol: market.symbol.to_string(),
+                    market_id: fill.market_id,
+                    order_id: fill.order_id,
+                    trade_id: fill.trade_id,
+                    side: fill.side,
+                    size: fill.size,
+                    price: fill.price,
+                    fee: fill.fee.unwrap_or(0.0),
+                    executed_at: fill.executed_at,
+                };
+                match stored_remainder {
+                    // A stored remainder is resized in place. It was already
+                    // announced, copied and rewarded, so it is not new.
+                    Some(size) => {
+                        if (size - new_fill.size).abs() > SIZE_EPSILON {
+                            diesel::insert_into(fills::table)
+                                .values(&new_fill)
+                                .on_conflict((fills::wallet_address, fills::market_id, fills::trade_id))
+                                .do_update()
+                                .set((
+                                    fills::size.eq(new_fill.size),
+                                    fills::price.eq(new_fill.price),
+                                    fills::fee.eq(new_fill.fee),
+                                    fills::executed_at.eq(new_fill.executed_at),
+                                ))
+                                .execute(conn)?;
+                            changed_markets.push(new_fill.market_id);
+                        }
+                    }
+                    None => new_fills.push(new_fill),
+                }
+            }
+
+            let inserted = diesel::insert_into(fills::table)
+                .values(&new_fills)
+                .on_conflict_do_nothing()
+                .returning(fills::id)
+                .get_results::<Uuid>(conn)?;
+
+            let mut markets: Vec<&str> = new_fills
+                .iter()
+                .map(|f| f.market_id.as_str())
+                .chain(changed_markets.iter().map(String::as_str))
+                .collect();
+            markets.sort_unstable();
+            markets.dedup();
+            if inserted.is_empty() && changed_markets.is_empty() {
+                return Ok((vec![], vec![]));
+            }
+
+            let mut activities = Vec::new();
+            for market_id in markets {
+                activities.extend(Self::recompute_realized_pnl(
+                    conn,
+                    &wallet.wallet_address,
+                    market_id,
+                    &inserted,
+                )?);
+            }
+
+            // Reload so the returned fills carry their realized PnL
+            let stored = fills::table
+                .filter(fills::id.eq_any(&inserted))
+                .order(fills::executed_at.asc())
+                .load::<Fill>(conn)?;
+            Ok((stored, activities))
+        })
+    }
+
+    // Replay a wallet's fills in one market from the start; fills can arrive out
+    // of order, so realized PnL is never computed incrementally. Returns the
+    // position opened/closed activities caused by the fills in `new_ids`.
+    fn recompute_realized_pnl(
+        conn: &mut PgConnection,
+        wallet_address: &str,
+        market_id: &str,
+        new_ids: &[Uuid],
+    ) -> Result<Vec<ActivityEvent>, AppError> {
+        let market_fills = fills::table
+            .filter(fills::wallet_address.eq(wallet_address))
+            .filter(fills::market_id.eq(market_id))
+            .order((fills::executed_at.asc(), fills::trade_id.asc()))
+            .load::<Fill>(conn)?;
+
+        let activity_cutoff = Utc::now() - Duration::hours(RECENT_FILL_MAX_AGE_HOURS);
+        let mut activities = Vec::new();
+        let mut ledger = LotLedger::default();
+        let mut round_trip_pnl = 0.0;
+        let mut updates: HashMap<Uuid, (f64, f64)> = HashMap::new();
+        for fill in &market_fills {
+            let before = ledger.position();
+            let (fifo, average) = ledger.apply(&fill.side, fill.size, fill.price);
+            let after = ledger.position();
+            round_trip_pnl += fifo;
+
+            let closed = before.abs() > SIZE_EPSILON
+                && (after.abs() <= SIZE_EPSILON || after.signum() != before.signum());
+            let opened = after.abs() > SIZE_EPSILON
+                && (before.abs() <= SIZE_EPSILON || after.signum() != before.signum());
+            let announce = new_ids.contains(&fill.id) && fill.executed_at >= activity_cutoff;
+            if closed {
+                if announce {
+                    activities.push(ActivityEvent::position_closed(
+                        fill.user_id,
+                        &fill.symbol,
+                        position_side(before),
+                        before.abs(),
+                        fill.price,
+                        round_trip_pnl,
+                    ));
+                }
+                round_trip_pnl = 0.0;
+            }
+            if opened && announce {
+                activities.push(ActivityEvent::position_opened(
+                    fill.user_id,
+                    &fill.symbol,
+                    position_side(after),
+                    after.abs(),
+                    fill.price,
+                ));
+            }
+
+            if (fifo - fill.realized_pnl).abs() > SIZE_EPSILON
+                || (average - fill.realized_pnl_avg_cost).abs() > SIZE_EPSILON
+            {
+                updates.insert(fill.id, (fifo, average));
+            }
+        }
+
+        for (id, (fifo, average)) in updates {
+            diesel::update(fills::table.find(id))
+                .set((
+                    fills::realized_pnl.eq(fifo),
+                    fills::realized_pnl_avg_cost.eq(average),
+                ))
+                .execute(conn)?;
+        }
+
+        Ok(activities)
+    }
+}
+
+// Background job that keeps the fill ledger in sync with Kana Labs
+pub struct FillReconciler;
+
+impl FillReconciler {
+    pub fn spawn(pool: DbPool) {
+        let interval_secs = AppConfig::global().fill_reconcile_interval_secs;
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(std::time::Duration::from_secs(interval_secs));
+
+            loop {
+                ticker.tick().await;
+
+                match FillService::reconcile_all(&pool).await {
+                    Ok(count) => log::debug!("Reconciled {} new fills", count),
+                    Err(e) => log::error!("Failed to reconcile fills: {}", e),
+                }
+            }
+        });
+    }
+}
+
+// Fills sized from an order's filled amount rather than reported as trades
+pub fn is_synthetic(trade_id: &str) -> bool {
+    trade_id.starts_with(SYNTHETIC_TRADE_PREFIX)
+}
+
+// Record a sync of `user_id` at `now` unless the last one was too recent.
+// Entries past the interval are dropped so the map stays small.
+fn claim_sync(last_sync: &mut HashMap<Uuid, Instant>, user_id: Uuid, now: Instant) -> bool {
+    let interval = std::time::Duration::from_secs(MIN_SYNC_INTERVAL_SECS);
+    last_sync.retain(|_, at| now.saturating_duration_since(*at) < interval);
+    if last_sync.contains_key(&user_id) {
+        return false;
+    }
+    last_sync.insert(user_id, now);
+    true
+}
+
+fn position_side(position: f64) -> &'static str {
+    if position < 0.0 { "short" } else { "long" }
+}
+
+fn bucket(
+    buckets: &mut BTreeMap<NaiveDate, PnlSummary>,
+    period: PnlPeriod,
+    at: DateTime<Utc>,
+) -> &mut PnlSummary {
+    let period_start = period.start_of(at.date_naive());
+    buckets.entry(period_start).or_insert_with(|| PnlSummary {
+        period_start,
+        realized_pnl: 0.0,
+        fees: 0.0,
+        funding: 0.0,
+        net_pnl: 0.0,
+        volume: 0.0,
+        fill_count: 0,
+    })
+}
+
+fn data(raw: &Value) -> &[Value] {
+    raw.get("data")
+        .and_then(|d| d.as_array())
+        .map(|entries| entries.as_slice())
+        .unwrap_or(&[])
+}
+
+fn string(value: &Value, keys: &[&str]) -> Option<String> {
+    keys.iter().find_map(|key| match value.get(*key)? {
+        Value::String(s) if !s.is_empty() => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    })
+}
+
+// Kana Labs reports trade_side (true = long) plus direction (true = closing);
+// closing a long or opening a short is a sell
+fn side(entry: &Value) -> Option<String> {
+    let side = match entry.get("trade_side").or_else(|| entry.get("side"))? {
+        Value::Bool(long) => {
+            let closing = entry.get("direction").and_then(|d| d.as_bool()).unwrap_or(false);
+            if *long != closing { "buy" } else { "sell" }
+        }
+        Value::String(s) => match s.to_lowercase().as_str() {
+            "buy" | "long" | "bid" => "buy",
+            "sell" | "short" | "ask" => "sell",
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(side.to_string())
+}
+
+// Timestamps come as RFC 3339 strings or as seconds, milliseconds or microseconds since the epoch
+fn timestamp(value: &Value, keys: &[&str]) -> Option<DateTime<Utc>> {
+    keys.iter().find_map(|key| {
+        let field = value.get(*key)?;
+        if let Some(parsed) = field
+            .as_str()
+            .and_then(|s| DateTime::parse_from_rfc3339(s).ok())
+        {
+            return Some(parsed.with_timezone(&Utc));
+        }
+
+        let raw = match field {
+            Value::Number(n) => n.as_f64()?,
+            Value::String(s) => s.parse::<f64>().ok()?,
+            _ => return None,
+        };
+        let millis = if raw >= 1e15 {
+            raw / 1000.0
+        } else if raw >= 1e12 {
+            raw
+        } else {
+            raw * 1000.0
+        };
+        Utc.timestamp_millis_opt(millis as i64).single()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(trade_id: &str, order_id: Option<&str>, size: f64, fee: Option<f64>) -> ParsedFill {
+        ParsedFill {
+            market_id: "501".to_string(),
+            order_id: order_id.map(str::to_string),
+            trade_id: trade_id.to_string(),
+            side: "buy".to_string(),
+            size,
+            price: 100.0,
+            fee,
+            executed_at: Utc::now(),
+        }
+    }
+
+    fn order(order_id: &str, filled: f64, fee: Option<f64>) -> ParsedFill {
+        parsed(&format!("{}{}", SYNTHETIC_TRADE_PREFIX, order_id), Some(order_id), filled, fee)
+    }
+
+    fn remainder<'a>(merged: &'a [ParsedFill], order_id: &str) -> &'a ParsedFill {
+        let trade_id = format!("{}{}", SYNTHETIC_TRADE_PREFIX, order_id);
+        merged.iter().find(|fill| fill.trade_id == trade_id).unwrap()
+    }
+
+    #[test]
+    fn test_fifo_matches_oldest_lots_first() {
+        let mut ledger = LotLedger::default();
+        assert_eq!(ledger.apply("buy", 1.0, 100.0), (0.0, 0.0));
+        assert_eq!(ledger.apply("buy", 1.0, 120.0), (0.0, 0.0));
+
+        // FIFO closes the 100 lot; average cost closes at 110
+        let (fifo, average) = ledger.apply("sell", 1.0, 130.0);
+        assert!((fifo - 30.0).abs() < 1e-9);
+        assert!((average - 20.0).abs() < 1e-9);
+
+        let (fifo, average) = ledger.apply("sell", 1.0, 130.0);
+        assert!((fifo - 10.0).abs() < 1e-9);
+        assert!((average - 20.0).abs() < 1e-9);
+        assert_eq!(ledger.position(), 0.0);
+    }
+
+    #[test]
+    fn test_fifo_splits_a_lot_and_flips_the_position() {
+        let mut ledger = LotLedger::default();
+        ledger.apply("buy", 2.0, 100.0);
+
+        let (fifo, _) = ledger.apply("sell", 0.5, 110.0);
+        assert!((fifo - 5.0).abs() < 1e-9);
+        assert!((ledger.position() - 1.5).abs() < 1e-9);
+
+        // Selling 3 closes the remaining 1.5 and opens a 1.5 short at 90
+        let (fifo, average) = ledger.apply("sell", 3.0, 90.0);
+        assert!((fifo + 15.0).abs() < 1e-9);
+        assert!((average + 15.0).abs() < 1e-9);
+        assert!((ledger.position() + 1.5).abs() < 1e-9);
+
+        let (fifo, average) = ledger.apply("buy", 1.5, 80.0);
+        assert!((fifo - 15.0).abs() < 1e-9);
+        assert!((average - 15.0).abs() < 1e-9);
+        assert_eq!(ledger.position(), 0.0);
+    }
+
+    #[test]
+    fn test_merge_spreads_order_fees_over_trades() {
+        let trades = vec![
+            parsed("t1", Some("o1"), 1.0, None),
+            parsed("t2", Some("o1"), 3.0, Some(0.5)),
+        ];
+        let merged = FillService::merge(trades, vec![order("o1", 4.0, Some(2.0))], &[]);
+
+        assert_eq!(merged[0].fee, Some(0.5));
+        assert_eq!(merged[1].fee, Some(0.5));
+        assert_eq!(remainder(&merged, "o1").size, 0.0);
+    }
+
+    #[test]
+    fn test_merge_counts_stored_trades_against_the_order() {
+        // t1 was stored by an earlier sync and is no longer returned
+        let stored = vec![
+            ("o1".to_string(), "t1".to_string(), 2.0),
+            ("o1".to_string(), "order-o1".to_string(), 3.0),
+        ];
+        let trades = vec![parsed("t2", Some("o1"), 1.0, None)];
+        let merged = FillService::merge(trades, vec![order("o1", 5.0, Some(1.0))], &stored);
+
+        let rest = remainder(&merged, "o1");
+        assert!((rest.size - 2.0).abs() < 1e-9);
+        assert!((rest.fee.unwrap() - 0.4).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_merge_does_not_count_a_fetched_trade_twice() {
+        let stored = vec![("o1".to_string(), "t1".to_string(), 2.0)];
+        let trades = vec![parsed("t1", Some("o1"), 2.0, None)];
+        let merged = FillService::merge(trades, vec![order("o1", 3.0, None)], &stored);
+
+        assert!((remainder(&merged, "o1").size - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_claim_sync_spaces_syncs_per_user() {
+        let mut last_sync = HashMap::new();
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        let start = Instant::now();
+
+        assert!(claim_sync(&mut last_sync, alice, start));
+        assert!(!claim_sync(&mut last_sync, alice, start + std::time::Duration::from_secs(1)));
+        assert!(claim_sync(&mut last_sync, bob, start + std::time::Duration::from_secs(1)));
+
+        let later = start + std::time::Duration::from_secs(MIN_SYNC_INTERVAL_SECS);
+        assert!(claim_sync(&mut last_sync, alice, later));
+        assert_eq!(last_sync.len(), 2);
+    }
+}
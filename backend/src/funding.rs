@@ -0,0 +1,247 @@
+use crate::config::AppConfig;
+use crate::kana_client::KanaClient;
+use crate::markets::MarketRegistry;
+use crate::models::{FundingPayment, FundingRate, NewFundingPayment, NewFundingRate};
+use crate::positions::PositionService;
+use crate::schema::{funding_payments, funding_rates};
+use crate::utils::AppError;
+use crate::wallets::WalletService;
+use crate::DbPool;
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use diesel::prelude::*;
+use uuid::Uuid;
+
+// Kana Labs settles funding every 8 hours (00:00, 08:00 and 16:00 UTC)
+pub const FUNDING_INTERVAL_HOURS: i64 = 8;
+
+// Most recent funding settlement at or before `now`
+pub fn funding_time_floor(now: DateTime<Utc>) -> DateTime<Utc> {
+    now.duration_trunc(Duration::hours(FUNDING_INTERVAL_HOURS))
+        .unwrap_or(now)
+}
+
+pub fn next_funding_time(now: DateTime<Utc>) -> DateTime<Utc> {
+    funding_time_floor(now) + Duration::hours(FUNDING_INTERVAL_HOURS)
+}
+
+pub struct FundingService;
+
+impl FundingService {
+    // Sample the current funding rate of every registry market
+    pub async fn record_rates(pool: &DbPool) -> Result<usize, AppError> {
+        let kana_client = KanaClient::new()?;
+
+        let mut samples = Vec::new();
+        for market in MarketRegistry::all() {
+            let funding_rate = match kana_client.get_funding_rate(market.symbol).await {
+                Ok(funding_rate) => funding_rate,
+                Err(e) => {
+                    log::warn!("Failed to fetch funding rate for {}: {}", market.symbol, e);
+                    continue;
+                }
+            };
+            // A sample without a live mark would charge funding on a made-up notional
+            let Some(mark_price) = market.mid_price().await else {
+                log::warn!("Skipping funding sample for {}: no live price", market.symbol);
+                continue;
+            };
+            samples.push(NewFundingRate {
+                symbol: market.symbol.to_string(),
+                market_id: market.market_id.to_string(),
+                funding_rate,
+                mark_price,
+            });
+        }
+
+        if samples.is_empty() {
+            return Ok(0);
+        }
+
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        diesel::insert_into(funding_rates::table)
+            .values(&samples)
+            .execute(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to record funding rates: {}", e)))
+    }
+
+    // Charge every linked wallet's open positions for the funding interval ending at `funding_time`
+    pub async fn settle_payments(
+        pool: &DbPool,
+        funding_time: DateTime<Utc>,
+    ) -> Result<usize, AppError> {
+        let wallets = WalletService::get_all_wallets(pool).await?;
+
+        let mut payments = Vec::new();
+        for wallet in wallets {
+            let positions = match PositionService::get_positions(&wallet.wallet_address, None).await {
+                Ok(positions) => positions,
+                Err(e) => {
+                    log::warn!("Failed to fetch positions for {}: {}", wallet.wallet_address, e);
+                    continue;
+                }
+            };
+
+            let conn = &mut pool.get()
+                .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+            for position in positions {
+                let rate = match Self::rate_at(conn, &position.symbol, funding_time)? {
+                    Some(rate) => rate,
+                    None => continue,
+                };
+                let Some(mark_price) = position.mark_price else {
+                    continue;
+                };
+
+                // Longs pay shorts when the rate is positive
+                let direction = if position.side == "short" { -1.0 } else { 1.0 };
+                payments.push(NewFundingPayment {
+                    user_id: wallet.user_id,
+                    wallet_address: wallet.wallet_address.clone(),
+                    symbol: position.symbol,
+                    market_id: position.market_id,
+                    side: position.side,
+                    position_size: position.size,
+                    mark_price,
+                    funding_rate: rate.funding_rate,
+                    payment: position.size * mark_price * rate.funding_rate * direction,
+                    funding_time,
+                });
+            }
+        }
+
+        if payments.is_empty() {
+            return Ok(0);
+        }
+
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        diesel::insert_into(funding_payments::table)
+            .values(&payments)
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to record funding payments: {}", e)))
+    }
+
+    // Recorded funding rates for a market, newest first
+    pub async fn get_funding_history(
+        pool: &DbPool,
+        symbol: &str,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(Vec<FundingRate>, i64), AppError> {
+        let market = MarketRegistry::require(symbol)?;
+
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let total = funding_rates::table
+            .filter(funding_rates::symbol.eq(market.symbol))
+            .count()
+            .get_result::<i64>(conn)?;
+
+        let rates = funding_rates::table
+            .filter(funding_rates::symbol.eq(market.symbol))
+            .order(funding_rates::recorded_at.desc())
+            .limit(limit)
+            .offset(offset)
+            .load::<FundingRate>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get funding history: {}", e)))?;
+
+        Ok((rates, total))
+    }
+
+    // Funding payments charged to a user's linked wallets, newest first
+    pub async fn get_user_payments(
+        pool: &DbPool,
+        user_id: Uuid,
+        symbol: Option<&str>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(Vec<FundingPayment>, i64), AppError> {
+        let symbol = symbol
+            .map(|s| MarketRegistry::require(s).map(|m| m.symbol))
+            .transpose()?;
+
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let mut count_query = funding_payments::table
+            .filter(funding_payments::user_id.eq(user_id))
+            .into_boxed();
+        let mut query = funding_payments::table
+            .filter(funding_payments::user_id.eq(user_id))
+            .into_boxed();
+        if let Some(symbol) = symbol {
+            count_query = count_query.filter(funding_payments::symbol.eq(symbol));
+            query = query.filter(funding_payments::symbol.eq(symbol));
+        }
+
+        let total = count_query.count().get_result::<i64>(conn)?;
+        let payments = query
+            .order(funding_payments::funding_time.desc())
+            .limit(limit)
+            .offset(offset)
+            .load::<FundingPayment>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get funding payments: {}", e)))?;
+
+        Ok((payments, total))
+    }
+
+    // Latest rate recorded for a market at or before `at`
+    fn rate_at(
+        conn: &mut PgConnection,
+        symbol: &str,
+        at: DateTime<Utc>,
+    ) -> Result<Option<FundingRate>, AppError> {
+        Ok(funding_rates::table
+            .filter(funding_rates::symbol.eq(symbol))
+            .filter(funding_rates::recorded_at.le(at))
+            .order(funding_rates::recorded_at.desc())
+            .first::<FundingRate>(conn)
+            .optional()?)
+    }
+}
+
+// Background job that samples funding rates and settles funding payments
+pub struct FundingCollector;
+
+impl FundingCollector {
+    pub fn spawn(pool: DbPool) {
+        let interval_secs = AppConfig::global().funding_collector_interval_secs;
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(std::time::Duration::from_secs(interval_secs));
+            let mut last_settled: Option<DateTime<Utc>> = None;
+
+            loop {
+                ticker.tick().await;
+
+                match FundingService::record_rates(&pool).await {
+                    Ok(count) => log::debug!("Recorded {} funding rates", count),
+                    Err(e) => log::error!("Failed to record funding rates: {}", e),
+                }
+
+                // Only settle shortly after a funding boundary so positions are close
+                // to what was actually held at settlement time
+                let now = Utc::now();
+                let funding_time = funding_time_floor(now);
+                let within_window = now - funding_time <= Duration::seconds(2 * interval_secs as i64);
+                if last_settled == Some(funding_time) || !within_window {
+                    continue;
+                }
+
+                match FundingService::settle_payments(&pool, funding_time).await {
+                    Ok(count) => {
+                        log::info!("Settled {} funding payments for {}", count, funding_time);
+                        last_settled = Some(funding_time);
+                    }
+                    Err(e) => log::error!("Failed to settle funding payments: {}", e),
+                }
+            }
+        });
+    }
+}
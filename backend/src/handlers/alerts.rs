@@ -0,0 +1,128 @@
+use crate::alerts::{AlertRuleRequest, AlertRuleUpdate, AlertService};
+use crate::middleware::AuthenticatedUser;
+use crate::utils::{ApiResponse, AppError, PageQuery, PaginatedResponse};
+use crate::DbPool;
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+use uuid::Uuid;
+use validator::Validate;
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateAlertRequest {
+    pub alert_type: String, // "price", "funding_rate", "pnl", "margin_ratio" or "liquidation_distance"
+    pub symbol: Option<String>,
+    pub condition: String, // "above" or "below"
+    pub threshold: f64,
+    pub repeating: Option<bool>,
+    #[validate(range(min = 0, max = 10080))]
+    pub cooldown_minutes: Option<i32>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct UpdateAlertRequest {
+    pub condition: Option<String>,
+    pub threshold: Option<f64>,
+    pub repeating: Option<bool>,
+    #[validate(range(min = 0, max = 10080))]
+    pub cooldown_minutes: Option<i32>,
+    pub is_active: Option<bool>,
+}
+
+// List the current user's alert rules
+#[actix_web::get("")]
+pub async fn get_alerts(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, AppError> {
+    let rules = AlertService::get_rules(&pool, user.id).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(rules)))
+}
+
+#[actix_web::post("")]
+pub async fn create_alert(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    request: web::Json<CreateAlertRequest>,
+) -> Result<HttpResponse, AppError> {
+    let request = request.into_inner();
+    request
+        .validate()
+        .map_err(|e| AppError::ValidationError(e.to_string()))?;
+
+    let rule = AlertService::create_rule(
+        &pool,
+        user.id,
+        AlertRuleRequest {
+            alert_type: request.alert_type,
+            symbol: request.symbol,
+            condition: request.condition,
+            threshold: request.threshold,
+            repeating: request.repeating.unwrap_or(false),
+            cooldown_minutes: request.cooldown_minutes.unwrap_or(60),
+        },
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(rule)))
+}
+
+// History of fired alerts, newest first
+#[actix_web::get("/history")]
+pub async fn get_alert_history(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    page: web::Query<PageQuery>,
+) -> Result<HttpResponse, AppError> {
+    let (entries, total) =
+        AlertService::get_history(&pool, user.id, page.per_page(), page.offset()).await?;
+
+    Ok(HttpResponse::Ok().json(PaginatedResponse::new(
+        entries,
+        page.page(),
+        page.per_page(),
+        total,
+    )))
+}
+
+#[actix_web::put("/{alert_id}")]
+pub async fn update_alert(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    alert_id: web::Path<Uuid>,
+    request: web::Json<UpdateAlertRequest>,
+) -> Result<HttpResponse, AppError> {
+    let request = request.into_inner();
+    request
+        .validate()
+        .map_err(|e| AppError::ValidationError(e.to_string()))?;
+
+    let rule = AlertService::update_rule(
+        &pool,
+        user.id,
+        alert_id.into_inner(),
+        AlertRuleUpdate {
+            condition: request.condition,
+            threshold: request.threshold,
+            repeating: request.repeating,
+            cooldown_minutes: request.cooldown_minutes,
+            is_active: request.is_active,
+        },
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(rule)))
+}
+
+#[actix_web::delete("/{alert_id}")]
+pub async fn delete_alert(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    alert_id: web::Path<Uuid>,
+) -> Result<HttpResponse, AppError> {
+    AlertService::delete_rule(&pool, user.id, alert_id.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(
+        serde_json::json!({ "message": "Alert deleted" })
+    )))
+}
@@ -1,7 +1,11 @@
 use crate::auth::{
     AuthService, LoginRequest as AuthLoginRequest, RegisterRequest as AuthRegisterRequest,
 };
+use crate::config::AppConfig;
 use crate::email::{EmailService, PasswordResetEmail};
+use crate::middleware::AuthenticatedUser;
+use crate::rate_limit::client_ip;
+use crate::referral_abuse::SignupContext;
 use crate::models::PasswordResetToken;
 use crate::schema::{password_reset_tokens, users};
 use crate::utils::{ApiResponse, AppError};
@@ -25,6 +29,8 @@ pub struct RegisterRequest {
     #[validate(length(min = 6))]
     pub password: String,
     pub referral_code: Option<String>,
+    // Client-generated identifier of the browser or device, for abuse checks
+    pub device_id: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Validate)]
@@ -60,6 +66,8 @@ pub struct ResetPasswordRequest {
 #[actix_web::post("/register")]
 pub async fn register(
     pool: web::Data<DbPool>,
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
     request: web::Json<RegisterRequest>,
 ) -> Result<HttpResponse, AppError> {
     // Validate request
@@ -73,6 +81,7 @@ pub async fn register(
         username: request.username.clone(),
         password: request.password.clone(),
         referral_code: request.referral_code.clone(),
+        signup: signup_context(&req, request.device_id.clone(), config.rate_limit_trust_proxy),
     };
 
     // Register user using auth service
@@ -146,28 +155,25 @@ pub async fn check_username(
     Ok(HttpResponse::Ok().json(ApiResponse::success(response)))
 }
 
-pub async fn me(pool: web::Data<DbPool>, req: HttpRequest) -> Result<HttpResponse, AppError> {
-    // Extract user ID from token
-    let auth_header = req
-        .headers()
-        .get("Authorization")
-        .and_then(|h| h.to_str().ok())
-        .ok_or_else(|| AppError::AuthenticationError("Missing authorization header".to_string()))?;
-
-    let token = auth_header.strip_prefix("Bearer ").ok_or_else(|| {
-        AppError::AuthenticationError("Invalid authorization header format".to_string())
-    })?;
-
-    let claims = AuthService::verify_access_token(token)?;
-    let user_profile = AuthService::get_user_profile(&pool, claims.sub.parse().unwrap()).await?;
+pub async fn me(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, AppError> {
+    let user_profile = AuthService::get_user_profile(&pool, user.id).await?;
 
     Ok(HttpResponse::Ok().json(ApiResponse::success(user_profile)))
 }
 
+// The signed-in user as the access token describes it: roles, scopes and session
+pub async fn session(user: AuthenticatedUser) -> Result<HttpResponse, AppError> {
+    Ok(HttpResponse::Ok().json(ApiResponse::success(user)))
+}
+
 // Forgot Password - Send reset email
 #[actix_web::post("/forgot-password")]
 pub async fn forgot_password(
     pool: web::Data<DbPool>,
+    config: web::Data<AppConfig>,
     request: web::Json<ForgotPasswordRequest>,
 ) -> Result<HttpResponse, AppError> {
     // Validate request
@@ -240,7 +246,7 @@ pub async fn forgot_password(
         })?;
 
     // Send reset email
-    let email_service = EmailService::new().map_err(|_| {
+    let email_service = EmailService::new(&config).map_err(|_| {
         AppError::InternalServerError("Email service initialization failed".to_string())
     })?;
 
@@ -248,10 +254,6 @@ pub async fn forgot_password(
         to: user.email.clone(),
         username: user.username.clone(),
         reset_token: reset_token.clone(),
-        reset_url: format!(
-            "https://aptora-kana.netlify.app/reset-password?token={}",
-            reset_token
-        ),
     };
 
     if let Err(e) = email_service.send_password_reset_email(email_data).await {
@@ -338,3 +340,49 @@ pub async fn reset_password(
         "Password has been reset successfully. Please log in with your new password.".to_string(),
     )))
 }
+
+// Where a registration came from. The device id can also be sent as X-Device-Id.
+// Forwarded addresses are only used behind a trusted proxy, since clients can
+// set them to anything.
+fn signup_context(req: &HttpRequest, device_id: Option<String>, trust_proxy: bool) -> SignupContext {
+    let header = |name: &str| {
+        req.headers()
+            .get(name)
+            .and_then(|h| h.to_str().ok())
+            .map(|s| s.to_string())
+    };
+
+    SignupContext {
+        ip_address: client_ip(req, trust_proxy),
+        device_id: device_id.or_else(|| header("X-Device-Id")),
+        user_agent: header("User-Agent"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn request() -> HttpRequest {
+        TestRequest::default()
+            .peer_addr("203.0.113.7:51000".parse().unwrap())
+            .insert_header(("X-Forwarded-For", "198.51.100.1"))
+            .insert_header(("X-Device-Id", "device-1"))
+            .to_http_request()
+    }
+
+    #[test]
+    fn test_signup_ip_ignores_forwarded_headers_by_default() {
+        let context = signup_context(&request(), None, false);
+        assert_eq!(context.ip_address.as_deref(), Some("203.0.113.7"));
+        assert_eq!(context.device_id.as_deref(), Some("device-1"));
+    }
+
+    #[test]
+    fn test_signup_ip_behind_a_trusted_proxy() {
+        let context = signup_context(&request(), Some("body-device".to_string()), true);
+        assert_eq!(context.ip_address.as_deref(), Some("198.51.100.1"));
+        assert_eq!(context.device_id.as_deref(), Some("body-device"));
+    }
+}
@@ -0,0 +1,139 @@
+use crate::copy_trading::{CopySubscriptionRequest, CopyTradingService};
+use crate::middleware::AuthenticatedUser;
+use crate::utils::{ApiResponse, AppError, PageQuery, PaginatedResponse};
+use crate::DbPool;
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+use uuid::Uuid;
+use validator::Validate;
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct SubscribeRequest {
+    #[validate(length(min = 1))]
+    pub leader_username: String,
+    #[validate(range(min = 1.0))]
+    pub allocation: f64,
+    #[validate(range(min = 1.0))]
+    pub max_leverage: f64,
+    #[validate(range(min = 1.0))]
+    pub max_trade_notional: f64,
+    pub mode: Option<String>, // "paper" (default) or "live"
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CopyTradeResultRequest {
+    pub status: String, // "executed", "failed" or "dismissed"
+    #[validate(length(max = 500))]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CopyTradesQuery {
+    pub subscription_id: Option<Uuid>,
+}
+
+// Start copying a trader, or update an existing subscription
+#[actix_web::post("/subscriptions")]
+pub async fn subscribe(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    request: web::Json<SubscribeRequest>,
+) -> Result<HttpResponse, AppError> {
+    let request = request.into_inner();
+    request
+        .validate()
+        .map_err(|e| AppError::ValidationError(e.to_string()))?;
+
+    let subscription = CopyTradingService::subscribe(
+        &pool,
+        user.id,
+        CopySubscriptionRequest {
+            leader_username: request.leader_username,
+            allocation: request.allocation,
+            max_leverage: request.max_leverage,
+            max_trade_notional: request.max_trade_notional,
+            mode: request.mode.unwrap_or_else(|| "paper".to_string()),
+        },
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(subscription)))
+}
+
+// List the current user's copy-trading subscriptions
+#[actix_web::get("/subscriptions")]
+pub async fn get_subscriptions(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, AppError> {
+    let subscriptions = CopyTradingService::get_subscriptions(&pool, user.id).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(subscriptions)))
+}
+
+// Stop copying a trader
+#[actix_web::delete("/subscriptions/{subscription_id}")]
+pub async fn stop_copying(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    subscription_id: web::Path<Uuid>,
+) -> Result<HttpResponse, AppError> {
+    let subscription =
+        CopyTradingService::stop_copying(&pool, user.id, subscription_id.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success_with_message(
+        subscription,
+        "Stopped copying".to_string(),
+    )))
+}
+
+// Log of copied trades, each linked to the leader's source trade
+#[actix_web::get("/trades")]
+pub async fn get_copy_trades(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    query: web::Query<CopyTradesQuery>,
+    page: web::Query<PageQuery>,
+) -> Result<HttpResponse, AppError> {
+    let (trades, total) = CopyTradingService::get_copy_trades(
+        &pool,
+        user.id,
+        query.subscription_id,
+        page.per_page(),
+        page.offset(),
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(PaginatedResponse::new(
+        trades,
+        page.page(),
+        page.per_page(),
+        total,
+    )))
+}
+
+// Report what became of a pending live copy once the follower's wallet has
+// handled it
+#[actix_web::post("/trades/{trade_id}/result")]
+pub async fn report_copy_trade_result(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    trade_id: web::Path<Uuid>,
+    request: web::Json<CopyTradeResultRequest>,
+) -> Result<HttpResponse, AppError> {
+    let request = request.into_inner();
+    request
+        .validate()
+        .map_err(|e| AppError::ValidationError(e.to_string()))?;
+
+    let trade = CopyTradingService::report_result(
+        &pool,
+        user.id,
+        trade_id.into_inner(),
+        &request.status,
+        request.reason,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(trade)))
+}
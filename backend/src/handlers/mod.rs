@@ -1,6 +1,13 @@
+pub mod alerts;
 pub mod auth;
+pub mod copy_trading;
 pub mod health;
+pub mod notifications;
+pub mod posts;
+pub mod referrals;
+pub mod relationships;
 pub mod trading;
 pub mod user;
 pub mod social;
 pub mod wallet;
+pub mod well_known;
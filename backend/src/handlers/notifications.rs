@@ -0,0 +1,128 @@
+use crate::middleware::AuthenticatedUser;
+use crate::notifications::{NotificationHub, NotificationService};
+use crate::utils::{ApiResponse, AppError, PageQuery, PaginatedResponse};
+use crate::DbPool;
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_ws::Message;
+use serde::Deserialize;
+use uuid::Uuid;
+
+#[derive(Debug, Deserialize)]
+pub struct NotificationsQuery {
+    pub unread_only: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MarkReadRequest {
+    // Omit to mark everything read
+    pub ids: Option<Vec<Uuid>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChannelQuery {
+    // Browsers cannot set headers on WebSocket requests
+    pub token: Option<String>,
+}
+
+// List the current user's notifications, newest first
+#[actix_web::get("")]
+pub async fn get_notifications(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    query: web::Query<NotificationsQuery>,
+    page: web::Query<PageQuery>,
+) -> Result<HttpResponse, AppError> {
+    let (notifications, total) = NotificationService::get_notifications(
+        &pool,
+        user.id,
+        query.unread_only.unwrap_or(false),
+        page.per_page(),
+        page.offset(),
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(PaginatedResponse::new(
+        notifications,
+        page.page(),
+        page.per_page(),
+        total,
+    )))
+}
+
+#[actix_web::post("/read")]
+pub async fn mark_read(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    request: web::Json<MarkReadRequest>,
+) -> Result<HttpResponse, AppError> {
+    let unread = NotificationService::mark_read(&pool, user.id, request.into_inner().ids).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(
+        serde_json::json!({ "unread_count": unread })
+    )))
+}
+
+#[actix_web::get("/unread-count")]
+pub async fn get_unread_count(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, AppError> {
+    let unread = NotificationService::unread_count(&pool, user.id).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(
+        serde_json::json!({ "unread_count": unread })
+    )))
+}
+
+// Private WebSocket channel. Pushes the unread count on connect, then every new
+// notification as it is created.
+#[actix_web::get("/private")]
+pub async fn private_channel(
+    pool: web::Data<DbPool>,
+    req: HttpRequest,
+    query: web::Query<ChannelQuery>,
+    body: web::Payload,
+) -> Result<HttpResponse, AppError> {
+    // Browsers cannot set headers on WebSocket requests, so the token may come
+    // in the query string instead
+    let user = match query.token.as_deref() {
+        Some(token) => AuthenticatedUser::from_token(token)?,
+        None => AuthenticatedUser::from_headers(req.headers())?,
+    };
+    let user_id = user.ensure_session(&pool).await?.id;
+    let unread = NotificationService::unread_count(&pool, user_id).await?;
+
+    let (response, mut session, mut stream) = actix_ws::handle(&req, body)
+        .map_err(|e| AppError::BadRequest(format!("WebSocket handshake failed: {}", e)))?;
+
+    let (sender, mut outgoing) = tokio::sync::mpsc::unbounded_channel::<String>();
+    let connection_id = NotificationHub::global().connect(user_id, sender);
+
+    actix_web::rt::spawn(async move {
+        let greeting = serde_json::json!({
+            "channel": "notifications",
+            "type": "unread_count",
+            "data": { "count": unread },
+        });
+        let mut open = session.text(greeting.to_string()).await.is_ok();
+
+        while open {
+            tokio::select! {
+                Some(message) = outgoing.recv() => {
+                    open = session.text(message).await.is_ok();
+                }
+                incoming = stream.recv() => match incoming {
+                    Some(Ok(Message::Ping(bytes))) => open = session.pong(&bytes).await.is_ok(),
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => open = false,
+                    // The channel is push-only
+                    Some(Ok(_)) => {}
+                },
+            }
+        }
+
+        NotificationHub::global().disconnect(user_id, connection_id);
+        let _ = session.close(None).await;
+    });
+
+    Ok(response)
+}
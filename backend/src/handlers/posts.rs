@@ -0,0 +1,271 @@
+use crate::middleware::AuthenticatedUser;
+use crate::posts::{CreatePostRequest, PostService};
+use crate::utils::{ApiResponse, AppError, PageQuery, PaginatedResponse};
+use crate::DbPool;
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+use uuid::Uuid;
+use validator::Validate;
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct NewPostRequest {
+    #[validate(length(min = 1, max = 5000))]
+    pub content: String,
+    pub symbol: Option<String>,
+    pub attach_position: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct EditPostRequest {
+    #[validate(length(min = 1, max = 5000))]
+    pub content: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct NewCommentRequest {
+    #[validate(length(min = 1, max = 1000))]
+    pub content: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ModerationRequest {
+    pub hidden: bool,
+    pub reason: Option<String>,
+}
+
+// Share a trade idea, optionally attaching a market and the current position in it
+#[actix_web::post("/posts")]
+pub async fn create_post(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    request: web::Json<NewPostRequest>,
+) -> Result<HttpResponse, AppError> {
+    let request = request.into_inner();
+    request
+        .validate()
+        .map_err(|e| AppError::ValidationError(e.to_string()))?;
+
+    let post = PostService::create_post(
+        &pool,
+        user.id,
+        CreatePostRequest {
+            content: request.content,
+            symbol: request.symbol,
+            attach_position: request.attach_position.unwrap_or(false),
+        },
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(post)))
+}
+
+// List a user's posts, newest first
+#[actix_web::get("/users/{username}/posts")]
+pub async fn get_user_posts(
+    pool: web::Data<DbPool>,
+    viewer: Option<AuthenticatedUser>,
+    username: web::Path<String>,
+    page: web::Query<PageQuery>,
+) -> Result<HttpResponse, AppError> {
+    // Authentication is optional; it adds `liked_by_me`
+    let viewer_id = viewer.map(|viewer| viewer.id);
+
+    let (posts, total) = PostService::get_user_posts(
+        &pool,
+        &username,
+        viewer_id,
+        page.per_page(),
+        page.offset(),
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(PaginatedResponse::new(
+        posts,
+        page.page(),
+        page.per_page(),
+        total,
+    )))
+}
+
+#[actix_web::get("/posts/{post_id}")]
+pub async fn get_post(
+    pool: web::Data<DbPool>,
+    viewer: Option<AuthenticatedUser>,
+    post_id: web::Path<Uuid>,
+) -> Result<HttpResponse, AppError> {
+    let viewer_id = viewer.map(|viewer| viewer.id);
+    let post = PostService::get_post(&pool, post_id.into_inner(), viewer_id).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(post)))
+}
+
+#[actix_web::put("/posts/{post_id}")]
+pub async fn update_post(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    post_id: web::Path<Uuid>,
+    request: web::Json<EditPostRequest>,
+) -> Result<HttpResponse, AppError> {
+    let request = request.into_inner();
+    request
+        .validate()
+        .map_err(|e| AppError::ValidationError(e.to_string()))?;
+
+    let post = PostService::update_post(&pool, user.id, post_id.into_inner(), request.content).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(post)))
+}
+
+#[actix_web::delete("/posts/{post_id}")]
+pub async fn delete_post(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    post_id: web::Path<Uuid>,
+) -> Result<HttpResponse, AppError> {
+    PostService::delete_post(&pool, user.id, post_id.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(
+        serde_json::json!({ "message": "Post deleted" })
+    )))
+}
+
+#[actix_web::post("/posts/{post_id}/like")]
+pub async fn like_post(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    post_id: web::Path<Uuid>,
+) -> Result<HttpResponse, AppError> {
+    let post = PostService::like_post(&pool, user.id, post_id.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(post)))
+}
+
+#[actix_web::delete("/posts/{post_id}/like")]
+pub async fn unlike_post(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    post_id: web::Path<Uuid>,
+) -> Result<HttpResponse, AppError> {
+    let post = PostService::unlike_post(&pool, user.id, post_id.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(post)))
+}
+
+#[actix_web::get("/posts/{post_id}/comments")]
+pub async fn get_comments(
+    pool: web::Data<DbPool>,
+    viewer: Option<AuthenticatedUser>,
+    post_id: web::Path<Uuid>,
+    page: web::Query<PageQuery>,
+) -> Result<HttpResponse, AppError> {
+    let viewer_id = viewer.map(|viewer| viewer.id);
+    let (comments, total) = PostService::get_comments(
+        &pool,
+        post_id.into_inner(),
+        viewer_id,
+        page.per_page(),
+        page.offset(),
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(PaginatedResponse::new(
+        comments,
+        page.page(),
+        page.per_page(),
+        total,
+    )))
+}
+
+#[actix_web::post("/posts/{post_id}/comments")]
+pub async fn add_comment(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    post_id: web::Path<Uuid>,
+    request: web::Json<NewCommentRequest>,
+) -> Result<HttpResponse, AppError> {
+    let request = request.into_inner();
+    request
+        .validate()
+        .map_err(|e| AppError::ValidationError(e.to_string()))?;
+
+    let comment = PostService::add_comment(&pool, user.id, post_id.into_inner(), request.content).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(comment)))
+}
+
+#[actix_web::delete("/comments/{comment_id}")]
+pub async fn delete_comment(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    comment_id: web::Path<Uuid>,
+) -> Result<HttpResponse, AppError> {
+    PostService::delete_comment(&pool, user.id, comment_id.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(
+        serde_json::json!({ "message": "Comment deleted" })
+    )))
+}
+
+// Admin only: hide or restore a post
+#[actix_web::put("/admin/posts/{post_id}/moderation")]
+pub async fn moderate_post(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    post_id: web::Path<Uuid>,
+    request: web::Json<ModerationRequest>,
+) -> Result<HttpResponse, AppError> {
+    user.require_admin()?;
+
+    let request = request.into_inner();
+    let post = PostService::moderate_post(
+        &pool,
+        user.id,
+        post_id.into_inner(),
+        request.hidden,
+        request.reason,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(post)))
+}
+
+// Admin only: hide or restore a comment
+#[actix_web::put("/admin/comments/{comment_id}/moderation")]
+pub async fn moderate_comment(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    comment_id: web::Path<Uuid>,
+    request: web::Json<ModerationRequest>,
+) -> Result<HttpResponse, AppError> {
+    user.require_admin()?;
+
+    let request = request.into_inner();
+    let comment = PostService::moderate_comment(
+        &pool,
+        user.id,
+        comment_id.into_inner(),
+        request.hidden,
+        request.reason,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(comment)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_post_and_comment_length_limits() {
+        let post = |content: String| NewPostRequest { content, symbol: None, attach_position: None };
+        assert!(post("gm".to_string()).validate().is_ok());
+        assert!(post(String::new()).validate().is_err());
+        assert!(post("a".repeat(5000)).validate().is_ok());
+        assert!(post("a".repeat(5001)).validate().is_err());
+
+        assert!(NewCommentRequest { content: "a".repeat(1000) }.validate().is_ok());
+        assert!(NewCommentRequest { content: "a".repeat(1001) }.validate().is_err());
+        assert!(EditPostRequest { content: String::new() }.validate().is_err());
+    }
+}
@@ -0,0 +1,256 @@
+use crate::config::AppConfig;
+use crate::middleware::AuthenticatedUser;
+use crate::rate_limit::client_ip;
+use crate::referral_abuse::ReferralAbuseService;
+use crate::referral_codes::{CreateReferralCodeRequest, ReferralCodeService, ReferralCodeUpdate};
+use crate::referrals::ReferralRewardService;
+use crate::social::{SocialService, MAX_REFERRAL_TREE_DEPTH};
+use crate::utils::{ApiResponse, AppError, PageQuery, PaginatedResponse};
+use crate::DbPool;
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use uuid::Uuid;
+
+#[derive(Debug, Deserialize)]
+pub struct ClickRequest {
+    pub source: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TreeQuery {
+    pub max_depth: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FlagsQuery {
+    pub status: Option<String>, // "open", "dismissed" or "confirmed"
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FlagReviewRequest {
+    pub status: String, // "dismissed" or "confirmed"
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RewardsQuery {
+    pub status: Option<String>, // "pending", "paid" or "cancelled"
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RewardStatusRequest {
+    pub status: String, // "paid" or "cancelled"
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DepositRequest {
+    pub user_id: Uuid,
+    pub amount: f64,
+    // Transaction hash or another unique reference for the deposit
+    pub reference: String,
+}
+
+// List the current user's referral codes with clicks, signups and earnings
+#[actix_web::get("/referral-codes")]
+pub async fn get_referral_codes(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, AppError> {
+    let codes = ReferralCodeService::get_codes(&pool, user.id).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(codes)))
+}
+
+// Create a vanity or generated code, optionally tagged with a campaign
+#[actix_web::post("/referral-codes")]
+pub async fn create_referral_code(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    request: web::Json<CreateReferralCodeRequest>,
+) -> Result<HttpResponse, AppError> {
+    let code = ReferralCodeService::create_code(&pool, user.id, request.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(code)))
+}
+
+#[actix_web::put("/referral-codes/{code_id}")]
+pub async fn update_referral_code(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    code_id: web::Path<Uuid>,
+    request: web::Json<ReferralCodeUpdate>,
+) -> Result<HttpResponse, AppError> {
+    let code = ReferralCodeService::update_code(&pool, user.id, code_id.into_inner(), request.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(code)))
+}
+
+// Record a visit to a referral link; no authentication needed
+#[actix_web::post("/referral-codes/{code}/click")]
+pub async fn record_referral_click(
+    pool: web::Data<DbPool>,
+    config: web::Data<AppConfig>,
+    req: HttpRequest,
+    code: web::Path<String>,
+    request: Option<web::Json<ClickRequest>>,
+) -> Result<HttpResponse, AppError> {
+    let source = request.and_then(|r| r.into_inner().source);
+    let ip_address = client_ip(&req, config.rate_limit_trust_proxy);
+    let lookup = ReferralCodeService::record_click(&pool, &code, source, ip_address.as_deref()).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(lookup)))
+}
+
+// List the rewards the current user earned from referrals, newest first
+#[actix_web::get("/referral-rewards")]
+pub async fn get_referral_rewards(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    query: web::Query<RewardsQuery>,
+    page: web::Query<PageQuery>,
+) -> Result<HttpResponse, AppError> {
+    let (rewards, total) = ReferralRewardService::get_rewards(
+        &pool,
+        user.id,
+        query.status.as_deref(),
+        page.per_page(),
+        page.offset(),
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(PaginatedResponse::new(
+        rewards,
+        page.page(),
+        page.per_page(),
+        total,
+    )))
+}
+
+// Admin only: mark a pending reward paid or cancel it
+#[actix_web::put("/admin/referral-rewards/{reward_id}")]
+pub async fn update_reward_status(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    reward_id: web::Path<Uuid>,
+    request: web::Json<RewardStatusRequest>,
+) -> Result<HttpResponse, AppError> {
+    user.require_admin()?;
+
+    let request = request.into_inner();
+    let reward = ReferralRewardService::update_status(
+        &pool,
+        user.id,
+        reward_id.into_inner(),
+        &request.status,
+        request.reason,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(reward)))
+}
+
+// Admin only: record a confirmed deposit of a referred user, granting the
+// referrer's deposit bonus
+#[actix_web::post("/admin/referral-deposits")]
+pub async fn record_deposit(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    request: web::Json<DepositRequest>,
+) -> Result<HttpResponse, AppError> {
+    user.require_admin()?;
+
+    let request = request.into_inner();
+    let reward = ReferralRewardService::reward_deposit(
+        &pool,
+        user.id,
+        request.user_id,
+        request.amount,
+        &request.reference,
+    )
+    .await?;
+
+    // `reward` is null when the deposit earns nothing or was already recorded
+    Ok(HttpResponse::Ok().json(ApiResponse::success(
+        serde_json::json!({ "reward": reward })
+    )))
+}
+
+// The current user's downline: counts per level and the referred users
+#[actix_web::get("/referral-tree")]
+pub async fn get_referral_tree(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    query: web::Query<TreeQuery>,
+) -> Result<HttpResponse, AppError> {
+    let max_depth = query.max_depth.unwrap_or(MAX_REFERRAL_TREE_DEPTH);
+    let tree = SocialService::get_referral_tree(&pool, user.id, max_depth).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(tree)))
+}
+
+// Admin only: the referral tree of any user
+#[actix_web::get("/admin/referral-tree/{username}")]
+pub async fn get_user_referral_tree(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    username: web::Path<String>,
+    query: web::Query<TreeQuery>,
+) -> Result<HttpResponse, AppError> {
+    user.require_admin()?;
+
+    let max_depth = query.max_depth.unwrap_or(MAX_REFERRAL_TREE_DEPTH);
+    let tree = SocialService::get_user_referral_tree(&pool, user.id, &username, max_depth).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(tree)))
+}
+
+// Admin only: referrals flagged by the abuse checks, newest first
+#[actix_web::get("/admin/referral-flags")]
+pub async fn get_referral_flags(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    query: web::Query<FlagsQuery>,
+    page: web::Query<PageQuery>,
+) -> Result<HttpResponse, AppError> {
+    user.require_admin()?;
+
+    let (flags, total) = ReferralAbuseService::get_flags(
+        &pool,
+        user.id,
+        query.status.as_deref(),
+        page.per_page(),
+        page.offset(),
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(PaginatedResponse::new(
+        flags,
+        page.page(),
+        page.per_page(),
+        total,
+    )))
+}
+
+// Admin only: confirm or dismiss a flag
+#[actix_web::put("/admin/referral-flags/{flag_id}")]
+pub async fn review_referral_flag(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    flag_id: web::Path<Uuid>,
+    request: web::Json<FlagReviewRequest>,
+) -> Result<HttpResponse, AppError> {
+    user.require_admin()?;
+
+    let request = request.into_inner();
+    let flag = ReferralAbuseService::review_flag(
+        &pool,
+        user.id,
+        flag_id.into_inner(),
+        &request.status,
+        request.note,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(flag)))
+}
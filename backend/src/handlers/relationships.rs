@@ -0,0 +1,163 @@
+use crate::middleware::AuthenticatedUser;
+use crate::relationships::RelationshipService;
+use crate::utils::{ApiResponse, AppError, PageQuery, PaginatedResponse};
+use crate::DbPool;
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+use uuid::Uuid;
+
+#[derive(Debug, Deserialize)]
+pub struct UsernameRequest {
+    pub username: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AccountPrivacyRequest {
+    pub is_private: bool,
+}
+
+// Block a user; follows between the two users are removed
+#[actix_web::post("/block")]
+pub async fn block_user(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    request: web::Json<UsernameRequest>,
+) -> Result<HttpResponse, AppError> {
+    RelationshipService::block_user(&pool, user.id, &request.username).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(
+        serde_json::json!({
+            "message": format!("Blocked @{}", request.username)
+        })
+    )))
+}
+
+#[actix_web::delete("/block/{username}")]
+pub async fn unblock_user(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    username: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    RelationshipService::unblock_user(&pool, user.id, &username).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(
+        serde_json::json!({
+            "message": format!("Unblocked @{}", username)
+        })
+    )))
+}
+
+#[actix_web::get("/blocks")]
+pub async fn get_blocks(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, AppError> {
+    let blocks = RelationshipService::get_blocks(&pool, user.id).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(blocks)))
+}
+
+// Mute a user; their activity no longer shows in the feed
+#[actix_web::post("/mute")]
+pub async fn mute_user(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    request: web::Json<UsernameRequest>,
+) -> Result<HttpResponse, AppError> {
+    RelationshipService::mute_user(&pool, user.id, &request.username).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(
+        serde_json::json!({
+            "message": format!("Muted @{}", request.username)
+        })
+    )))
+}
+
+#[actix_web::delete("/mute/{username}")]
+pub async fn unmute_user(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    username: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    RelationshipService::unmute_user(&pool, user.id, &username).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(
+        serde_json::json!({
+            "message": format!("Unmuted @{}", username)
+        })
+    )))
+}
+
+#[actix_web::get("/mutes")]
+pub async fn get_mutes(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, AppError> {
+    let mutes = RelationshipService::get_mutes(&pool, user.id).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(mutes)))
+}
+
+// Make the account private or public; going public approves pending requests
+#[actix_web::put("/account-privacy")]
+pub async fn set_account_privacy(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    request: web::Json<AccountPrivacyRequest>,
+) -> Result<HttpResponse, AppError> {
+    RelationshipService::set_private(&pool, user.id, request.is_private).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(
+        serde_json::json!({
+            "is_private": request.is_private
+        })
+    )))
+}
+
+// Pending requests to follow the current user
+#[actix_web::get("/follow-requests")]
+pub async fn get_follow_requests(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    page: web::Query<PageQuery>,
+) -> Result<HttpResponse, AppError> {
+    let (requests, total) =
+        RelationshipService::get_follow_requests(&pool, user.id, page.per_page(), page.offset()).await?;
+
+    Ok(HttpResponse::Ok().json(PaginatedResponse::new(
+        requests,
+        page.page(),
+        page.per_page(),
+        total,
+    )))
+}
+
+#[actix_web::post("/follow-requests/{request_id}/approve")]
+pub async fn approve_follow_request(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    request_id: web::Path<Uuid>,
+) -> Result<HttpResponse, AppError> {
+    RelationshipService::approve_follow_request(&pool, user.id, *request_id).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(
+        serde_json::json!({
+            "message": "Follow request approved"
+        })
+    )))
+}
+
+#[actix_web::post("/follow-requests/{request_id}/reject")]
+pub async fn reject_follow_request(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    request_id: web::Path<Uuid>,
+) -> Result<HttpResponse, AppError> {
+    RelationshipService::reject_follow_request(&pool, user.id, *request_id).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(
+        serde_json::json!({
+            "message": "Follow request rejected"
+        })
+    )))
+}
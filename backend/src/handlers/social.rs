@@ -1,10 +1,19 @@
 use actix_web::{web, HttpResponse, HttpRequest};
 use crate::DbPool;
+use crate::activity::{ActivityService, MAX_FEED_LIMIT};
+use crate::discovery::{DiscoveryService, MAX_SEARCH_LIMIT};
+use crate::models::{PublicProfileResponse, ReferralEarnings};
+use crate::posts::PostService;
+use crate::profiles::{ProfileService, ProfileUpdate};
+use crate::referrals::{ReferralRewardService, ReferralTierInfo};
+use crate::relationships::RelationshipService;
+use crate::leaderboard::{LeaderboardMetric, LeaderboardPeriod, LeaderboardService};
+use crate::middleware::AuthenticatedUser;
 use crate::social::SocialService;
-use crate::utils::{AppError, ApiResponse};
+use crate::utils::{AppError, ApiResponse, PageQuery};
 use serde::{Deserialize, Serialize};
 use serde_json;
-use uuid::Uuid;
+use std::collections::BTreeMap;
 // use rust_decimal::Decimal;
 
 // Request/Response structs
@@ -14,9 +23,32 @@ pub struct FollowRequest {
 }
 
 #[derive(Debug, Deserialize)]
-pub struct UpdateProfileRequest {
-    pub bio: Option<String>,
-    pub avatar_url: Option<String>,
+pub struct TradingLeaderboardQuery {
+    pub period: Option<String>, // "24h", "7d", "30d" or "all"
+    pub metric: Option<String>, // "pnl", "roi", "volume" or "win_rate"
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LeaderboardOptOutRequest {
+    pub opt_out: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FeedQuery {
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    pub q: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SuggestedUsersQuery {
+    pub limit: Option<i64>,
 }
 
 #[derive(Debug, Serialize)]
@@ -24,24 +56,33 @@ pub struct ReferralInfoResponse {
     pub referral_code: String,
     pub referral_count: i32,
     pub total_rewards: Option<f64>,
+    pub pending_rewards: f64,
+    pub held_rewards: f64,
+    pub paid_rewards: f64,
+    pub tier: ReferralTierInfo,
+    // Earnings per referred user
+    pub referrals: Vec<ReferralEarnings>,
 }
 
 // Follow a user
 #[actix_web::post("/follow")]
 pub async fn follow_user(
     pool: web::Data<DbPool>,
-    req: HttpRequest,
+    user: AuthenticatedUser,
     request: web::Json<FollowRequest>,
 ) -> Result<HttpResponse, AppError> {
-    // Extract user ID from token
-    let user_id = extract_user_id_from_token(&req)?;
-    
-    // Follow the user
-    SocialService::follow_user(&pool, user_id, &request.username).await?;
+    // Follow the user; private accounts get a follow request instead
+    let status = SocialService::follow_user(&pool, user.id, &request.username).await?;
+    let message = if status == "requested" {
+        format!("Follow request sent to @{}", request.username)
+    } else {
+        format!("Successfully followed @{}", request.username)
+    };
     
     Ok(HttpResponse::Ok().json(ApiResponse::success(
         serde_json::json!({
-            "message": format!("Successfully followed @{}", request.username)
+            "status": status,
+            "message": message
         })
     )))
 }
@@ -50,14 +91,11 @@ pub async fn follow_user(
 #[actix_web::delete("/follow/{username}")]
 pub async fn unfollow_user(
     pool: web::Data<DbPool>,
-    req: HttpRequest,
+    user: AuthenticatedUser,
     username: web::Path<String>,
 ) -> Result<HttpResponse, AppError> {
-    // Extract user ID from token
-    let user_id = extract_user_id_from_token(&req)?;
-    
     // Unfollow the user
-    SocialService::unfollow_user(&pool, user_id, &username).await?;
+    SocialService::unfollow_user(&pool, user.id, &username).await?;
     
     Ok(HttpResponse::Ok().json(ApiResponse::success(
         serde_json::json!({
@@ -70,13 +108,16 @@ pub async fn unfollow_user(
 #[actix_web::get("/followers/{username}")]
 pub async fn get_followers(
     pool: web::Data<DbPool>,
+    viewer: Option<AuthenticatedUser>,
     username: web::Path<String>,
     query: web::Query<std::collections::HashMap<String, String>>,
 ) -> Result<HttpResponse, AppError> {
     let limit = query.get("limit").and_then(|s| s.parse::<i64>().ok()).unwrap_or(20);
     let offset = query.get("offset").and_then(|s| s.parse::<i64>().ok()).unwrap_or(0);
     
-    let followers = SocialService::get_followers(&pool, &username, limit, offset).await?;
+    // Authentication is optional; private accounts only list for their followers
+    let viewer_id = viewer.map(|viewer| viewer.id);
+    let followers = SocialService::get_followers(&pool, &username, viewer_id, limit, offset).await?;
     
     Ok(HttpResponse::Ok().json(ApiResponse::success(followers)))
 }
@@ -85,13 +126,15 @@ pub async fn get_followers(
 #[actix_web::get("/following/{username}")]
 pub async fn get_following(
     pool: web::Data<DbPool>,
+    viewer: Option<AuthenticatedUser>,
     username: web::Path<String>,
     query: web::Query<std::collections::HashMap<String, String>>,
 ) -> Result<HttpResponse, AppError> {
     let limit = query.get("limit").and_then(|s| s.parse::<i64>().ok()).unwrap_or(20);
     let offset = query.get("offset").and_then(|s| s.parse::<i64>().ok()).unwrap_or(0);
     
-    let following = SocialService::get_following(&pool, &username, limit, offset).await?;
+    let viewer_id = viewer.map(|viewer| viewer.id);
+    let following = SocialService::get_following(&pool, &username, viewer_id, limit, offset).await?;
     
     Ok(HttpResponse::Ok().json(ApiResponse::success(following)))
 }
@@ -100,9 +143,11 @@ pub async fn get_following(
 #[actix_web::get("/stats/{username}")]
 pub async fn get_follow_stats(
     pool: web::Data<DbPool>,
+    viewer: Option<AuthenticatedUser>,
     username: web::Path<String>,
 ) -> Result<HttpResponse, AppError> {
-    let stats = SocialService::get_follow_stats(&pool, &username).await?;
+    let viewer_id = viewer.map(|viewer| viewer.id);
+    let stats = SocialService::get_follow_stats(&pool, &username, viewer_id).await?;
     
     Ok(HttpResponse::Ok().json(ApiResponse::success(stats)))
 }
@@ -111,65 +156,164 @@ pub async fn get_follow_stats(
 #[actix_web::get("/profile/{username}")]
 pub async fn get_public_profile(
     pool: web::Data<DbPool>,
+    req: HttpRequest,
+    viewer: Option<AuthenticatedUser>,
     username: web::Path<String>,
 ) -> Result<HttpResponse, AppError> {
-    let profile = SocialService::get_public_profile(&pool, &username).await?;
+    let viewer_id = viewer.map(|viewer| viewer.id);
+    let profile = match SocialService::get_public_profile(&pool, &username, viewer_id).await {
+        Ok(profile) => profile,
+        // Links to a changed username redirect to the new one
+        Err(AppError::NotFoundError(message)) => {
+            return match ProfileService::renamed_username(&pool, &username).await? {
+                Some(renamed) => Ok(redirect_to_username(&req, &renamed)),
+                None => Err(AppError::NotFoundError(message)),
+            };
+        }
+        Err(e) => return Err(e),
+    };
+
+    // Latest posts; the full list is at /social/users/{username}/posts.
+    // Private accounts show their profile but not their posts to non-followers.
+    let recent_posts = if RelationshipService::can_view_content(&pool, profile.id, viewer_id).await? {
+        PostService::get_user_posts(&pool, &username, viewer_id, 5, 0).await?.0
+    } else {
+        Vec::new()
+    };
     
-    Ok(HttpResponse::Ok().json(ApiResponse::success(profile)))
+    Ok(HttpResponse::Ok().json(ApiResponse::success(PublicProfileResponse {
+        profile,
+        recent_posts,
+    })))
 }
 
-// Update user profile
+// Same as PATCH /user/profile; kept for older clients
 #[actix_web::put("/profile")]
 pub async fn update_profile(
     pool: web::Data<DbPool>,
-    req: HttpRequest,
-    request: web::Json<UpdateProfileRequest>,
+    user: AuthenticatedUser,
+    update: web::Json<ProfileUpdate>,
 ) -> Result<HttpResponse, AppError> {
-    // Extract user ID from token
-    let user_id = extract_user_id_from_token(&req)?;
-    
-    // Update profile
-    SocialService::update_profile(&pool, user_id, request.bio.clone(), request.avatar_url.clone()).await?;
-    
-    Ok(HttpResponse::Ok().json(ApiResponse::success(
-        serde_json::json!({
-            "message": "Profile updated successfully"
-        })
-    )))
+    let profile = ProfileService::update_profile(&pool, user.id, update.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(profile)))
 }
 
 // Get referral leaderboard
 #[actix_web::get("/referral-leaderboard")]
 pub async fn get_referral_leaderboard(
     pool: web::Data<DbPool>,
+    viewer: Option<AuthenticatedUser>,
     query: web::Query<std::collections::HashMap<String, String>>,
 ) -> Result<HttpResponse, AppError> {
     let limit = query.get("limit").and_then(|s| s.parse::<i64>().ok()).unwrap_or(50);
     
-    let leaderboard = SocialService::get_referral_leaderboard(&pool, limit).await?;
+    let viewer_id = viewer.map(|viewer| viewer.id);
+    let leaderboard = SocialService::get_referral_leaderboard(&pool, limit, viewer_id).await?;
     
     Ok(HttpResponse::Ok().json(ApiResponse::success(leaderboard)))
 }
 
+// Get trading leaderboard
+#[actix_web::get("/trading-leaderboard")]
+pub async fn get_trading_leaderboard(
+    pool: web::Data<DbPool>,
+    viewer: Option<AuthenticatedUser>,
+    query: web::Query<TradingLeaderboardQuery>,
+    page: web::Query<PageQuery>,
+) -> Result<HttpResponse, AppError> {
+    let period = LeaderboardPeriod::parse(query.period.as_deref())?;
+    let metric = LeaderboardMetric::parse(query.metric.as_deref())?;
+
+    // Authentication is optional; signed-in users also get their own rank
+    let viewer_id = viewer.map(|viewer| viewer.id);
+
+    let leaderboard = LeaderboardService::get_leaderboard(
+        &pool,
+        period,
+        metric,
+        page.page(),
+        page.per_page(),
+        viewer_id,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(leaderboard)))
+}
+
+// Hide or show the current user on trading leaderboards
+#[actix_web::put("/leaderboard-opt-out")]
+pub async fn set_leaderboard_opt_out(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    request: web::Json<LeaderboardOptOutRequest>,
+) -> Result<HttpResponse, AppError> {
+    LeaderboardService::set_opt_out(&pool, user.id, request.opt_out).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(
+        serde_json::json!({
+            "opt_out": request.opt_out
+        })
+    )))
+}
+
+// Get the activity feed of the users the current user follows
+#[actix_web::get("/feed")]
+pub async fn get_feed(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    query: web::Query<FeedQuery>,
+) -> Result<HttpResponse, AppError> {
+    let limit = query.limit.unwrap_or(20).clamp(1, MAX_FEED_LIMIT);
+    let feed = ActivityService::get_feed(&pool, user.id, query.cursor.as_deref(), limit).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(feed)))
+}
+
+// Get who can see each type of the current user's activities
+#[actix_web::get("/activity-privacy")]
+pub async fn get_activity_privacy(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, AppError> {
+    let settings = ActivityService::get_privacy_settings(&pool, user.id).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(settings)))
+}
+
+// Update activity visibility, e.g. {"position_closed": "private"}
+#[actix_web::put("/activity-privacy")]
+pub async fn update_activity_privacy(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    request: web::Json<BTreeMap<String, String>>,
+) -> Result<HttpResponse, AppError> {
+    let settings = ActivityService::set_privacy_settings(&pool, user.id, request.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(settings)))
+}
+
 // Get user's referral information
 #[actix_web::get("/referral-info")]
 pub async fn get_referral_info(
     pool: web::Data<DbPool>,
-    req: HttpRequest,
+    user: AuthenticatedUser,
 ) -> Result<HttpResponse, AppError> {
-    // Extract user ID from token
-    let user_id = extract_user_id_from_token(&req)?;
-    
     // Get referral code
-    let referral_code = SocialService::get_referral_code(&pool, user_id).await?;
+    let referral_code = SocialService::get_referral_code(&pool, user.id).await?;
     
-    // Get user profile to get referral stats
-    let profile = SocialService::get_public_profile(&pool, &referral_code).await?;
+    // Get reward totals and the per-referral breakdown
+    let summary = ReferralRewardService::get_summary(&pool, user.id).await?;
     
     let response = ReferralInfoResponse {
         referral_code,
-        referral_count: profile.referral_count.unwrap_or(0),
-        total_rewards: None, // PublicUserProfile doesn't expose total_referral_rewards for privacy
+        referral_count: summary.referral_count,
+        total_rewards: Some(summary.total_rewards),
+        pending_rewards: summary.pending_rewards,
+        held_rewards: summary.held_rewards,
+        paid_rewards: summary.paid_rewards,
+        tier: summary.tier,
+        referrals: summary.referrals,
     };
     
     Ok(HttpResponse::Ok().json(ApiResponse::success(response)))
@@ -179,14 +323,11 @@ pub async fn get_referral_info(
 #[actix_web::get("/is-following/{username}")]
 pub async fn check_following_status(
     pool: web::Data<DbPool>,
-    req: HttpRequest,
+    user: AuthenticatedUser,
     username: web::Path<String>,
 ) -> Result<HttpResponse, AppError> {
-    // Extract user ID from token
-    let user_id = extract_user_id_from_token(&req)?;
-    
     // Check if following
-    let is_following = SocialService::is_following(&pool, user_id, &username).await?;
+    let is_following = SocialService::is_following(&pool, user.id, &username).await?;
     
     Ok(HttpResponse::Ok().json(ApiResponse::success(
         serde_json::json!({
@@ -200,49 +341,78 @@ pub async fn check_following_status(
 #[actix_web::get("/referred-users")]
 pub async fn get_referred_users(
     pool: web::Data<DbPool>,
-    req: HttpRequest,
+    user: AuthenticatedUser,
     query: web::Query<std::collections::HashMap<String, String>>,
 ) -> Result<HttpResponse, AppError> {
-    // Extract user ID from token
-    let user_id = extract_user_id_from_token(&req)?;
-    
     let limit = query.get("limit").and_then(|s| s.parse::<i64>().ok()).unwrap_or(20);
     let offset = query.get("offset").and_then(|s| s.parse::<i64>().ok()).unwrap_or(0);
     
-    let referred_users = SocialService::get_referred_users(&pool, user_id, limit, offset).await?;
+    let referred_users = SocialService::get_referred_users(&pool, user.id, limit, offset).await?;
     
     Ok(HttpResponse::Ok().json(ApiResponse::success(referred_users)))
 }
 
-// Helper function to extract user ID from token
-fn extract_user_id_from_token(req: &HttpRequest) -> Result<Uuid, AppError> {
-    let auth_header = req
-        .headers()
-        .get("Authorization")
-        .and_then(|h| h.to_str().ok())
-        .ok_or_else(|| AppError::AuthenticationError("Missing authorization header".to_string()))?;
-    
-    let token = auth_header
-        .strip_prefix("Bearer ")
-        .ok_or_else(|| AppError::AuthenticationError("Invalid authorization header format".to_string()))?;
-    
-    let claims = crate::auth::AuthService::verify_access_token(token)?;
-    let user_id = claims.sub.parse::<Uuid>()
-        .map_err(|_| AppError::AuthenticationError("Invalid user ID in token".to_string()))?;
-    
-    Ok(user_id)
+// Permanent redirect to the same path with the last segment replaced by `username`
+fn redirect_to_username(req: &HttpRequest, username: &str) -> HttpResponse {
+    let path = req.path();
+    let base = path.rsplit_once('/').map_or("", |(base, _)| base);
+    let location = match req.query_string() {
+        "" => format!("{}/{}", base, username),
+        query => format!("{}/{}?{}", base, username, query),
+    };
+    HttpResponse::MovedPermanently()
+        .insert_header((actix_web::http::header::LOCATION, location))
+        .finish()
 }
 
 // Get all users for discovery
 #[actix_web::get("/users")]
 pub async fn get_all_users(
     pool: web::Data<DbPool>,
+    viewer: Option<AuthenticatedUser>,
     query: web::Query<std::collections::HashMap<String, String>>,
 ) -> Result<HttpResponse, AppError> {
     let limit = query.get("limit").and_then(|s| s.parse::<i64>().ok()).unwrap_or(50);
     let offset = query.get("offset").and_then(|s| s.parse::<i64>().ok()).unwrap_or(0);
     
-    let users = SocialService::get_all_users(&pool, limit, offset).await?;
+    let viewer_id = viewer.map(|viewer| viewer.id);
+    let users = SocialService::get_all_users(&pool, viewer_id, limit, offset).await?;
     
     Ok(HttpResponse::Ok().json(ApiResponse::success(users)))
 }
+
+// Search users by username and bio; without `q`, lists users newest first
+#[actix_web::get("/search")]
+pub async fn search_users(
+    pool: web::Data<DbPool>,
+    viewer: Option<AuthenticatedUser>,
+    query: web::Query<SearchQuery>,
+) -> Result<HttpResponse, AppError> {
+    // Authentication is optional; signed-in users don't see blocked accounts
+    let viewer_id = viewer.map(|viewer| viewer.id);
+
+    let limit = query.limit.unwrap_or(20).clamp(1, MAX_SEARCH_LIMIT);
+    let results = DiscoveryService::search_users(
+        &pool,
+        query.q.as_deref(),
+        viewer_id,
+        query.cursor.as_deref(),
+        limit,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(results)))
+}
+
+// Get users the current user may want to follow
+#[actix_web::get("/suggested-users")]
+pub async fn get_suggested_users(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    query: web::Query<SuggestedUsersQuery>,
+) -> Result<HttpResponse, AppError> {
+    let limit = query.limit.unwrap_or(10).clamp(1, MAX_SEARCH_LIMIT);
+    let suggestions = DiscoveryService::get_suggested_users(&pool, user.id, limit).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(suggestions)))
+}
@@ -1,6 +1,12 @@
+use crate::copy_trading::CopyTradingService;
+use crate::funding::{self, FundingService};
 use crate::kana_client::KanaClient;
+use crate::markets::MarketRegistry;
+use crate::middleware::AuthenticatedUser;
 use crate::models::*;
-use crate::utils::{ApiResponse, AppError};
+use crate::paper_trading::{PaperOrderRequest, PaperTradingService};
+use crate::positions::PositionService;
+use crate::utils::{csv_response, to_csv, ApiResponse, AppError, PageQuery, PaginatedResponse};
 use crate::DbPool;
 use actix_web::{web, HttpResponse};
 use serde::{Deserialize, Serialize};
@@ -29,6 +35,23 @@ pub struct GetOrderbookRequest {
     pub depth: Option<u32>,
 }
 
+// `mode=paper` routes a request to the simulated paper-trading account
+#[derive(Debug, Deserialize)]
+pub struct TradingModeQuery {
+    pub mode: Option<String>,
+}
+
+impl TradingModeQuery {
+    pub fn is_paper(&self) -> bool {
+        is_paper_mode(self.mode.as_deref())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetPaperAccountRequest {
+    pub starting_balance: Option<f64>,
+}
+
 // Get all markets from Kana Labs
 #[actix_web::get("/markets")]
 pub async fn get_markets(_pool: web::Data<DbPool>) -> Result<HttpResponse, AppError> {
@@ -162,14 +185,44 @@ pub async fn get_orderbook(
 // Place an order via Kana Labs
 #[actix_web::post("/orders")]
 pub async fn place_order(
+    user: AuthenticatedUser,
+    mode: web::Query<TradingModeQuery>,
     order_data: web::Json<PlaceOrderRequest>,
-    _pool: web::Data<DbPool>,
+    pool: web::Data<DbPool>,
 ) -> Result<HttpResponse, AppError> {
     let order_data = order_data.into_inner();
     order_data
         .validate()
         .map_err(|e| AppError::ValidationError(e.to_string()))?;
 
+    if mode.is_paper() {
+        let paper_order = PaperTradingService::place_order(
+            &pool,
+            user.id,
+            PaperOrderRequest {
+                symbol: order_data.symbol,
+                side: order_data.side,
+                order_type: order_data.order_type,
+                size: order_data.size,
+                price: order_data.price,
+                leverage: order_data.leverage,
+                margin_type: order_data.margin_type,
+            },
+        )
+        .await?;
+
+        // Mirror the order to copy-trading subscribers without holding up the response
+        let copy_pool = pool.get_ref().clone();
+        let source_order = paper_order.clone();
+        tokio::spawn(async move {
+            if let Err(e) = CopyTradingService::copy_paper_order(&copy_pool, &source_order).await {
+                log::warn!("Failed to copy paper order {}: {}", source_order.id, e);
+            }
+        });
+
+        return Ok(HttpResponse::Ok().json(ApiResponse::success(paper_order)));
+    }
+
     let kana_client = KanaClient::new()?;
 
     // Convert our order request to Kana Labs format
@@ -214,10 +267,34 @@ pub async fn get_orders(_pool: web::Data<DbPool>) -> Result<HttpResponse, AppErr
 // Cancel an order via Kana Labs
 #[actix_web::delete("/orders/{order_id}")]
 pub async fn cancel_order(
+    user: AuthenticatedUser,
+    mode: web::Query<TradingModeQuery>,
     order_id: web::Path<String>,
-    _pool: web::Data<DbPool>,
+    pool: web::Data<DbPool>,
 ) -> Result<HttpResponse, AppError> {
     let order_id = order_id.into_inner();
+
+    if mode.is_paper() {
+        let order_id = order_id
+            .parse::<uuid::Uuid>()
+            .map_err(|_| AppError::ValidationError("Invalid paper order ID".to_string()))?;
+        let cancelled = PaperTradingService::cancel_order(&pool, user.id, order_id).await?;
+
+        // Cancel the subscribers' copies of the order too
+        let copy_pool = pool.get_ref().clone();
+        let source_order = cancelled.clone();
+        tokio::spawn(async move {
+            if let Err(e) = CopyTradingService::cancel_paper_copies(&copy_pool, &source_order).await {
+                log::warn!("Failed to cancel copies of paper order {}: {}", source_order.id, e);
+            }
+        });
+
+        return Ok(HttpResponse::Ok().json(ApiResponse::success_with_message(
+            cancelled,
+            "Order cancelled successfully".to_string(),
+        )));
+    }
+
     let kana_client = KanaClient::new()?;
 
     kana_client.cancel_order(&order_id).await?;
@@ -228,12 +305,20 @@ pub async fn cancel_order(
     )))
 }
 
-// Get user positions from Kana Labs
+// Get user positions from Kana Labs with PnL, margin ratio and liquidation price
 #[actix_web::get("/positions")]
 pub async fn get_positions(
+    user: AuthenticatedUser,
     query: web::Query<serde_json::Value>,
-    _pool: web::Data<DbPool>,
+    pool: web::Data<DbPool>,
 ) -> Result<HttpResponse, AppError> {
+    if is_paper_mode(query.get("mode").and_then(|v| v.as_str())) {
+        let symbol = query.get("symbol").and_then(|v| v.as_str());
+        let positions = PaperTradingService::get_positions(&pool, user.id, symbol).await?;
+
+        return Ok(HttpResponse::Ok().json(ApiResponse::success(positions)));
+    }
+
     let user_address = query
         .get("userAddress")
         .and_then(|v| v.as_str())
@@ -243,10 +328,7 @@ pub async fn get_positions(
 
     let market_id = query.get("marketId").and_then(|v| v.as_str());
 
-    let kana_client = KanaClient::new()?;
-    let positions = kana_client
-        .get_positions_with_user_address(user_address, market_id)
-        .await?;
+    let positions = PositionService::get_positions(user_address, market_id).await?;
 
     Ok(HttpResponse::Ok().json(ApiResponse::success(positions)))
 }
@@ -266,16 +348,53 @@ pub async fn get_funding_rate(
     struct FundingRateResponse {
         symbol: String,
         funding_rate: f64,
+        next_funding_time: chrono::DateTime<chrono::Utc>,
     }
 
     let response = FundingRateResponse {
         symbol,
         funding_rate,
+        next_funding_time: funding::next_funding_time(chrono::Utc::now()),
     };
 
     Ok(HttpResponse::Ok().json(ApiResponse::success(response)))
 }
 
+// Get recorded funding rate history for a market (paginated, or CSV with format=csv)
+#[actix_web::get("/funding-history/{symbol}")]
+pub async fn get_funding_history(
+    symbol: web::Path<String>,
+    query: web::Query<PageQuery>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, AppError> {
+    let symbol = symbol.into_inner();
+
+    if query.is_csv() {
+        let (rates, _) =
+            FundingService::get_funding_history(&pool, &symbol, PageQuery::MAX_EXPORT_ROWS, 0).await?;
+        let rows = rates
+            .into_iter()
+            .map(|rate| {
+                vec![
+                    rate.recorded_at.to_rfc3339(),
+                    rate.symbol,
+                    rate.market_id,
+                    rate.funding_rate.to_string(),
+                    rate.mark_price.to_string(),
+                ]
+            })
+            .collect();
+        let csv = to_csv(&["recorded_at", "symbol", "market_id", "funding_rate", "mark_price"], rows);
+
+        return Ok(csv_response("funding-history.csv", csv));
+    }
+
+    let (rates, total) =
+        FundingService::get_funding_history(&pool, &symbol, query.per_page(), query.offset()).await?;
+
+    Ok(HttpResponse::Ok().json(PaginatedResponse::new(rates, query.page(), query.per_page(), total)))
+}
+
 // Get market price from Kana Labs
 #[actix_web::get("/price/{symbol}")]
 pub async fn get_market_price(
@@ -283,22 +402,7 @@ pub async fn get_market_price(
     _pool: web::Data<DbPool>,
 ) -> Result<HttpResponse, AppError> {
     let symbol = symbol.into_inner();
-    let kana_client = KanaClient::new()?;
-
-    // Try to get price from Kana Labs API, fallback to mock data if it fails
-    let price = match kana_client.get_market_price(&symbol).await {
-        Ok(price) => price,
-        Err(_) => {
-            // Use fallback prices when Kana Labs API fails
-            match symbol.as_str() {
-                "APT/USDC" | "APT-USDC" => 8.50,
-                "BTC/USDC" | "BTC-USDC" => 45000.0,
-                "ETH/USDC" | "ETH-USDC" => 3200.0,
-                "SOL/USDC" | "SOL-USDC" | "SOL-USD" => 95.0,
-                _ => 0.0,
-            }
-        }
-    };
+    let price = MarketRegistry::require(&symbol)?.require_mid_price().await?;
 
     #[derive(Serialize)]
     struct PriceResponse {
@@ -360,9 +464,17 @@ pub async fn get_chart_data(
 // Get open orders with user address
 #[actix_web::get("/open-orders")]
 pub async fn get_open_orders(
+    user: AuthenticatedUser,
     params: web::Query<serde_json::Value>,
-    _pool: web::Data<DbPool>,
+    pool: web::Data<DbPool>,
 ) -> Result<HttpResponse, AppError> {
+    if is_paper_mode(params.get("mode").and_then(|v| v.as_str())) {
+        let symbol = params.get("symbol").and_then(|v| v.as_str());
+        let orders = PaperTradingService::get_open_orders(&pool, user.id, symbol).await?;
+
+        return Ok(HttpResponse::Ok().json(ApiResponse::success(orders)));
+    }
+
     let user_address = params
         .get("userAddress")
         .and_then(|v| v.as_str())
@@ -381,9 +493,17 @@ pub async fn get_open_orders(
 // Get order history with user address
 #[actix_web::get("/order-history")]
 pub async fn get_order_history(
+    user: AuthenticatedUser,
     params: web::Query<serde_json::Value>,
-    _pool: web::Data<DbPool>,
+    pool: web::Data<DbPool>,
 ) -> Result<HttpResponse, AppError> {
+    if is_paper_mode(params.get("mode").and_then(|v| v.as_str())) {
+        let symbol = params.get("symbol").and_then(|v| v.as_str());
+        let orders = PaperTradingService::get_order_history(&pool, user.id, symbol).await?;
+
+        return Ok(HttpResponse::Ok().json(ApiResponse::success(orders)));
+    }
+
     let user_address = params
         .get("userAddress")
         .and_then(|v| v.as_str())
@@ -636,3 +756,34 @@ pub async fn settle_pnl(
 
     Ok(HttpResponse::Ok().json(result))
 }
+
+// Get the paper-trading account summary, creating the account on first use
+#[actix_web::get("/paper/account")]
+pub async fn get_paper_account(
+    user: AuthenticatedUser,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, AppError> {
+    let summary = PaperTradingService::get_account_summary(&pool, user.id).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(summary)))
+}
+
+// Reset the paper-trading account to a fresh simulated balance
+#[actix_web::post("/paper/reset")]
+pub async fn reset_paper_account(
+    user: AuthenticatedUser,
+    request: web::Json<ResetPaperAccountRequest>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, AppError> {
+    let account =
+        PaperTradingService::reset_account(&pool, user.id, request.starting_balance).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success_with_message(
+        account,
+        "Paper account reset successfully".to_string(),
+    )))
+}
+
+fn is_paper_mode(mode: Option<&str>) -> bool {
+    matches!(mode, Some(mode) if mode.eq_ignore_ascii_case("paper"))
+}
@@ -1,141 +1,541 @@
 use crate::{
     DbPool,
-    models::User,
+    account_deletion::AccountDeletionService,
+    avatars::{self, AvatarService},
+    equity::{EquityRange, EquityService},
+    exports::DataExportService,
+    fills::{CostBasis, FillService, PnlPeriod},
+    funding::FundingService,
+    models::{User, UserProfile},
+    portfolio::PortfolioService,
+    profiles::{ProfileService, ProfileUpdate},
     schema::users,
-    utils::{ApiResponse, AppError},
+    utils::{csv_response, to_csv, ApiResponse, AppError, PageQuery, PaginatedResponse},
     kana_client::KanaClient,
+    middleware::AuthenticatedUser,
+    wallets::WalletService,
 };
-use actix_web::{get, put, web, HttpRequest, HttpResponse};
+use actix_files::NamedFile;
+use actix_multipart::Multipart;
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{delete, get, post, route, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
 use diesel::prelude::*;
+use futures_util::TryStreamExt;
 use serde::Deserialize;
-use validator::Validate;
 
-#[derive(Debug, Deserialize, Validate)]
-pub struct UpdateProfileRequest {
-    #[validate(length(min = 3, max = 50))]
-    pub username: Option<String>,
+#[derive(Debug, Deserialize)]
+pub struct WalletChallengeRequest {
+    pub wallet_address: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LinkWalletRequest {
+    pub wallet_address: String,
+    pub public_key: String,
+    pub signature: String,
+    // The full message returned by the wallet's signMessage, when used
+    pub full_message: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DataExportRequest {
+    pub format: Option<String>, // "json" (default) or "zip"
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AccountDeletionRequest {
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SymbolFilter {
+    pub symbol: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FillsQuery {
+    pub symbol: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EquityHistoryQuery {
+    pub range: Option<String>, // "24h", "7d", "30d", "90d", "1y" or "all"
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PnlQuery {
+    pub period: Option<String>, // "daily", "weekly" or "monthly"
+    pub method: Option<String>, // "fifo" or "average"
+    pub symbol: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
 }
 
 // Get user profile
 #[get("/profile")]
 pub async fn get_profile(
     pool: web::Data<DbPool>,
-    req: HttpRequest,
+    user: AuthenticatedUser,
 ) -> Result<HttpResponse, AppError> {
-    // Extract user ID from token
-    let auth_header = req
-        .headers()
-        .get("Authorization")
-        .and_then(|h| h.to_str().ok())
-        .ok_or_else(|| AppError::AuthenticationError("Missing authorization header".to_string()))?;
-    
-    let token = auth_header
-        .strip_prefix("Bearer ")
-        .ok_or_else(|| AppError::AuthenticationError("Invalid authorization header format".to_string()))?;
-    
-    let claims = crate::auth::AuthService::verify_access_token(token)
-        .map_err(|_| AppError::AuthenticationError("Invalid token".to_string()))?;
-    let user_id = claims.sub.parse::<uuid::Uuid>()
-        .map_err(|_| AppError::AuthenticationError("Invalid user ID in token".to_string()))?;
-    
     let conn = &mut pool.get().map_err(|_| AppError::InternalServerError("Database connection failed".to_string()))?;
     
-    let user = users::table
-        .find(user_id)
+    let profile: UserProfile = users::table
+        .find(user.id)
         .first::<User>(conn)
         .optional()?
-        .ok_or_else(|| AppError::NotFoundError("User not found".to_string()))?;
+        .ok_or_else(|| AppError::NotFoundError("User not found".to_string()))?
+        .into();
     
-    Ok(HttpResponse::Ok().json(ApiResponse::success(user)))
+    Ok(HttpResponse::Ok().json(ApiResponse::success(profile)))
 }
 
-// Update user profile
-#[put("/profile")]
+// Update the fields present in the body; PUT is kept for older clients
+#[route("/profile", method = "PATCH", method = "PUT")]
 pub async fn update_profile(
     pool: web::Data<DbPool>,
-    req: HttpRequest,
-    profile_data: web::Json<UpdateProfileRequest>,
+    user: AuthenticatedUser,
+    update: web::Json<ProfileUpdate>,
 ) -> Result<HttpResponse, AppError> {
-    // Validate request
-    profile_data.validate().map_err(|e| AppError::ValidationError(e.to_string()))?;
-    
-    // Extract user ID from token
-    let auth_header = req
-        .headers()
-        .get("Authorization")
-        .and_then(|h| h.to_str().ok())
-        .ok_or_else(|| AppError::AuthenticationError("Missing authorization header".to_string()))?;
-    
-    let token = auth_header
-        .strip_prefix("Bearer ")
-        .ok_or_else(|| AppError::AuthenticationError("Invalid authorization header format".to_string()))?;
-    
-    let claims = crate::auth::AuthService::verify_access_token(token)
-        .map_err(|_| AppError::AuthenticationError("Invalid token".to_string()))?;
-    let user_id = claims.sub.parse::<uuid::Uuid>()
-        .map_err(|_| AppError::AuthenticationError("Invalid user ID in token".to_string()))?;
-    
-    let conn = &mut pool.get().map_err(|_| AppError::InternalServerError("Database connection failed".to_string()))?;
-    
-    // Check if username is being updated and if it already exists
-    if let Some(new_username) = &profile_data.username {
-        // Check if username already exists for a different user
-        let existing_user = users::table
-            .filter(users::username.eq(new_username))
-            .filter(users::id.ne(user_id))
-            .first::<User>(conn)
-            .optional()?;
-        
-        if existing_user.is_some() {
-            return Err(AppError::ValidationError("Username already exists".to_string()));
+    let profile = ProfileService::update_profile(&pool, user.id, update.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(profile)))
+}
+
+// Every change made to the user's profile, newest first
+#[get("/profile/history")]
+pub async fn get_profile_history(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    page: web::Query<PageQuery>,
+) -> Result<HttpResponse, AppError> {
+    let (entries, total) =
+        ProfileService::get_audit_log(&pool, user.id, page.per_page(), page.offset()).await?;
+
+    Ok(HttpResponse::Ok().json(PaginatedResponse::new(
+        entries,
+        page.page(),
+        page.per_page(),
+        total,
+    )))
+}
+
+// Upload a new avatar as multipart/form-data with the image in an "avatar" field
+#[post("/avatar")]
+pub async fn upload_avatar(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    mut payload: Multipart,
+) -> Result<HttpResponse, AppError> {
+    let max_bytes = avatars::max_avatar_bytes();
+
+    while let Some(mut field) = payload
+        .try_next()
+        .await
+        .map_err(|e| AppError::BadRequest(format!("Invalid multipart body: {}", e)))?
+    {
+        if field.name() != Some("avatar") {
+            continue;
+        }
+        let content_type = field
+            .content_type()
+            .map(|mime| mime.essence_str().to_string())
+            .unwrap_or_default();
+        // Reject unsupported types before reading the body
+        avatars::image_format(&content_type)?;
+
+        let mut bytes = Vec::new();
+        while let Some(chunk) = field
+            .try_next()
+            .await
+            .map_err(|e| AppError::BadRequest(format!("Invalid multipart body: {}", e)))?
+        {
+            if bytes.len() + chunk.len() > max_bytes {
+                return Err(AppError::ValidationError(format!(
+                    "Avatar must be at most {} bytes",
+                    max_bytes
+                )));
+            }
+            bytes.extend_from_slice(&chunk);
         }
+        if bytes.is_empty() {
+            return Err(AppError::ValidationError("Avatar file is empty".to_string()));
+        }
+
+        let avatar = AvatarService::upload(&pool, user.id, &content_type, bytes).await?;
+        return Ok(HttpResponse::Ok().json(ApiResponse::success(avatar)));
     }
-    
-    // Update user profile - only update fields that are provided
-    let updated_user = if let Some(username) = &profile_data.username {
-        diesel::update(users::table.find(user_id))
-            .set((
-                users::username.eq(username),
-                users::updated_at.eq(chrono::Utc::now()),
-            ))
-            .get_result::<User>(conn)?
-    } else {
-        diesel::update(users::table.find(user_id))
-            .set(users::updated_at.eq(chrono::Utc::now()))
-            .get_result::<User>(conn)?
-    };
-    
-    Ok(HttpResponse::Ok().json(ApiResponse::success(updated_user)))
+
+    Err(AppError::ValidationError("Missing \"avatar\" file field".to_string()))
+}
+
+// Remove the current user's avatar
+#[delete("/avatar")]
+pub async fn delete_avatar(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, AppError> {
+    AvatarService::remove(&pool, user.id).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success_with_message(
+        (),
+        "Avatar removed".to_string(),
+    )))
+}
+
+// Queue an export of everything stored about the user; a notification is
+// sent when it is ready to download
+#[post("/export")]
+pub async fn request_data_export(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    request: Option<web::Json<DataExportRequest>>,
+) -> Result<HttpResponse, AppError> {
+    let format = request.and_then(|r| r.into_inner().format);
+    let export = DataExportService::request_export(&pool, user.id, format.as_deref().unwrap_or("json")).await?;
+
+    Ok(HttpResponse::Accepted().json(ApiResponse::success(export)))
+}
+
+#[get("/export/{export_id}")]
+pub async fn get_data_export(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    export_id: web::Path<uuid::Uuid>,
+) -> Result<HttpResponse, AppError> {
+    let export = DataExportService::get_export(&pool, user.id, *export_id).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(export)))
+}
+
+#[get("/export/{export_id}/download")]
+pub async fn download_data_export(
+    pool: web::Data<DbPool>,
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    export_id: web::Path<uuid::Uuid>,
+) -> Result<HttpResponse, AppError> {
+    let (path, file_name) = DataExportService::export_file(&pool, user.id, *export_id).await?;
+
+    let file = NamedFile::open(path)
+        .map_err(|_| AppError::NotFoundError("Export file not found".to_string()))?
+        .set_content_disposition(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(file_name)],
+        });
+    Ok(file.into_response(&req))
+}
+
+// Schedule the account for deletion after a grace period; requires the password
+#[post("/delete")]
+pub async fn request_account_deletion(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    request: web::Json<AccountDeletionRequest>,
+) -> Result<HttpResponse, AppError> {
+    let scheduled_at = AccountDeletionService::request_deletion(&pool, user.id, &request.password).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success_with_message(
+        serde_json::json!({ "deletion_scheduled_at": scheduled_at }),
+        format!("Your account will be deleted on {}", scheduled_at.format("%Y-%m-%d")),
+    )))
+}
+
+#[post("/delete/cancel")]
+pub async fn cancel_account_deletion(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, AppError> {
+    AccountDeletionService::cancel_deletion(&pool, user.id).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success_with_message(
+        (),
+        "Account deletion cancelled".to_string(),
+    )))
 }
 
 // Get user balance from Kana Labs
 #[get("/balance")]
 pub async fn get_balance(
-    _pool: web::Data<DbPool>,
-    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
 ) -> Result<HttpResponse, AppError> {
-    // Extract user ID from token
-    let auth_header = req
-        .headers()
-        .get("Authorization")
-        .and_then(|h| h.to_str().ok())
-        .ok_or_else(|| AppError::AuthenticationError("Missing authorization header".to_string()))?;
-    
-    let token = auth_header
-        .strip_prefix("Bearer ")
-        .ok_or_else(|| AppError::AuthenticationError("Invalid authorization header format".to_string()))?;
-    
-    let claims = crate::auth::AuthService::verify_access_token(token)
-        .map_err(|_| AppError::AuthenticationError("Invalid token".to_string()))?;
-    let _user_id = claims.sub.parse::<uuid::Uuid>()
-        .map_err(|_| AppError::AuthenticationError("Invalid user ID in token".to_string()))?;
-    
-    // For now, we'll need to get the wallet address from the user
-    // This is a placeholder - you'll need to implement proper wallet address mapping
-    let wallet_address = "placeholder_wallet_address"; // TODO: Get from user session
+    // Use the first wallet the user linked
+    let wallet = WalletService::get_user_wallets(&pool, user.id)
+        .await?
+        .into_iter()
+        .next()
+        .ok_or_else(|| AppError::BadRequest("No wallet linked to this account".to_string()))?;
     
     let kana_client = KanaClient::new()?;
-    let balances = kana_client.get_balance(wallet_address).await?;
+    let balances = kana_client.get_balance(&wallet.wallet_address).await?;
     
     Ok(HttpResponse::Ok().json(ApiResponse::success(balances)))
 }
+
+// List wallets linked to the current user
+#[get("/wallets")]
+pub async fn get_wallets(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, AppError> {
+    let wallets = WalletService::get_user_wallets(&pool, user.id).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(wallets)))
+}
+
+// Issue the challenge a wallet signs before it can be linked
+#[post("/wallets/challenge")]
+pub async fn create_wallet_challenge(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    request: web::Json<WalletChallengeRequest>,
+) -> Result<HttpResponse, AppError> {
+    let challenge = WalletService::create_challenge(&pool, user.id, &request.wallet_address).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(challenge)))
+}
+
+// Link a wallet address to the current user with a signature of its challenge
+#[post("/wallets")]
+pub async fn link_wallet(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    request: web::Json<LinkWalletRequest>,
+) -> Result<HttpResponse, AppError> {
+    let wallet = WalletService::link_wallet(
+        &pool,
+        user.id,
+        &request.wallet_address,
+        &request.public_key,
+        &request.signature,
+        request.full_message.as_deref(),
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(wallet)))
+}
+
+// Unlink a wallet address from the current user
+#[delete("/wallets/{wallet_address}")]
+pub async fn unlink_wallet(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    wallet_address: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    WalletService::unlink_wallet(&pool, user.id, &wallet_address).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success_with_message(
+        (),
+        "Wallet unlinked successfully".to_string(),
+    )))
+}
+
+// Funding paid or received on the current user's positions (paginated, or CSV with format=csv)
+#[get("/funding-payments")]
+pub async fn get_funding_payments(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    filter: web::Query<SymbolFilter>,
+    page: web::Query<PageQuery>,
+) -> Result<HttpResponse, AppError> {
+    let symbol = filter.symbol.as_deref();
+
+    if page.is_csv() {
+        let (payments, _) = FundingService::get_user_payments(
+            &pool,
+            user.id,
+            symbol,
+            PageQuery::MAX_EXPORT_ROWS,
+            0,
+        )
+        .await?;
+        let rows = payments
+            .into_iter()
+            .map(|payment| {
+                vec![
+                    payment.funding_time.to_rfc3339(),
+                    payment.wallet_address,
+                    payment.symbol,
+                    payment.side,
+                    payment.position_size.to_string(),
+                    payment.mark_price.to_string(),
+                    payment.funding_rate.to_string(),
+                    payment.payment.to_string(),
+                ]
+            })
+            .collect();
+        let csv = to_csv(
+            &[
+                "funding_time",
+                "wallet_address",
+                "symbol",
+                "side",
+                "position_size",
+                "mark_price",
+                "funding_rate",
+                "payment",
+            ],
+            rows,
+        );
+
+        return Ok(csv_response("funding-payments.csv", csv));
+    }
+
+    let (payments, total) = FundingService::get_user_payments(
+        &pool,
+        user.id,
+        symbol,
+        page.per_page(),
+        page.offset(),
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(PaginatedResponse::new(
+        payments,
+        page.page(),
+        page.per_page(),
+        total,
+    )))
+}
+
+// Fills of the current user's linked wallets (paginated, or CSV with format=csv)
+#[get("/fills")]
+pub async fn get_fills(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    filter: web::Query<FillsQuery>,
+    page: web::Query<PageQuery>,
+) -> Result<HttpResponse, AppError> {
+    let symbol = filter.symbol.as_deref();
+
+    if page.is_csv() {
+        let (fills, _) = FillService::get_fills(
+            &pool,
+            user.id,
+            symbol,
+            filter.from,
+            filter.to,
+            PageQuery::MAX_EXPORT_ROWS,
+            0,
+        )
+        .await?;
+        let rows = fills
+            .into_iter()
+            .map(|fill| {
+                vec![
+                    fill.executed_at.to_rfc3339(),
+                    fill.wallet_address,
+                    fill.symbol,
+                    fill.order_id.unwrap_or_default(),
+                    fill.trade_id,
+                    fill.side,
+                    fill.size.to_string(),
+                    fill.price.to_string(),
+                    fill.fee.to_string(),
+                    fill.realized_pnl.to_string(),
+                    fill.realized_pnl_avg_cost.to_string(),
+                ]
+            })
+            .collect();
+        let csv = to_csv(
+            &[
+                "executed_at",
+                "wallet_address",
+                "symbol",
+                "order_id",
+                "trade_id",
+                "side",
+                "size",
+                "price",
+                "fee",
+                "realized_pnl_fifo",
+                "realized_pnl_avg_cost",
+            ],
+            rows,
+        );
+
+        return Ok(csv_response("fills.csv", csv));
+    }
+
+    let (fills, total) = FillService::get_fills(
+        &pool,
+        user.id,
+        symbol,
+        filter.from,
+        filter.to,
+        page.per_page(),
+        page.offset(),
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(PaginatedResponse::new(
+        fills,
+        page.page(),
+        page.per_page(),
+        total,
+    )))
+}
+
+// Pull new fills for the current user's wallets from Kana Labs right away
+#[post("/fills/sync")]
+pub async fn sync_fills(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, AppError> {
+    let new_fills = FillService::reconcile_user(&pool, user.id).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(serde_json::json!({
+        "new_fills": new_fills
+    }))))
+}
+
+// Realized PnL summaries by day, week or month
+#[get("/pnl")]
+pub async fn get_pnl(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    query: web::Query<PnlQuery>,
+) -> Result<HttpResponse, AppError> {
+    let period = PnlPeriod::parse(query.period.as_deref())?;
+    let basis = CostBasis::parse(query.method.as_deref())?;
+
+    let summaries = FillService::get_pnl_summary(
+        &pool,
+        user.id,
+        period,
+        basis,
+        query.symbol.as_deref(),
+        query.from,
+        query.to,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(summaries)))
+}
+
+// Equity, collateral, exposure and open orders across all markets and linked wallets
+#[get("/portfolio")]
+pub async fn get_portfolio(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, AppError> {
+    let portfolio = PortfolioService::get_portfolio(&pool, user.id).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(portfolio)))
+}
+
+// Equity curve of the current user's linked wallets with performance stats
+#[get("/equity-history")]
+pub async fn get_equity_history(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    query: web::Query<EquityHistoryQuery>,
+) -> Result<HttpResponse, AppError> {
+    let range = EquityRange::parse(query.range.as_deref())?;
+    let history = EquityService::get_history(&pool, user.id, range).await?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(history)))
+}
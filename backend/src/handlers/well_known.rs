@@ -0,0 +1,11 @@
+use crate::jwt_keys::JwtKeys;
+use actix_web::{get, http::header, HttpResponse};
+
+// Public keys that verify access tokens. Verifiers may cache the set, but
+// should fetch it again when a token names a kid they don't know.
+#[get("/.well-known/jwks.json")]
+pub async fn jwks() -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header((header::CACHE_CONTROL, "public, max-age=300"))
+        .json(JwtKeys::global().jwk_set())
+}
@@ -0,0 +1,225 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm, OctetKeyPairParameters,
+    OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+};
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation};
+use ring::rand::SystemRandom;
+use ring::signature::{Ed25519KeyPair, KeyPair, RsaKeyPair, RsaPublicKeyComponents};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+// Kid of the key generated at startup when no key directory is configured
+pub const TEMPORARY_KEY_ID: &str = "temporary";
+
+static JWT_KEYS: OnceLock<JwtKeys> = OnceLock::new();
+
+// The keys that sign and verify access tokens. Every key verifies and is
+// published in the JWKS; the one named by the signing kid also signs. Tokens
+// name their key in the kid header, so a retired key keeps verifying the
+// tokens it signed for as long as it stays in the set.
+pub struct JwtKeys {
+    signing_kid: String,
+    keys: BTreeMap<String, JwtKey>,
+    issuer: String,
+    audience: String,
+}
+
+struct JwtKey {
+    algorithm: Algorithm,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    jwk: Jwk,
+}
+
+impl JwtKeys {
+    // Reads every <kid>.pem in the directory: PKCS#8 Ed25519 or RSA private
+    // keys, or PKCS#1 RSA ones. Without a directory a temporary Ed25519 key is
+    // generated, which is only fit for development.
+    pub fn load(
+        keys_dir: Option<&Path>,
+        signing_kid: Option<&str>,
+        issuer: &str,
+        audience: &str,
+    ) -> Result<Self, String> {
+        let (keys, signing_kid) = match keys_dir {
+            Some(dir) => {
+                let keys = read_keys(dir)?;
+                let signing_kid = signing_kid.ok_or("JWT_SIGNING_KID must name the signing key")?;
+                if !keys.contains_key(signing_kid) {
+                    return Err(format!("No key {}.pem in {}", signing_kid, dir.display()));
+                }
+                (keys, signing_kid.to_string())
+            }
+            None => {
+                let key = JwtKey::temporary(TEMPORARY_KEY_ID)?;
+                (BTreeMap::from([(TEMPORARY_KEY_ID.to_string(), key)]), TEMPORARY_KEY_ID.to_string())
+            }
+        };
+
+        Ok(JwtKeys {
+            signing_kid,
+            keys,
+            issuer: issuer.to_string(),
+            audience: audience.to_string(),
+        })
+    }
+
+    pub fn init(keys: JwtKeys) -> &'static JwtKeys {
+        JWT_KEYS.get_or_init(|| keys)
+    }
+
+    pub fn global() -> &'static JwtKeys {
+        JWT_KEYS.get().expect("JwtKeys::init must be called at startup")
+    }
+
+    pub fn signing_kid(&self) -> &str {
+        &self.signing_kid
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    pub fn audience(&self) -> &str {
+        &self.audience
+    }
+
+    pub fn sign<T: Serialize>(&self, claims: &T) -> Result<String, String> {
+        let key = &self.keys[&self.signing_kid];
+        let mut header = Header::new(key.algorithm);
+        header.kid = Some(self.signing_kid.clone());
+
+        encode(&header, claims, &key.encoding).map_err(|e| e.to_string())
+    }
+
+    // Checks the signature with the key the token names, pinned to that key's
+    // algorithm, along with exp, iss and aud
+    pub fn verify<T: DeserializeOwned>(&self, token: &str) -> Result<TokenData<T>, String> {
+        let header = decode_header(token).map_err(|e| e.to_string())?;
+        let key = header
+            .kid
+            .as_deref()
+            .and_then(|kid| self.keys.get(kid))
+            .ok_or("unknown signing key")?;
+
+        let mut validation = Validation::new(key.algorithm);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[&self.audience]);
+        validation.set_required_spec_claims(&["exp", "sub", "iss", "aud"]);
+
+        decode::<T>(token, &key.decoding, &validation).map_err(|e| e.to_string())
+    }
+
+    // Public halves of every key, as served at /.well-known/jwks.json
+    pub fn jwk_set(&self) -> JwkSet {
+        JwkSet {
+            keys: self.keys.values().map(|key| key.jwk.clone()).collect(),
+        }
+    }
+}
+
+impl JwtKey {
+    fn from_pem(kid: &str, pem_bytes: &[u8]) -> Result<Self, String> {
+        let parsed = pem::parse(pem_bytes).map_err(|e| format!("invalid PEM: {}", e))?;
+
+        match parsed.tag() {
+            "PRIVATE KEY" => {
+                if let Ok(key_pair) = Ed25519KeyPair::from_pkcs8_maybe_unchecked(parsed.contents()) {
+                    return Ok(Self::ed25519(kid, EncodingKey::from_ed_der(parsed.contents()), &key_pair));
+                }
+                let key_pair = RsaKeyPair::from_pkcs8(parsed.contents())
+                    .map_err(|e| format!("not an Ed25519 or RSA private key: {}", e))?;
+                let encoding = EncodingKey::from_rsa_pem(pem_bytes).map_err(|e| e.to_string())?;
+                Ok(Self::rsa(kid, encoding, &key_pair))
+            }
+            "RSA PRIVATE KEY" => {
+                let key_pair = RsaKeyPair::from_der(parsed.contents())
+                    .map_err(|e| format!("not an RSA private key: {}", e))?;
+                Ok(Self::rsa(kid, EncodingKey::from_rsa_der(parsed.contents()), &key_pair))
+            }
+            tag => Err(format!("expected a private key, found {}", tag)),
+        }
+    }
+
+    fn temporary(kid: &str) -> Result<Self, String> {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+            .map_err(|_| "Failed to generate a temporary signing key".to_string())?;
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).map_err(|e| e.to_string())?;
+        Ok(Self::ed25519(kid, EncodingKey::from_ed_der(pkcs8.as_ref()), &key_pair))
+    }
+
+    fn ed25519(kid: &str, encoding: EncodingKey, key_pair: &Ed25519KeyPair) -> Self {
+        let public_key = key_pair.public_key().as_ref();
+
+        JwtKey {
+            algorithm: Algorithm::EdDSA,
+            encoding,
+            decoding: DecodingKey::from_ed_der(public_key),
+            jwk: Jwk {
+                common: common_parameters(kid, KeyAlgorithm::EdDSA),
+                algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                    key_type: OctetKeyPairType::OctetKeyPair,
+                    curve: EllipticCurve::Ed25519,
+                    x: URL_SAFE_NO_PAD.encode(public_key),
+                }),
+            },
+        }
+    }
+
+    fn rsa(kid: &str, encoding: EncodingKey, key_pair: &RsaKeyPair) -> Self {
+        let public_key = RsaPublicKeyComponents::<Vec<u8>>::from(key_pair.public());
+
+        JwtKey {
+            algorithm: Algorithm::RS256,
+            encoding,
+            decoding: DecodingKey::from_rsa_raw_components(&public_key.n, &public_key.e),
+            jwk: Jwk {
+                common: common_parameters(kid, KeyAlgorithm::RS256),
+                algorithm: AlgorithmParameters::RSA(RSAKeyParameters {
+                    key_type: RSAKeyType::RSA,
+                    n: URL_SAFE_NO_PAD.encode(&public_key.n),
+                    e: URL_SAFE_NO_PAD.encode(&public_key.e),
+                }),
+            },
+        }
+    }
+}
+
+fn common_parameters(kid: &str, algorithm: KeyAlgorithm) -> CommonParameters {
+    CommonParameters {
+        public_key_use: Some(PublicKeyUse::Signature),
+        key_algorithm: Some(algorithm),
+        key_id: Some(kid.to_string()),
+        ..Default::default()
+    }
+}
+
+fn read_keys(dir: &Path) -> Result<BTreeMap<String, JwtKey>, String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("Cannot read {}: {}", dir.display(), e))?;
+
+    let mut keys = BTreeMap::new();
+    for entry in entries {
+        let path = entry.map_err(|e| format!("Cannot read {}: {}", dir.display(), e))?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("pem") {
+            continue;
+        }
+        let Some(kid) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+
+        let pem_bytes = fs::read(&path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+        let key = JwtKey::from_pem(kid, &pem_bytes).map_err(|e| format!("{}: {}", path.display(), e))?;
+        keys.insert(kid.to_string(), key);
+    }
+
+    if keys.is_empty() {
+        return Err(format!("No .pem keys in {}", dir.display()));
+    }
+    Ok(keys)
+}
@@ -1,9 +1,10 @@
+use crate::config::AppConfig;
+use crate::funding;
+use crate::markets::MarketRegistry;
 use crate::models::*;
 use crate::utils::AppError;
-use chrono;
 use reqwest::Client;
 use serde_json::Value;
-use std::env;
 
 pub struct KanaClient {
     client: Client,
@@ -13,16 +14,16 @@ pub struct KanaClient {
 
 impl KanaClient {
     pub fn new() -> Result<Self, AppError> {
-        let api_key = env::var("KANA_API_KEY")
-            .map_err(|_| AppError::ConfigurationError("KANA_API_KEY not set".to_string()))?;
-
-        // Use the correct testnet URL from Kana Labs documentation
-        let base_url = env::var("KANA_API_BASE_URL")
-            .unwrap_or_else(|_| "https://perps-tradeapi.kanalabs.io".to_string());
+        let config = AppConfig::global();
+        let api_key = config
+            .kana_api_key
+            .clone()
+            .ok_or_else(|| AppError::ConfigurationError("KANA_API_KEY not set".to_string()))?;
+        let base_url = config.kana_api_base_url.clone();
 
         // Create client with timeout to prevent hanging requests
         let client = Client::builder()
-            .timeout(std::time::Duration::from_secs(10))
+            .timeout(std::time::Duration::from_secs(config.kana_request_timeout_secs))
             .build()
             .map_err(|e| {
                 AppError::ConfigurationError(format!("Failed to create HTTP client: {}", e))
@@ -133,7 +134,7 @@ impl KanaClient {
             change_24h: 0.0,                 // Will be fetched separately
             volume_24h: 0.0,                 // Will be fetched separately
             funding_rate: 0.0,               // Will be fetched separately
-            next_funding_time: chrono::Utc::now(),
+            next_funding_time: funding::next_funding_time(chrono::Utc::now()),
             min_order_size: market_data
                 .get("min_lots")
                 .and_then(|v| v.as_str())
@@ -392,37 +393,18 @@ impl KanaClient {
     // Get market price
     pub async fn get_market_price(&self, symbol: &str) -> Result<f64, AppError> {
         // Extract market ID from symbol for Kana Labs API
-        let market_id = match symbol.to_uppercase().as_str() {
-            "APT/USDC" => "1338",
-            "BTC/USDC" => "1339",
-            "ETH/USDC" => "1340",
-            "SOL-USD" => "2387",
-            _ => {
-                return Err(AppError::ValidationError(format!(
-                    "Unsupported symbol: {}",
-                    symbol
-                )))
-            }
-        };
+        let market_id = MarketRegistry::require(symbol)?.market_id;
 
         // Get market info to extract current price
         let _market_info = self.get_market_info(market_id).await?;
 
-        // For now, we'll need to call a separate price endpoint or extract from orderbook
-        // Since Kana Labs doesn't have a direct price endpoint, we'll get the best bid from orderbook
+        // Kana Labs has no direct price endpoint, so the price is the mid of the
+        // top of the orderbook
         let orderbook = self.get_orderbook(symbol, Some(1)).await?;
 
-        let price = if let Some(best_bid) = orderbook.bids.first() {
-            best_bid.price
-        } else if let Some(best_ask) = orderbook.asks.first() {
-            best_ask.price
-        } else {
-            return Err(AppError::ExternalApiError(
-                "No price data available".to_string(),
-            ));
-        };
-
-        Ok(price)
+        mid_price(&orderbook).ok_or_else(|| {
+            AppError::ExternalApiError("No price data available".to_string())
+        })
     }
 
     // Get user balance
@@ -776,6 +758,7 @@ impl KanaClient {
     }
 
     // Get profile address for a user address
+    #[allow(dead_code)]
     pub async fn get_profile_address(
         &self,
         user_address: &str,
@@ -849,6 +832,7 @@ impl KanaClient {
     }
 
     // Deposit funds
+    #[allow(dead_code)]
     pub async fn deposit(
         &self,
         user_address: &str,
@@ -883,6 +867,7 @@ impl KanaClient {
     }
 
     // Withdraw from specific market
+    #[allow(dead_code)]
     pub async fn withdraw_specific_market(
         &self,
         user_address: &str,
@@ -1163,3 +1148,41 @@ impl KanaClient {
         Ok(balance_snapshot)
     }
 }
+
+// Halfway between the best bid and ask; None unless both sides are quoted and
+// the book is not crossed
+fn mid_price(orderbook: &KanaOrderbook) -> Option<f64> {
+    let bid = orderbook.bids.first()?.price;
+    let ask = orderbook.asks.first()?.price;
+    if bid > 0.0 && ask >= bid {
+        Some((bid + ask) / 2.0)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn orderbook(bids: &[f64], asks: &[f64]) -> KanaOrderbook {
+        let entries = |prices: &[f64]| {
+            prices.iter().map(|&price| KanaOrderbookEntry { price, size: 1.0 }).collect()
+        };
+        KanaOrderbook {
+            symbol: "APT/USDC".to_string(),
+            bids: entries(bids),
+            asks: entries(asks),
+            timestamp: chrono::Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_mid_price_is_halfway_between_best_bid_and_ask() {
+        assert_eq!(mid_price(&orderbook(&[8.49, 8.48], &[8.51, 8.52])), Some(8.5));
+        // One-sided and crossed books have no mid
+        assert_eq!(mid_price(&orderbook(&[8.49], &[])), None);
+        assert_eq!(mid_price(&orderbook(&[], &[8.51])), None);
+        assert_eq!(mid_price(&orderbook(&[8.52], &[8.51])), None);
+    }
+}
@@ -0,0 +1,466 @@
+use crate::config::AppConfig;
+use crate::models::{
+    NewTradingLeaderboardRow, TradingLeaderboardEntry, TradingLeaderboardResponse,
+    TradingLeaderboardRow,
+};
+use crate::relationships::RelationshipService;
+use crate::schema::{equity_snapshots, fills, trading_leaderboard, user_wallets, users};
+use crate::utils::AppError;
+use crate::DbPool;
+use chrono::{DateTime, Duration, Utc};
+use diesel::dsl::count_star;
+use diesel::prelude::*;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+// Fewer closing trades than this and a win rate says nothing
+const MIN_TRADES_FOR_WIN_RATE: i64 = 5;
+const INSERT_CHUNK_SIZE: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LeaderboardPeriod {
+    Day,
+    Week,
+    Month,
+    All,
+}
+
+impl LeaderboardPeriod {
+    pub const ALL: [LeaderboardPeriod; 4] = [
+        LeaderboardPeriod::Day,
+        LeaderboardPeriod::Week,
+        LeaderboardPeriod::Month,
+        LeaderboardPeriod::All,
+    ];
+
+    pub fn parse(period: Option<&str>) -> Result<Self, AppError> {
+        match period.map(|p| p.to_lowercase()).as_deref() {
+            Some("24h") | Some("1d") => Ok(LeaderboardPeriod::Day),
+            None | Some("7d") => Ok(LeaderboardPeriod::Week),
+            Some("30d") => Ok(LeaderboardPeriod::Month),
+            Some("all") => Ok(LeaderboardPeriod::All),
+            Some(other) => Err(AppError::ValidationError(format!(
+                "Unsupported period: {}",
+                other
+            ))),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LeaderboardPeriod::Day => "24h",
+            LeaderboardPeriod::Week => "7d",
+            LeaderboardPeriod::Month => "30d",
+            LeaderboardPeriod::All => "all",
+        }
+    }
+
+    // Start of the period; all-time starts at the epoch
+    fn since(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            LeaderboardPeriod::Day => now - Duration::hours(24),
+            LeaderboardPeriod::Week => now - Duration::days(7),
+            LeaderboardPeriod::Month => now - Duration::days(30),
+            LeaderboardPeriod::All => DateTime::<Utc>::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LeaderboardMetric {
+    Pnl,
+    Roi,
+    Volume,
+    WinRate,
+}
+
+impl LeaderboardMetric {
+    pub fn parse(metric: Option<&str>) -> Result<Self, AppError> {
+        match metric.map(|m| m.to_lowercase()).as_deref() {
+            None | Some("pnl") | Some("realized_pnl") => Ok(LeaderboardMetric::Pnl),
+            Some("roi") => Ok(LeaderboardMetric::Roi),
+            Some("volume") => Ok(LeaderboardMetric::Volume),
+            Some("win_rate") | Some("winrate") => Ok(LeaderboardMetric::WinRate),
+            Some(other) => Err(AppError::ValidationError(format!(
+                "Unsupported metric: {}",
+                other
+            ))),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LeaderboardMetric::Pnl => "pnl",
+            LeaderboardMetric::Roi => "roi",
+            LeaderboardMetric::Volume => "volume",
+            LeaderboardMetric::WinRate => "win_rate",
+        }
+    }
+
+    fn rank(&self, row: &TradingLeaderboardRow) -> Option<i32> {
+        match self {
+            LeaderboardMetric::Pnl => Some(row.pnl_rank),
+            LeaderboardMetric::Roi => row.roi_rank,
+            LeaderboardMetric::Volume => Some(row.volume_rank),
+            LeaderboardMetric::WinRate => row.win_rate_rank,
+        }
+    }
+}
+
+pub struct LeaderboardService;
+
+impl LeaderboardService {
+    // Recompute every period of the trading leaderboard from the fill ledger
+    pub async fn refresh(pool: &DbPool) -> Result<usize, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let now = Utc::now();
+        let mut total = 0;
+        for period in LeaderboardPeriod::ALL {
+            let rows = Self::compute_period(conn, period, now)?;
+            total += rows.len();
+
+            conn.transaction(|conn| {
+                diesel::delete(trading_leaderboard::table)
+                    .filter(trading_leaderboard::period.eq(period.as_str()))
+                    .execute(conn)?;
+                for chunk in rows.chunks(INSERT_CHUNK_SIZE) {
+                    diesel::insert_into(trading_leaderboard::table)
+                        .values(chunk)
+                        .execute(conn)?;
+                }
+                Ok::<_, AppError>(())
+            })?;
+        }
+
+        Ok(total)
+    }
+
+    pub async fn get_leaderboard(
+        pool: &DbPool,
+        period: LeaderboardPeriod,
+        metric: LeaderboardMetric,
+        page: i64,
+        per_page: i64,
+        viewer_id: Option<Uuid>,
+    ) -> Result<TradingLeaderboardResponse, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let hidden = RelationshipService::hidden_user_ids(conn, viewer_id)?;
+        let ranked = || {
+            let query = trading_leaderboard::table
+                .inner_join(users::table)
+                .filter(trading_leaderboard::period.eq(period.as_str()))
+                .filter(users::leaderboard_opt_out.eq(false))
+                .filter(users::is_private.eq(false))
+                .filter(users::id.ne_all(&hidden))
+                .into_boxed();
+            match metric {
+                LeaderboardMetric::Pnl => query.order(trading_leaderboard::pnl_rank.asc()),
+                LeaderboardMetric::Roi => query
+                    .filter(trading_leaderboard::roi_rank.is_not_null())
+                    .order(trading_leaderboard::roi_rank.asc()),
+                LeaderboardMetric::Volume => query.order(trading_leaderboard::volume_rank.asc()),
+                LeaderboardMetric::WinRate => query
+                    .filter(trading_leaderboard::win_rate_rank.is_not_null())
+                    .order(trading_leaderboard::win_rate_rank.asc()),
+            }
+        };
+
+        let total = ranked().count().get_result::<i64>(conn)?;
+        let rows = ranked()
+            .select((TradingLeaderboardRow::as_select(), users::username, users::avatar_url))
+            .limit(per_page)
+            .offset((page - 1).saturating_mul(per_page))
+            .load::<(TradingLeaderboardRow, String, Option<String>)>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get leaderboard: {}", e)))?;
+
+        let my_rank = match viewer_id {
+            Some(viewer_id) => ranked()
+                .filter(trading_leaderboard::user_id.eq(viewer_id))
+                .select((TradingLeaderboardRow::as_select(), users::username, users::avatar_url))
+                .first::<(TradingLeaderboardRow, String, Option<String>)>(conn)
+                .optional()?
+                .and_then(|(row, username, avatar_url)| entry(&row, username, avatar_url, metric)),
+            None => None,
+        };
+
+        let computed_at = rows.first().map(|(row, _, _)| row.computed_at);
+        let entries = rows
+            .into_iter()
+            .filter_map(|(row, username, avatar_url)| entry(&row, username, avatar_url, metric))
+            .collect();
+
+        Ok(TradingLeaderboardResponse {
+            period: period.as_str().to_string(),
+            metric: metric.as_str().to_string(),
+            entries,
+            page,
+            per_page,
+            total,
+            my_rank,
+            computed_at,
+        })
+    }
+
+    // Hide or show a user on trading leaderboards; hiding takes effect immediately
+    pub async fn set_opt_out(pool: &DbPool, user_id: Uuid, opt_out: bool) -> Result<(), AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        conn.transaction(|conn| {
+            diesel::update(users::table.find(user_id))
+                .set(users::leaderboard_opt_out.eq(opt_out))
+                .execute(conn)?;
+
+            if opt_out {
+                diesel::delete(trading_leaderboard::table)
+                    .filter(trading_leaderboard::user_id.eq(user_id))
+                    .execute(conn)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn compute_period(
+        conn: &mut PgConnection,
+        period: LeaderboardPeriod,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<NewTradingLeaderboardRow>, AppError> {
+        let since = period.since(now);
+
+        // Only fills and equity of wallets the user verified count
+        let verified_fill = || {
+            user_wallets::table.on(user_wallets::wallet_address
+                .eq(fills::wallet_address)
+                .and(user_wallets::user_id.eq(fills::user_id))
+                .and(user_wallets::verified_at.is_not_null()))
+        };
+
+        let totals = fills::table
+            .inner_join(users::table)
+            .inner_join(verified_fill())
+            // Private accounts are not ranked
+            .filter(users::leaderboard_opt_out.eq(false))
+            .filter(users::is_private.eq(false))
+            .filter(fills::executed_at.ge(since))
+            .group_by(fills::user_id)
+            .select((
+                fills::user_id,
+                diesel::dsl::sum(fills::realized_pnl),
+                diesel::dsl::sum(fills::fee),
+                diesel::dsl::sum(fills::size * fills::price),
+            ))
+            .load::<(Uuid, Option<f64>, Option<f64>, Option<f64>)>(conn)?;
+        if totals.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let closing: HashMap<Uuid, i64> = fills::table
+            .inner_join(verified_fill())
+            .filter(fills::executed_at.ge(since))
+            .filter(fills::realized_pnl.ne(0.0))
+            .group_by(fills::user_id)
+            .select((fills::user_id, count_star()))
+            .load::<(Uuid, i64)>(conn)?
+            .into_iter()
+            .collect();
+        let wins: HashMap<Uuid, i64> = fills::table
+            .inner_join(verified_fill())
+            .filter(fills::executed_at.ge(since))
+            .filter(fills::realized_pnl.gt(0.0))
+            .group_by(fills::user_id)
+            .select((fills::user_id, count_star()))
+            .load::<(Uuid, i64)>(conn)?
+            .into_iter()
+            .collect();
+
+        // ROI is measured against each user's equity at the start of the period,
+        // summed over the first snapshot of every wallet
+        let mut starting_equity: HashMap<Uuid, f64> = HashMap::new();
+        let first_snapshots = equity_snapshots::table
+            .inner_join(user_wallets::table.on(user_wallets::wallet_address
+                .eq(equity_snapshots::wallet_address)
+                .and(user_wallets::user_id.eq(equity_snapshots::user_id))
+                .and(user_wallets::verified_at.is_not_null())))
+            .filter(equity_snapshots::recorded_at.ge(since))
+            .order((
+                equity_snapshots::user_id,
+                equity_snapshots::wallet_address,
+                equity_snapshots::recorded_at.asc(),
+            ))
+            .distinct_on((equity_snapshots::user_id, equity_snapshots::wallet_address))
+            .select((equity_snapshots::user_id, equity_snapshots::equity))
+            .load::<(Uuid, f64)>(conn)?;
+        for (user_id, equity) in first_snapshots {
+            *starting_equity.entry(user_id).or_default() += equity;
+        }
+
+        let mut rows: Vec<NewTradingLeaderboardRow> = totals
+            .into_iter()
+            .map(|(user_id, realized_pnl, fees, volume)| {
+                let net_pnl = realized_pnl.unwrap_or(0.0) - fees.unwrap_or(0.0);
+                let trade_count = closing.get(&user_id).copied().unwrap_or(0);
+                let win_rate = (trade_count > 0).then(|| {
+                    wins.get(&user_id).copied().unwrap_or(0) as f64 / trade_count as f64
+                });
+                let roi = starting_equity
+                    .get(&user_id)
+                    .filter(|equity| **equity > 0.0)
+                    .map(|equity| net_pnl / equity);
+
+                NewTradingLeaderboardRow {
+                    user_id,
+                    period: period.as_str().to_string(),
+                    realized_pnl: net_pnl,
+                    roi,
+                    volume: volume.unwrap_or(0.0),
+                    win_rate,
+                    trade_count,
+                    pnl_rank: 0,
+                    roi_rank: None,
+                    volume_rank: 0,
+                    win_rate_rank: None,
+                    computed_at: now,
+                }
+            })
+            .collect();
+
+        let pnl_ranks = rank(rows.iter().map(|r| Some(r.realized_pnl)).collect());
+        let roi_ranks = rank(rows.iter().map(|r| r.roi).collect());
+        let volume_ranks = rank(rows.iter().map(|r| Some(r.volume)).collect());
+        let win_rate_ranks = rank(
+            rows.iter()
+                .map(|r| r.win_rate.filter(|_| r.trade_count >= MIN_TRADES_FOR_WIN_RATE))
+                .collect(),
+        );
+        for (index, row) in rows.iter_mut().enumerate() {
+            row.pnl_rank = pnl_ranks[index].unwrap_or_default();
+            row.roi_rank = roi_ranks[index];
+            row.volume_rank = volume_ranks[index].unwrap_or_default();
+            row.win_rate_rank = win_rate_ranks[index];
+        }
+
+        Ok(rows)
+    }
+}
+
+// Competition ranking ("1224"), highest value first; None values stay unranked
+fn rank(values: Vec<Option<f64>>) -> Vec<Option<i32>> {
+    let mut order: Vec<(usize, f64)> = values
+        .iter()
+        .enumerate()
+        .filter_map(|(index, value)| value.map(|v| (index, v)))
+        .collect();
+    order.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    let mut ranks = vec![None; values.len()];
+    let mut previous: Option<(f64, i32)> = None;
+    for (position, (index, value)) in order.into_iter().enumerate() {
+        let rank = match previous {
+            Some((previous_value, previous_rank)) if previous_value == value => previous_rank,
+            _ => position as i32 + 1,
+        };
+        ranks[index] = Some(rank);
+        previous = Some((value, rank));
+    }
+    ranks
+}
+
+fn entry(
+    row: &TradingLeaderboardRow,
+    username: String,
+    avatar_url: Option<String>,
+    metric: LeaderboardMetric,
+) -> Option<TradingLeaderboardEntry> {
+    Some(TradingLeaderboardEntry {
+        rank: metric.rank(row)?,
+        username,
+        avatar_url,
+        realized_pnl: row.realized_pnl,
+        roi: row.roi,
+        volume: row.volume,
+        win_rate: row.win_rate,
+        trade_count: row.trade_count,
+    })
+}
+
+// Background job that rebuilds the trading leaderboard
+pub struct LeaderboardJob;
+
+impl LeaderboardJob {
+    pub fn spawn(pool: DbPool) {
+        let interval_secs = AppConfig::global().leaderboard_refresh_interval_secs;
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(std::time::Duration::from_secs(interval_secs));
+
+            loop {
+                ticker.tick().await;
+
+                match LeaderboardService::refresh(&pool).await {
+                    Ok(count) => log::debug!("Refreshed {} trading leaderboard rows", count),
+                    Err(e) => log::error!("Failed to refresh trading leaderboard: {}", e),
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rank_uses_competition_ranking() {
+        let ranks = rank(vec![Some(10.0), Some(30.0), None, Some(10.0), Some(-5.0)]);
+        assert_eq!(ranks, vec![Some(2), Some(1), None, Some(2), Some(4)]);
+        assert!(rank(vec![]).is_empty());
+    }
+
+    #[test]
+    fn test_period_and_metric_parse() {
+        assert_eq!(LeaderboardPeriod::parse(None).unwrap(), LeaderboardPeriod::Week);
+        assert_eq!(LeaderboardPeriod::parse(Some("1D")).unwrap(), LeaderboardPeriod::Day);
+        assert!(LeaderboardPeriod::parse(Some("90d")).is_err());
+
+        assert_eq!(LeaderboardMetric::parse(None).unwrap(), LeaderboardMetric::Pnl);
+        assert_eq!(LeaderboardMetric::parse(Some("winrate")).unwrap(), LeaderboardMetric::WinRate);
+        assert!(LeaderboardMetric::parse(Some("sharpe")).is_err());
+    }
+
+    #[test]
+    fn test_period_since() {
+        let now = Utc::now();
+        assert_eq!(LeaderboardPeriod::Day.since(now), now - Duration::hours(24));
+        assert_eq!(LeaderboardPeriod::Month.since(now), now - Duration::days(30));
+        assert_eq!(LeaderboardPeriod::All.since(now).timestamp(), 0);
+    }
+
+    #[test]
+    fn test_entry_is_only_built_for_ranked_metrics() {
+        let row = TradingLeaderboardRow {
+            id: Uuid::new_v4(),
+            user_id: Uuid::new_v4(),
+            period: "7d".to_string(),
+            realized_pnl: 12.0,
+            roi: None,
+            volume: 1000.0,
+            win_rate: Some(0.5),
+            trade_count: 2,
+            pnl_rank: 3,
+            roi_rank: None,
+            volume_rank: 1,
+            win_rate_rank: None,
+            computed_at: Utc::now(),
+        };
+
+        assert_eq!(entry(&row, "alice".to_string(), None, LeaderboardMetric::Pnl).unwrap().rank, 3);
+        assert_eq!(entry(&row, "alice".to_string(), None, LeaderboardMetric::Volume).unwrap().rank, 1);
+        assert!(entry(&row, "alice".to_string(), None, LeaderboardMetric::Roi).is_none());
+        assert!(entry(&row, "alice".to_string(), None, LeaderboardMetric::WinRate).is_none());
+    }
+}
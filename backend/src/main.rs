@@ -1,21 +1,48 @@
-use actix_cors::Cors;
-use actix_web::{middleware::Logger, web, App, HttpServer};
+use actix_web::{guard, web, App, HttpServer};
 use diesel::pg::PgConnection;
 use diesel::r2d2::{self, ConnectionManager};
 use dotenv::dotenv;
 use log::info;
-use std::env;
+use middleware::AuthMiddleware;
 
+mod account_deletion;
+mod activity;
+mod alerts;
 mod auth;
+mod avatars;
+mod config;
+mod copy_trading;
 mod db;
+mod discovery;
 mod email;
+mod equity;
+mod exports;
+mod fills;
+mod funding;
 mod handlers;
+mod jwt_keys;
 mod kana_client;
+mod leaderboard;
+mod markets;
 mod middleware;
 mod models;
+mod notifications;
+mod paper_trading;
+mod portfolio;
+mod positions;
+mod posts;
+mod profiles;
+mod rate_limit;
+mod referral_abuse;
+mod referral_codes;
+mod referrals;
+mod relationships;
+mod risk;
 mod schema;
+mod security;
 mod social;
 mod utils;
+mod wallets;
 
 pub type DbPool = r2d2::Pool<ConnectionManager<PgConnection>>;
 
@@ -24,25 +51,43 @@ async fn main() -> std::io::Result<()> {
     dotenv().ok();
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
 
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    let host = env::var("HOST").unwrap_or_else(|_| "0.0.0.0".to_string()); // Default to 0.0.0.0 for Railway
-    let port = env::var("PORT").unwrap_or_else(|_| "8081".to_string());
-    let bind_address = format!("{}:{}", host, port);
+    let config = match config::AppConfig::load() {
+        Ok(config) => config::AppConfig::init(config).clone(),
+        Err(e) => {
+            log::error!("Failed to load configuration: {}", e);
+            return Err(std::io::Error::other(e.to_string()));
+        }
+    };
+
+    let jwt_keys = match jwt_keys::JwtKeys::load(
+        config.jwt_keys_dir.as_deref().map(std::path::Path::new),
+        config.jwt_signing_kid.as_deref(),
+        &config.jwt_issuer,
+        &config.jwt_audience,
+    ) {
+        Ok(jwt_keys) => jwt_keys::JwtKeys::init(jwt_keys),
+        Err(e) => {
+            log::error!("Failed to load JWT keys: {}", e);
+            return Err(std::io::Error::other(e));
+        }
+    };
+    if config.jwt_keys_dir.is_none() {
+        log::warn!("JWT_KEYS_DIR is not set; tokens are signed with a temporary key and stop working on restart");
+    }
+    info!("Signing access tokens with key {}", jwt_keys.signing_kid());
+
+    let bind_address = format!("{}:{}", config.host, config.port);
 
     info!("Binding to: {}", bind_address);
-    info!(
-        "Database URL configured: {}",
-        if database_url.is_empty() { "No" } else { "Yes" }
-    );
 
-    // Create database connection pool with better configuration
-    let manager = ConnectionManager::<PgConnection>::new(database_url);
+    // Create database connection pool
+    let manager = ConnectionManager::<PgConnection>::new(config.database_url.clone());
     let pool = r2d2::Pool::builder()
-        .max_size(10) // Maximum number of connections in the pool
-        .min_idle(Some(1)) // Minimum idle connections
-        .connection_timeout(std::time::Duration::from_secs(30))
-        .idle_timeout(Some(std::time::Duration::from_secs(600))) // 10 minutes
-        .max_lifetime(Some(std::time::Duration::from_secs(1800))) // 30 minutes
+        .max_size(config.database_max_connections)
+        .min_idle(Some(config.database_min_idle))
+        .connection_timeout(std::time::Duration::from_secs(config.database_connect_timeout_secs))
+        .idle_timeout(Some(std::time::Duration::from_secs(config.database_idle_timeout_secs)))
+        .max_lifetime(Some(std::time::Duration::from_secs(config.database_max_lifetime_secs)))
         .test_on_check_out(true) // Test connections before use
         .build(manager)
         .expect("Failed to create pool");
@@ -50,22 +95,39 @@ async fn main() -> std::io::Result<()> {
     // Run database migrations
     if let Err(e) = db::run_migrations(&pool) {
         log::error!("Failed to run migrations: {}", e);
-        return Err(std::io::Error::new(std::io::ErrorKind::Other, e));
+        return Err(std::io::Error::other(e));
     }
 
+    // Start background jobs
+    funding::FundingCollector::spawn(pool.clone());
+    fills::FillReconciler::spawn(pool.clone());
+    equity::EquitySnapshotter::spawn(pool.clone());
+    leaderboard::LeaderboardJob::spawn(pool.clone());
+    alerts::AlertWorker::spawn(pool.clone());
+    avatars::AvatarGcJob::spawn(pool.clone());
+    exports::DataExportJob::spawn(pool.clone());
+    account_deletion::AccountDeletionJob::spawn(pool.clone());
+
+    let rate_limiter = match rate_limit::RateLimiter::from_config(&config).await {
+        Ok(rate_limiter) => rate_limiter,
+        Err(e) => {
+            log::error!("Failed to set up rate limiting: {}", e);
+            return Err(std::io::Error::other(e.to_string()));
+        }
+    };
+
     info!("Starting server at {}", bind_address);
 
     HttpServer::new(move || {
-        let cors = Cors::default()
-            .allow_any_origin()
-            .allow_any_method()
-            .allow_any_header()
-            .max_age(3600);
-
         App::new()
-            .wrap(Logger::default())
-            .wrap(cors)
+            .wrap(security::access_logger())
+            .wrap(rate_limiter.clone())
+            .wrap(security::cors(&config.cors_allowed_origins).expose_headers(rate_limit::RATE_LIMIT_HEADERS))
+            .wrap(security::SecurityHeaders::new(config.hsts_max_age_secs.unwrap_or_default()))
             .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(config.clone()))
+            .configure(avatars::LocalAvatarStorage::configure)
+            .service(handlers::well_known::jwks)
             .service(
                 web::scope("/api")
                     .service(handlers::health::health_check)
@@ -78,33 +140,58 @@ async fn main() -> std::io::Result<()> {
                             .service(handlers::auth::check_username)
                             .service(handlers::auth::forgot_password)
                             .service(handlers::auth::reset_password)
-                            .service(web::resource("/me").route(web::get().to(handlers::auth::me))),
+                            .service(
+                                web::resource("/me")
+                                    .wrap(AuthMiddleware::required())
+                                    .route(web::get().to(handlers::auth::me)),
+                            )
+                            .service(
+                                web::resource("/session")
+                                    .wrap(AuthMiddleware::required())
+                                    .route(web::get().to(handlers::auth::session)),
+                            ),
                     )
                     .service(
+                        // Market data is public; reads of the user's own orders and
+                        // positions check the token themselves
                         web::scope("/trading")
+                            .wrap(AuthMiddleware::optional())
+                            // Every write needs a signed-in user. Writes only reach the routes in
+                            // here, so one registered elsewhere in the scope answers 404 rather
+                            // than running without a token.
+                            .service(
+                                web::scope("")
+                                    .guard(guard::fn_guard(middleware::is_write))
+                                    .wrap(AuthMiddleware::required())
+                                    .service(handlers::trading::place_order)
+                                    .service(handlers::trading::cancel_order)
+                                    .service(handlers::trading::cancel_multiple_orders)
+                                    .service(handlers::trading::cancel_and_place_multiple_orders)
+                                    .service(handlers::trading::reset_paper_account),
+                            )
                             .service(handlers::trading::get_markets)
                             .service(handlers::trading::get_orderbook)
-                            .service(handlers::trading::place_order)
                             .service(handlers::trading::get_orders)
-                            .service(handlers::trading::cancel_order)
                             .service(handlers::trading::get_positions)
                             .service(handlers::trading::get_funding_rate)
+                            .service(handlers::trading::get_funding_history)
                             .service(handlers::trading::get_market_price)
                             .service(handlers::trading::get_chart_data)
                             .service(handlers::trading::get_open_orders)
                             .service(handlers::trading::get_order_history)
                             .service(handlers::trading::place_limit_order)
-                            .service(handlers::trading::cancel_multiple_orders)
-                            .service(handlers::trading::cancel_and_place_multiple_orders)
                             .service(handlers::trading::get_order_status_by_order_id)
                             .service(handlers::trading::get_market_price_by_id)
                             .service(handlers::trading::get_last_placed_price)
                             .service(handlers::trading::add_margin)
                             .service(handlers::trading::collapse_position)
-                            .service(handlers::trading::settle_pnl),
+                            .service(handlers::trading::settle_pnl)
+                            .service(handlers::trading::get_paper_account),
                     )
                     .service(
+                        // Lookups and unsigned transaction payloads; nothing here writes
                         web::scope("/wallet")
+                            .wrap(AuthMiddleware::optional())
                             .service(handlers::wallet::get_profile_address)
                             .service(handlers::wallet::get_wallet_account_balance)
                             .service(handlers::wallet::get_profile_balance_snapshot)
@@ -113,24 +200,126 @@ async fn main() -> std::io::Result<()> {
                     )
                     .service(
                         web::scope("/user")
+                            .wrap(AuthMiddleware::required())
                             .service(handlers::user::get_profile)
                             .service(handlers::user::update_profile)
-                            .service(handlers::user::get_balance),
+                            .service(handlers::user::get_profile_history)
+                            .service(handlers::user::upload_avatar)
+                            .service(handlers::user::delete_avatar)
+                            .service(handlers::user::request_data_export)
+                            .service(handlers::user::get_data_export)
+                            .service(handlers::user::download_data_export)
+                            .service(handlers::user::request_account_deletion)
+                            .service(handlers::user::cancel_account_deletion)
+                            .service(handlers::user::get_balance)
+                            .service(handlers::user::get_wallets)
+                            .service(handlers::user::create_wallet_challenge)
+                            .service(handlers::user::link_wallet)
+                            .service(handlers::user::unlink_wallet)
+                            .service(handlers::user::get_funding_payments)
+                            .service(handlers::user::get_fills)
+                            .service(handlers::user::sync_fills)
+                            .service(handlers::user::get_pnl)
+                            .service(handlers::user::get_portfolio)
+                            .service(handlers::user::get_equity_history),
+                    )
+                    .service(
+                        web::scope("/copy-trading")
+                            .wrap(AuthMiddleware::required())
+                            .service(handlers::copy_trading::subscribe)
+                            .service(handlers::copy_trading::get_subscriptions)
+                            .service(handlers::copy_trading::stop_copying)
+                            .service(handlers::copy_trading::get_copy_trades)
+                            .service(handlers::copy_trading::report_copy_trade_result),
+                    )
+                    .service(
+                        web::scope("/alerts")
+                            .wrap(AuthMiddleware::required())
+                            .service(handlers::alerts::get_alerts)
+                            .service(handlers::alerts::create_alert)
+                            .service(handlers::alerts::get_alert_history)
+                            .service(handlers::alerts::update_alert)
+                            .service(handlers::alerts::delete_alert),
+                    )
+                    .service(
+                        web::scope("/notifications")
+                            .wrap(AuthMiddleware::required())
+                            .service(handlers::notifications::get_notifications)
+                            .service(handlers::notifications::mark_read)
+                            .service(handlers::notifications::get_unread_count),
+                    )
+                    // Browsers cannot set headers on WebSocket requests, so the
+                    // channel accepts its token in the query string
+                    .service(
+                        web::scope("/ws")
+                            .service(handlers::notifications::private_channel),
                     )
                     .service(
+                        // Profiles, posts and leaderboards are public; the user's own
+                        // lists and settings check the token themselves
                         web::scope("/social")
-                            .service(handlers::social::follow_user)
-                            .service(handlers::social::unfollow_user)
+                            .wrap(AuthMiddleware::optional())
+                            // Anyone following a referral link, signed in or not
+                            .service(handlers::referrals::record_referral_click)
+                            // Every write needs a signed-in user. Writes only reach the routes in
+                            // here, so one registered elsewhere in the scope answers 404 rather
+                            // than running without a token.
+                            .service(
+                                web::scope("")
+                                    .guard(guard::fn_guard(middleware::is_write))
+                                    .wrap(AuthMiddleware::required())
+                                    .service(handlers::social::follow_user)
+                                    .service(handlers::social::unfollow_user)
+                                    .service(handlers::social::update_profile)
+                                    .service(handlers::social::set_leaderboard_opt_out)
+                                    .service(handlers::social::update_activity_privacy)
+                                    .service(handlers::posts::create_post)
+                                    .service(handlers::posts::update_post)
+                                    .service(handlers::posts::delete_post)
+                                    .service(handlers::posts::like_post)
+                                    .service(handlers::posts::unlike_post)
+                                    .service(handlers::posts::add_comment)
+                                    .service(handlers::posts::delete_comment)
+                                    .service(handlers::posts::moderate_post)
+                                    .service(handlers::posts::moderate_comment)
+                                    .service(handlers::referrals::create_referral_code)
+                                    .service(handlers::referrals::update_referral_code)
+                                    .service(handlers::referrals::update_reward_status)
+                                    .service(handlers::referrals::record_deposit)
+                                    .service(handlers::referrals::review_referral_flag)
+                                    .service(handlers::relationships::block_user)
+                                    .service(handlers::relationships::unblock_user)
+                                    .service(handlers::relationships::mute_user)
+                                    .service(handlers::relationships::unmute_user)
+                                    .service(handlers::relationships::set_account_privacy)
+                                    .service(handlers::relationships::approve_follow_request)
+                                    .service(handlers::relationships::reject_follow_request),
+                            )
                             .service(handlers::social::get_followers)
                             .service(handlers::social::get_following)
                             .service(handlers::social::get_follow_stats)
                             .service(handlers::social::get_public_profile)
-                            .service(handlers::social::update_profile)
                             .service(handlers::social::get_referral_leaderboard)
+                            .service(handlers::social::get_trading_leaderboard)
+                            .service(handlers::social::get_feed)
+                            .service(handlers::social::get_activity_privacy)
+                            .service(handlers::posts::get_user_posts)
+                            .service(handlers::posts::get_post)
+                            .service(handlers::posts::get_comments)
                             .service(handlers::social::get_referral_info)
                             .service(handlers::social::get_referred_users)
+                            .service(handlers::referrals::get_referral_codes)
+                            .service(handlers::referrals::get_referral_rewards)
+                            .service(handlers::referrals::get_referral_tree)
+                            .service(handlers::referrals::get_user_referral_tree)
+                            .service(handlers::referrals::get_referral_flags)
+                            .service(handlers::relationships::get_blocks)
+                            .service(handlers::relationships::get_mutes)
+                            .service(handlers::relationships::get_follow_requests)
                             .service(handlers::social::check_following_status)
-                            .service(handlers::social::get_all_users),
+                            .service(handlers::social::get_all_users)
+                            .service(handlers::social::search_users)
+                            .service(handlers::social::get_suggested_users),
                     ),
             )
     })
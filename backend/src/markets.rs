@@ -0,0 +1,118 @@
+use crate::kana_client::KanaClient;
+use crate::utils::AppError;
+use serde::Serialize;
+
+// Static description of a Kana Labs perps market we support
+#[derive(Debug, Clone, Serialize)]
+pub struct MarketSpec {
+    pub symbol: &'static str,
+    pub market_id: &'static str,
+    pub base_asset: &'static str,
+    pub quote_asset: &'static str,
+    pub tick_size: f64,
+    pub min_order_size: f64,
+    pub max_leverage: f64,
+    // Fraction of notional that must remain as margin before liquidation
+    pub maintenance_margin_rate: f64,
+}
+
+const MARKETS: &[MarketSpec] = &[
+    MarketSpec {
+        symbol: "APT/USDC",
+        market_id: "1338",
+        base_asset: "APT",
+        quote_asset: "USDC",
+        tick_size: 0.001,
+        min_order_size: 0.001,
+        max_leverage: 20.0,
+        maintenance_margin_rate: 0.025,
+    },
+    MarketSpec {
+        symbol: "BTC/USDC",
+        market_id: "1339",
+        base_asset: "BTC",
+        quote_asset: "USDC",
+        tick_size: 0.1,
+        min_order_size: 0.0001,
+        max_leverage: 50.0,
+        maintenance_margin_rate: 0.005,
+    },
+    MarketSpec {
+        symbol: "ETH/USDC",
+        market_id: "1340",
+        base_asset: "ETH",
+        quote_asset: "USDC",
+        tick_size: 0.01,
+        min_order_size: 0.001,
+        max_leverage: 50.0,
+        maintenance_margin_rate: 0.01,
+    },
+    MarketSpec {
+        symbol: "SOL-USD",
+        market_id: "2387",
+        base_asset: "SOL",
+        quote_asset: "USDC",
+        tick_size: 0.001,
+        min_order_size: 0.01,
+        max_leverage: 20.0,
+        maintenance_margin_rate: 0.025,
+    },
+];
+
+pub struct MarketRegistry;
+
+impl MarketRegistry {
+    // All markets known to the backend
+    pub fn all() -> &'static [MarketSpec] {
+        MARKETS
+    }
+
+    // Look up a market by symbol ("APT/USDC", "APT-USDC" and "apt/usdc" all match)
+    pub fn by_symbol(symbol: &str) -> Option<&'static MarketSpec> {
+        let wanted = normalize_symbol(symbol);
+        MARKETS
+            .iter()
+            .find(|market| normalize_symbol(market.symbol) == wanted)
+    }
+
+    // Look up a market by Kana Labs market ID
+    pub fn by_market_id(market_id: &str) -> Option<&'static MarketSpec> {
+        MARKETS.iter().find(|market| market.market_id == market_id)
+    }
+
+    // Same as by_symbol but fails with a validation error for unknown symbols
+    pub fn require(symbol: &str) -> Result<&'static MarketSpec, AppError> {
+        Self::by_symbol(symbol)
+            .ok_or_else(|| AppError::ValidationError(format!("Unsupported symbol: {}", symbol)))
+    }
+}
+
+impl MarketSpec {
+    // Live mid price from Kana Labs; None when there is none, as a stale price
+    // would mark positions and fill paper orders at a value the market left long ago
+    pub async fn mid_price(&self) -> Option<f64> {
+        let client = KanaClient::new().ok()?;
+        match client.get_market_price(self.symbol).await {
+            Ok(price) => Some(price),
+            Err(e) => {
+                log::warn!("No live price for {}: {}", self.symbol, e);
+                None
+            }
+        }
+    }
+
+    // Same as mid_price but fails when there is no live price
+    pub async fn require_mid_price(&self) -> Result<f64, AppError> {
+        self.mid_price()
+            .await
+            .ok_or_else(|| AppError::ExternalApiError(format!("No live price for {}", self.symbol)))
+    }
+}
+
+// SOL is listed against USD on Kana Labs, every other market against USDC
+fn normalize_symbol(symbol: &str) -> String {
+    symbol
+        .to_uppercase()
+        .replace('-', "/")
+        .replace("/USDC", "/USD")
+}
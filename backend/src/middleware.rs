@@ -1,48 +1,189 @@
 use actix_web::{
-    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
-    error::ErrorUnauthorized,
-    http::header,
-    Error, HttpMessage, HttpRequest,
+    body::EitherBody,
+    dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    guard::GuardContext,
+    http::{
+        header::{self, HeaderMap},
+        Method,
+    },
+    web, Error, FromRequest, HttpMessage, HttpRequest,
 };
 use futures_util::future::LocalBoxFuture;
+use serde::Serialize;
+use std::future::{ready, Ready};
 use std::rc::Rc;
-use crate::auth::{AuthService, Claims};
+use uuid::Uuid;
+use crate::auth::{AuthService, Claims, ROLE_ADMIN};
 use crate::utils::AppError;
+use crate::DbPool;
 
-pub struct AuthMiddleware;
+// The signed-in user of a request. As an extractor it turns the request away
+// with 401 unless it carries a valid access token of an open session; take
+// Option<AuthenticatedUser> on routes where signing in is optional.
+#[derive(Debug, Clone, Serialize)]
+pub struct AuthenticatedUser {
+    pub id: Uuid,
+    pub username: String,
+    pub roles: Vec<String>,
+    pub scopes: Vec<String>,
+    // Session the token was issued for; None for tokens issued before tokens
+    // named their session, which ensure_session turns away
+    pub session_id: Option<Uuid>,
+}
+
+impl AuthenticatedUser {
+    pub fn from_token(token: &str) -> Result<Self, AppError> {
+        let claims = AuthService::verify_access_token(token)
+            .map_err(|_| AppError::AuthenticationError("Invalid token".to_string()))?;
+        Self::try_from(claims)
+    }
+
+    // From the Authorization header, for routes outside AuthMiddleware
+    pub fn from_headers(headers: &HeaderMap) -> Result<Self, AppError> {
+        let auth_header = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .ok_or_else(|| AppError::AuthenticationError("Missing authorization header".to_string()))?;
+
+        let token = auth_header
+            .strip_prefix("Bearer ")
+            .filter(|token| !token.is_empty())
+            .ok_or_else(|| AppError::AuthenticationError("Invalid authorization header format".to_string()))?;
+
+        Self::from_token(token)
+    }
+
+    // A signature only shows the token was issued, so this checks its session
+    // has not since ended; otherwise a logged-out token would keep working
+    // until it expired
+    pub async fn ensure_session(self, pool: &DbPool) -> Result<Self, AppError> {
+        let session_id = self
+            .session_id
+            .ok_or_else(|| AppError::AuthenticationError("Token does not name a session".to_string()))?;
+
+        if AuthService::session_active(pool, self.id, session_id).await? {
+            Ok(self)
+        } else {
+            Err(AppError::AuthenticationError("Session has ended".to_string()))
+        }
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.roles.iter().any(|role| role == ROLE_ADMIN)
+    }
+
+    // Turns non-admins away before any work is done. Roles can be a token
+    // lifetime out of date, so services still check the database.
+    pub fn require_admin(&self) -> Result<(), AppError> {
+        if self.is_admin() {
+            Ok(())
+        } else {
+            Err(AppError::AuthorizationError("Admin access required".to_string()))
+        }
+    }
+}
+
+impl TryFrom<Claims> for AuthenticatedUser {
+    type Error = AppError;
+
+    fn try_from(claims: Claims) -> Result<Self, Self::Error> {
+        let id = claims
+            .sub
+            .parse::<Uuid>()
+            .map_err(|_| AppError::AuthenticationError("Invalid user ID in token".to_string()))?;
+
+        Ok(AuthenticatedUser {
+            id,
+            username: claims.username,
+            roles: claims.roles,
+            scopes: claims.scope.split_whitespace().map(str::to_string).collect(),
+            session_id: claims.sid,
+        })
+    }
+}
+
+// The user of the Authorization header, once their session is confirmed open
+async fn authenticate(req: &HttpRequest) -> Result<AuthenticatedUser, AppError> {
+    let user = AuthenticatedUser::from_headers(req.headers())?;
+    let pool = req
+        .app_data::<web::Data<DbPool>>()
+        .ok_or_else(|| AppError::InternalServerError("Database pool not configured".to_string()))?;
+    user.ensure_session(pool).await
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = AppError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        // AuthMiddleware has already checked the token when it let a user through
+        if let Some(user) = req.extensions().get::<AuthenticatedUser>() {
+            let user = user.clone();
+            return Box::pin(async move { Ok(user) });
+        }
+
+        let req = req.clone();
+        Box::pin(async move { authenticate(&req).await })
+    }
+}
+
+// Guard for requests that may change state: any method but GET, HEAD and
+// OPTIONS. A scope behind it and AuthMiddleware::required() makes every write
+// of a public scope need a signed-in user.
+pub fn is_write(ctx: &GuardContext) -> bool {
+    !matches!(ctx.head().method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+// Authenticates the requests of a scope. `required` answers 401 to requests
+// without a valid access token; `optional` lets them through anonymously.
+// Either way handlers find the AuthenticatedUser of a valid token.
+#[derive(Clone, Copy)]
+pub struct AuthMiddleware {
+    required: bool,
+}
+
+impl AuthMiddleware {
+    pub fn required() -> Self {
+        AuthMiddleware { required: true }
+    }
+
+    pub fn optional() -> Self {
+        AuthMiddleware { required: false }
+    }
+}
 
 impl<S, B> Transform<S, ServiceRequest> for AuthMiddleware
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static + Send,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
-    type Response = ServiceResponse<B>;
+    type Response = ServiceResponse<EitherBody<B>>;
     type Error = Error;
     type InitError = ();
     type Transform = AuthMiddlewareService<S>;
-    type Future = std::pin::Pin<Box<dyn std::future::Future<Output = Result<Self::Transform, Self::InitError>> + Send>>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
 
     fn new_transform(&self, service: S) -> Self::Future {
-        Box::pin(async move {
-            Ok(AuthMiddlewareService {
-                service: Rc::new(service),
-            })
-        })
+        ready(Ok(AuthMiddlewareService {
+            service: Rc::new(service),
+            required: self.required,
+        }))
     }
 }
 
 pub struct AuthMiddlewareService<S> {
     service: Rc<S>,
+    required: bool,
 }
 
 impl<S, B> Service<ServiceRequest> for AuthMiddlewareService<S>
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static + Send,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
-    type Response = ServiceResponse<B>;
+    type Response = ServiceResponse<EitherBody<B>>;
     type Error = Error;
     type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
 
@@ -50,59 +191,157 @@ where
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
         let svc = self.service.clone();
+        let required = self.required;
 
         Box::pin(async move {
-            // Extract token from Authorization header
-            let token = extract_token_from_request(&req)?;
-            
-            // Verify token
-            let claims = AuthService::verify_access_token(&token)
-                .map_err(|e| ErrorUnauthorized(e.to_string()))?;
+            // An outer AuthMiddleware has already let this user through
+            if req.extensions().contains::<AuthenticatedUser>() {
+                let res = svc.call(req).await?;
+                return Ok(res.map_into_left_body());
+            }
 
-            // Add claims to request extensions
-            req.extensions_mut().insert(claims);
+            match authenticate(req.request()).await {
+                Ok(user) => {
+                    req.extensions_mut().insert(user);
+                }
+                // Answered here rather than returned as an error, so the
+                // response still passes through CORS and the other middleware
+                Err(e) if required => return Ok(req.error_response(e).map_into_right_body()),
+                Err(_) => {}
+            }
 
-            // Continue with the request
             let res = svc.call(req).await?;
-            Ok(res)
+            Ok(res.map_into_left_body())
         })
     }
 }
 
-fn extract_token_from_request(req: &ServiceRequest) -> Result<String, Error> {
-    let auth_header = req
-        .headers()
-        .get(header::AUTHORIZATION)
-        .and_then(|h| h.to_str().ok())
-        .ok_or_else(|| {
-            ErrorUnauthorized("Authorization header missing")
-        })?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::{insert_user, test_database};
+    use crate::models::NewSession;
+    use crate::schema::{sessions, users};
+    use actix_web::{guard, test, App, HttpResponse};
+    use chrono::{Duration, Utc};
+    use diesel::prelude::*;
+    use diesel::r2d2::{ConnectionManager, Pool};
 
-    if !auth_header.starts_with("Bearer ") {
-        return Err(ErrorUnauthorized("Invalid authorization header format"));
+    fn user(session_id: Option<Uuid>) -> AuthenticatedUser {
+        AuthenticatedUser {
+            id: Uuid::new_v4(),
+            username: "alice".to_string(),
+            roles: vec![],
+            scopes: vec![],
+            session_id,
+        }
     }
 
-    let token = auth_header[7..].to_string();
-    if token.is_empty() {
-        return Err(ErrorUnauthorized("Token is empty"));
+    // Never connects; only for paths that are settled before the lookup
+    fn pool() -> DbPool {
+        Pool::builder().build_unchecked(ConnectionManager::new("postgres://localhost/unused"))
     }
 
-    Ok(token)
-}
+    #[actix_web::test]
+    async fn test_logged_out_tokens_are_refused() {
+        let Some(pool) = test_database() else { return };
+        let conn = &mut pool.get().unwrap();
 
-// Helper function to extract claims from request extensions
-#[allow(dead_code)]
-pub fn extract_claims_from_request(req: &HttpRequest) -> Result<Claims, AppError> {
-    req.extensions()
-        .get::<Claims>()
-        .cloned()
-        .ok_or_else(|| AppError::Unauthorized("User not authenticated".to_string()))
-}
+        let user_id = insert_user(conn, "logout");
+
+        // Stored the way sign-in stores it
+        let refresh_token = AuthService::generate_refresh_token();
+        let session_id = diesel::insert_into(sessions::table)
+            .values(&NewSession {
+                user_id,
+                refresh_token_hash: AuthService::hash_refresh_token(&refresh_token),
+                expires_at: Utc::now() + Duration::days(1),
+            })
+            .returning(sessions::id)
+            .get_result::<Uuid>(conn)
+            .unwrap();
+
+        let signed_in = AuthenticatedUser { id: user_id, ..user(Some(session_id)) };
+        assert!(signed_in.clone().ensure_session(&pool).await.is_ok());
+
+        AuthService::logout(&pool, &refresh_token).await.unwrap();
+        let error = signed_in.ensure_session(&pool).await.unwrap_err();
+        assert!(matches!(error, AppError::AuthenticationError(_)));
+
+        diesel::delete(users::table.find(user_id)).execute(conn).unwrap();
+    }
 
-// Helper function to get user ID from request
-#[allow(dead_code)]
-pub fn get_user_id_from_request(req: &HttpRequest) -> Result<uuid::Uuid, AppError> {
-    let claims = extract_claims_from_request(req)?;
-    uuid::Uuid::parse_str(&claims.sub)
-        .map_err(|_| AppError::Unauthorized("Invalid user ID in token".to_string()))
+    #[actix_web::test]
+    async fn test_tokens_without_a_session_are_refused() {
+        let error = user(None).ensure_session(&pool()).await.unwrap_err();
+        assert!(matches!(error, AppError::AuthenticationError(_)));
+    }
+
+    #[actix_web::test]
+    async fn test_extractor_needs_a_token() {
+        let req = test::TestRequest::default().to_http_request();
+        assert!(AuthenticatedUser::extract(&req).await.is_err());
+
+        // A user AuthMiddleware let through is taken as is
+        let through = user(Some(Uuid::new_v4()));
+        req.extensions_mut().insert(through.clone());
+        assert_eq!(AuthenticatedUser::extract(&req).await.unwrap().id, through.id);
+    }
+
+    #[actix_web::test]
+    async fn test_required_turns_away_requests_without_a_token() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool()))
+                .wrap(AuthMiddleware::required())
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let res = test::call_service(&app, test::TestRequest::get().uri("/").to_request()).await;
+        assert_eq!(res.status(), 401);
+    }
+
+    #[actix_web::test]
+    async fn test_optional_lets_anonymous_requests_through() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool()))
+                .wrap(AuthMiddleware::optional())
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let res = test::call_service(&app, test::TestRequest::get().uri("/").to_request()).await;
+        assert_eq!(res.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_writes_of_a_public_scope_need_a_token() {
+        let app = test::init_service(
+            App::new().app_data(web::Data::new(pool())).service(
+                web::scope("/public")
+                    .wrap(AuthMiddleware::optional())
+                    .service(
+                        web::scope("")
+                            .guard(guard::fn_guard(is_write))
+                            .wrap(AuthMiddleware::required())
+                            .route("/items", web::post().to(HttpResponse::Created)),
+                    )
+                    .route("/items", web::get().to(HttpResponse::Ok))
+                    // Registered outside the write scope, so never reached
+                    .route("/other", web::delete().to(HttpResponse::Ok)),
+            ),
+        )
+        .await;
+
+        let get = test::TestRequest::get().uri("/public/items").to_request();
+        assert_eq!(test::call_service(&app, get).await.status(), 200);
+
+        let post = test::TestRequest::post().uri("/public/items").to_request();
+        assert_eq!(test::call_service(&app, post).await.status(), 401);
+
+        let delete = test::TestRequest::delete().uri("/public/other").to_request();
+        assert_eq!(test::call_service(&app, delete).await.status(), 401);
+    }
 }
@@ -23,6 +23,12 @@ pub struct User {
     pub avatar_url: Option<String>,
     pub is_verified: Option<bool>,
     pub last_active: Option<DateTime<Utc>>,
+    pub leaderboard_opt_out: bool,
+    pub is_admin: bool,
+    pub referral_code_id: Option<Uuid>,
+    pub is_private: bool,
+    pub deletion_scheduled_at: Option<DateTime<Utc>>,
+    pub deleted_at: Option<DateTime<Utc>>,
 }
 
 // Minimal User struct for basic authentication queries
@@ -85,6 +91,25 @@ pub struct UserProfile {
     pub updated_at: Option<DateTime<Utc>>,
 }
 
+// The user's own view of their account, without credentials or admin flags
+impl From<User> for UserProfile {
+    fn from(user: User) -> Self {
+        UserProfile {
+            id: user.id,
+            email: user.email,
+            username: user.username,
+            bio: user.bio,
+            avatar_url: user.avatar_url,
+            is_verified: user.is_verified,
+            referral_count: user.referral_count,
+            total_referral_rewards: user.total_referral_rewards,
+            last_active: user.last_active,
+            created_at: user.created_at,
+            updated_at: user.updated_at,
+        }
+    }
+}
+
 // New Models for Kana Labs Perps
 
 // Kana Labs API Response Models
@@ -126,6 +151,12 @@ pub struct ReferralReward {
     pub status: Option<String>,
     pub created_at: Option<DateTime<Utc>>,
     pub paid_at: Option<DateTime<Utc>>,
+    pub source_ref: Option<String>,
+    pub base_amount: Option<f64>,
+    pub rate: Option<f64>,
+    pub tier: Option<i32>,
+    pub cancelled_at: Option<DateTime<Utc>>,
+    pub cancel_reason: Option<String>,
 }
 
 #[derive(Debug, Insertable)]
@@ -135,10 +166,164 @@ pub struct NewReferralReward {
     pub referred_user_id: Uuid,
     pub reward_amount: f64,
     pub reward_type: String,
+    pub source_ref: Option<String>,
+    pub base_amount: Option<f64>,
+    pub rate: Option<f64>,
+    pub tier: Option<i32>,
+    // None stores the default, 'pending'
+    pub status: Option<String>,
 }
 
-// Public User Profile (for other users to see)
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::referral_codes)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ReferralCode {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub code: String,
+    pub is_vanity: bool,
+    pub campaign: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub max_uses: Option<i32>,
+    pub use_count: i32,
+    pub click_count: i32,
+    pub is_active: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::referral_codes)]
+pub struct NewReferralCode {
+    pub user_id: Uuid,
+    pub code: String,
+    pub is_vanity: bool,
+    pub campaign: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub max_uses: Option<i32>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::referral_clicks)]
+pub struct NewReferralClick {
+    pub referral_code_id: Uuid,
+    pub source: Option<String>,
+    pub ip_hash: Option<String>,
+}
+
+// A referral code with its conversion stats
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReferralCodeStats {
+    #[serde(flatten)]
+    pub code: ReferralCode,
+    // Signups (use_count) per click, None before the first click
+    pub conversion_rate: Option<f64>,
+    // Referees from this code who have traded
+    pub active_referees: i64,
+    // Pending and paid rewards earned from this code's referees
+    pub rewards: f64,
+}
+
+// What a referrer earned from one referee
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReferralEarnings {
+    pub user_id: Uuid,
+    pub username: String,
+    pub joined_at: Option<DateTime<Utc>>,
+    pub signup: f64,
+    pub fee_rebates: f64,
+    pub deposits: f64,
+    pub pending: f64,
+    // Held while the referral is flagged for review; not part of `total`
+    pub held: f64,
+    pub paid: f64,
+    pub total: f64,
+    pub flagged: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::referral_flags)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ReferralFlag {
+    pub id: Uuid,
+    pub referrer_id: Uuid,
+    pub referred_user_id: Uuid,
+    pub reason: String,
+    pub details: Option<String>,
+    pub status: String,
+    pub reviewed_by: Option<Uuid>,
+    pub reviewed_at: Option<DateTime<Utc>>,
+    pub review_note: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::referral_flags)]
+pub struct NewReferralFlag {
+    pub referrer_id: Uuid,
+    pub referred_user_id: Uuid,
+    pub reason: String,
+    pub details: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReferralFlagResponse {
+    #[serde(flatten)]
+    pub flag: ReferralFlag,
+    pub referrer_username: String,
+    pub referred_username: String,
+    // Rewards from this referral waiting on the review
+    pub held_rewards: f64,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::signup_fingerprints)]
+pub struct NewSignupFingerprint {
+    pub user_id: Uuid,
+    pub ip_address: Option<String>,
+    pub device_id: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::wallet_link_history)]
+pub struct NewWalletLink {
+    pub wallet_address: String,
+    pub user_id: Uuid,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReferralTreeNode {
+    pub user_id: Uuid,
+    pub username: String,
+    pub referred_by: Uuid,
+    // 1 for direct referrals
+    pub level: i32,
+    pub joined_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReferralTreeLevel {
+    pub level: i32,
+    pub count: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReferralTree {
+    pub user_id: Uuid,
+    pub username: String,
+    // Everyone below the user, up to `max_depth` levels
+    pub total: i64,
+    pub depth: i32,
+    pub max_depth: i32,
+    pub levels: Vec<ReferralTreeLevel>,
+    pub nodes: Vec<ReferralTreeNode>,
+    // True when `nodes` was cut at the node limit; counts are always complete
+    pub truncated: bool,
+}
+
+// Public User Profile (for other users to see)
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable)]
 pub struct PublicUserProfile {
     pub id: Uuid,
     pub username: String,
@@ -148,6 +333,25 @@ pub struct PublicUserProfile {
     pub referral_count: Option<i32>,
     pub created_at: Option<DateTime<Utc>>,
     pub last_active: Option<DateTime<Utc>>,
+    pub is_private: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserSearchResponse {
+    pub users: Vec<PublicUserProfile>,
+    // Pass back as `cursor` to get the next page; None on the last page
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SuggestedUser {
+    #[serde(flatten)]
+    pub profile: PublicUserProfile,
+    // Users the viewer follows who follow this user
+    pub mutual_follows: i64,
+    // 30 day PnL rank, unless the user opted out of leaderboards
+    pub pnl_rank: Option<i32>,
+    pub score: f64,
 }
 
 // Referral Leaderboard Entry
@@ -159,6 +363,421 @@ pub struct ReferralLeaderboardEntry {
     pub rank: i32,
 }
 
+// Trading Leaderboard
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::trading_leaderboard)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct TradingLeaderboardRow {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub period: String,
+    pub realized_pnl: f64,
+    pub roi: Option<f64>,
+    pub volume: f64,
+    pub win_rate: Option<f64>,
+    pub trade_count: i64,
+    pub pnl_rank: i32,
+    pub roi_rank: Option<i32>,
+    pub volume_rank: i32,
+    pub win_rate_rank: Option<i32>,
+    pub computed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = crate::schema::trading_leaderboard)]
+pub struct NewTradingLeaderboardRow {
+    pub user_id: Uuid,
+    pub period: String,
+    pub realized_pnl: f64,
+    pub roi: Option<f64>,
+    pub volume: f64,
+    pub win_rate: Option<f64>,
+    pub trade_count: i64,
+    pub pnl_rank: i32,
+    pub roi_rank: Option<i32>,
+    pub volume_rank: i32,
+    pub win_rate_rank: Option<i32>,
+    pub computed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TradingLeaderboardEntry {
+    pub rank: i32,
+    pub username: String,
+    pub avatar_url: Option<String>,
+    pub realized_pnl: f64,
+    pub roi: Option<f64>,
+    pub volume: f64,
+    pub win_rate: Option<f64>,
+    pub trade_count: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TradingLeaderboardResponse {
+    pub period: String,
+    pub metric: String,
+    pub entries: Vec<TradingLeaderboardEntry>,
+    pub page: i64,
+    pub per_page: i64,
+    pub total: i64,
+    // The requesting user's entry, when authenticated and ranked
+    pub my_rank: Option<TradingLeaderboardEntry>,
+    pub computed_at: Option<DateTime<Utc>>,
+}
+
+// Activity Feed
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::activities)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Activity {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub activity_type: String,
+    pub visibility: String,
+    pub subject_user_id: Option<Uuid>,
+    pub symbol: Option<String>,
+    pub side: Option<String>,
+    pub size: Option<f64>,
+    pub price: Option<f64>,
+    pub pnl: Option<f64>,
+    pub created_at: DateTime<Utc>,
+    pub post_id: Option<Uuid>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::activities)]
+pub struct NewActivity {
+    pub user_id: Uuid,
+    pub activity_type: String,
+    pub visibility: String,
+    pub subject_user_id: Option<Uuid>,
+    pub symbol: Option<String>,
+    pub side: Option<String>,
+    pub size: Option<f64>,
+    pub price: Option<f64>,
+    pub pnl: Option<f64>,
+    pub post_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable, Insertable)]
+#[diesel(table_name = crate::schema::activity_privacy_settings)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ActivityPrivacySetting {
+    pub user_id: Uuid,
+    pub activity_type: String,
+    pub visibility: String,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActivityResponse {
+    pub id: Uuid,
+    pub activity_type: String,
+    pub username: String,
+    pub avatar_url: Option<String>,
+    pub subject_username: Option<String>,
+    pub symbol: Option<String>,
+    pub side: Option<String>,
+    pub size: Option<f64>,
+    pub price: Option<f64>,
+    pub pnl: Option<f64>,
+    pub post: Option<PostResponse>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActivityFeedResponse {
+    pub activities: Vec<ActivityResponse>,
+    // Pass back as `cursor` to get the next page; None on the last page
+    pub next_cursor: Option<String>,
+}
+
+// Posts
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::posts)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Post {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub content: String,
+    pub symbol: Option<String>,
+    pub market_price: Option<f64>,
+    pub position_side: Option<String>,
+    pub position_size: Option<f64>,
+    pub entry_price: Option<f64>,
+    pub leverage: Option<f64>,
+    pub unrealized_pnl: Option<f64>,
+    pub like_count: i32,
+    pub comment_count: i32,
+    pub edited_at: Option<DateTime<Utc>>,
+    pub hidden_at: Option<DateTime<Utc>>,
+    pub hidden_by: Option<Uuid>,
+    pub hidden_reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::posts)]
+pub struct NewPost {
+    pub user_id: Uuid,
+    pub content: String,
+    pub symbol: Option<String>,
+    pub market_price: Option<f64>,
+    pub position_side: Option<String>,
+    pub position_size: Option<f64>,
+    pub entry_price: Option<f64>,
+    pub leverage: Option<f64>,
+    pub unrealized_pnl: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::post_comments)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct PostComment {
+    pub id: Uuid,
+    pub post_id: Uuid,
+    pub user_id: Uuid,
+    pub content: String,
+    pub hidden_at: Option<DateTime<Utc>>,
+    pub hidden_by: Option<Uuid>,
+    pub hidden_reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::post_comments)]
+pub struct NewPostComment {
+    pub post_id: Uuid,
+    pub user_id: Uuid,
+    pub content: String,
+}
+
+// The author's position in the attached market when the post was written
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PostPositionSnapshot {
+    pub side: String,
+    pub size: f64,
+    pub entry_price: Option<f64>,
+    pub leverage: Option<f64>,
+    pub unrealized_pnl: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PostResponse {
+    pub id: Uuid,
+    pub username: String,
+    pub avatar_url: Option<String>,
+    pub content: String,
+    pub symbol: Option<String>,
+    pub market_price: Option<f64>,
+    pub position: Option<PostPositionSnapshot>,
+    pub like_count: i32,
+    pub comment_count: i32,
+    // None for anonymous viewers
+    pub liked_by_me: Option<bool>,
+    pub is_hidden: bool,
+    pub edited_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommentResponse {
+    pub id: Uuid,
+    pub post_id: Uuid,
+    pub username: String,
+    pub avatar_url: Option<String>,
+    pub content: String,
+    pub is_hidden: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PublicProfileResponse {
+    #[serde(flatten)]
+    pub profile: PublicUserProfile,
+    pub recent_posts: Vec<PostResponse>,
+}
+
+// Avatar uploads
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::avatar_uploads)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct AvatarUpload {
+    pub id: Uuid,
+    pub user_id: Option<Uuid>,
+    pub url: String,
+    pub storage_keys: Vec<String>,
+    pub content_type: String,
+    pub original_bytes: i32,
+    pub created_at: DateTime<Utc>,
+    pub replaced_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::avatar_uploads)]
+pub struct NewAvatarUpload {
+    pub id: Uuid,
+    pub user_id: Option<Uuid>,
+    pub url: String,
+    pub storage_keys: Vec<String>,
+    pub content_type: String,
+    pub original_bytes: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AvatarResponse {
+    pub avatar_url: String,
+    // Thumbnail URL by edge length in pixels
+    pub thumbnails: std::collections::BTreeMap<u32, String>,
+}
+
+// Profile history
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::username_history)]
+pub struct NewUsernameChange {
+    pub user_id: Uuid,
+    pub old_username: String,
+    pub new_username: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable)]
+pub struct ProfileAuditEntry {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub actor_id: Option<Uuid>,
+    pub field: String,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::profile_audit_log)]
+pub struct NewProfileAuditEntry {
+    pub user_id: Uuid,
+    pub actor_id: Option<Uuid>,
+    pub field: String,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+}
+
+// Data exports
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::data_exports)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct DataExport {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub format: String,
+    pub status: String,
+    #[serde(skip_serializing)]
+    pub file_path: Option<String>,
+    pub file_bytes: Option<i64>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::data_exports)]
+pub struct NewDataExport {
+    pub user_id: Uuid,
+    pub format: String,
+}
+
+// Notifications
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::notifications)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Notification {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub notification_type: String,
+    pub title: String,
+    pub body: String,
+    pub actor_id: Option<Uuid>,
+    pub entity_id: Option<Uuid>,
+    pub read_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = crate::schema::notifications)]
+pub struct NewNotification {
+    pub user_id: Uuid,
+    pub notification_type: String,
+    pub title: String,
+    pub body: String,
+    pub actor_id: Option<Uuid>,
+    pub entity_id: Option<Uuid>,
+}
+
+// Alerts
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::alert_rules)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct AlertRule {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub alert_type: String,
+    pub symbol: Option<String>,
+    pub condition: String,
+    pub threshold: f64,
+    pub repeating: bool,
+    pub cooldown_minutes: i32,
+    pub is_active: bool,
+    pub last_value: Option<f64>,
+    pub last_triggered_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::alert_rules)]
+pub struct NewAlertRule {
+    pub user_id: Uuid,
+    pub alert_type: String,
+    pub symbol: Option<String>,
+    pub condition: String,
+    pub threshold: f64,
+    pub repeating: bool,
+    pub cooldown_minutes: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::alert_history)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct AlertHistoryEntry {
+    pub id: Uuid,
+    pub rule_id: Uuid,
+    pub user_id: Uuid,
+    pub alert_type: String,
+    pub symbol: Option<String>,
+    pub condition: String,
+    pub threshold: f64,
+    pub value: f64,
+    pub delivery_status: String,
+    pub error: Option<String>,
+    pub triggered_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::alert_history)]
+pub struct NewAlertHistoryEntry {
+    pub rule_id: Uuid,
+    pub user_id: Uuid,
+    pub alert_type: String,
+    pub symbol: Option<String>,
+    pub condition: String,
+    pub threshold: f64,
+    pub value: f64,
+    pub delivery_status: String,
+    pub error: Option<String>,
+}
+
 // Follow Statistics
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FollowStats {
@@ -166,6 +785,449 @@ pub struct FollowStats {
     pub following_count: i64,
 }
 
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::follow_requests)]
+pub struct NewFollowRequest {
+    pub requester_id: Uuid,
+    pub target_id: Uuid,
+}
+
+// A pending request to follow a private account
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FollowRequestResponse {
+    pub id: Uuid,
+    pub requester_id: Uuid,
+    pub username: String,
+    pub avatar_url: Option<String>,
+    pub is_verified: Option<bool>,
+    pub created_at: DateTime<Utc>,
+}
+
+// A user the current user has blocked or muted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RelatedUser {
+    pub user_id: Uuid,
+    pub username: String,
+    pub avatar_url: Option<String>,
+    pub since: DateTime<Utc>,
+}
+
+// Paper Trading Models
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::paper_accounts)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct PaperAccount {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub starting_balance: f64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::paper_accounts)]
+pub struct NewPaperAccount {
+    pub user_id: Uuid,
+    pub starting_balance: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::paper_balances)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct PaperBalance {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub asset: String,
+    pub available: f64,
+    pub locked: f64,
+    pub total: f64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::paper_balances)]
+pub struct NewPaperBalance {
+    pub user_id: Uuid,
+    pub asset: String,
+    pub available: f64,
+    pub locked: f64,
+    pub total: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::paper_orders)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct PaperOrder {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub symbol: String,
+    pub order_type: String,
+    pub side: String,
+    pub quantity: f64,
+    pub price: Option<f64>,
+    pub status: String,
+    pub filled_quantity: f64,
+    pub average_price: Option<f64>,
+    pub leverage: f64,
+    pub margin_type: String,
+    pub reserved_margin: f64,
+    pub fee: f64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::paper_orders)]
+pub struct NewPaperOrder {
+    pub user_id: Uuid,
+    pub symbol: String,
+    pub order_type: String,
+    pub side: String,
+    pub quantity: f64,
+    pub price: Option<f64>,
+    pub status: String,
+    pub leverage: f64,
+    pub margin_type: String,
+    pub reserved_margin: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::paper_positions)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct PaperPosition {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub symbol: String,
+    pub side: String,
+    pub size: f64,
+    pub entry_price: f64,
+    pub mark_price: f64,
+    pub unrealized_pnl: f64,
+    pub realized_pnl: f64,
+    pub margin: f64,
+    pub leverage: f64,
+    pub liquidation_price: Option<f64>,
+    pub margin_type: String,
+    pub funding_paid: f64,
+    pub last_funding_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::paper_positions)]
+pub struct NewPaperPosition {
+    pub user_id: Uuid,
+    pub symbol: String,
+    pub side: String,
+    pub size: f64,
+    pub entry_price: f64,
+    pub mark_price: f64,
+    pub margin: f64,
+    pub leverage: f64,
+    pub margin_type: String,
+}
+
+// Paper account summary (balances plus marked-to-market positions)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PaperAccountSummary {
+    pub starting_balance: f64,
+    pub balance: PaperBalance,
+    pub positions: Vec<PaperPosition>,
+    pub open_orders: Vec<PaperOrder>,
+    pub unrealized_pnl: f64,
+    pub realized_pnl: f64,
+    pub funding_paid: f64,
+    pub equity: f64,
+}
+
+// Linked Wallets
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::user_wallets)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct UserWallet {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub wallet_address: String,
+    pub created_at: DateTime<Utc>,
+    // Set once the wallet signed a link challenge; unverified wallets are
+    // left out of leaderboards, copy trading and referral rewards
+    pub verified_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::user_wallets)]
+pub struct NewUserWallet {
+    pub user_id: Uuid,
+    pub wallet_address: String,
+    pub verified_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::wallet_challenges)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct WalletChallenge {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub wallet_address: String,
+    pub nonce: String,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::wallet_challenges)]
+pub struct NewWalletChallenge {
+    pub user_id: Uuid,
+    pub wallet_address: String,
+    pub nonce: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+// What the wallet signs to prove it belongs to the user
+#[derive(Debug, Serialize)]
+pub struct WalletChallengeResponse {
+    pub wallet_address: String,
+    pub message: String,
+    pub nonce: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+// Funding Models
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::funding_rates)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct FundingRate {
+    pub id: Uuid,
+    pub symbol: String,
+    pub market_id: String,
+    pub funding_rate: f64,
+    pub mark_price: f64,
+    pub recorded_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::funding_rates)]
+pub struct NewFundingRate {
+    pub symbol: String,
+    pub market_id: String,
+    pub funding_rate: f64,
+    pub mark_price: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::funding_payments)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct FundingPayment {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub wallet_address: String,
+    pub symbol: String,
+    pub market_id: String,
+    pub side: String,
+    pub position_size: f64,
+    pub mark_price: f64,
+    pub funding_rate: f64,
+    pub payment: f64,
+    pub funding_time: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::funding_payments)]
+pub struct NewFundingPayment {
+    pub user_id: Uuid,
+    pub wallet_address: String,
+    pub symbol: String,
+    pub market_id: String,
+    pub side: String,
+    pub position_size: f64,
+    pub mark_price: f64,
+    pub funding_rate: f64,
+    pub payment: f64,
+    pub funding_time: DateTime<Utc>,
+}
+
+// Trade Fills
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::fills)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Fill {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub wallet_address: String,
+    pub symbol: String,
+    pub market_id: String,
+    pub order_id: Option<String>,
+    pub trade_id: String,
+    pub side: String,
+    pub size: f64,
+    pub price: f64,
+    pub fee: f64,
+    pub realized_pnl: f64,
+    pub realized_pnl_avg_cost: f64,
+    pub executed_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = crate::schema::fills)]
+pub struct NewFill {
+    pub user_id: Uuid,
+    pub wallet_address: String,
+    pub symbol: String,
+    pub market_id: String,
+    pub order_id: Option<String>,
+    pub trade_id: String,
+    pub side: String,
+    pub size: f64,
+    pub price: f64,
+    pub fee: f64,
+    pub executed_at: DateTime<Utc>,
+}
+
+// Realized PnL over one day, week or month
+#[derive(Debug, Clone, Serialize)]
+pub struct PnlSummary {
+    pub period_start: chrono::NaiveDate,
+    pub realized_pnl: f64,
+    pub fees: f64,
+    pub funding: f64,
+    pub net_pnl: f64,
+    pub volume: f64,
+    pub fill_count: i64,
+}
+
+// Equity Snapshots
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::equity_snapshots)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct EquitySnapshot {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub wallet_address: String,
+    pub equity: f64,
+    pub free_collateral: f64,
+    pub margin_used: f64,
+    pub unrealized_pnl: f64,
+    pub resolution: String,
+    pub recorded_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = crate::schema::equity_snapshots)]
+pub struct NewEquitySnapshot {
+    pub user_id: Uuid,
+    pub wallet_address: String,
+    pub equity: f64,
+    pub free_collateral: f64,
+    pub margin_used: f64,
+    pub unrealized_pnl: f64,
+    pub resolution: String,
+    pub recorded_at: DateTime<Utc>,
+}
+
+// One point of the equity curve, summed over all linked wallets
+#[derive(Debug, Clone, Serialize)]
+pub struct EquityPoint {
+    pub timestamp: DateTime<Utc>,
+    pub equity: f64,
+    pub free_collateral: f64,
+    pub margin_used: f64,
+    pub unrealized_pnl: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EquityStats {
+    pub start_equity: f64,
+    pub end_equity: f64,
+    pub change: f64,
+    // Annualized from daily returns; None with fewer than two days of data
+    pub sharpe_ratio: Option<f64>,
+    // Largest peak-to-trough decline, as a fraction of the peak
+    pub max_drawdown: f64,
+    // Share of closing fills in the range with positive realized PnL
+    pub win_rate: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EquityHistoryResponse {
+    pub range: String,
+    pub points: Vec<EquityPoint>,
+    pub stats: EquityStats,
+}
+
+// Copy Trading
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::copy_subscriptions)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct CopySubscription {
+    pub id: Uuid,
+    pub follower_id: Uuid,
+    pub leader_id: Uuid,
+    pub allocation: f64,
+    pub max_leverage: f64,
+    pub max_trade_notional: f64,
+    pub mode: String,
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub stopped_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::copy_subscriptions)]
+pub struct NewCopySubscription {
+    pub follower_id: Uuid,
+    pub leader_id: Uuid,
+    pub allocation: f64,
+    pub max_leverage: f64,
+    pub max_trade_notional: f64,
+    pub mode: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::copy_trades)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct CopyTrade {
+    pub id: Uuid,
+    pub subscription_id: Uuid,
+    pub follower_id: Uuid,
+    pub leader_id: Uuid,
+    pub source_type: String,
+    pub source_id: Uuid,
+    pub symbol: String,
+    pub side: String,
+    pub source_size: f64,
+    pub size: f64,
+    pub price: f64,
+    pub leverage: f64,
+    pub status: String,
+    pub reason: Option<String>,
+    pub paper_order_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::copy_trades)]
+pub struct NewCopyTrade {
+    pub subscription_id: Uuid,
+    pub follower_id: Uuid,
+    pub leader_id: Uuid,
+    pub source_type: String,
+    pub source_id: Uuid,
+    pub symbol: String,
+    pub side: String,
+    pub source_size: f64,
+    pub size: f64,
+    pub price: f64,
+    pub leverage: f64,
+    pub status: String,
+    pub reason: Option<String>,
+    pub paper_order_id: Option<Uuid>,
+}
+
 // Insert Models
 #[derive(Debug, Insertable)]
 #[diesel(table_name = crate::schema::users)]
@@ -180,6 +1242,7 @@ pub struct NewUser {
     pub avatar_url: Option<String>,
     pub is_verified: Option<bool>,
     pub last_active: Option<chrono::DateTime<Utc>>,
+    pub referral_code_id: Option<Uuid>,
 }
 
 //     pub size: f64,
@@ -190,6 +1253,7 @@ pub struct NewUser {
 // }
 
 // API Response Models
+#[allow(dead_code)]
 #[derive(Debug, Serialize)]
 pub struct UserResponse {
     pub id: Uuid,
@@ -227,6 +1291,7 @@ pub struct OrderResponse {
     pub transaction_payload: Option<serde_json::Value>,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Serialize)]
 pub struct BalanceResponse {
     pub asset: String,
@@ -235,20 +1300,65 @@ pub struct BalanceResponse {
     pub total: f64,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct PositionResponse {
-    pub id: Uuid,
-    pub market_id: Uuid,
+    pub market_id: String,
+    pub symbol: String,
     pub side: String,
     pub size: f64,
     pub entry_price: f64,
-    pub mark_price: f64,
-    pub unrealized_pnl: f64,
+    // None when the market has no price source; the fields that need a mark
+    // are None with it
+    pub mark_price: Option<f64>,
+    pub notional: Option<f64>,
+    pub unrealized_pnl: Option<f64>,
     pub realized_pnl: f64,
+    pub roe: Option<f64>,
     pub margin: f64,
+    pub margin_type: String,
     pub leverage: f64,
+    pub maintenance_margin: Option<f64>,
+    pub margin_ratio: Option<f64>,
     pub liquidation_price: Option<f64>,
-    pub created_at: DateTime<Utc>,
+}
+
+// Exposure of all linked wallets to a single market
+#[derive(Debug, Clone, Serialize)]
+pub struct MarketExposure {
+    pub symbol: String,
+    pub market_id: String,
+    pub long_notional: f64,
+    pub short_notional: f64,
+    pub net_notional: f64,
+    pub margin: f64,
+    pub unrealized_pnl: f64,
+    pub open_orders: usize,
+}
+
+// A wallet or market whose data could not be loaded
+#[derive(Debug, Clone, Serialize)]
+pub struct PortfolioError {
+    pub wallet_address: String,
+    pub symbol: Option<String>,
+    pub source: String, // "balance", "positions" or "open_orders"
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PortfolioResponse {
+    pub wallets: Vec<String>,
+    pub total_equity: f64,
+    pub free_collateral: f64,
+    pub margin_used: f64,
+    pub unrealized_pnl: f64,
+    pub pnl_24h: f64,
+    pub open_orders_count: usize,
+    pub markets: Vec<MarketExposure>,
+    pub positions: Vec<PositionResponse>,
+    // True when some of the data above is missing; see `errors`
+    pub partial: bool,
+    pub errors: Vec<PortfolioError>,
+    pub updated_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -0,0 +1,307 @@
+use crate::models::{NewNotification, Notification};
+use crate::schema::notifications;
+use crate::utils::AppError;
+use crate::DbPool;
+use chrono::Utc;
+use diesel::prelude::*;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use tokio::sync::mpsc::UnboundedSender;
+use uuid::Uuid;
+
+// (connection id, outgoing messages) of one open WebSocket
+type Connection = (Uuid, UnboundedSender<String>);
+
+// Open private WebSocket connections, by user. A user can have several tabs open.
+// Connections live in this process only: a notification created on another
+// instance is stored but not pushed here.
+pub struct NotificationHub {
+    connections: Mutex<HashMap<Uuid, Vec<Connection>>>,
+}
+
+impl NotificationHub {
+    pub fn global() -> &'static NotificationHub {
+        static HUB: OnceLock<NotificationHub> = OnceLock::new();
+        HUB.get_or_init(|| NotificationHub {
+            connections: Mutex::new(HashMap::new()),
+        })
+    }
+
+    // Returns the connection id to pass to `disconnect`
+    pub fn connect(&self, user_id: Uuid, sender: UnboundedSender<String>) -> Uuid {
+        let connection_id = Uuid::new_v4();
+        let mut connections = self.connections.lock().unwrap_or_else(|e| e.into_inner());
+        connections.entry(user_id).or_default().push((connection_id, sender));
+        connection_id
+    }
+
+    pub fn disconnect(&self, user_id: Uuid, connection_id: Uuid) {
+        let mut connections = self.connections.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(senders) = connections.get_mut(&user_id) {
+            senders.retain(|(id, _)| *id != connection_id);
+            if senders.is_empty() {
+                connections.remove(&user_id);
+            }
+        }
+    }
+
+    // Deliver to every open connection of the user; closed ones are dropped
+    pub fn send(&self, user_id: Uuid, message: &str) {
+        let mut connections = self.connections.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(senders) = connections.get_mut(&user_id) {
+            senders.retain(|(_, sender)| sender.send(message.to_string()).is_ok());
+            if senders.is_empty() {
+                connections.remove(&user_id);
+            }
+        }
+    }
+}
+
+pub struct NotificationService;
+
+impl NotificationService {
+    // Store notifications and push them to connected clients. Like activities,
+    // notifications are a side effect, so failures are only logged.
+    pub async fn notify(pool: &DbPool, new_notifications: Vec<NewNotification>) {
+        if new_notifications.is_empty() {
+            return;
+        }
+
+        let stored = pool
+            .get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))
+            .and_then(|mut conn| {
+                diesel::insert_into(notifications::table)
+                    .values(&new_notifications)
+                    .get_results::<Notification>(&mut conn)
+                    .map_err(AppError::from)
+            });
+
+        match stored {
+            Ok(stored) => {
+                for notification in stored {
+                    let message = serde_json::json!({
+                        "channel": "notifications",
+                        "type": "notification",
+                        "data": notification,
+                    });
+                    NotificationHub::global().send(notification.user_id, &message.to_string());
+                }
+            }
+            Err(e) => log::warn!("Failed to store notifications: {}", e),
+        }
+    }
+
+    pub async fn get_notifications(
+        pool: &DbPool,
+        user_id: Uuid,
+        unread_only: bool,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(Vec<Notification>, i64), AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let filtered = || {
+            let mut query = notifications::table
+                .filter(notifications::user_id.eq(user_id))
+                .into_boxed();
+            if unread_only {
+                query = query.filter(notifications::read_at.is_null());
+            }
+            query
+        };
+
+        let total = filtered().count().get_result::<i64>(conn)?;
+        let items = filtered()
+            .order(notifications::created_at.desc())
+            .limit(limit)
+            .offset(offset)
+            .load::<Notification>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get notifications: {}", e)))?;
+
+        Ok((items, total))
+    }
+
+    // Mark the given notifications read, or all of them when `ids` is None.
+    // Returns the new unread count.
+    pub async fn mark_read(
+        pool: &DbPool,
+        user_id: Uuid,
+        ids: Option<Vec<Uuid>>,
+    ) -> Result<i64, AppError> {
+        {
+            let conn = &mut pool.get()
+                .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+            let mut query = diesel::update(notifications::table)
+                .filter(notifications::user_id.eq(user_id))
+                .filter(notifications::read_at.is_null())
+                .into_boxed();
+            if let Some(ids) = ids {
+                query = query.filter(notifications::id.eq_any(ids));
+            }
+            query
+                .set(notifications::read_at.eq(Some(Utc::now())))
+                .execute(conn)
+                .map_err(|e| AppError::InternalServerError(format!("Failed to mark notifications read: {}", e)))?;
+        }
+
+        let unread = Self::unread_count(pool, user_id).await?;
+        let message = serde_json::json!({
+            "channel": "notifications",
+            "type": "unread_count",
+            "data": { "count": unread },
+        });
+        NotificationHub::global().send(user_id, &message.to_string());
+
+        Ok(unread)
+    }
+
+    pub async fn unread_count(pool: &DbPool, user_id: Uuid) -> Result<i64, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        notifications::table
+            .filter(notifications::user_id.eq(user_id))
+            .filter(notifications::read_at.is_null())
+            .count()
+            .get_result::<i64>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to count notifications: {}", e)))
+    }
+
+    pub fn new_follower(user_id: Uuid, follower_id: Uuid, follower_username: &str) -> NewNotification {
+        NewNotification {
+            user_id,
+            notification_type: "new_follower".to_string(),
+            title: "New follower".to_string(),
+            body: format!("@{} started following you", follower_username),
+            actor_id: Some(follower_id),
+            entity_id: None,
+        }
+    }
+
+    pub fn follow_request(
+        user_id: Uuid,
+        requester_id: Uuid,
+        requester_username: &str,
+        request_id: Uuid,
+    ) -> NewNotification {
+        NewNotification {
+            user_id,
+            notification_type: "follow_request".to_string(),
+            title: "Follow request".to_string(),
+            body: format!("@{} asked to follow you", requester_username),
+            actor_id: Some(requester_id),
+            entity_id: Some(request_id),
+        }
+    }
+
+    pub fn referral_signup(referrer_id: Uuid, referred_id: Uuid, referred_username: &str) -> NewNotification {
+        NewNotification {
+            user_id: referrer_id,
+            notification_type: "referral_signup".to_string(),
+            title: "Referral joined".to_string(),
+            body: format!("@{} signed up with your referral code", referred_username),
+            actor_id: Some(referred_id),
+            entity_id: None,
+        }
+    }
+
+    pub fn data_export_ready(user_id: Uuid, export_id: Uuid) -> NewNotification {
+        NewNotification {
+            user_id,
+            notification_type: "data_export_ready".to_string(),
+            title: "Data export ready".to_string(),
+            body: "Your data export is ready to download".to_string(),
+            actor_id: None,
+            entity_id: Some(export_id),
+        }
+    }
+
+    // `paper` distinguishes paper-trading fills from live ones in the message
+    pub fn order_filled(
+        user_id: Uuid,
+        entity_id: Uuid,
+        symbol: &str,
+        side: &str,
+        size: f64,
+        price: f64,
+        paper: bool,
+    ) -> NewNotification {
+        NewNotification {
+            user_id,
+            notification_type: "order_filled".to_string(),
+            title: if paper { "Paper order filled" } else { "Order filled" }.to_string(),
+            body: format!("{} {} {} at {}", capitalize(side), size, symbol, price),
+            actor_id: None,
+            entity_id: Some(entity_id),
+        }
+    }
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    fn hub() -> NotificationHub {
+        NotificationHub {
+            connections: Mutex::new(HashMap::new()),
+        }
+    }
+
+    #[test]
+    fn test_hub_sends_to_every_connection_of_the_user() {
+        let hub = hub();
+        let (user, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let (first, mut first_rx) = unbounded_channel();
+        let (second, mut second_rx) = unbounded_channel();
+        let (third, mut third_rx) = unbounded_channel();
+        hub.connect(user, first);
+        let second_id = hub.connect(user, second);
+        hub.connect(other, third);
+
+        hub.send(user, "hello");
+        assert_eq!(first_rx.try_recv().unwrap(), "hello");
+        assert_eq!(second_rx.try_recv().unwrap(), "hello");
+        assert!(third_rx.try_recv().is_err());
+
+        hub.disconnect(user, second_id);
+        hub.send(user, "again");
+        assert_eq!(first_rx.try_recv().unwrap(), "again");
+        assert!(second_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_hub_drops_closed_connections() {
+        let hub = hub();
+        let user = Uuid::new_v4();
+        let (sender, receiver) = unbounded_channel();
+        hub.connect(user, sender);
+        drop(receiver);
+
+        hub.send(user, "hello");
+        assert!(hub.connections.lock().unwrap().get(&user).is_none());
+    }
+
+    #[test]
+    fn test_order_filled_message() {
+        let live = NotificationService::order_filled(Uuid::new_v4(), Uuid::new_v4(), "APT-USD", "buy", 10.0, 9.4, false);
+        assert_eq!(live.title, "Order filled");
+        assert_eq!(live.body, "Buy 10 APT-USD at 9.4");
+
+        let paper = NotificationService::order_filled(Uuid::new_v4(), Uuid::new_v4(), "APT-USD", "sell", 1.5, 9.0, true);
+        assert_eq!(paper.title, "Paper order filled");
+        assert_eq!(paper.body, "Sell 1.5 APT-USD at 9");
+        assert_eq!(capitalize(""), "");
+    }
+}
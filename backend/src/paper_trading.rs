@@ -0,0 +1,1016 @@
+use crate::funding::FUNDING_INTERVAL_HOURS;
+use crate::kana_client::KanaClient;
+use crate::markets::{MarketRegistry, MarketSpec};
+use crate::models::{
+    NewPaperAccount, NewPaperBalance, NewPaperOrder, NewPaperPosition, PaperAccount,
+    PaperAccountSummary, PaperBalance, PaperOrder, PaperPosition,
+};
+use crate::notifications::NotificationService;
+use crate::positions::{maintenance_margin_rate, PositionAnalytics};
+use crate::schema::{paper_accounts, paper_balances, paper_orders, paper_positions};
+use crate::utils::AppError;
+use crate::DbPool;
+use chrono::{DateTime, Duration, Utc};
+use diesel::prelude::*;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+pub const PAPER_ASSET: &str = "USDC";
+pub const DEFAULT_STARTING_BALANCE: f64 = 10_000.0;
+// Keeps balances, and the notionals they allow, within sensible float range
+pub const MAX_STARTING_BALANCE: f64 = 10_000_000.0;
+
+const TAKER_FEE_RATE: f64 = 0.0005;
+const MAKER_FEE_RATE: f64 = 0.0002;
+const QUANTITY_EPSILON: f64 = 1e-9;
+
+#[derive(Debug, Clone)]
+pub struct PaperOrderRequest {
+    pub symbol: String,
+    pub side: String,
+    pub order_type: String,
+    pub size: f64,
+    pub price: Option<f64>,
+    pub leverage: Option<f64>,
+    pub margin_type: Option<String>,
+}
+
+// How a fill changes the existing position: it adds to a same-side position,
+// or closes part of an opposite one and opens the rest the other way
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FillEffect {
+    pub added: f64,
+    pub closed: f64,
+    pub opened: f64,
+    // PnL and margin of the closed quantity
+    pub realized_pnl: f64,
+    pub released_margin: f64,
+}
+
+// Price-time-priority matching of resting paper orders against a mid price
+pub struct MatchingEngine;
+
+impl MatchingEngine {
+    // Sort orders so the best-priced, oldest order comes first.
+    // Bids rank by highest price, asks by lowest; market orders always lead.
+    pub fn sort_by_priority(orders: &mut [PaperOrder]) {
+        orders.sort_by(|a, b| {
+            Self::type_rank(a)
+                .cmp(&Self::type_rank(b))
+                .then_with(|| Self::compare_price(a, b))
+                .then_with(|| a.created_at.cmp(&b.created_at))
+        });
+    }
+
+    // A market order always crosses; a limit order crosses once the mid trades through it
+    pub fn crosses(order: &PaperOrder, mid: f64) -> bool {
+        match (order.order_type.as_str(), order.price) {
+            ("limit", Some(limit)) if order.side == "buy" => mid <= limit,
+            ("limit", Some(limit)) => mid >= limit,
+            _ => true,
+        }
+    }
+
+    // Marketable orders fill at the mid; the limit price is only a cap
+    pub fn execution_price(order: &PaperOrder, mid: f64) -> f64 {
+        match (order.order_type.as_str(), order.price) {
+            ("limit", Some(limit)) if order.side == "buy" => mid.min(limit),
+            ("limit", Some(limit)) => mid.max(limit),
+            _ => mid,
+        }
+    }
+
+    // Orders that fill at `mid`, in the order they must be executed
+    pub fn match_orders(mut orders: Vec<PaperOrder>, mid: f64) -> Vec<(PaperOrder, f64)> {
+        Self::sort_by_priority(&mut orders);
+        orders
+            .into_iter()
+            .filter(|order| Self::crosses(order, mid))
+            .map(|order| {
+                let price = Self::execution_price(&order, mid);
+                (order, price)
+            })
+            .collect()
+    }
+
+    pub fn fill_effect(position: Option<&PaperPosition>, side: &str, quantity: f64, price: f64) -> FillEffect {
+        match position.filter(|p| p.size > 0.0) {
+            Some(position) if position.side == side => FillEffect {
+                added: quantity,
+                ..Default::default()
+            },
+            Some(position) => {
+                let closed = quantity.min(position.size);
+                FillEffect {
+                    closed,
+                    opened: quantity - closed,
+                    realized_pnl: PositionAnalytics::unrealized_pnl(&position.side, closed, position.entry_price, price),
+                    released_margin: position.margin * closed / position.size,
+                    ..Default::default()
+                }
+            }
+            None => FillEffect {
+                opened: quantity,
+                ..Default::default()
+            },
+        }
+    }
+
+    fn type_rank(order: &PaperOrder) -> u8 {
+        if order.order_type == "market" {
+            0
+        } else {
+            1
+        }
+    }
+
+    fn compare_price(a: &PaperOrder, b: &PaperOrder) -> Ordering {
+        let (pa, pb) = (a.price.unwrap_or(0.0), b.price.unwrap_or(0.0));
+        if a.side == "buy" {
+            pb.partial_cmp(&pa).unwrap_or(Ordering::Equal)
+        } else {
+            pa.partial_cmp(&pb).unwrap_or(Ordering::Equal)
+        }
+    }
+}
+
+pub struct PaperTradingService;
+
+impl PaperTradingService {
+    // Live funding rate from Kana Labs, zero when unavailable
+    async fn funding_rate(market: &MarketSpec) -> f64 {
+        match KanaClient::new() {
+            Ok(client) => client.get_funding_rate(market.symbol).await.unwrap_or(0.0),
+            Err(_) => 0.0,
+        }
+    }
+
+    // Create the paper account and its balance on first use
+    pub async fn get_or_create_account(
+        pool: &DbPool,
+        user_id: Uuid,
+    ) -> Result<PaperAccount, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        conn.transaction(|conn| Self::ensure_account(conn, user_id))
+    }
+
+    // Place a paper order and immediately match it against the current mid
+    pub async fn place_order(
+        pool: &DbPool,
+        user_id: Uuid,
+        request: PaperOrderRequest,
+    ) -> Result<PaperOrder, AppError> {
+        let market = MarketRegistry::require(&request.symbol)?;
+        Self::validate_order(market, &request)?;
+
+        // Without a live price the order can neither be margin-checked nor matched
+        let mid = market.require_mid_price().await?;
+
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let result = conn.transaction(|conn| {
+            Self::ensure_account(conn, user_id)?;
+            // Held until commit, so concurrent orders can't each pass the balance check
+            let balance = Self::lock_balance(conn, user_id)?;
+
+            let leverage = request.leverage.unwrap_or(1.0);
+            let reference_price = request.price.unwrap_or(mid);
+            let opening_size = Self::opening_size(conn, user_id, market.symbol, &request.side, request.size)?;
+            let reserved_margin = opening_size * reference_price / leverage;
+            let fee_estimate = request.size * reference_price * TAKER_FEE_RATE;
+
+            if balance.available + QUANTITY_EPSILON < reserved_margin + fee_estimate {
+                return Err(AppError::BadRequest(format!(
+                    "Insufficient paper balance: {:.2} {} available, {:.2} required",
+                    balance.available,
+                    PAPER_ASSET,
+                    reserved_margin + fee_estimate
+                )));
+            }
+
+            Self::adjust_balance(conn, user_id, -reserved_margin, reserved_margin)?;
+
+            let order: PaperOrder = diesel::insert_into(paper_orders::table)
+                .values(&NewPaperOrder {
+                    user_id,
+                    symbol: market.symbol.to_string(),
+                    order_type: request.order_type.clone(),
+                    side: request.side.clone(),
+                    quantity: request.size,
+                    price: request.price,
+                    status: "open".to_string(),
+                    leverage,
+                    margin_type: request.margin_type.clone().unwrap_or_else(|| "cross".to_string()),
+                    reserved_margin,
+                })
+                .get_result(conn)
+                .map_err(|e| AppError::InternalServerError(format!("Failed to create paper order: {}", e)))?;
+
+            let filled = Self::match_symbol(conn, user_id, market.symbol, mid)?;
+
+            let order = paper_orders::table
+                .find(order.id)
+                .first::<PaperOrder>(conn)
+                .map_err(|e| AppError::InternalServerError(format!("Failed to reload paper order: {}", e)))?;
+            Ok((order, filled))
+        });
+        let (order, filled) = result?;
+
+        Self::notify_fills(pool, filled).await;
+        Ok(order)
+    }
+
+    // Cancel a resting paper order and release its reserved margin
+    pub async fn cancel_order(
+        pool: &DbPool,
+        user_id: Uuid,
+        order_id: Uuid,
+    ) -> Result<PaperOrder, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        conn.transaction(|conn| {
+            let order = paper_orders::table
+                .filter(paper_orders::id.eq(order_id))
+                .filter(paper_orders::user_id.eq(user_id))
+                .first::<PaperOrder>(conn)
+                .optional()?
+                .ok_or_else(|| AppError::NotFoundError("Paper order not found".to_string()))?;
+
+            if order.status != "open" {
+                return Err(AppError::BadRequest(format!(
+                    "Order is already {}",
+                    order.status
+                )));
+            }
+
+            Self::adjust_balance(conn, user_id, order.reserved_margin, -order.reserved_margin)?;
+
+            diesel::update(paper_orders::table.find(order.id))
+                .set((
+                    paper_orders::status.eq("cancelled"),
+                    paper_orders::reserved_margin.eq(0.0),
+                ))
+                .get_result::<PaperOrder>(conn)
+                .map_err(AppError::from)
+        })
+    }
+
+    // Match resting orders, mark positions to market and accrue funding
+    pub async fn sync_account(pool: &DbPool, user_id: Uuid) -> Result<(), AppError> {
+        let symbols = {
+            let conn = &mut pool.get()
+                .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+            let mut symbols = paper_orders::table
+                .filter(paper_orders::user_id.eq(user_id))
+                .filter(paper_orders::status.eq("open"))
+                .select(paper_orders::symbol)
+                .distinct()
+                .load::<String>(conn)?;
+            symbols.extend(
+                paper_positions::table
+                    .filter(paper_positions::user_id.eq(user_id))
+                    .filter(paper_positions::size.gt(0.0))
+                    .select(paper_positions::symbol)
+                    .load::<String>(conn)?,
+            );
+            symbols.sort();
+            symbols.dedup();
+            symbols
+        };
+
+        // Markets without a live price are left alone, keeping their last mark
+        // until one is available again
+        let mut market_data = HashMap::new();
+        for symbol in symbols {
+            if let Some(market) = MarketRegistry::by_symbol(&symbol) {
+                let Some(mid) = market.mid_price().await else { continue };
+                let funding_rate = Self::funding_rate(market).await;
+                market_data.insert(symbol, (mid, funding_rate));
+            }
+        }
+        if market_data.is_empty() {
+            return Ok(());
+        }
+
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let filled = conn.transaction(|conn| {
+            Self::lock_balance(conn, user_id)?;
+            let now = Utc::now();
+            let mut filled = Vec::new();
+            for (symbol, (mid, funding_rate)) in &market_data {
+                filled.extend(Self::match_symbol(conn, user_id, symbol, *mid)?);
+                Self::mark_position(conn, user_id, symbol, *mid, *funding_rate, now)?;
+            }
+            Ok::<_, AppError>(filled)
+        })?;
+
+        Self::notify_fills(pool, filled).await;
+        Ok(())
+    }
+
+    async fn notify_fills(pool: &DbPool, filled: Vec<(PaperOrder, f64)>) {
+        let notifications = filled
+            .iter()
+            .map(|(order, price)| {
+                NotificationService::order_filled(
+                    order.user_id,
+                    order.id,
+                    &order.symbol,
+                    &order.side,
+                    order.quantity,
+                    *price,
+                    true,
+                )
+            })
+            .collect();
+        NotificationService::notify(pool, notifications).await;
+    }
+
+    pub async fn get_positions(
+        pool: &DbPool,
+        user_id: Uuid,
+        symbol: Option<&str>,
+    ) -> Result<Vec<PaperPosition>, AppError> {
+        Self::sync_account(pool, user_id).await?;
+
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let mut query = paper_positions::table
+            .filter(paper_positions::user_id.eq(user_id))
+            .filter(paper_positions::size.gt(0.0))
+            .into_boxed();
+        if let Some(symbol) = symbol {
+            query = query.filter(paper_positions::symbol.eq(MarketRegistry::require(symbol)?.symbol));
+        }
+
+        Ok(query
+            .order(paper_positions::created_at.asc())
+            .load::<PaperPosition>(conn)?)
+    }
+
+    pub async fn get_open_orders(
+        pool: &DbPool,
+        user_id: Uuid,
+        symbol: Option<&str>,
+    ) -> Result<Vec<PaperOrder>, AppError> {
+        Self::sync_account(pool, user_id).await?;
+        Self::load_orders(pool, user_id, symbol, true)
+    }
+
+    pub async fn get_order_history(
+        pool: &DbPool,
+        user_id: Uuid,
+        symbol: Option<&str>,
+    ) -> Result<Vec<PaperOrder>, AppError> {
+        Self::sync_account(pool, user_id).await?;
+        Self::load_orders(pool, user_id, symbol, false)
+    }
+
+    pub async fn get_account_summary(
+        pool: &DbPool,
+        user_id: Uuid,
+    ) -> Result<PaperAccountSummary, AppError> {
+        let account = Self::get_or_create_account(pool, user_id).await?;
+        let positions = Self::get_positions(pool, user_id, None).await?;
+        let open_orders = Self::load_orders(pool, user_id, None, true)?;
+
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+        let balance = Self::load_balance(conn, user_id)?;
+
+        let realized_pnl = paper_positions::table
+            .filter(paper_positions::user_id.eq(user_id))
+            .select(paper_positions::realized_pnl)
+            .load::<f64>(conn)?
+            .into_iter()
+            .sum();
+        let unrealized_pnl: f64 = positions.iter().map(|p| p.unrealized_pnl).sum();
+        let funding_paid: f64 = positions.iter().map(|p| p.funding_paid).sum();
+
+        Ok(PaperAccountSummary {
+            starting_balance: account.starting_balance,
+            equity: balance.total + unrealized_pnl,
+            balance,
+            positions,
+            open_orders,
+            unrealized_pnl,
+            realized_pnl,
+            funding_paid,
+        })
+    }
+
+    // Wipe all paper orders and positions and restore the starting balance
+    pub async fn reset_account(
+        pool: &DbPool,
+        user_id: Uuid,
+        starting_balance: Option<f64>,
+    ) -> Result<PaperAccount, AppError> {
+        let starting_balance = starting_balance.unwrap_or(DEFAULT_STARTING_BALANCE);
+        if !(starting_balance > 0.0 && starting_balance <= MAX_STARTING_BALANCE) {
+            return Err(AppError::ValidationError(format!(
+                "Starting balance must be positive and at most {}",
+                MAX_STARTING_BALANCE
+            )));
+        }
+
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        conn.transaction(|conn| {
+            diesel::delete(paper_orders::table.filter(paper_orders::user_id.eq(user_id))).execute(conn)?;
+            diesel::delete(paper_positions::table.filter(paper_positions::user_id.eq(user_id))).execute(conn)?;
+            diesel::delete(paper_balances::table.filter(paper_balances::user_id.eq(user_id))).execute(conn)?;
+            diesel::delete(paper_accounts::table.filter(paper_accounts::user_id.eq(user_id))).execute(conn)?;
+
+            Self::create_account(conn, user_id, starting_balance)
+        })
+    }
+
+    fn validate_order(market: &MarketSpec, request: &PaperOrderRequest) -> Result<(), AppError> {
+        if request.side != "buy" && request.side != "sell" {
+            return Err(AppError::ValidationError("side must be 'buy' or 'sell'".to_string()));
+        }
+        if request.size < market.min_order_size {
+            return Err(AppError::ValidationError(format!(
+                "Minimum order size for {} is {}",
+                market.symbol, market.min_order_size
+            )));
+        }
+        match (request.order_type.as_str(), request.price) {
+            ("market", _) => {}
+            ("limit", Some(price)) if price > 0.0 => {}
+            ("limit", _) => {
+                return Err(AppError::ValidationError(
+                    "Limit orders require a positive price".to_string(),
+                ))
+            }
+            _ => {
+                return Err(AppError::ValidationError(
+                    "order_type must be 'market' or 'limit'".to_string(),
+                ))
+            }
+        }
+        let leverage = request.leverage.unwrap_or(1.0);
+        if leverage < 1.0 || leverage > market.max_leverage {
+            return Err(AppError::ValidationError(format!(
+                "Leverage for {} must be between 1 and {}",
+                market.symbol, market.max_leverage
+            )));
+        }
+        // Every paper position draws on the one paper balance
+        if let Some(margin_type) = &request.margin_type {
+            if margin_type != "cross" {
+                return Err(AppError::ValidationError(
+                    "Paper trading supports cross margin only".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn ensure_account(conn: &mut PgConnection, user_id: Uuid) -> Result<PaperAccount, AppError> {
+        let existing = paper_accounts::table
+            .filter(paper_accounts::user_id.eq(user_id))
+            .first::<PaperAccount>(conn)
+            .optional()?;
+
+        match existing {
+            Some(account) => Ok(account),
+            None => Self::create_account(conn, user_id, DEFAULT_STARTING_BALANCE),
+        }
+    }
+
+    fn create_account(
+        conn: &mut PgConnection,
+        user_id: Uuid,
+        starting_balance: f64,
+    ) -> Result<PaperAccount, AppError> {
+        let account = diesel::insert_into(paper_accounts::table)
+            .values(&NewPaperAccount {
+                user_id,
+                starting_balance,
+            })
+            .get_result::<PaperAccount>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to create paper account: {}", e)))?;
+
+        diesel::insert_into(paper_balances::table)
+            .values(&NewPaperBalance {
+                user_id,
+                asset: PAPER_ASSET.to_string(),
+                available: starting_balance,
+                locked: 0.0,
+                total: starting_balance,
+            })
+            .execute(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to create paper balance: {}", e)))?;
+
+        Ok(account)
+    }
+
+    fn load_balance(conn: &mut PgConnection, user_id: Uuid) -> Result<PaperBalance, AppError> {
+        paper_balances::table
+            .filter(paper_balances::user_id.eq(user_id))
+            .filter(paper_balances::asset.eq(PAPER_ASSET))
+            .first::<PaperBalance>(conn)
+            .optional()?
+            .ok_or_else(|| AppError::NotFoundError("Paper account not found".to_string()))
+    }
+
+    // load_balance, locking the row until the transaction ends. Every paper
+    // write that checks the balance takes this lock first.
+    fn lock_balance(conn: &mut PgConnection, user_id: Uuid) -> Result<PaperBalance, AppError> {
+        paper_balances::table
+            .filter(paper_balances::user_id.eq(user_id))
+            .filter(paper_balances::asset.eq(PAPER_ASSET))
+            .for_update()
+            .first::<PaperBalance>(conn)
+            .optional()?
+            .ok_or_else(|| AppError::NotFoundError("Paper account not found".to_string()))
+    }
+
+    // Move funds between available and locked; total always equals their sum
+    fn adjust_balance(
+        conn: &mut PgConnection,
+        user_id: Uuid,
+        available_delta: f64,
+        locked_delta: f64,
+    ) -> Result<(), AppError> {
+        diesel::update(
+            paper_balances::table
+                .filter(paper_balances::user_id.eq(user_id))
+                .filter(paper_balances::asset.eq(PAPER_ASSET)),
+        )
+        .set((
+            paper_balances::available.eq(paper_balances::available + available_delta),
+            paper_balances::locked.eq(paper_balances::locked + locked_delta),
+            paper_balances::total.eq(paper_balances::total + available_delta + locked_delta),
+        ))
+        .execute(conn)?;
+        Ok(())
+    }
+
+    fn load_orders(
+        pool: &DbPool,
+        user_id: Uuid,
+        symbol: Option<&str>,
+        open_only: bool,
+    ) -> Result<Vec<PaperOrder>, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let mut query = paper_orders::table
+            .filter(paper_orders::user_id.eq(user_id))
+            .into_boxed();
+        if open_only {
+            query = query.filter(paper_orders::status.eq("open"));
+        }
+        if let Some(symbol) = symbol {
+            query = query.filter(paper_orders::symbol.eq(MarketRegistry::require(symbol)?.symbol));
+        }
+
+        Ok(query
+            .order(paper_orders::created_at.desc())
+            .load::<PaperOrder>(conn)?)
+    }
+
+    fn load_position(
+        conn: &mut PgConnection,
+        user_id: Uuid,
+        symbol: &str,
+    ) -> Result<Option<PaperPosition>, AppError> {
+        Ok(paper_positions::table
+            .filter(paper_positions::user_id.eq(user_id))
+            .filter(paper_positions::symbol.eq(symbol))
+            .first::<PaperPosition>(conn)
+            .optional()?)
+    }
+
+    // Portion of an order that adds exposure rather than reducing an opposite position
+    fn opening_size(
+        conn: &mut PgConnection,
+        user_id: Uuid,
+        symbol: &str,
+        side: &str,
+        size: f64,
+    ) -> Result<f64, AppError> {
+        let position = Self::load_position(conn, user_id, symbol)?;
+        Ok(match position {
+            Some(position) if position.size > 0.0 && position.side != position_side(side) => {
+                (size - position.size).max(0.0)
+            }
+            _ => size,
+        })
+    }
+
+    fn match_symbol(
+        conn: &mut PgConnection,
+        user_id: Uuid,
+        symbol: &str,
+        mid: f64,
+    ) -> Result<Vec<(PaperOrder, f64)>, AppError> {
+        let open_orders = paper_orders::table
+            .filter(paper_orders::user_id.eq(user_id))
+            .filter(paper_orders::symbol.eq(symbol))
+            .filter(paper_orders::status.eq("open"))
+            .load::<PaperOrder>(conn)?;
+
+        let filled = MatchingEngine::match_orders(open_orders, mid);
+        for (order, price) in &filled {
+            Self::fill_order(conn, order, *price)?;
+        }
+        Ok(filled)
+    }
+
+    fn fill_order(conn: &mut PgConnection, order: &PaperOrder, price: f64) -> Result<(), AppError> {
+        let quantity = order.quantity - order.filled_quantity;
+        let fee_rate = if order.order_type == "market" {
+            TAKER_FEE_RATE
+        } else {
+            MAKER_FEE_RATE
+        };
+        let fee = quantity * price * fee_rate;
+        let side = position_side(&order.side);
+
+        // Release the reservation; margin is re-locked below for the opening portion
+        Self::adjust_balance(conn, order.user_id, order.reserved_margin - fee, -order.reserved_margin)?;
+
+        let position = Self::load_position(conn, order.user_id, &order.symbol)?;
+        let effect = MatchingEngine::fill_effect(position.as_ref(), side, quantity, price);
+
+        if let Some(position) = position.as_ref().filter(|p| p.size > 0.0) {
+            if effect.added > 0.0 {
+                let new_size = position.size + effect.added;
+                let entry_price = (position.entry_price * position.size + price * effect.added) / new_size;
+                let added_margin = effect.added * price / order.leverage;
+                let margin = position.margin + added_margin;
+
+                Self::adjust_balance(conn, order.user_id, -added_margin, added_margin)?;
+                diesel::update(paper_positions::table.find(position.id))
+                    .set((
+                        paper_positions::size.eq(new_size),
+                        paper_positions::entry_price.eq(entry_price),
+                        paper_positions::margin.eq(margin),
+                        paper_positions::leverage.eq(new_size * entry_price / margin),
+                    ))
+                    .execute(conn)?;
+            } else {
+                let realized_pnl = Self::settle_close(conn, order.user_id, effect.released_margin, effect.realized_pnl)?;
+                let remaining = position.size - effect.closed;
+
+                diesel::update(paper_positions::table.find(position.id))
+                    .set((
+                        paper_positions::size.eq(if remaining > QUANTITY_EPSILON { remaining } else { 0.0 }),
+                        paper_positions::margin.eq(position.margin - effect.released_margin),
+                        paper_positions::realized_pnl.eq(position.realized_pnl + realized_pnl),
+                        paper_positions::unrealized_pnl.eq(PositionAnalytics::unrealized_pnl(
+                            &position.side,
+                            remaining,
+                            position.entry_price,
+                            price,
+                        )),
+                    ))
+                    .execute(conn)?;
+            }
+        }
+
+        let opening_quantity = effect.opened;
+        if opening_quantity > QUANTITY_EPSILON {
+            let margin = opening_quantity * price / order.leverage;
+            Self::adjust_balance(conn, order.user_id, -margin, margin)?;
+
+            match position {
+                // Reopen the existing row so cumulative realized PnL and funding are kept
+                Some(position) => {
+                    diesel::update(paper_positions::table.find(position.id))
+                        .set((
+                            paper_positions::side.eq(side),
+                            paper_positions::size.eq(opening_quantity),
+                            paper_positions::entry_price.eq(price),
+                            paper_positions::mark_price.eq(price),
+                            paper_positions::unrealized_pnl.eq(0.0),
+                            paper_positions::margin.eq(margin),
+                            paper_positions::leverage.eq(order.leverage),
+                            paper_positions::margin_type.eq(&order.margin_type),
+                            paper_positions::last_funding_at.eq(Utc::now()),
+                        ))
+                        .execute(conn)?;
+                }
+                None => {
+                    diesel::insert_into(paper_positions::table)
+                        .values(&NewPaperPosition {
+                            user_id: order.user_id,
+                            symbol: order.symbol.clone(),
+                            side: side.to_string(),
+                            size: opening_quantity,
+                            entry_price: price,
+                            mark_price: price,
+                            margin,
+                            leverage: order.leverage,
+                            margin_type: order.margin_type.clone(),
+                        })
+                        .execute(conn)?;
+                }
+            }
+        }
+
+        diesel::update(paper_orders::table.find(order.id))
+            .set((
+                paper_orders::status.eq("filled"),
+                paper_orders::filled_quantity.eq(order.quantity),
+                paper_orders::average_price.eq(price),
+                paper_orders::reserved_margin.eq(0.0),
+                paper_orders::fee.eq(fee),
+            ))
+            .execute(conn)?;
+
+        Ok(())
+    }
+
+    // Return a closed quantity's margin and PnL to the balance. A loss can take
+    // no more than the balance holds; returns the PnL actually realized.
+    fn settle_close(
+        conn: &mut PgConnection,
+        user_id: Uuid,
+        released_margin: f64,
+        pnl: f64,
+    ) -> Result<f64, AppError> {
+        let available = Self::load_balance(conn, user_id)?.available;
+        let credit = close_settlement(available, released_margin, pnl);
+        Self::adjust_balance(conn, user_id, credit, -released_margin)?;
+        Ok(credit - released_margin)
+    }
+
+    // Update mark price and unrealized PnL, settle any funding intervals that
+    // elapsed, and liquidate the position once the mark reaches its liquidation price
+    fn mark_position(
+        conn: &mut PgConnection,
+        user_id: Uuid,
+        symbol: &str,
+        mid: f64,
+        funding_rate: f64,
+        now: DateTime<Utc>,
+    ) -> Result<(), AppError> {
+        let position = match Self::load_position(conn, user_id, symbol)? {
+            Some(position) if position.size > 0.0 => position,
+            _ => return Ok(()),
+        };
+
+        let direction = if position.side == "long" { 1.0 } else { -1.0 };
+        let unrealized_pnl =
+            PositionAnalytics::unrealized_pnl(&position.side, position.size, position.entry_price, mid);
+
+        let intervals = (now - position.last_funding_at).num_hours() / FUNDING_INTERVAL_HOURS;
+        let mut funding_paid = position.funding_paid;
+        let mut last_funding_at = position.last_funding_at;
+        if intervals > 0 {
+            // Longs pay shorts when the rate is positive, up to what the balance holds
+            let payment = position.size * mid * funding_rate * direction * intervals as f64;
+            let available = Self::load_balance(conn, user_id)?.available;
+            let payment = if payment > 0.0 { payment.min(available.max(0.0)) } else { payment };
+            Self::adjust_balance(conn, user_id, -payment, 0.0)?;
+            funding_paid += payment;
+            last_funding_at += Duration::hours(intervals * FUNDING_INTERVAL_HOURS);
+        }
+
+        let free_collateral = Self::load_balance(conn, user_id)?.available;
+        let total_cross_margin = paper_positions::table
+            .filter(paper_positions::user_id.eq(user_id))
+            .filter(paper_positions::margin_type.eq("cross"))
+            .filter(paper_positions::size.gt(0.0))
+            .select(diesel::dsl::sum(paper_positions::margin))
+            .first::<Option<f64>>(conn)?
+            .unwrap_or(0.0);
+        let free_collateral_share =
+            PositionAnalytics::free_collateral_share(position.margin, total_cross_margin, free_collateral);
+        let liquidation_price = PositionAnalytics::liquidation_price(
+            &position.side,
+            position.size,
+            position.entry_price,
+            PositionAnalytics::collateral(&position.margin_type, position.margin, free_collateral_share),
+            maintenance_margin_rate(MarketRegistry::by_symbol(symbol)),
+        );
+
+        if is_liquidated(&position.side, mid, liquidation_price) {
+            let realized_pnl = Self::settle_close(conn, user_id, position.margin, unrealized_pnl)?;
+            log::info!("Liquidated paper {} position of user {} at {}", symbol, user_id, mid);
+
+            diesel::update(paper_positions::table.find(position.id))
+                .set((
+                    paper_positions::size.eq(0.0),
+                    paper_positions::margin.eq(0.0),
+                    paper_positions::mark_price.eq(mid),
+                    paper_positions::unrealized_pnl.eq(0.0),
+                    paper_positions::realized_pnl.eq(position.realized_pnl + realized_pnl),
+                    paper_positions::liquidation_price.eq(None::<f64>),
+                    paper_positions::funding_paid.eq(funding_paid),
+                    paper_positions::last_funding_at.eq(last_funding_at),
+                ))
+                .execute(conn)?;
+            return Ok(());
+        }
+
+        diesel::update(paper_positions::table.find(position.id))
+            .set((
+                paper_positions::mark_price.eq(mid),
+                paper_positions::unrealized_pnl.eq(unrealized_pnl),
+                paper_positions::liquidation_price.eq(liquidation_price),
+                paper_positions::funding_paid.eq(funding_paid),
+                paper_positions::last_funding_at.eq(last_funding_at),
+            ))
+            .execute(conn)?;
+
+        Ok(())
+    }
+}
+
+// What closing credits back to the available balance: the released margin
+// plus PnL, where a loss can take at most what is available
+pub fn close_settlement(available: f64, released_margin: f64, pnl: f64) -> f64 {
+    (released_margin + pnl).max(-available.max(0.0))
+}
+
+// A long is liquidated once the mark falls to its liquidation price, a short
+// once it rises to it
+pub fn is_liquidated(side: &str, mark_price: f64, liquidation_price: Option<f64>) -> bool {
+    match liquidation_price {
+        Some(price) if side == "short" => mark_price >= price,
+        Some(price) => mark_price <= price,
+        None => false,
+    }
+}
+
+fn position_side(order_side: &str) -> &'static str {
+    if order_side == "buy" {
+        "long"
+    } else {
+        "short"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(side: &str, order_type: &str, price: Option<f64>, age_secs: i64) -> PaperOrder {
+        let now = Utc::now();
+        PaperOrder {
+            id: Uuid::new_v4(),
+            user_id: Uuid::nil(),
+            symbol: "APT/USDC".to_string(),
+            order_type: order_type.to_string(),
+            side: side.to_string(),
+            quantity: 1.0,
+            price,
+            status: "open".to_string(),
+            filled_quantity: 0.0,
+            average_price: None,
+            leverage: 1.0,
+            margin_type: "cross".to_string(),
+            reserved_margin: 0.0,
+            fee: 0.0,
+            created_at: now - Duration::seconds(age_secs),
+            updated_at: now,
+        }
+    }
+
+    fn position(side: &str, size: f64, entry_price: f64, margin: f64) -> PaperPosition {
+        let now = Utc::now();
+        PaperPosition {
+            id: Uuid::new_v4(),
+            user_id: Uuid::nil(),
+            symbol: "APT/USDC".to_string(),
+            side: side.to_string(),
+            size,
+            entry_price,
+            mark_price: entry_price,
+            unrealized_pnl: 0.0,
+            realized_pnl: 0.0,
+            margin,
+            leverage: size * entry_price / margin,
+            liquidation_price: None,
+            margin_type: "cross".to_string(),
+            funding_paid: 0.0,
+            last_funding_at: now,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    #[test]
+    fn test_match_orders_by_price_then_time() {
+        let best = order("buy", "limit", Some(10.0), 0);
+        let older = order("buy", "limit", Some(9.0), 60);
+        let newer = order("buy", "limit", Some(9.0), 0);
+        let market = order("buy", "market", None, 0);
+        let away = order("buy", "limit", Some(8.0), 120);
+
+        let ids: Vec<Uuid> = MatchingEngine::match_orders(
+            vec![newer.clone(), away, best.clone(), older.clone(), market.clone()],
+            9.0,
+        )
+        .into_iter()
+        .map(|(order, _)| order.id)
+        .collect();
+
+        // The 8.0 bid is below the mid and rests
+        assert_eq!(ids, vec![market.id, best.id, older.id, newer.id]);
+    }
+
+    #[test]
+    fn test_limit_orders_fill_at_the_better_of_mid_and_limit() {
+        let buy = order("buy", "limit", Some(10.0), 0);
+        assert!(MatchingEngine::crosses(&buy, 9.5));
+        assert_eq!(MatchingEngine::execution_price(&buy, 9.5), 9.5);
+        assert!(!MatchingEngine::crosses(&buy, 10.5));
+
+        let sell = order("sell", "limit", Some(10.0), 0);
+        assert!(MatchingEngine::crosses(&sell, 10.5));
+        assert_eq!(MatchingEngine::execution_price(&sell, 10.5), 10.5);
+        assert!(!MatchingEngine::crosses(&sell, 9.5));
+
+        let market = order("sell", "market", None, 0);
+        assert!(MatchingEngine::crosses(&market, 1.0));
+        assert_eq!(MatchingEngine::execution_price(&market, 1.0), 1.0);
+    }
+
+    #[test]
+    fn test_fill_effect_opens_and_adds() {
+        assert_eq!(
+            MatchingEngine::fill_effect(None, "long", 2.0, 10.0),
+            FillEffect { opened: 2.0, ..Default::default() }
+        );
+
+        let long = position("long", 1.0, 10.0, 5.0);
+        assert_eq!(
+            MatchingEngine::fill_effect(Some(&long), "long", 2.0, 12.0),
+            FillEffect { added: 2.0, ..Default::default() }
+        );
+
+        // A flat row left from an earlier position is reopened, not closed
+        let flat = position("short", 0.0, 10.0, 1.0);
+        assert_eq!(MatchingEngine::fill_effect(Some(&flat), "long", 1.0, 10.0).opened, 1.0);
+    }
+
+    #[test]
+    fn test_fill_effect_partial_close() {
+        let long = position("long", 4.0, 10.0, 20.0);
+        let effect = MatchingEngine::fill_effect(Some(&long), "short", 1.0, 12.0);
+
+        assert_eq!(effect.closed, 1.0);
+        assert_eq!(effect.opened, 0.0);
+        assert_eq!(effect.realized_pnl, 2.0);
+        assert_eq!(effect.released_margin, 5.0);
+    }
+
+    #[test]
+    fn test_fill_effect_reversal() {
+        let short = position("short", 2.0, 10.0, 10.0);
+        let effect = MatchingEngine::fill_effect(Some(&short), "long", 5.0, 11.0);
+
+        assert_eq!(effect.closed, 2.0);
+        assert_eq!(effect.opened, 3.0);
+        assert_eq!(effect.realized_pnl, -2.0);
+        assert_eq!(effect.released_margin, 10.0);
+    }
+
+    #[test]
+    fn test_close_settlement_never_overdraws() {
+        // Margin back plus profit
+        assert_eq!(close_settlement(100.0, 10.0, 5.0), 15.0);
+        // A loss within the margin
+        assert_eq!(close_settlement(0.0, 10.0, -4.0), 6.0);
+        // A loss beyond margin takes from the available balance, down to zero
+        assert_eq!(close_settlement(3.0, 10.0, -12.0), -2.0);
+        assert_eq!(close_settlement(3.0, 10.0, -50.0), -3.0);
+        assert_eq!(close_settlement(-1.0, 10.0, -50.0), 0.0);
+    }
+
+    #[test]
+    fn test_liquidation_price_and_trigger() {
+        // Long 10 @ 100 with 100 collateral and 5% maintenance:
+        // 100 + (P - 100) * 10 = 10 * P * 0.05  =>  P = 900 / 9.5
+        let long = PositionAnalytics::liquidation_price("long", 10.0, 100.0, 100.0, 0.05).unwrap();
+        assert!((long - 900.0 / 9.5).abs() < 1e-9);
+        assert!(!is_liquidated("long", long + 0.01, Some(long)));
+        assert!(is_liquidated("long", long, Some(long)));
+
+        // Short 10 @ 100: 100 - (P - 100) * 10 = 10 * P * 0.05  =>  P = 1100 / 10.5
+        let short = PositionAnalytics::liquidation_price("short", 10.0, 100.0, 100.0, 0.05).unwrap();
+        assert!((short - 1100.0 / 10.5).abs() < 1e-9);
+        assert!(!is_liquidated("short", short - 0.01, Some(short)));
+        assert!(is_liquidated("short", short, Some(short)));
+
+        // A fully collateralized long cannot be liquidated
+        assert_eq!(PositionAnalytics::liquidation_price("long", 1.0, 100.0, 100.0, 0.05), None);
+        assert!(!is_liquidated("long", 0.0, None));
+    }
+}
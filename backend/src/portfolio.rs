@@ -0,0 +1,252 @@
+use crate::fills::{CostBasis, FillService, PnlPeriod};
+use crate::kana_client::KanaClient;
+use crate::markets::{MarketRegistry, MarketSpec};
+use crate::models::{MarketExposure, PortfolioError, PortfolioResponse, PositionResponse};
+use crate::positions::{number, PositionService};
+use crate::utils::AppError;
+use crate::wallets::WalletService;
+use crate::DbPool;
+use chrono::{Duration, Utc};
+use futures::future::join_all;
+use serde_json::Value;
+use uuid::Uuid;
+
+pub struct PortfolioService;
+
+impl PortfolioService {
+    pub async fn get_portfolio(pool: &DbPool, user_id: Uuid) -> Result<PortfolioResponse, AppError> {
+        let wallets: Vec<String> = WalletService::get_user_wallets(pool, user_id)
+            .await?
+            .into_iter()
+            .map(|wallet| wallet.wallet_address)
+            .collect();
+        if wallets.is_empty() {
+            return Err(AppError::BadRequest("No wallet linked to this account".to_string()));
+        }
+
+        let mut portfolio = Self::aggregate(wallets).await?;
+
+        // Realized PnL, fees and funding booked in the last 24 hours
+        let since = Utc::now() - Duration::hours(24);
+        portfolio.pnl_24h = FillService::get_pnl_summary(
+            pool,
+            user_id,
+            PnlPeriod::Daily,
+            CostBasis::Fifo,
+            None,
+            Some(since),
+            None,
+        )
+        .await?
+        .iter()
+        .map(|summary| summary.net_pnl)
+        .sum();
+
+        Ok(portfolio)
+    }
+
+    // Aggregate balances, positions and open orders of the given wallets across
+    // all registry markets. Every (wallet, market) pair is fetched concurrently and
+    // failures are reported alongside whatever data did load.
+    pub async fn aggregate(wallets: Vec<String>) -> Result<PortfolioResponse, AppError> {
+        let kana_client = KanaClient::new()?;
+        let pairs: Vec<(&str, &'static MarketSpec)> = wallets
+            .iter()
+            .flat_map(|wallet| MarketRegistry::all().iter().map(move |market| (wallet.as_str(), market)))
+            .collect();
+
+        let balances = join_all(wallets.iter().map(|wallet| async {
+            let result = kana_client.get_wallet_account_balance(wallet).await;
+            (wallet.as_str(), result)
+        }));
+        let positions = join_all(pairs.iter().map(|&(wallet, market)| async move {
+            let result = PositionService::get_positions(wallet, Some(market.market_id)).await;
+            (wallet, market, result)
+        }));
+        let open_orders = join_all(pairs.iter().map(|&(wallet, market)| {
+            let kana_client = &kana_client;
+            async move {
+                let result = kana_client.get_open_orders(wallet, Some(market.market_id)).await;
+                (wallet, market, result)
+            }
+        }));
+        let (balances, positions, open_orders) = tokio::join!(balances, positions, open_orders);
+
+        let mut errors = Vec::new();
+        let mut free_collateral = 0.0;
+        for (wallet, result) in balances {
+            match result.map(|raw| parse_balance(&raw)) {
+                Ok(Some(balance)) => free_collateral += balance,
+                Ok(None) => errors.push(portfolio_error(wallet, None, "balance", "Unrecognized balance response")),
+                Err(e) => errors.push(portfolio_error(wallet, None, "balance", &e.to_string())),
+            }
+        }
+
+        let mut markets: Vec<MarketExposure> = MarketRegistry::all()
+            .iter()
+            .map(|market| MarketExposure {
+                symbol: market.symbol.to_string(),
+                market_id: market.market_id.to_string(),
+                long_notional: 0.0,
+                short_notional: 0.0,
+                net_notional: 0.0,
+                margin: 0.0,
+                unrealized_pnl: 0.0,
+                open_orders: 0,
+            })
+            .collect();
+
+        let mut all_positions: Vec<PositionResponse> = Vec::new();
+        for (wallet, market, result) in positions {
+            match result {
+                Ok(market_positions) => {
+                    let entry = exposure(&mut markets, market);
+                    for position in &market_positions {
+                        add_position(entry, position);
+                    }
+                    all_positions.extend(market_positions);
+                }
+                Err(e) => errors.push(portfolio_error(wallet, Some(market.symbol), "positions", &e.to_string())),
+            }
+        }
+
+        for (wallet, market, result) in open_orders {
+            match result {
+                Ok(raw) => exposure(&mut markets, market).open_orders += open_order_count(&raw),
+                Err(e) => errors.push(portfolio_error(wallet, Some(market.symbol), "open_orders", &e.to_string())),
+            }
+        }
+
+        for market in &mut markets {
+            market.net_notional = market.long_notional - market.short_notional;
+        }
+
+        let margin_used: f64 = markets.iter().map(|m| m.margin).sum();
+        let unrealized_pnl: f64 = markets.iter().map(|m| m.unrealized_pnl).sum();
+        let open_orders_count = markets.iter().map(|m| m.open_orders).sum();
+
+        Ok(PortfolioResponse {
+            wallets,
+            total_equity: free_collateral + margin_used + unrealized_pnl,
+            free_collateral,
+            margin_used,
+            unrealized_pnl,
+            pnl_24h: 0.0,
+            open_orders_count,
+            markets,
+            positions: all_positions,
+            partial: !errors.is_empty(),
+            errors,
+            updated_at: Utc::now(),
+        })
+    }
+}
+
+// getWalletAccountBalance returns the balance either as `data` itself or as a field of it
+fn parse_balance(raw: &Value) -> Option<f64> {
+    match raw.get("data")? {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.parse::<f64>().ok(),
+        data => number(data, &["balance", "availableBalance", "available_balance"]),
+    }
+}
+
+// Registry markets always have a mark price
+fn add_position(entry: &mut MarketExposure, position: &PositionResponse) {
+    if position.side == "short" {
+        entry.short_notional += position.notional.unwrap_or(0.0);
+    } else {
+        entry.long_notional += position.notional.unwrap_or(0.0);
+    }
+    entry.margin += position.margin;
+    entry.unrealized_pnl += position.unrealized_pnl.unwrap_or(0.0);
+}
+
+fn open_order_count(raw: &Value) -> usize {
+    raw.get("data").and_then(|d| d.as_array()).map_or(0, |d| d.len())
+}
+
+// Markets are built from the registry, so every registry market has an entry
+fn exposure<'a>(markets: &'a mut [MarketExposure], market: &MarketSpec) -> &'a mut MarketExposure {
+    markets
+        .iter_mut()
+        .find(|m| m.market_id == market.market_id)
+        .expect("exposure entry for every registry market")
+}
+
+fn portfolio_error(
+    wallet_address: &str,
+    symbol: Option<&str>,
+    source: &str,
+    message: &str,
+) -> PortfolioError {
+    PortfolioError {
+        wallet_address: wallet_address.to_string(),
+        symbol: symbol.map(|s| s.to_string()),
+        source: source.to_string(),
+        message: message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn position(side: &str, notional: f64, margin: f64, unrealized_pnl: f64) -> PositionResponse {
+        PositionResponse {
+            market_id: "501".to_string(),
+            symbol: "APT-USD".to_string(),
+            side: side.to_string(),
+            size: 1.0,
+            entry_price: notional,
+            mark_price: Some(notional),
+            notional: Some(notional),
+            unrealized_pnl: Some(unrealized_pnl),
+            realized_pnl: 0.0,
+            roe: None,
+            margin,
+            margin_type: "cross".to_string(),
+            leverage: notional / margin,
+            maintenance_margin: None,
+            margin_ratio: None,
+            liquidation_price: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_balance_shapes() {
+        assert_eq!(parse_balance(&json!({ "data": 12.5 })), Some(12.5));
+        assert_eq!(parse_balance(&json!({ "data": "7.25" })), Some(7.25));
+        assert_eq!(parse_balance(&json!({ "data": { "availableBalance": "3" } })), Some(3.0));
+        assert_eq!(parse_balance(&json!({ "data": { "other": 1 } })), None);
+        assert_eq!(parse_balance(&json!({ "error": "down" })), None);
+    }
+
+    #[test]
+    fn test_exposure_sums_sides_separately() {
+        let market = &MarketRegistry::all()[0];
+        let mut markets = vec![MarketExposure {
+            symbol: market.symbol.to_string(),
+            market_id: market.market_id.to_string(),
+            long_notional: 0.0,
+            short_notional: 0.0,
+            net_notional: 0.0,
+            margin: 0.0,
+            unrealized_pnl: 0.0,
+            open_orders: 0,
+        }];
+
+        let entry = exposure(&mut markets, market);
+        add_position(entry, &position("long", 100.0, 10.0, 5.0));
+        add_position(entry, &position("short", 40.0, 4.0, -2.0));
+        entry.open_orders += open_order_count(&json!({ "data": [{}, {}] }));
+        entry.open_orders += open_order_count(&json!({ "data": null }));
+
+        assert_eq!(entry.long_notional, 100.0);
+        assert_eq!(entry.short_notional, 40.0);
+        assert_eq!(entry.margin, 14.0);
+        assert_eq!(entry.unrealized_pnl, 3.0);
+        assert_eq!(entry.open_orders, 2);
+    }
+}
@@ -0,0 +1,431 @@
+use crate::kana_client::KanaClient;
+use crate::markets::{MarketRegistry, MarketSpec};
+use crate::models::PositionResponse;
+use crate::utils::AppError;
+use serde_json::Value;
+use std::collections::HashMap;
+
+// Used for markets that are not in the registry yet
+const DEFAULT_MAINTENANCE_MARGIN_RATE: f64 = 0.05;
+
+// One position entry as reported by Kana Labs; several legs of the same
+// market and side are merged into a single position
+#[derive(Debug, Clone)]
+pub struct PositionLeg {
+    pub market_id: String,
+    pub side: String, // "long" or "short"
+    pub size: f64,
+    pub entry_price: f64,
+    pub margin: f64,
+    pub leverage: f64,
+    pub realized_pnl: f64,
+    pub margin_type: String, // "isolated" or "cross"
+}
+
+// Pure position math, shared by live and paper positions
+pub struct PositionAnalytics;
+
+impl PositionAnalytics {
+    // Size-weighted average entry price of (size, price) pairs
+    pub fn weighted_entry_price(entries: &[(f64, f64)]) -> f64 {
+        let total_size: f64 = entries.iter().map(|(size, _)| size).sum();
+        if total_size <= 0.0 {
+            return 0.0;
+        }
+        entries.iter().map(|(size, price)| size * price).sum::<f64>() / total_size
+    }
+
+    pub fn unrealized_pnl(side: &str, size: f64, entry_price: f64, mark_price: f64) -> f64 {
+        (mark_price - entry_price) * size * direction(side)
+    }
+
+    // Return on equity: unrealized PnL relative to the margin posted
+    pub fn roe(unrealized_pnl: f64, margin: f64) -> f64 {
+        if margin > 0.0 {
+            unrealized_pnl / margin
+        } else {
+            0.0
+        }
+    }
+
+    pub fn maintenance_margin(size: f64, mark_price: f64, maintenance_margin_rate: f64) -> f64 {
+        size * mark_price * maintenance_margin_rate
+    }
+
+    // Maintenance margin over remaining collateral; the position is liquidated at 1.0
+    pub fn margin_ratio(maintenance_margin: f64, collateral: f64, unrealized_pnl: f64) -> f64 {
+        let equity = collateral + unrealized_pnl;
+        if equity > 0.0 {
+            maintenance_margin / equity
+        } else {
+            f64::INFINITY
+        }
+    }
+
+    // Mark price at which collateral + PnL falls to the maintenance requirement.
+    // Solves collateral + (P - entry) * size * dir = size * P * mmr for P.
+    pub fn liquidation_price(
+        side: &str,
+        size: f64,
+        entry_price: f64,
+        collateral: f64,
+        maintenance_margin_rate: f64,
+    ) -> Option<f64> {
+        if size <= 0.0 {
+            return None;
+        }
+
+        let dir = direction(side);
+        let price = (entry_price * size * dir - collateral) / (size * (dir - maintenance_margin_rate));
+        if price.is_finite() && price > 0.0 {
+            Some(price)
+        } else {
+            None
+        }
+    }
+
+    // Isolated positions can only lose their own margin; cross positions can also
+    // draw on their share of the account's free collateral
+    pub fn collateral(margin_type: &str, margin: f64, free_collateral_share: f64) -> f64 {
+        if margin_type == "cross" {
+            margin + free_collateral_share.max(0.0)
+        } else {
+            margin
+        }
+    }
+
+    // Cross positions all draw on the same free collateral, so each is given a
+    // part of it in proportion to its margin rather than all of it
+    pub fn free_collateral_share(margin: f64, total_cross_margin: f64, free_collateral: f64) -> f64 {
+        if total_cross_margin > 0.0 {
+            free_collateral.max(0.0) * margin / total_cross_margin
+        } else {
+            0.0
+        }
+    }
+
+    pub fn analyze(
+        leg: &PositionLeg,
+        symbol: &str,
+        maintenance_margin_rate: f64,
+        mark_price: Option<f64>,
+        free_collateral_share: f64,
+    ) -> PositionResponse {
+        let unrealized_pnl =
+            mark_price.map(|mark| Self::unrealized_pnl(&leg.side, leg.size, leg.entry_price, mark));
+        let maintenance_margin =
+            mark_price.map(|mark| Self::maintenance_margin(leg.size, mark, maintenance_margin_rate));
+        let collateral = Self::collateral(&leg.margin_type, leg.margin, free_collateral_share);
+
+        PositionResponse {
+            market_id: leg.market_id.clone(),
+            symbol: symbol.to_string(),
+            side: leg.side.clone(),
+            size: leg.size,
+            entry_price: leg.entry_price,
+            mark_price,
+            notional: mark_price.map(|mark| leg.size * mark),
+            unrealized_pnl,
+            realized_pnl: leg.realized_pnl,
+            roe: unrealized_pnl.map(|pnl| Self::roe(pnl, leg.margin)),
+            margin: leg.margin,
+            margin_type: leg.margin_type.clone(),
+            leverage: leg.leverage,
+            maintenance_margin,
+            margin_ratio: maintenance_margin
+                .zip(unrealized_pnl)
+                .map(|(maintenance, pnl)| Self::margin_ratio(maintenance, collateral, pnl)),
+            liquidation_price: Self::liquidation_price(
+                &leg.side,
+                leg.size,
+                leg.entry_price,
+                collateral,
+                maintenance_margin_rate,
+            ),
+        }
+    }
+}
+
+pub struct PositionService;
+
+impl PositionService {
+    // Fetch a wallet's Kana Labs positions and compute analytics at the current mark
+    pub async fn get_positions(
+        user_address: &str,
+        market_id: Option<&str>,
+    ) -> Result<Vec<PositionResponse>, AppError> {
+        let kana_client = KanaClient::new()?;
+        let raw = kana_client
+            .get_positions_with_user_address(user_address, market_id)
+            .await?;
+
+        let legs = Self::merge_legs(Self::parse_kana_positions(&raw));
+        if legs.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let free_collateral = match kana_client.get_profile_balance_snapshot(user_address).await {
+            Ok(snapshot) => number(&snapshot, &["availableBalance", "available_balance"]).unwrap_or(0.0),
+            Err(e) => {
+                log::warn!("Failed to fetch balance snapshot for {}: {}", user_address, e);
+                0.0
+            }
+        };
+
+        let total_cross_margin: f64 = legs
+            .iter()
+            .filter(|leg| leg.margin_type == "cross")
+            .map(|leg| leg.margin)
+            .sum();
+
+        let mut mark_prices: HashMap<String, Option<f64>> = HashMap::new();
+        let mut positions = Vec::with_capacity(legs.len());
+        for leg in &legs {
+            let market = MarketRegistry::by_market_id(&leg.market_id);
+            // Markets outside the registry and markets without a live price are
+            // reported without a mark rather than priced at entry or a stale value
+            let mark_price = match (mark_prices.get(&leg.market_id), market) {
+                (Some(price), _) => *price,
+                (None, Some(market)) => {
+                    let price = market.mid_price().await;
+                    mark_prices.insert(leg.market_id.clone(), price);
+                    price
+                }
+                (None, None) => None,
+            };
+
+            positions.push(PositionAnalytics::analyze(
+                leg,
+                market.map(|m| m.symbol).unwrap_or(&leg.market_id),
+                maintenance_margin_rate(market),
+                mark_price,
+                PositionAnalytics::free_collateral_share(leg.margin, total_cross_margin, free_collateral),
+            ));
+        }
+
+        Ok(positions)
+    }
+
+    // Parse the `data` array of a Kana Labs getPositions response
+    pub fn parse_kana_positions(raw: &Value) -> Vec<PositionLeg> {
+        let entries = match raw.get("data").and_then(|d| d.as_array()) {
+            Some(entries) => entries,
+            None => return vec![],
+        };
+
+        entries
+            .iter()
+            .filter_map(|entry| {
+                let market_id = match entry.get("market_id").or_else(|| entry.get("marketId")) {
+                    Some(Value::String(id)) => id.clone(),
+                    Some(Value::Number(id)) => id.to_string(),
+                    _ => return None,
+                };
+                let size = number(entry, &["size"])?;
+                if size <= 0.0 {
+                    return None;
+                }
+
+                // Kana Labs reports the side as a boolean trade_side (true = long).
+                // A position of unknown side is left out rather than guessed, as
+                // guessing wrong inverts its PnL and liquidation price.
+                let side = match entry.get("trade_side").or_else(|| entry.get("side")) {
+                    Some(Value::Bool(true)) => "long",
+                    Some(Value::Bool(false)) => "short",
+                    Some(Value::String(s)) if s == "long" || s == "buy" => "long",
+                    Some(Value::String(s)) if s == "short" || s == "sell" => "short",
+                    other => {
+                        log::warn!("Skipping position in market {} with unrecognized side {:?}", market_id, other);
+                        return None;
+                    }
+                };
+
+                let entry_price = number(entry, &["entry_price", "entryPrice"]).unwrap_or(0.0);
+                let leverage = number(entry, &["leverage"]).unwrap_or(1.0).max(1.0);
+                let margin = number(entry, &["margin"]).unwrap_or(size * entry_price / leverage);
+
+                Some(PositionLeg {
+                    market_id,
+                    side: side.to_string(),
+                    size,
+                    entry_price,
+                    margin,
+                    leverage,
+                    realized_pnl: number(entry, &["realized_pnl", "realizedPnl"]).unwrap_or(0.0),
+                    margin_type: entry
+                        .get("margin_type")
+                        .and_then(|v| v.as_str())
+                        .unwrap_or("isolated")
+                        .to_string(),
+                })
+            })
+            .collect()
+    }
+
+    // Merge legs of the same market and side using an entry-weighted average price
+    pub fn merge_legs(legs: Vec<PositionLeg>) -> Vec<PositionLeg> {
+        let mut merged: Vec<(PositionLeg, Vec<(f64, f64)>)> = Vec::new();
+        for leg in legs {
+            match merged
+                .iter_mut()
+                .find(|(m, _)| m.market_id == leg.market_id && m.side == leg.side)
+            {
+                Some((m, entries)) => {
+                    entries.push((leg.size, leg.entry_price));
+                    m.size += leg.size;
+                    m.margin += leg.margin;
+                    m.realized_pnl += leg.realized_pnl;
+                }
+                None => {
+                    let entries = vec![(leg.size, leg.entry_price)];
+                    merged.push((leg, entries));
+                }
+            }
+        }
+
+        merged
+            .into_iter()
+            .map(|(mut leg, entries)| {
+                leg.entry_price = PositionAnalytics::weighted_entry_price(&entries);
+                if leg.margin > 0.0 {
+                    leg.leverage = leg.size * leg.entry_price / leg.margin;
+                }
+                leg
+            })
+            .collect()
+    }
+}
+
+pub fn maintenance_margin_rate(market: Option<&MarketSpec>) -> f64 {
+    market
+        .map(|m| m.maintenance_margin_rate)
+        .unwrap_or(DEFAULT_MAINTENANCE_MARGIN_RATE)
+}
+
+fn direction(side: &str) -> f64 {
+    if side == "short" {
+        -1.0
+    } else {
+        1.0
+    }
+}
+
+// Kana Labs returns numbers either as JSON numbers or as strings
+pub fn number(value: &Value, keys: &[&str]) -> Option<f64> {
+    keys.iter().find_map(|key| match value.get(*key)? {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.parse::<f64>().ok(),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn leg(market_id: &str, side: &str, size: f64, entry_price: f64, margin: f64) -> PositionLeg {
+        PositionLeg {
+            market_id: market_id.to_string(),
+            side: side.to_string(),
+            size,
+            entry_price,
+            margin,
+            leverage: size * entry_price / margin,
+            realized_pnl: 0.0,
+            margin_type: "isolated".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_unrealized_pnl_and_roe() {
+        assert_eq!(PositionAnalytics::unrealized_pnl("long", 2.0, 10.0, 12.0), 4.0);
+        assert_eq!(PositionAnalytics::unrealized_pnl("short", 2.0, 10.0, 12.0), -4.0);
+        assert_eq!(PositionAnalytics::roe(4.0, 8.0), 0.5);
+        assert_eq!(PositionAnalytics::roe(4.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn test_weighted_entry_price() {
+        assert_eq!(PositionAnalytics::weighted_entry_price(&[(1.0, 10.0), (3.0, 14.0)]), 13.0);
+        assert_eq!(PositionAnalytics::weighted_entry_price(&[]), 0.0);
+    }
+
+    #[test]
+    fn test_margin_ratio_is_infinite_without_equity() {
+        assert_eq!(PositionAnalytics::margin_ratio(5.0, 20.0, -10.0), 0.5);
+        assert_eq!(PositionAnalytics::margin_ratio(5.0, 20.0, -20.0), f64::INFINITY);
+    }
+
+    #[test]
+    fn test_cross_collateral_includes_free_balance() {
+        assert_eq!(PositionAnalytics::collateral("cross", 10.0, 40.0), 50.0);
+        assert_eq!(PositionAnalytics::collateral("cross", 10.0, -5.0), 10.0);
+        assert_eq!(PositionAnalytics::collateral("isolated", 10.0, 40.0), 10.0);
+    }
+
+    #[test]
+    fn test_cross_positions_split_the_free_balance() {
+        // Margins of 10 and 30 out of 40 take a quarter and three quarters
+        assert_eq!(PositionAnalytics::free_collateral_share(10.0, 40.0, 80.0), 20.0);
+        assert_eq!(PositionAnalytics::free_collateral_share(30.0, 40.0, 80.0), 60.0);
+        assert_eq!(PositionAnalytics::free_collateral_share(10.0, 40.0, -5.0), 0.0);
+        assert_eq!(PositionAnalytics::free_collateral_share(0.0, 0.0, 80.0), 0.0);
+    }
+
+    #[test]
+    fn test_liquidation_price_lands_on_maintenance() {
+        let (size, entry, collateral, mmr) = (5.0, 20.0, 25.0, 0.025);
+        for side in ["long", "short"] {
+            let price = PositionAnalytics::liquidation_price(side, size, entry, collateral, mmr).unwrap();
+            let equity = collateral + PositionAnalytics::unrealized_pnl(side, size, entry, price);
+            let maintenance = PositionAnalytics::maintenance_margin(size, price, mmr);
+            assert!((equity - maintenance).abs() < 1e-9, "{} at {}", side, price);
+        }
+        assert_eq!(PositionAnalytics::liquidation_price("long", 0.0, entry, collateral, mmr), None);
+    }
+
+    #[test]
+    fn test_analyze_without_mark_price_reports_no_pnl() {
+        let position = PositionAnalytics::analyze(&leg("9999", "long", 2.0, 10.0, 5.0), "9999", 0.05, None, 0.0);
+
+        assert_eq!(position.mark_price, None);
+        assert_eq!(position.unrealized_pnl, None);
+        assert_eq!(position.notional, None);
+        assert_eq!(position.roe, None);
+        assert_eq!(position.margin_ratio, None);
+        // Needs no mark
+        assert!(position.liquidation_price.is_some());
+
+        let priced = PositionAnalytics::analyze(&leg("9999", "long", 2.0, 10.0, 5.0), "9999", 0.05, Some(11.0), 0.0);
+        assert_eq!(priced.unrealized_pnl, Some(2.0));
+        assert_eq!(priced.notional, Some(22.0));
+    }
+
+    #[test]
+    fn test_parse_and_merge_kana_positions() {
+        let raw = json!({
+            "data": [
+                { "market_id": "1338", "trade_side": true, "size": "2", "entry_price": "10", "leverage": "2" },
+                { "marketId": 1338, "trade_side": true, "size": 2.0, "entry_price": 14.0, "margin": 14.0 },
+                { "market_id": "1338", "trade_side": false, "size": "1", "entry_price": "12" },
+                { "market_id": "1339", "size": "0", "entry_price": "100" },
+                { "market_id": "1339", "side": "sideways", "size": "1", "entry_price": "100" },
+                { "market_id": "1339", "size": "1", "entry_price": "100" }
+            ]
+        });
+
+        let legs = PositionService::parse_kana_positions(&raw);
+        assert_eq!(legs.len(), 3);
+        assert_eq!(legs[0].margin, 10.0);
+        assert_eq!(legs[2].side, "short");
+
+        let merged = PositionService::merge_legs(legs);
+        assert_eq!(merged.len(), 2);
+        let long = &merged[0];
+        assert_eq!(long.size, 4.0);
+        assert_eq!(long.entry_price, 12.0);
+        assert_eq!(long.margin, 24.0);
+        assert_eq!(long.leverage, 2.0);
+    }
+}
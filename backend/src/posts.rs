@@ -0,0 +1,556 @@
+use crate::activity::{ActivityEvent, ActivityService};
+use crate::markets::MarketRegistry;
+use crate::models::{
+    CommentResponse, NewPost, NewPostComment, Post, PostComment, PostPositionSnapshot, PostResponse,
+};
+use crate::positions::PositionService;
+use crate::relationships::RelationshipService;
+use crate::schema::{post_comments, post_likes, posts, users};
+use crate::utils::AppError;
+use crate::wallets::WalletService;
+use crate::DbPool;
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+#[derive(Debug, Clone)]
+pub struct CreatePostRequest {
+    pub content: String,
+    pub symbol: Option<String>,
+    // Snapshot the author's current position in `symbol`
+    pub attach_position: bool,
+}
+
+pub struct PostService;
+
+impl PostService {
+    pub async fn create_post(
+        pool: &DbPool,
+        user_id: Uuid,
+        request: CreatePostRequest,
+    ) -> Result<PostResponse, AppError> {
+        let mut new_post = NewPost {
+            user_id,
+            content: request.content,
+            symbol: None,
+            market_price: None,
+            position_side: None,
+            position_size: None,
+            entry_price: None,
+            leverage: None,
+            unrealized_pnl: None,
+        };
+
+        match request.symbol.as_deref() {
+            Some(symbol) => {
+                let market = MarketRegistry::require(symbol)?;
+                new_post.symbol = Some(market.symbol.to_string());
+                new_post.market_price = market.mid_price().await;
+
+                if request.attach_position {
+                    let position = Self::find_position(pool, user_id, market.market_id)
+                        .await?
+                        .ok_or_else(|| {
+                            AppError::BadRequest(format!("No open {} position to attach", market.symbol))
+                        })?;
+                    new_post.position_side = Some(position.side);
+                    new_post.position_size = Some(position.size);
+                    new_post.entry_price = position.entry_price;
+                    new_post.leverage = position.leverage;
+                    new_post.unrealized_pnl = position.unrealized_pnl;
+                }
+            }
+            None if request.attach_position => {
+                return Err(AppError::ValidationError("symbol is required to attach a position".to_string()));
+            }
+            None => {}
+        }
+
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let post = diesel::insert_into(posts::table)
+            .values(&new_post)
+            .get_result::<Post>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to create post: {}", e)))?;
+
+        ActivityService::record(pool, vec![ActivityEvent::post_created(user_id, post.id)]).await;
+
+        Self::response(conn, post, Some(user_id))
+    }
+
+    // Only the author can edit; edits are marked with `edited_at`
+    pub async fn update_post(
+        pool: &DbPool,
+        user_id: Uuid,
+        post_id: Uuid,
+        content: String,
+    ) -> Result<PostResponse, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let post = Self::find_post(conn, post_id)?;
+        if post.user_id != user_id {
+            return Err(AppError::AuthorizationError("You can only edit your own posts".to_string()));
+        }
+
+        let post = diesel::update(posts::table.find(post_id))
+            .set((posts::content.eq(content), posts::edited_at.eq(Some(Utc::now()))))
+            .get_result::<Post>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to update post: {}", e)))?;
+
+        Self::response(conn, post, Some(user_id))
+    }
+
+    // Authors delete their own posts; admins can delete any post
+    pub async fn delete_post(pool: &DbPool, user_id: Uuid, post_id: Uuid) -> Result<(), AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let post = Self::find_post(conn, post_id)?;
+        if post.user_id != user_id && !Self::is_admin(conn, user_id)? {
+            return Err(AppError::AuthorizationError("You can only delete your own posts".to_string()));
+        }
+
+        // Comments, likes and the feed activity go with it
+        diesel::delete(posts::table.find(post_id))
+            .execute(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to delete post: {}", e)))?;
+
+        Ok(())
+    }
+
+    pub async fn get_post(
+        pool: &DbPool,
+        post_id: Uuid,
+        viewer_id: Option<Uuid>,
+    ) -> Result<PostResponse, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let post = Self::find_post(conn, post_id)?;
+        if !Self::can_view(conn, post.user_id, post.hidden_at, viewer_id)? {
+            return Err(AppError::NotFoundError("Post not found".to_string()));
+        }
+
+        Self::response(conn, post, viewer_id)
+    }
+
+    // Newest posts of a user; hidden posts are only listed for their author and admins.
+    // Private accounts only show posts to approved followers.
+    pub async fn get_user_posts(
+        pool: &DbPool,
+        username: &str,
+        viewer_id: Option<Uuid>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(Vec<PostResponse>, i64), AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let author_id = RelationshipService::find_visible_user(conn, username, viewer_id)?.id;
+        RelationshipService::require_content_visible(conn, author_id, viewer_id)?;
+
+        let include_hidden = match viewer_id {
+            Some(viewer_id) => viewer_id == author_id || Self::is_admin(conn, viewer_id)?,
+            None => false,
+        };
+        let filtered = || {
+            let mut query = posts::table.filter(posts::user_id.eq(author_id)).into_boxed();
+            if !include_hidden {
+                query = query.filter(posts::hidden_at.is_null());
+            }
+            query
+        };
+
+        let total = filtered().count().get_result::<i64>(conn)?;
+        let user_posts = filtered()
+            .order(posts::created_at.desc())
+            .limit(limit)
+            .offset(offset)
+            .load::<Post>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get posts: {}", e)))?;
+
+        Ok((Self::responses(conn, user_posts, viewer_id)?, total))
+    }
+
+    pub async fn like_post(pool: &DbPool, user_id: Uuid, post_id: Uuid) -> Result<PostResponse, AppError> {
+        Self::set_like(pool, user_id, post_id, true)
+    }
+
+    pub async fn unlike_post(pool: &DbPool, user_id: Uuid, post_id: Uuid) -> Result<PostResponse, AppError> {
+        Self::set_like(pool, user_id, post_id, false)
+    }
+
+    // Liking twice or removing a missing like is a no-op, so the counter stays exact
+    fn set_like(pool: &DbPool, user_id: Uuid, post_id: Uuid, liked: bool) -> Result<PostResponse, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let post = Self::find_post(conn, post_id)?;
+        if !Self::can_view(conn, post.user_id, post.hidden_at, Some(user_id))? {
+            return Err(AppError::NotFoundError("Post not found".to_string()));
+        }
+
+        let post = conn.transaction(|conn| {
+            let changed = if liked {
+                diesel::insert_into(post_likes::table)
+                    .values((post_likes::post_id.eq(post_id), post_likes::user_id.eq(user_id)))
+                    .on_conflict_do_nothing()
+                    .execute(conn)? as i32
+            } else {
+                -(diesel::delete(post_likes::table.find((post_id, user_id))).execute(conn)? as i32)
+            };
+
+            diesel::update(posts::table.find(post_id))
+                .set(posts::like_count.eq(posts::like_count + changed))
+                .get_result::<Post>(conn)
+                .map_err(AppError::from)
+        })?;
+
+        Self::response(conn, post, Some(user_id))
+    }
+
+    pub async fn add_comment(
+        pool: &DbPool,
+        user_id: Uuid,
+        post_id: Uuid,
+        content: String,
+    ) -> Result<CommentResponse, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let post = Self::find_post(conn, post_id)?;
+        if !Self::can_view(conn, post.user_id, post.hidden_at, Some(user_id))? {
+            return Err(AppError::NotFoundError("Post not found".to_string()));
+        }
+
+        let comment = conn.transaction(|conn| {
+            let comment = diesel::insert_into(post_comments::table)
+                .values(&NewPostComment { post_id, user_id, content })
+                .get_result::<PostComment>(conn)?;
+            diesel::update(posts::table.find(post_id))
+                .set(posts::comment_count.eq(posts::comment_count + 1))
+                .execute(conn)?;
+            Ok::<_, AppError>(comment)
+        })?;
+
+        Ok(Self::comment_responses(conn, vec![comment])?.remove(0))
+    }
+
+    // Oldest first, so threads read top to bottom
+    pub async fn get_comments(
+        pool: &DbPool,
+        post_id: Uuid,
+        viewer_id: Option<Uuid>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(Vec<CommentResponse>, i64), AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let post = Self::find_post(conn, post_id)?;
+        if !Self::can_view(conn, post.user_id, post.hidden_at, viewer_id)? {
+            return Err(AppError::NotFoundError("Post not found".to_string()));
+        }
+
+        // Hidden comments stay visible to their author and admins
+        let is_admin = match viewer_id {
+            Some(viewer_id) => Self::is_admin(conn, viewer_id)?,
+            None => false,
+        };
+        // Comments by users on either side of a block are left out
+        let hidden = RelationshipService::hidden_user_ids(conn, viewer_id)?;
+        let filtered = || {
+            let mut query = post_comments::table
+                .filter(post_comments::post_id.eq(post_id))
+                .filter(post_comments::user_id.ne_all(&hidden))
+                .into_boxed();
+            if !is_admin {
+                query = match viewer_id {
+                    Some(viewer_id) => query.filter(
+                        post_comments::hidden_at.is_null().or(post_comments::user_id.eq(viewer_id)),
+                    ),
+                    None => query.filter(post_comments::hidden_at.is_null()),
+                };
+            }
+            query
+        };
+
+        let total = filtered().count().get_result::<i64>(conn)?;
+        let comments = filtered()
+            .order(post_comments::created_at.asc())
+            .limit(limit)
+            .offset(offset)
+            .load::<PostComment>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get comments: {}", e)))?;
+
+        Ok((Self::comment_responses(conn, comments)?, total))
+    }
+
+    // Comment authors, the post's author and admins can delete a comment
+    pub async fn delete_comment(pool: &DbPool, user_id: Uuid, comment_id: Uuid) -> Result<(), AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let comment = Self::find_comment(conn, comment_id)?;
+        let post = Self::find_post(conn, comment.post_id)?;
+        if comment.user_id != user_id && post.user_id != user_id && !Self::is_admin(conn, user_id)? {
+            return Err(AppError::AuthorizationError("You cannot delete this comment".to_string()));
+        }
+
+        conn.transaction(|conn| {
+            diesel::delete(post_comments::table.find(comment_id)).execute(conn)?;
+            diesel::update(posts::table.find(comment.post_id))
+                .set(posts::comment_count.eq(posts::comment_count - 1))
+                .execute(conn)?;
+            Ok::<_, AppError>(())
+        })
+    }
+
+    // Admin moderation: hide or restore a post without deleting it
+    pub async fn moderate_post(
+        pool: &DbPool,
+        admin_id: Uuid,
+        post_id: Uuid,
+        hidden: bool,
+        reason: Option<String>,
+    ) -> Result<PostResponse, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+        Self::require_admin(conn, admin_id)?;
+        Self::find_post(conn, post_id)?;
+
+        let (hidden_at, hidden_by, reason) = moderation(admin_id, hidden, reason);
+        let post = diesel::update(posts::table.find(post_id))
+            .set((
+                posts::hidden_at.eq(hidden_at),
+                posts::hidden_by.eq(hidden_by),
+                posts::hidden_reason.eq(reason),
+            ))
+            .get_result::<Post>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to moderate post: {}", e)))?;
+
+        log::info!("Admin {} set post {} hidden={}", admin_id, post_id, hidden);
+        Self::response(conn, post, Some(admin_id))
+    }
+
+    pub async fn moderate_comment(
+        pool: &DbPool,
+        admin_id: Uuid,
+        comment_id: Uuid,
+        hidden: bool,
+        reason: Option<String>,
+    ) -> Result<CommentResponse, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+        Self::require_admin(conn, admin_id)?;
+        Self::find_comment(conn, comment_id)?;
+
+        let (hidden_at, hidden_by, reason) = moderation(admin_id, hidden, reason);
+        let comment = diesel::update(post_comments::table.find(comment_id))
+            .set((
+                post_comments::hidden_at.eq(hidden_at),
+                post_comments::hidden_by.eq(hidden_by),
+                post_comments::hidden_reason.eq(reason),
+            ))
+            .get_result::<PostComment>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to moderate comment: {}", e)))?;
+
+        log::info!("Admin {} set comment {} hidden={}", admin_id, comment_id, hidden);
+        Ok(Self::comment_responses(conn, vec![comment])?.remove(0))
+    }
+
+    pub fn is_admin(conn: &mut PgConnection, user_id: Uuid) -> Result<bool, AppError> {
+        Ok(users::table
+            .find(user_id)
+            .select(users::is_admin)
+            .first::<bool>(conn)
+            .optional()?
+            .unwrap_or(false))
+    }
+
+    pub fn require_admin(conn: &mut PgConnection, user_id: Uuid) -> Result<(), AppError> {
+        if Self::is_admin(conn, user_id)? {
+            Ok(())
+        } else {
+            Err(AppError::AuthorizationError("Admin access required".to_string()))
+        }
+    }
+
+    fn can_view(
+        conn: &mut PgConnection,
+        author_id: Uuid,
+        hidden_at: Option<DateTime<Utc>>,
+        viewer_id: Option<Uuid>,
+    ) -> Result<bool, AppError> {
+        if !RelationshipService::content_visible(conn, author_id, viewer_id)? {
+            return Ok(false);
+        }
+        match (hidden_at, viewer_id) {
+            (None, _) => Ok(true),
+            (Some(_), Some(viewer_id)) => Ok(viewer_id == author_id || Self::is_admin(conn, viewer_id)?),
+            (Some(_), None) => Ok(false),
+        }
+    }
+
+    fn find_post(conn: &mut PgConnection, post_id: Uuid) -> Result<Post, AppError> {
+        posts::table
+            .find(post_id)
+            .first::<Post>(conn)
+            .optional()?
+            .ok_or_else(|| AppError::NotFoundError("Post not found".to_string()))
+    }
+
+    fn find_comment(conn: &mut PgConnection, comment_id: Uuid) -> Result<PostComment, AppError> {
+        post_comments::table
+            .find(comment_id)
+            .first::<PostComment>(conn)
+            .optional()?
+            .ok_or_else(|| AppError::NotFoundError("Comment not found".to_string()))
+    }
+
+    // First open position in the market across the user's linked wallets
+    async fn find_position(
+        pool: &DbPool,
+        user_id: Uuid,
+        market_id: &str,
+    ) -> Result<Option<PostPositionSnapshot>, AppError> {
+        for wallet in WalletService::get_user_wallets(pool, user_id).await? {
+            let positions = PositionService::get_positions(&wallet.wallet_address, Some(market_id)).await?;
+            if let Some(position) = positions.into_iter().find(|p| p.size > 0.0) {
+                return Ok(Some(PostPositionSnapshot {
+                    side: position.side,
+                    size: position.size,
+                    entry_price: Some(position.entry_price),
+                    leverage: Some(position.leverage),
+                    unrealized_pnl: position.unrealized_pnl,
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    fn response(conn: &mut PgConnection, post: Post, viewer_id: Option<Uuid>) -> Result<PostResponse, AppError> {
+        Ok(Self::responses(conn, vec![post], viewer_id)?.remove(0))
+    }
+
+    // Attach authors and the viewer's likes to a batch of posts, keeping their order
+    pub fn responses(
+        conn: &mut PgConnection,
+        batch: Vec<Post>,
+        viewer_id: Option<Uuid>,
+    ) -> Result<Vec<PostResponse>, AppError> {
+        let post_ids: Vec<Uuid> = batch.iter().map(|post| post.id).collect();
+        let authors = authors(conn, batch.iter().map(|post| post.user_id).collect())?;
+
+        let liked: Option<HashSet<Uuid>> = match viewer_id {
+            Some(viewer_id) => Some(
+                post_likes::table
+                    .filter(post_likes::user_id.eq(viewer_id))
+                    .filter(post_likes::post_id.eq_any(&post_ids))
+                    .select(post_likes::post_id)
+                    .load::<Uuid>(conn)?
+                    .into_iter()
+                    .collect(),
+            ),
+            None => None,
+        };
+
+        Ok(batch
+            .into_iter()
+            .map(|post| {
+                let (username, avatar_url) = authors.get(&post.user_id).cloned().unwrap_or_default();
+                let position = post.position_side.map(|side| PostPositionSnapshot {
+                    side,
+                    size: post.position_size.unwrap_or(0.0),
+                    entry_price: post.entry_price,
+                    leverage: post.leverage,
+                    unrealized_pnl: post.unrealized_pnl,
+                });
+                PostResponse {
+                    id: post.id,
+                    username,
+                    avatar_url,
+                    content: post.content,
+                    symbol: post.symbol,
+                    market_price: post.market_price,
+                    position,
+                    like_count: post.like_count,
+                    comment_count: post.comment_count,
+                    liked_by_me: liked.as_ref().map(|liked| liked.contains(&post.id)),
+                    is_hidden: post.hidden_at.is_some(),
+                    edited_at: post.edited_at,
+                    created_at: post.created_at,
+                }
+            })
+            .collect())
+    }
+
+    fn comment_responses(
+        conn: &mut PgConnection,
+        comments: Vec<PostComment>,
+    ) -> Result<Vec<CommentResponse>, AppError> {
+        let authors = authors(conn, comments.iter().map(|comment| comment.user_id).collect())?;
+
+        Ok(comments
+            .into_iter()
+            .map(|comment| {
+                let (username, avatar_url) = authors.get(&comment.user_id).cloned().unwrap_or_default();
+                CommentResponse {
+                    id: comment.id,
+                    post_id: comment.post_id,
+                    username,
+                    avatar_url,
+                    content: comment.content,
+                    is_hidden: comment.hidden_at.is_some(),
+                    created_at: comment.created_at,
+                }
+            })
+            .collect())
+    }
+}
+
+fn authors(
+    conn: &mut PgConnection,
+    user_ids: Vec<Uuid>,
+) -> Result<HashMap<Uuid, (String, Option<String>)>, AppError> {
+    Ok(users::table
+        .filter(users::id.eq_any(user_ids))
+        .select((users::id, users::username, users::avatar_url))
+        .load::<(Uuid, String, Option<String>)>(conn)?
+        .into_iter()
+        .map(|(id, username, avatar_url)| (id, (username, avatar_url)))
+        .collect())
+}
+
+// Column values for hiding (or restoring, when `hidden` is false) content
+fn moderation(
+    admin_id: Uuid,
+    hidden: bool,
+    reason: Option<String>,
+) -> (Option<DateTime<Utc>>, Option<Uuid>, Option<String>) {
+    if hidden {
+        (Some(Utc::now()), Some(admin_id), reason)
+    } else {
+        (None, None, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_moderation_hides_and_restores() {
+        let admin_id = Uuid::new_v4();
+        let (hidden_at, hidden_by, reason) = moderation(admin_id, true, Some("spam".to_string()));
+        assert!(hidden_at.is_some());
+        assert_eq!(hidden_by, Some(admin_id));
+        assert_eq!(reason.as_deref(), Some("spam"));
+
+        // Restoring clears the reason along with the rest
+        assert_eq!(moderation(admin_id, false, Some("spam".to_string())), (None, None, None));
+    }
+}
@@ -0,0 +1,395 @@
+use crate::activity::{ActivityEvent, ActivityService};
+use crate::avatars::avatar_storage;
+use crate::config::AppConfig;
+use crate::models::{NewProfileAuditEntry, NewUsernameChange, ProfileAuditEntry, User, UserProfile};
+use crate::schema::{profile_audit_log, username_history, users};
+use crate::utils::{double_option, AppError};
+use crate::DbPool;
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+use serde::Deserialize;
+use uuid::Uuid;
+
+const MIN_USERNAME_LENGTH: usize = 3;
+const MAX_USERNAME_LENGTH: usize = 50;
+const MAX_BIO_LENGTH: usize = 500;
+const MAX_AVATAR_URL_LENGTH: usize = 2048;
+
+sql_function!(fn lower(x: Text) -> Text);
+
+// PATCH body: omitted fields are left alone, an explicit null clears bio or avatar_url
+#[derive(Debug, Default, Deserialize)]
+pub struct ProfileUpdate {
+    pub username: Option<String>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub bio: Option<Option<String>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub avatar_url: Option<Option<String>>,
+}
+
+#[derive(AsChangeset)]
+#[diesel(table_name = users)]
+struct ProfileChangeset {
+    username: Option<String>,
+    bio: Option<Option<String>>,
+    avatar_url: Option<Option<String>>,
+    updated_at: chrono::DateTime<Utc>,
+}
+
+// (field, old value, new value)
+type FieldChange = (&'static str, Option<String>, Option<String>);
+
+pub struct ProfileService;
+
+impl ProfileService {
+    // Apply the fields present in `update`. Every changed field gets an audit
+    // entry; a new username is also kept in the history.
+    pub async fn update_profile(
+        pool: &DbPool,
+        user_id: Uuid,
+        update: ProfileUpdate,
+    ) -> Result<UserProfile, AppError> {
+        let username = update.username.as_deref().map(validate_username).transpose()?;
+        let bio = update.bio.map(|bio| normalize_bio(bio.as_deref())).transpose()?;
+        let avatar_url = update
+            .avatar_url
+            .map(|url| {
+                // A blank URL clears the avatar, like null
+                url.as_deref()
+                    .map(str::trim)
+                    .filter(|url| !url.is_empty())
+                    .map(validate_avatar_url)
+                    .transpose()
+            })
+            .transpose()?;
+
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let (user, changes) = conn.transaction(|conn| {
+            let user = users::table
+                .find(user_id)
+                .for_update()
+                .first::<User>(conn)
+                .optional()?
+                .ok_or_else(|| AppError::NotFoundError("User not found".to_string()))?;
+
+            let username = username.filter(|username| *username != user.username);
+            let bio = bio.filter(|bio| *bio != user.bio);
+            let avatar_url = avatar_url.filter(|url| *url != user.avatar_url);
+
+            let mut changes: Vec<FieldChange> = Vec::new();
+            if let Some(username) = &username {
+                // Changing only the letter case keeps the name: no cooldown, no history
+                if !username.eq_ignore_ascii_case(&user.username) {
+                    Self::check_username_change(conn, &user, username)?;
+                    diesel::insert_into(username_history::table)
+                        .values(&NewUsernameChange {
+                            user_id,
+                            old_username: user.username.clone(),
+                            new_username: username.clone(),
+                        })
+                        .execute(conn)?;
+                }
+                changes.push(("username", Some(user.username.clone()), Some(username.clone())));
+            }
+            if let Some(bio) = &bio {
+                changes.push(("bio", user.bio.clone(), bio.clone()));
+            }
+            if let Some(avatar_url) = &avatar_url {
+                changes.push(("avatar_url", user.avatar_url.clone(), avatar_url.clone()));
+            }
+            if changes.is_empty() {
+                return Ok::<_, AppError>((user, changes));
+            }
+
+            let user = diesel::update(users::table.find(user_id))
+                .set(&ProfileChangeset {
+                    username,
+                    bio,
+                    avatar_url,
+                    updated_at: Utc::now(),
+                })
+                .get_result::<User>(conn)?;
+            Self::audit(conn, user_id, Some(user_id), &changes)?;
+
+            Ok((user, changes))
+        })?;
+
+        if !changes.is_empty() {
+            ActivityService::record(pool, vec![ActivityEvent::profile_updated(user_id)]).await;
+        }
+
+        Ok(user.into())
+    }
+
+    // Set or clear the avatar inside the caller's transaction, with an audit entry
+    pub fn set_avatar_url(
+        conn: &mut PgConnection,
+        user_id: Uuid,
+        avatar_url: Option<&str>,
+    ) -> Result<(), AppError> {
+        let old = users::table
+            .find(user_id)
+            .select(users::avatar_url)
+            .for_update()
+            .first::<Option<String>>(conn)?;
+        let new = avatar_url.map(str::to_string);
+        if old == new {
+            return Ok(());
+        }
+
+        diesel::update(users::table.find(user_id))
+            .set((users::avatar_url.eq(&new), users::updated_at.eq(Utc::now())))
+            .execute(conn)?;
+        Self::audit(conn, user_id, Some(user_id), &[("avatar_url", old, new)])
+    }
+
+    pub async fn get_audit_log(
+        pool: &DbPool,
+        user_id: Uuid,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(Vec<ProfileAuditEntry>, i64), AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let total = profile_audit_log::table
+            .filter(profile_audit_log::user_id.eq(user_id))
+            .count()
+            .get_result::<i64>(conn)?;
+        let entries = profile_audit_log::table
+            .filter(profile_audit_log::user_id.eq(user_id))
+            .order((profile_audit_log::created_at.desc(), profile_audit_log::id.desc()))
+            .limit(limit)
+            .offset(offset)
+            .load::<ProfileAuditEntry>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get profile history: {}", e)))?;
+
+        Ok((entries, total))
+    }
+
+    // Where links to an old username should now point
+    pub async fn renamed_username(pool: &DbPool, old_username: &str) -> Result<Option<String>, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        Ok(Self::renamed_user(conn, old_username)?.map(|(_, username)| username))
+    }
+
+    // (id, current username) of whoever last gave up `old_username`, as long as
+    // nobody has taken it since
+    pub fn renamed_user(conn: &mut PgConnection, old_username: &str) -> Result<Option<(Uuid, String)>, AppError> {
+        let old_username = old_username.trim().to_lowercase();
+        let taken = users::table
+            .filter(lower(users::username).eq(&old_username))
+            .count()
+            .get_result::<i64>(conn)?
+            > 0;
+        if taken {
+            return Ok(None);
+        }
+
+        username_history::table
+            .inner_join(users::table)
+            .filter(lower(username_history::old_username).eq(&old_username))
+            .order(username_history::changed_at.desc())
+            .select((users::id, users::username))
+            .first::<(Uuid, String)>(conn)
+            .optional()
+            .map_err(AppError::from)
+    }
+
+    fn check_username_change(conn: &mut PgConnection, user: &User, username: &str) -> Result<(), AppError> {
+        let taken = users::table
+            .filter(lower(users::username).eq(username.to_lowercase()))
+            .filter(users::id.ne(user.id))
+            .count()
+            .get_result::<i64>(conn)?
+            > 0;
+        if taken {
+            return Err(AppError::ValidationError("Username already exists".to_string()));
+        }
+
+        let last_change = username_history::table
+            .filter(username_history::user_id.eq(user.id))
+            .select(username_history::changed_at)
+            .order(username_history::changed_at.desc())
+            .first::<chrono::DateTime<Utc>>(conn)
+            .optional()?;
+        if let Some(last_change) = last_change {
+            let next_allowed = last_change + username_change_cooldown();
+            if next_allowed > Utc::now() {
+                return Err(AppError::ValidationError(format!(
+                    "Username can be changed again after {}",
+                    next_allowed.format("%Y-%m-%d %H:%M UTC")
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn audit(
+        conn: &mut PgConnection,
+        user_id: Uuid,
+        actor_id: Option<Uuid>,
+        changes: &[FieldChange],
+    ) -> Result<(), AppError> {
+        let entries: Vec<NewProfileAuditEntry> = changes
+            .iter()
+            .map(|(field, old_value, new_value)| NewProfileAuditEntry {
+                user_id,
+                actor_id,
+                field: field.to_string(),
+                old_value: old_value.clone(),
+                new_value: new_value.clone(),
+            })
+            .collect();
+        diesel::insert_into(profile_audit_log::table)
+            .values(&entries)
+            .execute(conn)?;
+        Ok(())
+    }
+}
+
+fn username_change_cooldown() -> Duration {
+    Duration::days(AppConfig::global().username_change_cooldown_days)
+}
+
+fn validate_username(username: &str) -> Result<String, AppError> {
+    let username = username.trim();
+    let length = username.chars().count();
+    if !(MIN_USERNAME_LENGTH..=MAX_USERNAME_LENGTH).contains(&length) {
+        return Err(AppError::ValidationError(format!(
+            "Username must be {} to {} characters",
+            MIN_USERNAME_LENGTH, MAX_USERNAME_LENGTH
+        )));
+    }
+    if !username.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        return Err(AppError::ValidationError(
+            "Username may only contain letters, digits, '_' and '-'".to_string(),
+        ));
+    }
+    Ok(username.to_string())
+}
+
+// A blank bio clears it
+fn normalize_bio(bio: Option<&str>) -> Result<Option<String>, AppError> {
+    let bio = bio.map(str::trim).filter(|bio| !bio.is_empty());
+    if bio.is_some_and(|bio| bio.chars().count() > MAX_BIO_LENGTH) {
+        return Err(AppError::ValidationError(format!(
+            "Bio must be at most {} characters",
+            MAX_BIO_LENGTH
+        )));
+    }
+    Ok(bio.map(str::to_string))
+}
+
+// An absolute http(s) URL, or an avatar uploaded to our own storage
+fn validate_avatar_url(url: &str) -> Result<String, AppError> {
+    let url = url.trim();
+    if url.len() > MAX_AVATAR_URL_LENGTH {
+        return Err(AppError::ValidationError(format!(
+            "Avatar URL must be at most {} characters",
+            MAX_AVATAR_URL_LENGTH
+        )));
+    }
+
+    let uploaded_prefix = avatar_storage()?.url("");
+    if url.starts_with(&uploaded_prefix) && !url.contains("..") {
+        return Ok(url.to_string());
+    }
+
+    match url::Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") && parsed.host().is_some() => {
+            Ok(url.to_string())
+        }
+        _ => Err(AppError::ValidationError(
+            "Avatar URL must be an http or https URL".to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user() -> User {
+        User {
+            id: Uuid::new_v4(),
+            email: "alice@example.com".to_string(),
+            password_hash: "$argon2id$secret".to_string(),
+            username: "alice".to_string(),
+            created_at: Some(Utc::now()),
+            updated_at: Some(Utc::now()),
+            referred_by: None,
+            referral_count: Some(0),
+            total_referral_rewards: Some(0.0),
+            bio: None,
+            avatar_url: None,
+            is_verified: Some(false),
+            last_active: None,
+            leaderboard_opt_out: false,
+            is_admin: true,
+            referral_code_id: None,
+            is_private: false,
+            deletion_scheduled_at: None,
+            deleted_at: None,
+        }
+    }
+
+    #[test]
+    fn test_validate_username() {
+        assert_eq!(validate_username("  alice_01 ").unwrap(), "alice_01");
+        assert_eq!(validate_username("a-b").unwrap(), "a-b");
+        assert!(validate_username("ab").is_err());
+        assert!(validate_username(&"a".repeat(MAX_USERNAME_LENGTH + 1)).is_err());
+        assert!(validate_username(&"a".repeat(MAX_USERNAME_LENGTH)).is_ok());
+        assert!(validate_username("has space").is_err());
+        assert!(validate_username("ålice").is_err());
+        assert!(validate_username("alice.eth").is_err());
+    }
+
+    #[test]
+    fn test_normalize_bio() {
+        assert_eq!(normalize_bio(Some("  hi  ")).unwrap().as_deref(), Some("hi"));
+        assert_eq!(normalize_bio(Some("   ")).unwrap(), None);
+        assert_eq!(normalize_bio(None).unwrap(), None);
+        // Counted in characters, not bytes
+        assert!(normalize_bio(Some(&"é".repeat(MAX_BIO_LENGTH))).is_ok());
+        assert!(normalize_bio(Some(&"a".repeat(MAX_BIO_LENGTH + 1))).is_err());
+    }
+
+    #[test]
+    fn test_validate_avatar_url() {
+        AppConfig::for_tests();
+        assert!(validate_avatar_url("https://cdn.example.com/a.png").is_ok());
+        assert!(validate_avatar_url("javascript:alert(1)").is_err());
+        assert!(validate_avatar_url("ftp://example.com/a.png").is_err());
+        assert!(validate_avatar_url("/relative/a.png").is_err());
+        assert!(validate_avatar_url(&format!("https://example.com/{}", "a".repeat(MAX_AVATAR_URL_LENGTH))).is_err());
+
+        let uploaded = avatar_storage().unwrap().url("user/upload_256.jpg");
+        assert!(validate_avatar_url(&uploaded).is_ok());
+        let escaped = avatar_storage().unwrap().url("../secrets");
+        assert!(validate_avatar_url(&escaped).is_err());
+    }
+
+    #[test]
+    fn test_update_tells_null_from_omitted() {
+        let update: ProfileUpdate = serde_json::from_str(r#"{"bio": null}"#).unwrap();
+        assert_eq!(update.bio, Some(None));
+        assert_eq!(update.avatar_url, None);
+        assert_eq!(update.username, None);
+    }
+
+    #[test]
+    fn test_profile_leaves_out_credentials() {
+        let profile = serde_json::to_value(UserProfile::from(user())).unwrap();
+        assert_eq!(profile["username"], "alice");
+        assert!(profile.get("password_hash").is_none());
+        assert!(profile.get("is_admin").is_none());
+    }
+}
@@ -0,0 +1,615 @@
+use crate::auth::AuthService;
+use crate::config::{AppConfig, RateLimitStoreKind};
+use crate::utils::AppError;
+use actix_web::{
+    body::EitherBody,
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{self, HeaderMap, HeaderName, HeaderValue},
+    http::Method,
+    Error, HttpRequest, ResponseError,
+};
+use futures_util::future::LocalBoxFuture;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::future::{ready, Ready};
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Full buckets are dropped from memory this often; a missing bucket is a full one
+const MEMORY_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+const REDIS_KEY_PREFIX: &str = "rate_limit";
+
+// Token bucket in Redis, using the server clock so instances agree.
+// KEYS[1] bucket, ARGV[1] capacity, ARGV[2] tokens per millisecond.
+// Returns {allowed, tokens left}.
+const REDIS_TOKEN_BUCKET: &str = r#"
+local capacity = tonumber(ARGV[1])
+local rate = tonumber(ARGV[2])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'ts')
+local tokens = tonumber(bucket[1]) or capacity
+local ts = tonumber(bucket[2]) or now
+tokens = math.min(capacity, tokens + math.max(0, now - ts) * rate)
+
+local allowed = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+    allowed = 1
+end
+
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'ts', now)
+redis.call('PEXPIRE', KEYS[1], math.ceil((capacity - tokens) / rate) + 1000)
+return {allowed, tostring(tokens)}
+"#;
+
+const RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+const RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+const RATELIMIT_RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+const RATELIMIT_POLICY: HeaderName = HeaderName::from_static("ratelimit-policy");
+const API_KEY_HEADER: &str = "x-api-key";
+
+// Response headers browsers may read across origins
+pub const RATE_LIMIT_HEADERS: [HeaderName; 5] = [
+    RATELIMIT_LIMIT,
+    RATELIMIT_REMAINING,
+    RATELIMIT_RESET,
+    RATELIMIT_POLICY,
+    header::RETRY_AFTER,
+];
+
+// Trading endpoints that change state even though they are GETs
+const TRADING_WRITE_GETS: [&str; 4] = [
+    "/api/trading/place-limit-order",
+    "/api/trading/add-margin",
+    "/api/trading/collapse-position",
+    "/api/trading/settle-pnl",
+];
+
+// `requests` per `period_secs`, written "10/60" in the config
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Quota {
+    pub requests: u32,
+    pub period_secs: u64,
+}
+
+impl Quota {
+    fn tokens_per_sec(self) -> f64 {
+        self.requests as f64 / self.period_secs as f64
+    }
+}
+
+impl TryFrom<String> for Quota {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("invalid rate limit '{}', expected <requests>/<seconds>", value);
+        let (requests, period_secs) = value.trim().split_once('/').ok_or_else(invalid)?;
+        let requests = requests.trim().parse::<u32>().map_err(|_| invalid())?;
+        let period_secs = period_secs.trim().parse::<u64>().map_err(|_| invalid())?;
+        if requests == 0 || period_secs == 0 {
+            return Err(invalid());
+        }
+        Ok(Quota { requests, period_secs })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RouteGroup {
+    Auth,
+    TradingWrites,
+    MarketData,
+    Default,
+}
+
+impl RouteGroup {
+    // The group a request is counted in; None for routes that are not limited
+    pub fn of(method: &Method, path: &str) -> Option<Self> {
+        if method == Method::OPTIONS || path == "/api/health" || path.starts_with("/api/ws/") {
+            return None;
+        }
+
+        // Referral link clicks are public and feed referrers' stats
+        let group = if path.starts_with("/api/auth/") || is_referral_click(method, path) {
+            RouteGroup::Auth
+        } else if path.starts_with("/api/trading/") {
+            if method != Method::GET || TRADING_WRITE_GETS.contains(&path) {
+                RouteGroup::TradingWrites
+            } else {
+                RouteGroup::MarketData
+            }
+        } else if path.starts_with("/api/copy-trading/") && method != Method::GET {
+            RouteGroup::TradingWrites
+        } else {
+            RouteGroup::Default
+        };
+        Some(group)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            RouteGroup::Auth => "auth",
+            RouteGroup::TradingWrites => "trading_writes",
+            RouteGroup::MarketData => "market_data",
+            RouteGroup::Default => "default",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Decision {
+    allowed: bool,
+    remaining: u32,
+    // Seconds until the bucket is full again
+    reset_secs: u64,
+    // Seconds until the next request is allowed
+    retry_after_secs: u64,
+}
+
+impl Decision {
+    fn new(quota: Quota, tokens_left: f64, allowed: bool) -> Self {
+        let rate = quota.tokens_per_sec();
+        Decision {
+            allowed,
+            remaining: tokens_left.floor() as u32,
+            reset_secs: ((quota.requests as f64 - tokens_left) / rate).ceil() as u64,
+            retry_after_secs: ((1.0 - tokens_left) / rate).ceil().max(1.0) as u64,
+        }
+    }
+
+    fn write_headers(&self, headers: &mut HeaderMap, quota: Quota) {
+        headers.insert(RATELIMIT_LIMIT, HeaderValue::from(quota.requests));
+        headers.insert(RATELIMIT_REMAINING, HeaderValue::from(self.remaining));
+        headers.insert(RATELIMIT_RESET, HeaderValue::from(self.reset_secs));
+        if let Ok(policy) = HeaderValue::from_str(&format!("{};w={}", quota.requests, quota.period_secs)) {
+            headers.insert(RATELIMIT_POLICY, policy);
+        }
+        if !self.allowed {
+            headers.insert(header::RETRY_AFTER, HeaderValue::from(self.retry_after_secs));
+        }
+    }
+}
+
+// Where the token buckets live
+pub trait RateLimitStore: Send + Sync {
+    // Take one token from the bucket under `key`, creating it full if missing
+    fn acquire<'a>(&'a self, key: &'a str, quota: Quota) -> LocalBoxFuture<'a, Result<Decision, AppError>>;
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    full_at: Instant,
+}
+
+impl Bucket {
+    fn full(quota: Quota, now: Instant) -> Self {
+        Bucket {
+            tokens: quota.requests as f64,
+            updated: now,
+            full_at: now,
+        }
+    }
+
+    // Refill for the time since the last request, then take a token if there
+    // is a whole one. Returns whether it was taken.
+    fn take(&mut self, quota: Quota, now: Instant) -> bool {
+        let capacity = quota.requests as f64;
+        let rate = quota.tokens_per_sec();
+
+        let tokens = (self.tokens + now.duration_since(self.updated).as_secs_f64() * rate).min(capacity);
+        let allowed = tokens >= 1.0;
+        self.tokens = if allowed { tokens - 1.0 } else { tokens };
+        self.updated = now;
+        self.full_at = now + Duration::from_secs_f64((capacity - self.tokens) / rate);
+        allowed
+    }
+}
+
+struct MemoryBuckets {
+    buckets: HashMap<String, Bucket>,
+    pruned: Instant,
+}
+
+// Buckets in process memory; each instance counts on its own
+pub struct MemoryStore {
+    inner: Mutex<MemoryBuckets>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore {
+            inner: Mutex::new(MemoryBuckets {
+                buckets: HashMap::new(),
+                pruned: Instant::now(),
+            }),
+        }
+    }
+}
+
+impl RateLimitStore for MemoryStore {
+    fn acquire<'a>(&'a self, key: &'a str, quota: Quota) -> LocalBoxFuture<'a, Result<Decision, AppError>> {
+        let now = Instant::now();
+
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        if now.duration_since(inner.pruned) >= MEMORY_PRUNE_INTERVAL {
+            inner.buckets.retain(|_, bucket| bucket.full_at > now);
+            inner.pruned = now;
+        }
+
+        let bucket = inner.buckets.entry(key.to_string()).or_insert_with(|| Bucket::full(quota, now));
+        let allowed = bucket.take(quota, now);
+
+        Box::pin(ready(Ok(Decision::new(quota, bucket.tokens, allowed))))
+    }
+}
+
+// Buckets in Redis, shared by every instance
+pub struct RedisStore {
+    connection: redis::aio::ConnectionManager,
+    script: redis::Script,
+}
+
+impl RedisStore {
+    pub async fn connect(url: &str) -> Result<Self, AppError> {
+        let client = redis::Client::open(url)
+            .map_err(|e| AppError::ConfigurationError(format!("Invalid REDIS_URL: {}", e)))?;
+        let connection = redis::aio::ConnectionManager::new(client)
+            .await
+            .map_err(|e| AppError::ConfigurationError(format!("Failed to connect to Redis: {}", e)))?;
+
+        Ok(RedisStore {
+            connection,
+            script: redis::Script::new(REDIS_TOKEN_BUCKET),
+        })
+    }
+}
+
+impl RateLimitStore for RedisStore {
+    fn acquire<'a>(&'a self, key: &'a str, quota: Quota) -> LocalBoxFuture<'a, Result<Decision, AppError>> {
+        Box::pin(async move {
+            let mut connection = self.connection.clone();
+            let (allowed, tokens): (i64, String) = self
+                .script
+                .key(format!("{}:{}", REDIS_KEY_PREFIX, key))
+                .arg(quota.requests)
+                .arg(quota.tokens_per_sec() / 1000.0)
+                .invoke_async(&mut connection)
+                .await
+                .map_err(|e| AppError::InternalServerError(format!("Rate limit store error: {}", e)))?;
+            let tokens = tokens.parse::<f64>().unwrap_or(0.0);
+
+            Ok(Decision::new(quota, tokens, allowed == 1))
+        })
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Quotas {
+    auth: Quota,
+    trading_writes: Quota,
+    market_data: Quota,
+    default: Quota,
+}
+
+impl Quotas {
+    fn get(&self, group: RouteGroup) -> Quota {
+        match group {
+            RouteGroup::Auth => self.auth,
+            RouteGroup::TradingWrites => self.trading_writes,
+            RouteGroup::MarketData => self.market_data,
+            RouteGroup::Default => self.default,
+        }
+    }
+}
+
+// Token-bucket limits per route group. Requests are counted per user when they
+// carry a valid access token, else per API key (X-API-Key) when it is one of
+// the configured keys, else per client IP.
+#[derive(Clone)]
+pub struct RateLimiter {
+    // None when rate limiting is disabled
+    store: Option<Arc<dyn RateLimitStore>>,
+    quotas: Quotas,
+    trust_proxy: bool,
+    // SHA-256 hex digests of the known API keys
+    api_keys: Arc<HashSet<String>>,
+}
+
+impl RateLimiter {
+    pub async fn from_config(config: &AppConfig) -> Result<Self, AppError> {
+        let store: Option<Arc<dyn RateLimitStore>> = if !config.rate_limit_enabled {
+            None
+        } else {
+            match (config.rate_limit_store, config.redis_url.as_deref()) {
+                (RateLimitStoreKind::Redis, Some(url)) => Some(Arc::new(RedisStore::connect(url).await?)),
+                (RateLimitStoreKind::Redis, None) => {
+                    return Err(AppError::ConfigurationError("REDIS_URL not set".to_string()))
+                }
+                (RateLimitStoreKind::Memory, _) => Some(Arc::new(MemoryStore::new())),
+            }
+        };
+
+        Ok(RateLimiter {
+            store,
+            quotas: Quotas {
+                auth: config.rate_limit_auth,
+                trading_writes: config.rate_limit_trading_writes,
+                market_data: config.rate_limit_market_data,
+                default: config.rate_limit_default,
+            },
+            trust_proxy: config.rate_limit_trust_proxy,
+            api_keys: Arc::new(config.rate_limit_api_keys.iter().map(|digest| digest.to_lowercase()).collect()),
+        })
+    }
+
+    fn client_key(&self, req: &ServiceRequest) -> String {
+        let user_id = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.strip_prefix("Bearer "))
+            .and_then(|token| AuthService::verify_access_token(token).ok())
+            .map(|claims| claims.sub);
+        if let Some(user_id) = user_id {
+            return format!("user:{}", user_id);
+        }
+
+        // Unknown keys are ignored, or a fresh key per request would escape
+        // every quota. Hashed so keys never end up in Redis.
+        let api_key = req
+            .headers()
+            .get(API_KEY_HEADER)
+            .map(|key| format!("{:x}", Sha256::digest(key.as_bytes())))
+            .filter(|digest| self.api_keys.contains(digest));
+        if let Some(digest) = api_key {
+            return format!("key:{}", digest);
+        }
+
+        let ip = client_ip(req.request(), self.trust_proxy);
+        format!("ip:{}", ip.unwrap_or_else(|| "unknown".to_string()))
+    }
+}
+
+// The client's IP: the connection's peer, or with `trust_proxy` the address
+// the proxy reports in Forwarded/X-Forwarded-For
+pub fn client_ip(req: &HttpRequest, trust_proxy: bool) -> Option<String> {
+    if trust_proxy {
+        // The forwarded address may include a port
+        req.connection_info().realip_remote_addr().map(|addr| {
+            addr.parse::<SocketAddr>()
+                .map(|socket| socket.ip().to_string())
+                .unwrap_or_else(|_| addr.to_string())
+        })
+    } else {
+        req.peer_addr().map(|addr| addr.ip().to_string())
+    }
+}
+
+fn is_referral_click(method: &Method, path: &str) -> bool {
+    method == Method::POST && path.starts_with("/api/social/referral-codes/") && path.ends_with("/click")
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimiter
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RateLimiterService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimiterService {
+            service: Rc::new(service),
+            limiter: self.clone(),
+        }))
+    }
+}
+
+pub struct RateLimiterService<S> {
+    service: Rc<S>,
+    limiter: RateLimiter,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimiterService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let svc = self.service.clone();
+        let limiter = self.limiter.clone();
+
+        Box::pin(async move {
+            let group = RouteGroup::of(req.method(), req.path());
+            let (Some(store), Some(group)) = (limiter.store.clone(), group) else {
+                return svc.call(req).await.map(ServiceResponse::map_into_left_body);
+            };
+
+            let quota = limiter.quotas.get(group);
+            let key = format!("{}:{}", group.name(), limiter.client_key(&req));
+            let decision = match store.acquire(&key, quota).await {
+                Ok(decision) => decision,
+                Err(e) => {
+                    // Better to serve unlimited than not at all
+                    log::warn!("Rate limiting skipped: {}", e);
+                    return svc.call(req).await.map(ServiceResponse::map_into_left_body);
+                }
+            };
+
+            if !decision.allowed {
+                let mut res = AppError::RateLimited(format!(
+                    "Too many requests, try again in {} seconds",
+                    decision.retry_after_secs
+                ))
+                .error_response();
+                decision.write_headers(res.headers_mut(), quota);
+                return Ok(req.into_response(res).map_into_right_body());
+            }
+
+            let mut res = svc.call(req).await?;
+            decision.write_headers(res.headers_mut(), quota);
+            Ok(res.map_into_left_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Claims;
+    use crate::jwt_keys::JwtKeys;
+    use actix_web::test::TestRequest;
+
+    const KNOWN_KEY: &str = "issued-key";
+
+    fn quota(spec: &str) -> Quota {
+        Quota::try_from(spec.to_string()).unwrap()
+    }
+
+    fn limiter(trust_proxy: bool) -> RateLimiter {
+        let default = quota("120/60");
+        RateLimiter {
+            store: None,
+            quotas: Quotas {
+                auth: default,
+                trading_writes: default,
+                market_data: default,
+                default,
+            },
+            trust_proxy,
+            api_keys: Arc::new(HashSet::from([format!("{:x}", Sha256::digest(KNOWN_KEY))])),
+        }
+    }
+
+    fn request() -> TestRequest {
+        TestRequest::default()
+            .peer_addr("203.0.113.7:51000".parse().unwrap())
+            .insert_header(("X-Forwarded-For", "198.51.100.1"))
+    }
+
+    #[test]
+    fn test_quota_parsing() {
+        let parsed = quota(" 10 / 60 ");
+        assert_eq!((parsed.requests, parsed.period_secs), (10, 60));
+        assert!((parsed.tokens_per_sec() - 1.0 / 6.0).abs() < 1e-12);
+
+        for invalid in ["", "10", "10/", "/60", "0/60", "10/0", "-1/60", "ten/60", "10/60/1"] {
+            assert!(Quota::try_from(invalid.to_string()).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_route_groups() {
+        assert_eq!(RouteGroup::of(&Method::POST, "/api/auth/login"), Some(RouteGroup::Auth));
+        assert_eq!(RouteGroup::of(&Method::POST, "/api/social/referral-codes/ALICE/click"), Some(RouteGroup::Auth));
+        assert_eq!(RouteGroup::of(&Method::GET, "/api/social/referral-codes"), Some(RouteGroup::Default));
+        assert_eq!(RouteGroup::of(&Method::POST, "/api/trading/orders"), Some(RouteGroup::TradingWrites));
+        assert_eq!(RouteGroup::of(&Method::GET, "/api/trading/add-margin"), Some(RouteGroup::TradingWrites));
+        assert_eq!(RouteGroup::of(&Method::GET, "/api/trading/markets"), Some(RouteGroup::MarketData));
+        assert_eq!(RouteGroup::of(&Method::DELETE, "/api/copy-trading/subscriptions/1"), Some(RouteGroup::TradingWrites));
+        assert_eq!(RouteGroup::of(&Method::GET, "/api/copy-trading/subscriptions"), Some(RouteGroup::Default));
+        assert_eq!(RouteGroup::of(&Method::GET, "/api/user/profile"), Some(RouteGroup::Default));
+
+        assert_eq!(RouteGroup::of(&Method::GET, "/api/health"), None);
+        assert_eq!(RouteGroup::of(&Method::GET, "/api/ws/private"), None);
+        assert_eq!(RouteGroup::of(&Method::OPTIONS, "/api/auth/login"), None);
+    }
+
+    #[test]
+    fn test_bucket_empties_and_refills_evenly() {
+        let quota = quota("2/10");
+        let start = Instant::now();
+        let mut bucket = Bucket::full(quota, start);
+
+        assert!(bucket.take(quota, start));
+        assert!(bucket.take(quota, start));
+        assert!(!bucket.take(quota, start));
+        assert_eq!(bucket.full_at, start + Duration::from_secs(10));
+
+        // One token comes back every 5 seconds
+        assert!(!bucket.take(quota, start + Duration::from_secs(4)));
+        assert!(bucket.take(quota, start + Duration::from_secs(5)));
+        assert!(!bucket.take(quota, start + Duration::from_secs(5)));
+
+        // A long pause never fills past the capacity
+        let later = start + Duration::from_secs(3600);
+        assert!(bucket.take(quota, later));
+        assert!(bucket.take(quota, later));
+        assert!(!bucket.take(quota, later));
+    }
+
+    #[test]
+    fn test_decision_headers() {
+        let quota = quota("10/60");
+        let decision = Decision::new(quota, 0.5, false);
+        assert_eq!(decision.remaining, 0);
+        assert_eq!(decision.retry_after_secs, 3);
+        assert_eq!(decision.reset_secs, 57);
+    }
+
+    #[test]
+    fn test_client_key_ignores_unknown_api_keys() {
+        let limiter = limiter(false);
+
+        let known = request().insert_header((API_KEY_HEADER, KNOWN_KEY)).to_srv_request();
+        assert_eq!(limiter.client_key(&known), format!("key:{:x}", Sha256::digest(KNOWN_KEY)));
+
+        let unknown = request().insert_header((API_KEY_HEADER, "made-up")).to_srv_request();
+        assert_eq!(limiter.client_key(&unknown), "ip:203.0.113.7");
+    }
+
+    #[test]
+    fn test_client_key_trusts_forwarded_ip_only_behind_a_proxy() {
+        assert_eq!(limiter(false).client_key(&request().to_srv_request()), "ip:203.0.113.7");
+        assert_eq!(limiter(true).client_key(&request().to_srv_request()), "ip:198.51.100.1");
+    }
+
+    #[test]
+    fn test_client_key_of_a_signed_in_user() {
+        let keys = JwtKeys::init(JwtKeys::load(None, None, "aptora", "aptora-api").unwrap());
+        let user_id = uuid::Uuid::new_v4();
+        let token = keys
+            .sign(&Claims {
+                sub: user_id.to_string(),
+                exp: chrono::Utc::now().timestamp() + 900,
+                iat: chrono::Utc::now().timestamp(),
+                iss: keys.issuer().to_string(),
+                aud: keys.audience().to_string(),
+                email: "alice@example.com".to_string(),
+                username: "alice".to_string(),
+                sid: None,
+                roles: vec![],
+                scope: String::new(),
+            })
+            .unwrap();
+
+        let signed_in = request()
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+            .to_srv_request();
+        assert_eq!(limiter(false).client_key(&signed_in), format!("user:{}", user_id));
+
+        // A bad token is counted like no token
+        let forged = request()
+            .insert_header((header::AUTHORIZATION, "Bearer not-a-token"))
+            .to_srv_request();
+        assert_eq!(limiter(false).client_key(&forged), "ip:203.0.113.7");
+    }
+}
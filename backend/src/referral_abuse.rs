@@ -0,0 +1,390 @@
+use crate::models::{
+    NewReferralFlag, NewSignupFingerprint, ReferralFlag, ReferralFlagResponse,
+};
+use crate::posts::PostService;
+use crate::referrals::ReferralRewardService;
+use crate::schema::{referral_flags, referral_rewards, signup_fingerprints, users, wallet_link_history};
+use crate::utils::AppError;
+use crate::DbPool;
+use chrono::Utc;
+use diesel::prelude::*;
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+// How far up the referral chain circular referrals are looked for
+const MAX_UPLINE_DEPTH: usize = 10;
+
+pub const FLAG_STATUSES: [&str; 3] = ["open", "dismissed", "confirmed"];
+
+// Request details stored with a signup
+#[derive(Debug, Clone, Default)]
+pub struct SignupContext {
+    pub ip_address: Option<String>,
+    pub device_id: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+pub struct ReferralAbuseService;
+
+impl ReferralAbuseService {
+    // Store where an account signed up from. Meant to run in the registration transaction.
+    pub fn record_signup(
+        conn: &mut PgConnection,
+        user_id: Uuid,
+        context: SignupContext,
+    ) -> Result<(), AppError> {
+        let truncate = |value: Option<String>, max: usize| {
+            value
+                .map(|v| v.trim().chars().take(max).collect::<String>())
+                .filter(|v| !v.is_empty())
+        };
+
+        diesel::insert_into(signup_fingerprints::table)
+            .values(&NewSignupFingerprint {
+                user_id,
+                ip_address: truncate(context.ip_address, 45),
+                device_id: truncate(context.device_id, 128),
+                user_agent: truncate(context.user_agent, 512),
+            })
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+        Ok(())
+    }
+
+    // Compare a new referee's signup IP and device with their referral network.
+    // Returns the number of new flags.
+    pub async fn check_signup(pool: &DbPool, user_id: Uuid) -> Result<usize, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let fingerprint = signup_fingerprints::table
+            .find(user_id)
+            .select((signup_fingerprints::ip_address, signup_fingerprints::device_id))
+            .first::<(Option<String>, Option<String>)>(conn)
+            .optional()?;
+        let Some((ip_address, device_id)) = fingerprint else {
+            return Ok(0);
+        };
+
+        let mut created = 0;
+        if let Some(ip_address) = ip_address {
+            let matches = signup_fingerprints::table
+                .filter(signup_fingerprints::ip_address.eq(&ip_address))
+                .filter(signup_fingerprints::user_id.ne(user_id))
+                .select(signup_fingerprints::user_id)
+                .load::<Uuid>(conn)?;
+            let details = format!("Signed up from the same IP address ({})", ip_address);
+            created += Self::check_matches(conn, user_id, &matches, "same_ip", &details)?;
+        }
+        if let Some(device_id) = device_id {
+            let matches = signup_fingerprints::table
+                .filter(signup_fingerprints::device_id.eq(&device_id))
+                .filter(signup_fingerprints::user_id.ne(user_id))
+                .select(signup_fingerprints::user_id)
+                .load::<Uuid>(conn)?;
+            let details = "Signed up from the same device".to_string();
+            created += Self::check_matches(conn, user_id, &matches, "same_device", &details)?;
+        }
+
+        Ok(created)
+    }
+
+    // Compare a newly linked wallet with the accounts it was linked to before.
+    // Returns the number of new flags.
+    pub async fn check_wallet(
+        pool: &DbPool,
+        user_id: Uuid,
+        wallet_address: &str,
+    ) -> Result<usize, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let previous_owners = wallet_link_history::table
+            .filter(wallet_link_history::wallet_address.eq(wallet_address))
+            .filter(wallet_link_history::user_id.ne(user_id))
+            .select(wallet_link_history::user_id)
+            .load::<Uuid>(conn)?;
+        let details = format!("Wallet {} was linked to another account", wallet_address);
+
+        let mut created = Self::check_matches(conn, user_id, &previous_owners, "wallet_reuse", &details)?;
+        // The earlier owner may be the referee, e.g. a referrer reusing a referee's wallet
+        for owner in previous_owners {
+            created += Self::check_matches(conn, owner, &[user_id], "wallet_reuse", &details)?;
+        }
+        Ok(created)
+    }
+
+    // Admin only
+    pub async fn get_flags(
+        pool: &DbPool,
+        admin_id: Uuid,
+        status: Option<&str>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(Vec<ReferralFlagResponse>, i64), AppError> {
+        if let Some(status) = status {
+            validate_flag_status(status)?;
+        }
+
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+        PostService::require_admin(conn, admin_id)?;
+
+        let filtered = || {
+            let mut query = referral_flags::table.into_boxed();
+            if let Some(status) = status {
+                query = query.filter(referral_flags::status.eq(status));
+            }
+            query
+        };
+
+        let total = filtered().count().get_result::<i64>(conn)?;
+        let flags = filtered()
+            .order((referral_flags::created_at.desc(), referral_flags::id.desc()))
+            .limit(limit)
+            .offset(offset)
+            .load::<ReferralFlag>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get referral flags: {}", e)))?;
+
+        Ok((Self::responses(conn, flags)?, total))
+    }
+
+    // Admin only: confirming a flag cancels the referral's unpaid rewards and
+    // any future ones; dismissing it releases held rewards once no other flag
+    // on the referral is open.
+    pub async fn review_flag(
+        pool: &DbPool,
+        admin_id: Uuid,
+        flag_id: Uuid,
+        status: &str,
+        note: Option<String>,
+    ) -> Result<ReferralFlagResponse, AppError> {
+        if status != "dismissed" && status != "confirmed" {
+            return Err(AppError::ValidationError(
+                "status must be 'dismissed' or 'confirmed'".to_string(),
+            ));
+        }
+
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+        PostService::require_admin(conn, admin_id)?;
+
+        let flag = conn.transaction(|conn| {
+            let flag = referral_flags::table
+                .find(flag_id)
+                .for_update()
+                .first::<ReferralFlag>(conn)
+                .optional()?
+                .ok_or_else(|| AppError::NotFoundError("Referral flag not found".to_string()))?;
+            if flag.status != "open" {
+                return Err(AppError::BadRequest(format!("Flag is already {}", flag.status)));
+            }
+
+            let now = Utc::now();
+            let flag = diesel::update(referral_flags::table.find(flag.id))
+                .set((
+                    referral_flags::status.eq(status),
+                    referral_flags::reviewed_by.eq(Some(admin_id)),
+                    referral_flags::reviewed_at.eq(Some(now)),
+                    referral_flags::review_note.eq(note),
+                ))
+                .get_result::<ReferralFlag>(conn)?;
+
+            let rewards = referral_rewards::table
+                .filter(referral_rewards::referrer_id.eq(flag.referrer_id))
+                .filter(referral_rewards::referred_user_id.eq(flag.referred_user_id));
+
+            if status == "confirmed" {
+                diesel::update(rewards.filter(referral_rewards::status.eq_any(["pending", "held"])))
+                    .set((
+                        referral_rewards::status.eq("cancelled"),
+                        referral_rewards::cancelled_at.eq(Some(now)),
+                        referral_rewards::cancel_reason.eq(Some("Referral abuse confirmed")),
+                    ))
+                    .execute(conn)?;
+            } else {
+                let still_flagged = referral_flags::table
+                    .filter(referral_flags::referrer_id.eq(flag.referrer_id))
+                    .filter(referral_flags::referred_user_id.eq(flag.referred_user_id))
+                    .filter(referral_flags::status.ne("dismissed"))
+                    .count()
+                    .get_result::<i64>(conn)?
+                    > 0;
+                if !still_flagged {
+                    diesel::update(rewards.filter(referral_rewards::status.eq("held")))
+                        .set(referral_rewards::status.eq("pending"))
+                        .execute(conn)?;
+                }
+            }
+
+            ReferralRewardService::refresh_total(conn, flag.referrer_id)?;
+            Ok::<_, AppError>(flag)
+        })?;
+
+        Self::responses(conn, vec![flag])?
+            .pop()
+            .ok_or_else(|| AppError::InternalServerError("Failed to load referral flag".to_string()))
+    }
+
+    // Flag the referral of `referee_id` when one of `matches` is its referrer,
+    // an account further up the chain (a circular referral) or another referee
+    // of the same referrer, in which case both referrals are flagged.
+    fn check_matches(
+        conn: &mut PgConnection,
+        referee_id: Uuid,
+        matches: &[Uuid],
+        reason: &str,
+        details: &str,
+    ) -> Result<usize, AppError> {
+        if matches.is_empty() {
+            return Ok(0);
+        }
+        let upline = Self::upline(conn, referee_id)?;
+        let Some(&referrer_id) = upline.first() else {
+            return Ok(0);
+        };
+        let matches: HashSet<Uuid> = matches.iter().copied().collect();
+
+        let mut created = 0;
+        if matches.contains(&referrer_id) {
+            created += Self::flag(conn, referrer_id, referee_id, reason, details)?;
+        }
+        if let Some(levels_up) = upline.iter().skip(1).position(|id| matches.contains(id)) {
+            let details = format!("{}; the other account is {} levels up the referral chain", details, levels_up + 2);
+            created += Self::flag(conn, referrer_id, referee_id, "circular_referral", &details)?;
+        }
+
+        let siblings = users::table
+            .filter(users::referred_by.eq(referrer_id))
+            .filter(users::id.ne(referee_id))
+            .filter(users::id.eq_any(matches.iter().copied().collect::<Vec<_>>()))
+            .select(users::id)
+            .load::<Uuid>(conn)?;
+        for sibling in siblings {
+            created += Self::flag(conn, referrer_id, referee_id, reason, details)?;
+            created += Self::flag(conn, referrer_id, sibling, reason, details)?;
+        }
+
+        Ok(created)
+    }
+
+    // Referrer, referrer's referrer and so on, nearest first
+    fn upline(conn: &mut PgConnection, user_id: Uuid) -> Result<Vec<Uuid>, AppError> {
+        let mut upline = Vec::new();
+        let mut current = user_id;
+        while upline.len() < MAX_UPLINE_DEPTH {
+            let referred_by = users::table
+                .find(current)
+                .select(users::referred_by)
+                .first::<Option<Uuid>>(conn)
+                .optional()?
+                .flatten();
+            match referred_by {
+                Some(referrer) if referrer != user_id && !upline.contains(&referrer) => {
+                    upline.push(referrer);
+                    current = referrer;
+                }
+                _ => break,
+            }
+        }
+        Ok(upline)
+    }
+
+    // Record a flag and hold the referral's pending rewards. Returns 1 for a
+    // new flag and 0 when the referral already had one for the reason.
+    fn flag(
+        conn: &mut PgConnection,
+        referrer_id: Uuid,
+        referee_id: Uuid,
+        reason: &str,
+        details: &str,
+    ) -> Result<usize, AppError> {
+        conn.transaction(|conn| {
+            let inserted = diesel::insert_into(referral_flags::table)
+                .values(&NewReferralFlag {
+                    referrer_id,
+                    referred_user_id: referee_id,
+                    reason: reason.to_string(),
+                    details: Some(details.to_string()),
+                })
+                .on_conflict_do_nothing()
+                .execute(conn)?;
+
+            if inserted > 0 {
+                log::warn!(
+                    "Flagged referral {} -> {} for {}: {}",
+                    referrer_id,
+                    referee_id,
+                    reason,
+                    details
+                );
+                diesel::update(
+                    referral_rewards::table
+                        .filter(referral_rewards::referrer_id.eq(referrer_id))
+                        .filter(referral_rewards::referred_user_id.eq(referee_id))
+                        .filter(referral_rewards::status.eq("pending")),
+                )
+                .set(referral_rewards::status.eq("held"))
+                .execute(conn)?;
+                ReferralRewardService::refresh_total(conn, referrer_id)?;
+            }
+            Ok::<_, AppError>(inserted)
+        })
+    }
+
+    fn responses(
+        conn: &mut PgConnection,
+        flags: Vec<ReferralFlag>,
+    ) -> Result<Vec<ReferralFlagResponse>, AppError> {
+        let user_ids: Vec<Uuid> = flags
+            .iter()
+            .flat_map(|f| [f.referrer_id, f.referred_user_id])
+            .collect();
+        let usernames: HashMap<Uuid, String> = users::table
+            .filter(users::id.eq_any(&user_ids))
+            .select((users::id, users::username))
+            .load::<(Uuid, String)>(conn)?
+            .into_iter()
+            .collect();
+
+        let referee_ids: Vec<Uuid> = flags.iter().map(|f| f.referred_user_id).collect();
+        let held = referral_rewards::table
+            .filter(referral_rewards::referred_user_id.eq_any(&referee_ids))
+            .filter(referral_rewards::status.eq("held"))
+            .select((
+                referral_rewards::referrer_id,
+                referral_rewards::referred_user_id,
+                referral_rewards::reward_amount,
+            ))
+            .load::<(Uuid, Uuid, f64)>(conn)?;
+        let mut held_by_referral: HashMap<(Uuid, Uuid), f64> = HashMap::new();
+        for (referrer_id, referee_id, amount) in held {
+            *held_by_referral.entry((referrer_id, referee_id)).or_default() += amount;
+        }
+
+        let username = |id: &Uuid| usernames.get(id).cloned().unwrap_or_default();
+        Ok(flags
+            .into_iter()
+            .map(|flag| ReferralFlagResponse {
+                referrer_username: username(&flag.referrer_id),
+                referred_username: username(&flag.referred_user_id),
+                held_rewards: held_by_referral
+                    .get(&(flag.referrer_id, flag.referred_user_id))
+                    .copied()
+                    .unwrap_or(0.0),
+                flag,
+            })
+            .collect())
+    }
+}
+
+fn validate_flag_status(status: &str) -> Result<(), AppError> {
+    if FLAG_STATUSES.contains(&status) {
+        Ok(())
+    } else {
+        Err(AppError::ValidationError(format!(
+            "status must be one of: {}",
+            FLAG_STATUSES.join(", ")
+        )))
+    }
+}
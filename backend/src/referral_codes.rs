@@ -0,0 +1,489 @@
+use crate::models::{NewReferralClick, NewReferralCode, ReferralCode, ReferralCodeStats};
+use crate::profiles::ProfileService;
+use crate::schema::{fills, referral_clicks, referral_codes, referral_rewards, users};
+use crate::utils::{double_option, AppError};
+use crate::DbPool;
+use chrono::{DateTime, Duration, Utc};
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+pub const MAX_CODES_PER_USER: i64 = 20;
+const GENERATED_CODE_LENGTH: usize = 8;
+const MIN_VANITY_LENGTH: usize = 4;
+const MAX_CODE_LENGTH: usize = 32;
+const MAX_CAMPAIGN_LENGTH: usize = 64;
+// Repeat clicks from one IP on one code count once per window
+const CLICK_DEDUP_WINDOW_MINUTES: i64 = 60;
+
+sql_function!(fn lower(x: Text) -> Text);
+
+#[derive(Debug, Deserialize)]
+pub struct CreateReferralCodeRequest {
+    // Vanity code; a random one is generated when omitted
+    pub code: Option<String>,
+    pub campaign: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub max_uses: Option<i32>,
+}
+
+// Omitted fields are left alone; an explicit null removes the expiry or use limit
+#[derive(Debug, Default, Deserialize)]
+pub struct ReferralCodeUpdate {
+    pub campaign: Option<String>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub expires_at: Option<Option<DateTime<Utc>>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub max_uses: Option<Option<i32>>,
+    pub is_active: Option<bool>,
+}
+
+// What a landing page needs to know about a shared link
+#[derive(Debug, Clone, Serialize)]
+pub struct ReferralCodeLookup {
+    pub code: String,
+    pub referrer_username: String,
+    pub campaign: Option<String>,
+    pub valid: bool,
+}
+
+pub struct ReferralCodeService;
+
+impl ReferralCodeService {
+    pub async fn create_code(
+        pool: &DbPool,
+        user_id: Uuid,
+        request: CreateReferralCodeRequest,
+    ) -> Result<ReferralCode, AppError> {
+        let campaign = normalize_campaign(request.campaign)?;
+        if let Some(max_uses) = request.max_uses {
+            if max_uses < 1 {
+                return Err(AppError::ValidationError("max_uses must be at least 1".to_string()));
+            }
+        }
+        if request.expires_at.is_some_and(|at| at <= Utc::now()) {
+            return Err(AppError::ValidationError("expires_at must be in the future".to_string()));
+        }
+        if let Some(code) = &request.code {
+            validate_vanity_code(code)?;
+        }
+
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let count = referral_codes::table
+            .filter(referral_codes::user_id.eq(user_id))
+            .count()
+            .get_result::<i64>(conn)?;
+        if count >= MAX_CODES_PER_USER {
+            return Err(AppError::BadRequest(format!(
+                "You can have at most {} referral codes",
+                MAX_CODES_PER_USER
+            )));
+        }
+
+        let new_code = |code: String, is_vanity: bool| NewReferralCode {
+            user_id,
+            code,
+            is_vanity,
+            campaign: campaign.clone(),
+            expires_at: request.expires_at,
+            max_uses: request.max_uses,
+        };
+
+        match request.code {
+            Some(code) => Self::insert(conn, &new_code(code, true))?
+                .ok_or_else(|| AppError::BadRequest("Referral code is already taken".to_string())),
+            None => Self::insert_generated(conn, |code| new_code(code, false)),
+        }
+    }
+
+    pub async fn update_code(
+        pool: &DbPool,
+        user_id: Uuid,
+        code_id: Uuid,
+        update: ReferralCodeUpdate,
+    ) -> Result<ReferralCode, AppError> {
+        if update.max_uses.flatten().is_some_and(|max| max < 1) {
+            return Err(AppError::ValidationError("max_uses must be at least 1".to_string()));
+        }
+        if update.expires_at.flatten().is_some_and(|at| at <= Utc::now()) {
+            return Err(AppError::ValidationError("expires_at must be in the future".to_string()));
+        }
+
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let code = referral_codes::table
+            .filter(referral_codes::id.eq(code_id))
+            .filter(referral_codes::user_id.eq(user_id))
+            .first::<ReferralCode>(conn)
+            .optional()?
+            .ok_or_else(|| AppError::NotFoundError("Referral code not found".to_string()))?;
+
+        let campaign = match update.campaign {
+            Some(campaign) => normalize_campaign(Some(campaign))?,
+            None => code.campaign,
+        };
+
+        diesel::update(referral_codes::table.find(code.id))
+            .set((
+                referral_codes::campaign.eq(campaign),
+                referral_codes::expires_at.eq(update.expires_at.unwrap_or(code.expires_at)),
+                referral_codes::max_uses.eq(update.max_uses.unwrap_or(code.max_uses)),
+                referral_codes::is_active.eq(update.is_active.unwrap_or(code.is_active)),
+            ))
+            .get_result::<ReferralCode>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to update referral code: {}", e)))
+    }
+
+    // The user's codes, oldest first, with conversion stats
+    pub async fn get_codes(pool: &DbPool, user_id: Uuid) -> Result<Vec<ReferralCodeStats>, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let codes = referral_codes::table
+            .filter(referral_codes::user_id.eq(user_id))
+            .order(referral_codes::created_at.asc())
+            .load::<ReferralCode>(conn)?;
+        let code_ids: Vec<Uuid> = codes.iter().map(|c| c.id).collect();
+
+        // (referee, code) for everyone who signed up with one of these codes
+        let referees = users::table
+            .filter(users::referral_code_id.eq_any(&code_ids))
+            .select((users::id, users::referral_code_id))
+            .load::<(Uuid, Option<Uuid>)>(conn)?;
+        let code_of: HashMap<Uuid, Uuid> = referees
+            .into_iter()
+            .filter_map(|(user, code)| code.map(|code| (user, code)))
+            .collect();
+        let referee_ids: Vec<Uuid> = code_of.keys().copied().collect();
+
+        let traders = fills::table
+            .filter(fills::user_id.eq_any(&referee_ids))
+            .select(fills::user_id)
+            .distinct()
+            .load::<Uuid>(conn)?;
+        let mut active_referees: HashMap<Uuid, i64> = HashMap::new();
+        for trader in traders {
+            if let Some(code) = code_of.get(&trader) {
+                *active_referees.entry(*code).or_default() += 1;
+            }
+        }
+
+        let rewards = referral_rewards::table
+            .filter(referral_rewards::referrer_id.eq(user_id))
+            .filter(referral_rewards::referred_user_id.eq_any(&referee_ids))
+            .filter(referral_rewards::status.eq_any(["pending", "paid"]))
+            .select((referral_rewards::referred_user_id, referral_rewards::reward_amount))
+            .load::<(Uuid, f64)>(conn)?;
+        let mut rewards_by_code: HashMap<Uuid, f64> = HashMap::new();
+        for (referee, amount) in rewards {
+            if let Some(code) = code_of.get(&referee) {
+                *rewards_by_code.entry(*code).or_default() += amount;
+            }
+        }
+
+        Ok(codes
+            .into_iter()
+            .map(|code| ReferralCodeStats {
+                conversion_rate: (code.click_count > 0)
+                    .then(|| code.use_count as f64 / code.click_count as f64),
+                active_referees: active_referees.get(&code.id).copied().unwrap_or(0),
+                rewards: rewards_by_code.get(&code.id).copied().unwrap_or(0.0),
+                code,
+            })
+            .collect())
+    }
+
+    // Count a visit to a referral link. Public, so landing pages can call it
+    // before the visitor has an account. Clicks from an IP that already
+    // clicked the code within the window are not counted again.
+    pub async fn record_click(
+        pool: &DbPool,
+        code: &str,
+        source: Option<String>,
+        ip_address: Option<&str>,
+    ) -> Result<ReferralCodeLookup, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let find = |conn: &mut PgConnection, code: &str| {
+            referral_codes::table
+                .inner_join(users::table)
+                .filter(lower(referral_codes::code).eq(code.trim().to_lowercase()))
+                .select((ReferralCode::as_select(), users::username))
+                .first::<(ReferralCode, String)>(conn)
+                .optional()
+        };
+        let found = match find(conn, code)? {
+            Some(found) => Some(found),
+            None => match Self::renamed_user_code(conn, code)? {
+                Some(code) => find(conn, &code)?,
+                None => None,
+            },
+        };
+        let (referral_code, referrer_username) =
+            found.ok_or_else(|| AppError::NotFoundError("Referral code not found".to_string()))?;
+
+        let valid = is_usable(&referral_code);
+        if valid {
+            let source = source
+                .map(|s| s.trim().chars().take(255).collect::<String>())
+                .filter(|s| !s.is_empty());
+            let ip_hash = ip_address.map(|ip| format!("{:x}", Sha256::digest(ip.as_bytes())));
+            conn.transaction(|conn| {
+                if let Some(ip_hash) = &ip_hash {
+                    // Serialize clicks on the code so two requests cannot both count
+                    referral_codes::table
+                        .find(referral_code.id)
+                        .select(referral_codes::id)
+                        .for_update()
+                        .first::<Uuid>(conn)?;
+
+                    let since = Utc::now() - Duration::minutes(CLICK_DEDUP_WINDOW_MINUTES);
+                    let repeat = referral_clicks::table
+                        .filter(referral_clicks::referral_code_id.eq(referral_code.id))
+                        .filter(referral_clicks::ip_hash.eq(ip_hash))
+                        .filter(referral_clicks::created_at.gt(since))
+                        .count()
+                        .get_result::<i64>(conn)?
+                        > 0;
+                    if repeat {
+                        return Ok(());
+                    }
+                }
+
+                diesel::insert_into(referral_clicks::table)
+                    .values(&NewReferralClick {
+                        referral_code_id: referral_code.id,
+                        source,
+                        ip_hash,
+                    })
+                    .execute(conn)?;
+                diesel::update(referral_codes::table.find(referral_code.id))
+                    .set(referral_codes::click_count.eq(referral_codes::click_count + 1))
+                    .execute(conn)?;
+                Ok::<_, AppError>(())
+            })?;
+        }
+
+        Ok(ReferralCodeLookup {
+            code: referral_code.code,
+            referrer_username,
+            campaign: referral_code.campaign,
+            valid,
+        })
+    }
+
+    // Take one use of a code for a signup. Meant to run in the registration
+    // transaction so a failed signup does not use up the code.
+    pub fn claim(conn: &mut PgConnection, code: &str) -> Result<ReferralCode, AppError> {
+        let code = code.trim().to_lowercase();
+        let now = Utc::now();
+
+        let claimed = diesel::update(
+            referral_codes::table
+                .filter(lower(referral_codes::code).eq(&code))
+                .filter(referral_codes::is_active.eq(true))
+                .filter(referral_codes::expires_at.is_null().or(referral_codes::expires_at.gt(now)))
+                .filter(
+                    referral_codes::max_uses
+                        .is_null()
+                        .or(referral_codes::max_uses.gt(referral_codes::use_count.nullable())),
+                ),
+        )
+        .set(referral_codes::use_count.eq(referral_codes::use_count + 1))
+        .get_result::<ReferralCode>(conn)
+        .optional()?;
+
+        if let Some(claimed) = claimed {
+            return Ok(claimed);
+        }
+
+        let exists = referral_codes::table
+            .filter(lower(referral_codes::code).eq(&code))
+            .count()
+            .get_result::<i64>(conn)?
+            > 0;
+        if exists {
+            Err(AppError::BadRequest(
+                "Referral code has expired or reached its usage limit".to_string(),
+            ))
+        } else if let Some(renamed) = Self::renamed_user_code(conn, &code)? {
+            Self::claim(conn, &renamed)
+        } else {
+            Err(AppError::BadRequest("Invalid referral code".to_string()))
+        }
+    }
+
+    // Links shared as a username that has since changed go to that user's default code
+    fn renamed_user_code(conn: &mut PgConnection, code: &str) -> Result<Option<String>, AppError> {
+        match ProfileService::renamed_user(conn, code)? {
+            Some((user_id, _)) => Self::default_code(conn, user_id).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    // Give a new user their first code: the username when it is a valid code
+    // that is still free, a generated one otherwise.
+    pub fn create_default_code(
+        conn: &mut PgConnection,
+        user_id: Uuid,
+        username: &str,
+    ) -> Result<ReferralCode, AppError> {
+        let new_code = |code: String, is_vanity: bool| NewReferralCode {
+            user_id,
+            code,
+            is_vanity,
+            campaign: None,
+            expires_at: None,
+            max_uses: None,
+        };
+
+        if validate_vanity_code(username).is_ok() {
+            if let Some(code) = Self::insert(conn, &new_code(username.to_string(), true))? {
+                return Ok(code);
+            }
+        }
+        Self::insert_generated(conn, |code| new_code(code, false))
+    }
+
+    // The code to show by default: the oldest usable code outside a campaign,
+    // then any usable code. Users without one get a generated code.
+    pub fn default_code(conn: &mut PgConnection, user_id: Uuid) -> Result<String, AppError> {
+        let codes = referral_codes::table
+            .filter(referral_codes::user_id.eq(user_id))
+            .order(referral_codes::created_at.asc())
+            .load::<ReferralCode>(conn)?;
+
+        let usable = || codes.iter().filter(|c| is_usable(c));
+        if let Some(code) = usable().find(|c| c.campaign.is_none()).or_else(|| usable().next()) {
+            return Ok(code.code.clone());
+        }
+
+        Self::insert_generated(conn, |code| NewReferralCode {
+            user_id,
+            code,
+            is_vanity: false,
+            campaign: None,
+            expires_at: None,
+            max_uses: None,
+        })
+        .map(|code| code.code)
+    }
+
+    // None when the code is taken
+    fn insert(conn: &mut PgConnection, new_code: &NewReferralCode) -> Result<Option<ReferralCode>, AppError> {
+        diesel::insert_into(referral_codes::table)
+            .values(new_code)
+            .on_conflict_do_nothing()
+            .get_result::<ReferralCode>(conn)
+            .optional()
+            .map_err(AppError::from)
+    }
+
+    fn insert_generated(
+        conn: &mut PgConnection,
+        new_code: impl Fn(String) -> NewReferralCode,
+    ) -> Result<ReferralCode, AppError> {
+        // Collisions are very unlikely with 36^8 codes, so a few attempts suffice
+        for _ in 0..5 {
+            let code: String = thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(GENERATED_CODE_LENGTH)
+                .map(|c| char::from(c).to_ascii_uppercase())
+                .collect();
+            if let Some(code) = Self::insert(conn, &new_code(code))? {
+                return Ok(code);
+            }
+        }
+        Err(AppError::InternalServerError("Failed to generate a referral code".to_string()))
+    }
+}
+
+fn is_usable(code: &ReferralCode) -> bool {
+    code.is_active
+        && code.expires_at.is_none_or(|at| at > Utc::now())
+        && code.max_uses.is_none_or(|max| code.use_count < max)
+}
+
+fn validate_vanity_code(code: &str) -> Result<(), AppError> {
+    let valid_chars = code.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if !valid_chars || code.len() < MIN_VANITY_LENGTH || code.len() > MAX_CODE_LENGTH {
+        return Err(AppError::ValidationError(format!(
+            "Referral codes must be {}-{} letters, digits, '_' or '-'",
+            MIN_VANITY_LENGTH, MAX_CODE_LENGTH
+        )));
+    }
+    Ok(())
+}
+
+fn normalize_campaign(campaign: Option<String>) -> Result<Option<String>, AppError> {
+    let campaign = campaign.map(|c| c.trim().to_string()).filter(|c| !c.is_empty());
+    if campaign.as_ref().is_some_and(|c| c.len() > MAX_CAMPAIGN_LENGTH) {
+        return Err(AppError::ValidationError(format!(
+            "campaign must be at most {} characters",
+            MAX_CAMPAIGN_LENGTH
+        )));
+    }
+    Ok(campaign)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn code(expires_at: Option<DateTime<Utc>>, max_uses: Option<i32>, use_count: i32) -> ReferralCode {
+        ReferralCode {
+            id: Uuid::new_v4(),
+            user_id: Uuid::new_v4(),
+            code: "alice".to_string(),
+            is_vanity: true,
+            campaign: None,
+            expires_at,
+            max_uses,
+            use_count,
+            click_count: 0,
+            is_active: true,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_update_tells_null_from_omitted() {
+        let update: ReferralCodeUpdate = serde_json::from_str(r#"{"expires_at": null, "max_uses": null}"#).unwrap();
+        assert_eq!(update.expires_at, Some(None));
+        assert_eq!(update.max_uses, Some(None));
+
+        let update: ReferralCodeUpdate = serde_json::from_str(r#"{"max_uses": 5}"#).unwrap();
+        assert_eq!(update.expires_at, None);
+        assert_eq!(update.max_uses, Some(Some(5)));
+    }
+
+    #[test]
+    fn test_is_usable() {
+        assert!(is_usable(&code(None, None, 100)));
+        assert!(is_usable(&code(Some(Utc::now() + Duration::days(1)), Some(2), 1)));
+        assert!(!is_usable(&code(Some(Utc::now() - Duration::days(1)), None, 0)));
+        assert!(!is_usable(&code(None, Some(2), 2)));
+
+        let mut inactive = code(None, None, 0);
+        inactive.is_active = false;
+        assert!(!is_usable(&inactive));
+    }
+
+    #[test]
+    fn test_vanity_codes() {
+        assert!(validate_vanity_code("alice_01").is_ok());
+        assert!(validate_vanity_code("abc").is_err());
+        assert!(validate_vanity_code("has space").is_err());
+        assert!(validate_vanity_code(&"a".repeat(MAX_CODE_LENGTH + 1)).is_err());
+    }
+}
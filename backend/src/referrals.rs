@@ -0,0 +1,639 @@
+use crate::config::AppConfig;
+use crate::fills::is_synthetic;
+use crate::models::{Fill, NewReferralReward, ReferralEarnings, ReferralReward};
+use crate::posts::PostService;
+use crate::schema::{referral_flags, referral_rewards, user_wallets, users};
+use crate::utils::AppError;
+use crate::DbPool;
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+pub const REWARD_STATUSES: [&str; 4] = ["pending", "held", "paid", "cancelled"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RebateTier {
+    pub min_referrals: i32,
+    pub rebate_pct: f64,
+}
+
+// Reward amounts, configured so they can change without a release
+#[derive(Debug, Clone)]
+pub struct ReferralRewardConfig {
+    // Flat amount per referee signup
+    pub signup_bonus: f64,
+    // Percentage of each referee deposit, up to `deposit_bonus_cap` per referee
+    pub deposit_bonus_pct: f64,
+    pub deposit_bonus_cap: f64,
+    pub min_deposit: f64,
+    // Fee rebate percentage by referee count, sorted by `min_referrals`
+    pub tiers: Vec<RebateTier>,
+}
+
+impl ReferralRewardConfig {
+    pub fn from_config(config: &AppConfig) -> Self {
+        ReferralRewardConfig {
+            signup_bonus: config.referral_signup_bonus,
+            deposit_bonus_pct: config.referral_deposit_bonus_pct,
+            deposit_bonus_cap: config.referral_deposit_bonus_cap,
+            min_deposit: config.referral_min_deposit,
+            tiers: config.referral_rebate_tiers.clone(),
+        }
+    }
+
+    pub fn parse_tiers(spec: &str) -> Option<Vec<RebateTier>> {
+        let mut tiers = spec
+            .split(',')
+            .map(|pair| {
+                let (min_referrals, rebate_pct) = pair.trim().split_once(':')?;
+                let tier = RebateTier {
+                    min_referrals: min_referrals.trim().parse().ok()?,
+                    rebate_pct: rebate_pct.trim().parse().ok()?,
+                };
+                (tier.min_referrals >= 0 && (0.0..=100.0).contains(&tier.rebate_pct)).then_some(tier)
+            })
+            .collect::<Option<Vec<_>>>()?;
+        if tiers.is_empty() {
+            return None;
+        }
+        tiers.sort_by_key(|t| t.min_referrals);
+        Some(tiers)
+    }
+
+    // 1-based level of the highest tier the referee count reaches
+    pub fn tier_for(&self, referral_count: i32) -> (i32, RebateTier) {
+        self.tiers
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, tier)| referral_count >= tier.min_referrals)
+            .map(|(i, tier)| (i as i32 + 1, *tier))
+            .unwrap_or((0, RebateTier { min_referrals: 0, rebate_pct: 0.0 }))
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReferralTierInfo {
+    pub level: i32,
+    pub rebate_pct: f64,
+    pub min_referrals: i32,
+    pub next_tier_at: Option<i32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReferralSummary {
+    pub referral_count: i32,
+    // Pending plus paid; held and cancelled rewards are not counted
+    pub total_rewards: f64,
+    pub pending_rewards: f64,
+    // Waiting on the review of a flagged referral
+    pub held_rewards: f64,
+    pub paid_rewards: f64,
+    pub tier: ReferralTierInfo,
+    pub referrals: Vec<ReferralEarnings>,
+}
+
+pub struct ReferralRewardService;
+
+impl ReferralRewardService {
+    // Signup bonus for the referrer of a newly registered user
+    pub async fn reward_signup(
+        pool: &DbPool,
+        referred_user_id: Uuid,
+    ) -> Result<Option<ReferralReward>, AppError> {
+        let config = ReferralRewardConfig::from_config(AppConfig::global());
+        if config.signup_bonus <= 0.0 {
+            return Ok(None);
+        }
+
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let Some((referrer_id, referral_count)) = Self::referrer_of(conn, referred_user_id)? else {
+            return Ok(None);
+        };
+        let (level, _) = config.tier_for(referral_count);
+
+        Self::grant(
+            conn,
+            NewReferralReward {
+                referrer_id,
+                referred_user_id,
+                reward_amount: config.signup_bonus,
+                reward_type: "signup".to_string(),
+                source_ref: Some(referred_user_id.to_string()),
+                base_amount: None,
+                rate: None,
+                tier: Some(level),
+                status: None,
+            },
+        )
+    }
+
+    // Rebate a share of the fees referees paid on their fills to their
+    // referrers, at the referrer's current tier. Returns the rewards created.
+    pub async fn reward_fees(pool: &DbPool, fills: &[Fill]) -> Result<usize, AppError> {
+        let fills: Vec<&Fill> = fills.iter().filter(|f| f.fee > 0.0).collect();
+        if fills.is_empty() {
+            return Ok(0);
+        }
+
+        let config = ReferralRewardConfig::from_config(AppConfig::global());
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let addresses: Vec<&str> = fills.iter().map(|f| f.wallet_address.as_str()).collect();
+        let verified: HashSet<(Uuid, String)> = user_wallets::table
+            .filter(user_wallets::wallet_address.eq_any(&addresses))
+            .filter(user_wallets::verified_at.is_not_null())
+            .select((user_wallets::user_id, user_wallets::wallet_address))
+            .load::<(Uuid, String)>(conn)?
+            .into_iter()
+            .collect();
+        let fills: Vec<&Fill> = fills.into_iter().filter(|f| rebatable(f, &verified)).collect();
+
+        let mut referrers: HashMap<Uuid, Option<(Uuid, i32)>> = HashMap::new();
+        let mut created = 0;
+        for fill in fills {
+            let referrer = match referrers.get(&fill.user_id) {
+                Some(referrer) => *referrer,
+                None => {
+                    let referrer = Self::referrer_of(conn, fill.user_id)?;
+                    referrers.insert(fill.user_id, referrer);
+                    referrer
+                }
+            };
+            let Some((referrer_id, referral_count)) = referrer else {
+                continue;
+            };
+
+            let (level, tier) = config.tier_for(referral_count);
+            let amount = fill.fee * tier.rebate_pct / 100.0;
+            if amount <= 0.0 {
+                continue;
+            }
+
+            let reward = Self::grant(
+                conn,
+                NewReferralReward {
+                    referrer_id,
+                    referred_user_id: fill.user_id,
+                    reward_amount: amount,
+                    reward_type: "fee_rebate".to_string(),
+                    source_ref: Some(fill.id.to_string()),
+                    base_amount: Some(fill.fee),
+                    rate: Some(tier.rebate_pct),
+                    tier: Some(level),
+                    status: None,
+                },
+            )?;
+            if reward.is_some() {
+                created += 1;
+            }
+        }
+
+        Ok(created)
+    }
+
+    // Admin only: deposits are not visible to the backend, so operations record
+    // confirmed ones. `reference` (e.g. the transaction hash) makes this idempotent.
+    pub async fn reward_deposit(
+        pool: &DbPool,
+        admin_id: Uuid,
+        referred_user_id: Uuid,
+        amount: f64,
+        reference: &str,
+    ) -> Result<Option<ReferralReward>, AppError> {
+        if !amount.is_finite() || amount <= 0.0 {
+            return Err(AppError::ValidationError("Deposit amount must be positive".to_string()));
+        }
+        let reference = reference.trim();
+        if reference.is_empty() {
+            return Err(AppError::ValidationError("Deposit reference is required".to_string()));
+        }
+
+        let config = ReferralRewardConfig::from_config(AppConfig::global());
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+        PostService::require_admin(conn, admin_id)?;
+
+        if amount < config.min_deposit {
+            return Ok(None);
+        }
+        let Some((referrer_id, referral_count)) = Self::referrer_of(conn, referred_user_id)? else {
+            return Ok(None);
+        };
+
+        // The cap applies to everything earned from this referee's deposits
+        let earned: Option<f64> = referral_rewards::table
+            .filter(referral_rewards::referred_user_id.eq(referred_user_id))
+            .filter(referral_rewards::reward_type.eq("deposit"))
+            .filter(referral_rewards::status.ne("cancelled"))
+            .select(diesel::dsl::sum(referral_rewards::reward_amount))
+            .first(conn)?;
+        let remaining = config.deposit_bonus_cap - earned.unwrap_or(0.0);
+        let amount_earned = (amount * config.deposit_bonus_pct / 100.0).min(remaining);
+        if amount_earned <= 0.0 {
+            return Ok(None);
+        }
+
+        let (level, _) = config.tier_for(referral_count);
+        Self::grant(
+            conn,
+            NewReferralReward {
+                referrer_id,
+                referred_user_id,
+                reward_amount: amount_earned,
+                reward_type: "deposit".to_string(),
+                source_ref: Some(reference.to_string()),
+                base_amount: Some(amount),
+                rate: Some(config.deposit_bonus_pct),
+                tier: Some(level),
+                status: None,
+            },
+        )
+    }
+
+    // Admin only: a pending reward moves to paid or cancelled, never back.
+    // Held rewards can only be cancelled. Cancelling removes a reward from the
+    // referrer's total.
+    pub async fn update_status(
+        pool: &DbPool,
+        admin_id: Uuid,
+        reward_id: Uuid,
+        status: &str,
+        reason: Option<String>,
+    ) -> Result<ReferralReward, AppError> {
+        if status != "paid" && status != "cancelled" {
+            return Err(AppError::ValidationError(
+                "status must be 'paid' or 'cancelled'".to_string(),
+            ));
+        }
+
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+        PostService::require_admin(conn, admin_id)?;
+
+        conn.transaction(|conn| {
+            let now = Utc::now();
+            let target = referral_rewards::table.filter(referral_rewards::id.eq(reward_id));
+
+            let updated = if status == "paid" {
+                diesel::update(target.filter(referral_rewards::status.eq("pending")))
+                    .set((
+                        referral_rewards::status.eq("paid"),
+                        referral_rewards::paid_at.eq(Some(now)),
+                    ))
+                    .get_result::<ReferralReward>(conn)
+                    .optional()?
+            } else {
+                diesel::update(target.filter(referral_rewards::status.eq_any(["pending", "held"])))
+                    .set((
+                        referral_rewards::status.eq("cancelled"),
+                        referral_rewards::cancelled_at.eq(Some(now)),
+                        referral_rewards::cancel_reason.eq(reason),
+                    ))
+                    .get_result::<ReferralReward>(conn)
+                    .optional()?
+            };
+
+            let Some(reward) = updated else {
+                let current = referral_rewards::table
+                    .find(reward_id)
+                    .select(referral_rewards::status)
+                    .first::<Option<String>>(conn)
+                    .optional()?
+                    .ok_or_else(|| AppError::NotFoundError("Referral reward not found".to_string()))?;
+                return Err(AppError::BadRequest(format!(
+                    "Reward is already {}",
+                    current.unwrap_or_else(|| "pending".to_string())
+                )));
+            };
+
+            Self::refresh_total(conn, reward.referrer_id)?;
+            Ok::<_, AppError>(reward)
+        })
+    }
+
+    pub async fn get_rewards(
+        pool: &DbPool,
+        referrer_id: Uuid,
+        status: Option<&str>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(Vec<ReferralReward>, i64), AppError> {
+        if let Some(status) = status {
+            if !REWARD_STATUSES.contains(&status) {
+                return Err(AppError::ValidationError(format!(
+                    "status must be one of: {}",
+                    REWARD_STATUSES.join(", ")
+                )));
+            }
+        }
+
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let filtered = || {
+            let mut query = referral_rewards::table
+                .filter(referral_rewards::referrer_id.eq(referrer_id))
+                .into_boxed();
+            if let Some(status) = status {
+                query = query.filter(referral_rewards::status.eq(status));
+            }
+            query
+        };
+
+        let total = filtered().count().get_result::<i64>(conn)?;
+        let rewards = filtered()
+            .order((referral_rewards::created_at.desc(), referral_rewards::id.desc()))
+            .limit(limit)
+            .offset(offset)
+            .load::<ReferralReward>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get referral rewards: {}", e)))?;
+
+        Ok((rewards, total))
+    }
+
+    // Totals, tier and a per-referee breakdown for the referrer
+    pub async fn get_summary(pool: &DbPool, referrer_id: Uuid) -> Result<ReferralSummary, AppError> {
+        let config = ReferralRewardConfig::from_config(AppConfig::global());
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let referral_count = users::table
+            .find(referrer_id)
+            .select(users::referral_count)
+            .first::<Option<i32>>(conn)
+            .optional()?
+            .ok_or_else(|| AppError::NotFoundError("User not found".to_string()))?
+            .unwrap_or(0);
+
+        let referees = users::table
+            .filter(users::referred_by.eq(referrer_id))
+            .select((users::id, users::username, users::created_at))
+            .order(users::created_at.desc())
+            .load::<(Uuid, String, Option<DateTime<Utc>>)>(conn)?;
+
+        let rewards = referral_rewards::table
+            .filter(referral_rewards::referrer_id.eq(referrer_id))
+            .filter(referral_rewards::status.ne("cancelled"))
+            .select((
+                referral_rewards::referred_user_id,
+                referral_rewards::reward_type,
+                referral_rewards::status,
+                referral_rewards::reward_amount,
+            ))
+            .load::<(Uuid, String, Option<String>, f64)>(conn)?;
+
+        let flagged: HashSet<Uuid> = referral_flags::table
+            .filter(referral_flags::referrer_id.eq(referrer_id))
+            .filter(referral_flags::status.eq("open"))
+            .select(referral_flags::referred_user_id)
+            .load::<Uuid>(conn)?
+            .into_iter()
+            .collect();
+
+        let mut referrals: Vec<ReferralEarnings> = referees
+            .into_iter()
+            .map(|(user_id, username, joined_at)| ReferralEarnings {
+                user_id,
+                username,
+                joined_at,
+                signup: 0.0,
+                fee_rebates: 0.0,
+                deposits: 0.0,
+                pending: 0.0,
+                held: 0.0,
+                paid: 0.0,
+                total: 0.0,
+                flagged: flagged.contains(&user_id),
+            })
+            .collect();
+        let index: HashMap<Uuid, usize> = referrals
+            .iter()
+            .enumerate()
+            .map(|(i, r)| (r.user_id, i))
+            .collect();
+
+        let (mut pending_rewards, mut held_rewards, mut paid_rewards) = (0.0, 0.0, 0.0);
+        for (referred_user_id, reward_type, status, amount) in rewards {
+            let status = status.unwrap_or_else(|| "pending".to_string());
+            match status.as_str() {
+                "paid" => paid_rewards += amount,
+                "held" => held_rewards += amount,
+                _ => pending_rewards += amount,
+            }
+
+            // Referees that deleted their account keep counting towards the totals
+            let Some(&i) = index.get(&referred_user_id) else {
+                continue;
+            };
+            let earnings = &mut referrals[i];
+            match status.as_str() {
+                "paid" => earnings.paid += amount,
+                "held" => {
+                    earnings.held += amount;
+                    continue;
+                }
+                _ => earnings.pending += amount,
+            }
+            match reward_type.as_str() {
+                "signup" => earnings.signup += amount,
+                "fee_rebate" => earnings.fee_rebates += amount,
+                _ => earnings.deposits += amount,
+            }
+            earnings.total += amount;
+        }
+
+        let (level, tier) = config.tier_for(referral_count);
+        let next_tier_at = config
+            .tiers
+            .iter()
+            .map(|t| t.min_referrals)
+            .find(|&min| min > referral_count);
+
+        Ok(ReferralSummary {
+            referral_count,
+            total_rewards: pending_rewards + paid_rewards,
+            pending_rewards,
+            held_rewards,
+            paid_rewards,
+            tier: ReferralTierInfo {
+                level,
+                rebate_pct: tier.rebate_pct,
+                min_referrals: tier.min_referrals,
+                next_tier_at,
+            },
+            referrals,
+        })
+    }
+
+    // (referrer id, referrer's referee count) of a user, if they were referred
+    fn referrer_of(conn: &mut PgConnection, user_id: Uuid) -> Result<Option<(Uuid, i32)>, AppError> {
+        let referred_by = users::table
+            .find(user_id)
+            .select(users::referred_by)
+            .first::<Option<Uuid>>(conn)
+            .optional()?
+            .flatten();
+        let Some(referrer_id) = referred_by else {
+            return Ok(None);
+        };
+
+        let referral_count = users::table
+            .find(referrer_id)
+            .select(users::referral_count)
+            .first::<Option<i32>>(conn)
+            .optional()?;
+        Ok(referral_count.map(|count| (referrer_id, count.unwrap_or(0))))
+    }
+
+    // Store a reward and update the referrer's total in one transaction.
+    // Returns None when the source event was already rewarded or the referral
+    // was confirmed as abuse. Rewards of flagged referrals are held.
+    fn grant(
+        conn: &mut PgConnection,
+        mut new_reward: NewReferralReward,
+    ) -> Result<Option<ReferralReward>, AppError> {
+        conn.transaction(|conn| {
+            let flags = referral_flags::table
+                .filter(referral_flags::referrer_id.eq(new_reward.referrer_id))
+                .filter(referral_flags::referred_user_id.eq(new_reward.referred_user_id))
+                .filter(referral_flags::status.ne("dismissed"))
+                .select(referral_flags::status)
+                .load::<String>(conn)?;
+            if flags.iter().any(|status| status == "confirmed") {
+                return Ok(None);
+            }
+            if !flags.is_empty() {
+                new_reward.status = Some("held".to_string());
+            }
+
+            let reward = diesel::insert_into(referral_rewards::table)
+                .values(&new_reward)
+                .on_conflict_do_nothing()
+                .get_result::<ReferralReward>(conn)
+                .optional()?;
+
+            if reward.is_some() {
+                Self::refresh_total(conn, new_reward.referrer_id)?;
+            }
+            Ok::<_, AppError>(reward)
+        })
+    }
+
+    // users.total_referral_rewards is pending plus paid rewards. Recomputing it
+    // keeps it exact when rewards are held or cancelled.
+    pub fn refresh_total(conn: &mut PgConnection, referrer_id: Uuid) -> Result<(), AppError> {
+        // Lock the referrer so concurrent grants do not overwrite each other's sums
+        users::table
+            .find(referrer_id)
+            .select(users::id)
+            .for_update()
+            .first::<Uuid>(conn)?;
+
+        let total: Option<f64> = referral_rewards::table
+            .filter(referral_rewards::referrer_id.eq(referrer_id))
+            .filter(referral_rewards::status.eq_any(["pending", "paid"]))
+            .select(diesel::dsl::sum(referral_rewards::reward_amount))
+            .first(conn)?;
+
+        diesel::update(users::table.find(referrer_id))
+            .set(users::total_referral_rewards.eq(Some(total.unwrap_or(0.0))))
+            .execute(conn)?;
+        Ok(())
+    }
+}
+
+// Only fills reported as trades, from a wallet the user proved to own, earn a
+// rebate. Synthetic fills are resized or replaced once the trades show up, so
+// rebating them would pay for the same volume twice.
+fn rebatable(fill: &Fill, verified: &HashSet<(Uuid, String)>) -> bool {
+    !is_synthetic(&fill.trade_id) && verified.contains(&(fill.user_id, fill.wallet_address.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tier(min_referrals: i32, rebate_pct: f64) -> RebateTier {
+        RebateTier { min_referrals, rebate_pct }
+    }
+
+    fn config(tiers: &str) -> ReferralRewardConfig {
+        ReferralRewardConfig {
+            tiers: ReferralRewardConfig::parse_tiers(tiers).unwrap(),
+            ..ReferralRewardConfig::from_config(AppConfig::for_tests())
+        }
+    }
+
+    fn fill(user_id: Uuid, wallet_address: &str, trade_id: &str) -> Fill {
+        Fill {
+            id: Uuid::new_v4(),
+            user_id,
+            wallet_address: wallet_address.to_string(),
+            symbol: "APT/USDC".to_string(),
+            market_id: "501".to_string(),
+            order_id: Some("1".to_string()),
+            trade_id: trade_id.to_string(),
+            side: "buy".to_string(),
+            size: 1.0,
+            price: 10.0,
+            fee: 0.01,
+            realized_pnl: 0.0,
+            realized_pnl_avg_cost: 0.0,
+            executed_at: Utc::now(),
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_parse_tiers_sorts_by_referee_count() {
+        assert_eq!(
+            ReferralRewardConfig::parse_tiers(" 50:20, 0:10 ,10:15"),
+            Some(vec![tier(0, 10.0), tier(10, 15.0), tier(50, 20.0)])
+        );
+    }
+
+    #[test]
+    fn test_parse_tiers_rejects_invalid_specs() {
+        assert_eq!(ReferralRewardConfig::parse_tiers(""), None);
+        assert_eq!(ReferralRewardConfig::parse_tiers("0:10,10"), None);
+        assert_eq!(ReferralRewardConfig::parse_tiers("0:10,ten:15"), None);
+        assert_eq!(ReferralRewardConfig::parse_tiers("-1:10"), None);
+        assert_eq!(ReferralRewardConfig::parse_tiers("0:101"), None);
+        assert_eq!(ReferralRewardConfig::from_config(AppConfig::for_tests()).tiers.len(), 4);
+    }
+
+    #[test]
+    fn test_tier_for_picks_the_highest_tier_reached() {
+        let config = config("0:10,10:15,50:20,100:25");
+        assert_eq!(config.tier_for(0), (1, tier(0, 10.0)));
+        assert_eq!(config.tier_for(9), (1, tier(0, 10.0)));
+        assert_eq!(config.tier_for(10), (2, tier(10, 15.0)));
+        assert_eq!(config.tier_for(99), (3, tier(50, 20.0)));
+        assert_eq!(config.tier_for(1000), (4, tier(100, 25.0)));
+    }
+
+    #[test]
+    fn test_tier_for_below_the_first_tier_earns_nothing() {
+        let config = config("5:10,20:15");
+        assert_eq!(config.tier_for(4), (0, tier(0, 0.0)));
+        assert_eq!(config.tier_for(5), (1, tier(5, 10.0)));
+    }
+
+    #[test]
+    fn test_only_trades_of_verified_wallets_are_rebated() {
+        let user_id = Uuid::new_v4();
+        let verified = HashSet::from([(user_id, "0xabc".to_string())]);
+
+        assert!(rebatable(&fill(user_id, "0xabc", "t-1"), &verified));
+        assert!(!rebatable(&fill(user_id, "0xdef", "t-1"), &verified));
+        // The address is verified, but by someone else
+        assert!(!rebatable(&fill(Uuid::new_v4(), "0xabc", "t-1"), &verified));
+        assert!(!rebatable(&fill(user_id, "0xabc", "order-1"), &verified));
+    }
+}
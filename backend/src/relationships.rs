@@ -0,0 +1,406 @@
+use crate::activity::{ActivityEvent, ActivityService};
+use crate::models::{FollowRequestResponse, NewFollow, RelatedUser, User};
+use crate::posts::PostService;
+use crate::schema::{copy_subscriptions, follow_requests, follows, user_blocks, user_mutes, users};
+use crate::utils::AppError;
+use crate::DbPool;
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use uuid::Uuid;
+
+// Blocks, mutes and follow requests between users. The sync helpers are used by
+// every social query that lists or shows other users.
+pub struct RelationshipService;
+
+impl RelationshipService {
+    // Blocking removes follows, pending follow requests and copy subscriptions
+    // in both directions
+    pub async fn block_user(pool: &DbPool, blocker_id: Uuid, username: &str) -> Result<(), AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let blocked_id = Self::find_user_id(conn, username)?;
+        if blocked_id == blocker_id {
+            return Err(AppError::BadRequest("Cannot block yourself".to_string()));
+        }
+
+        conn.transaction(|conn| {
+            diesel::insert_into(user_blocks::table)
+                .values((user_blocks::blocker_id.eq(blocker_id), user_blocks::blocked_id.eq(blocked_id)))
+                .on_conflict_do_nothing()
+                .execute(conn)?;
+
+            diesel::delete(follows::table.filter(
+                follows::follower_id.eq(blocker_id).and(follows::following_id.eq(blocked_id))
+                    .or(follows::follower_id.eq(blocked_id).and(follows::following_id.eq(blocker_id))),
+            ))
+            .execute(conn)?;
+
+            diesel::delete(follow_requests::table.filter(
+                follow_requests::requester_id.eq(blocker_id).and(follow_requests::target_id.eq(blocked_id))
+                    .or(follow_requests::requester_id.eq(blocked_id).and(follow_requests::target_id.eq(blocker_id))),
+            ))
+            .execute(conn)?;
+
+            diesel::update(
+                copy_subscriptions::table
+                    .filter(
+                        copy_subscriptions::follower_id.eq(blocker_id).and(copy_subscriptions::leader_id.eq(blocked_id))
+                            .or(copy_subscriptions::follower_id.eq(blocked_id).and(copy_subscriptions::leader_id.eq(blocker_id))),
+                    )
+                    .filter(copy_subscriptions::status.ne("stopped")),
+            )
+            .set((
+                copy_subscriptions::status.eq("stopped"),
+                copy_subscriptions::stopped_at.eq(Some(Utc::now())),
+            ))
+            .execute(conn)?;
+
+            Ok::<_, AppError>(())
+        })
+    }
+
+    pub async fn unblock_user(pool: &DbPool, blocker_id: Uuid, username: &str) -> Result<(), AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let blocked_id = Self::find_user_id(conn, username)?;
+        let deleted = diesel::delete(user_blocks::table.find((blocker_id, blocked_id)))
+            .execute(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to unblock user: {}", e)))?;
+
+        if deleted == 0 {
+            return Err(AppError::BadRequest("User is not blocked".to_string()));
+        }
+        Ok(())
+    }
+
+    pub async fn get_blocks(pool: &DbPool, user_id: Uuid) -> Result<Vec<RelatedUser>, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let rows = user_blocks::table
+            .inner_join(users::table.on(user_blocks::blocked_id.eq(users::id)))
+            .filter(user_blocks::blocker_id.eq(user_id))
+            .select((users::id, users::username, users::avatar_url, user_blocks::created_at))
+            .order(user_blocks::created_at.desc())
+            .load::<(Uuid, String, Option<String>, DateTime<Utc>)>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get blocked users: {}", e)))?;
+
+        Ok(rows.into_iter().map(related_user).collect())
+    }
+
+    // Muting only hides the user's activity from the muter's feed
+    pub async fn mute_user(pool: &DbPool, muter_id: Uuid, username: &str) -> Result<(), AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let muted_id = Self::find_user_id(conn, username)?;
+        if muted_id == muter_id {
+            return Err(AppError::BadRequest("Cannot mute yourself".to_string()));
+        }
+
+        diesel::insert_into(user_mutes::table)
+            .values((user_mutes::muter_id.eq(muter_id), user_mutes::muted_id.eq(muted_id)))
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to mute user: {}", e)))?;
+
+        Ok(())
+    }
+
+    pub async fn unmute_user(pool: &DbPool, muter_id: Uuid, username: &str) -> Result<(), AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let muted_id = Self::find_user_id(conn, username)?;
+        let deleted = diesel::delete(user_mutes::table.find((muter_id, muted_id)))
+            .execute(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to unmute user: {}", e)))?;
+
+        if deleted == 0 {
+            return Err(AppError::BadRequest("User is not muted".to_string()));
+        }
+        Ok(())
+    }
+
+    pub async fn get_mutes(pool: &DbPool, user_id: Uuid) -> Result<Vec<RelatedUser>, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let rows = user_mutes::table
+            .inner_join(users::table.on(user_mutes::muted_id.eq(users::id)))
+            .filter(user_mutes::muter_id.eq(user_id))
+            .select((users::id, users::username, users::avatar_url, user_mutes::created_at))
+            .order(user_mutes::created_at.desc())
+            .load::<(Uuid, String, Option<String>, DateTime<Utc>)>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get muted users: {}", e)))?;
+
+        Ok(rows.into_iter().map(related_user).collect())
+    }
+
+    // Making an account public approves every pending follow request
+    pub async fn set_private(pool: &DbPool, user_id: Uuid, is_private: bool) -> Result<(), AppError> {
+        let approved = {
+            let conn = &mut pool.get()
+                .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+            conn.transaction(|conn| {
+                diesel::update(users::table.find(user_id))
+                    .set((users::is_private.eq(is_private), users::updated_at.eq(Utc::now())))
+                    .execute(conn)?;
+
+                if is_private {
+                    return Ok::<_, AppError>(Vec::new());
+                }
+                let requester_ids = diesel::delete(follow_requests::table.filter(follow_requests::target_id.eq(user_id)))
+                    .returning(follow_requests::requester_id)
+                    .get_results::<Uuid>(conn)?;
+                let new_follows: Vec<NewFollow> = requester_ids
+                    .iter()
+                    .map(|&follower_id| NewFollow { follower_id, following_id: user_id })
+                    .collect();
+                diesel::insert_into(follows::table)
+                    .values(&new_follows)
+                    .on_conflict_do_nothing()
+                    .execute(conn)?;
+                Ok(requester_ids)
+            })?
+        };
+
+        let events = approved
+            .into_iter()
+            .map(|follower_id| ActivityEvent::new_follower(user_id, follower_id))
+            .collect();
+        ActivityService::record(pool, events).await;
+
+        Ok(())
+    }
+
+    // Pending requests to follow the current user, newest first
+    pub async fn get_follow_requests(
+        pool: &DbPool,
+        user_id: Uuid,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(Vec<FollowRequestResponse>, i64), AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let total = follow_requests::table
+            .filter(follow_requests::target_id.eq(user_id))
+            .count()
+            .get_result::<i64>(conn)?;
+        let rows = follow_requests::table
+            .inner_join(users::table.on(follow_requests::requester_id.eq(users::id)))
+            .filter(follow_requests::target_id.eq(user_id))
+            .select((
+                follow_requests::id,
+                users::id,
+                users::username,
+                users::avatar_url,
+                users::is_verified,
+                follow_requests::created_at,
+            ))
+            .order(follow_requests::created_at.desc())
+            .limit(limit)
+            .offset(offset)
+            .load::<(Uuid, Uuid, String, Option<String>, Option<bool>, DateTime<Utc>)>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get follow requests: {}", e)))?;
+
+        let requests = rows
+            .into_iter()
+            .map(|(id, requester_id, username, avatar_url, is_verified, created_at)| FollowRequestResponse {
+                id,
+                requester_id,
+                username,
+                avatar_url,
+                is_verified,
+                created_at,
+            })
+            .collect();
+
+        Ok((requests, total))
+    }
+
+    pub async fn approve_follow_request(pool: &DbPool, user_id: Uuid, request_id: Uuid) -> Result<(), AppError> {
+        let follower_id = {
+            let conn = &mut pool.get()
+                .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+            conn.transaction(|conn| {
+                let follower_id = Self::take_follow_request(conn, user_id, request_id)?;
+                diesel::insert_into(follows::table)
+                    .values(&NewFollow { follower_id, following_id: user_id })
+                    .on_conflict_do_nothing()
+                    .execute(conn)?;
+                Ok::<_, AppError>(follower_id)
+            })?
+        };
+
+        ActivityService::record(pool, vec![ActivityEvent::new_follower(user_id, follower_id)]).await;
+
+        Ok(())
+    }
+
+    pub async fn reject_follow_request(pool: &DbPool, user_id: Uuid, request_id: Uuid) -> Result<(), AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        Self::take_follow_request(conn, user_id, request_id)?;
+        Ok(())
+    }
+
+    pub async fn can_view_content(pool: &DbPool, owner_id: Uuid, viewer_id: Option<Uuid>) -> Result<bool, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        Self::content_visible(conn, owner_id, viewer_id)
+    }
+
+    // Look up a user by username as seen by `viewer_id`; users on either side
+    // of a block do not exist for each other
+    pub fn find_visible_user(
+        conn: &mut PgConnection,
+        username: &str,
+        viewer_id: Option<Uuid>,
+    ) -> Result<User, AppError> {
+        let user = users::table
+            .filter(users::username.eq(username))
+            .filter(users::deleted_at.is_null())
+            .first::<User>(conn)
+            .optional()
+            .map_err(|e| AppError::InternalServerError(format!("Database error: {}", e)))?
+            .ok_or_else(|| AppError::NotFoundError("User not found".to_string()))?;
+
+        match viewer_id {
+            Some(viewer_id) if Self::is_blocked_between(conn, viewer_id, user.id)? => {
+                Err(AppError::NotFoundError("User not found".to_string()))
+            }
+            _ => Ok(user),
+        }
+    }
+
+    // True when either user has blocked the other
+    pub fn is_blocked_between(conn: &mut PgConnection, user_a: Uuid, user_b: Uuid) -> Result<bool, AppError> {
+        let blocks = user_blocks::table
+            .filter(
+                user_blocks::blocker_id.eq(user_a).and(user_blocks::blocked_id.eq(user_b))
+                    .or(user_blocks::blocker_id.eq(user_b).and(user_blocks::blocked_id.eq(user_a))),
+            )
+            .count()
+            .get_result::<i64>(conn)?;
+        Ok(blocks > 0)
+    }
+
+    // Users the viewer blocked or was blocked by; anonymous viewers see everyone
+    pub fn hidden_user_ids(conn: &mut PgConnection, viewer_id: Option<Uuid>) -> Result<Vec<Uuid>, AppError> {
+        let Some(viewer_id) = viewer_id else {
+            return Ok(Vec::new());
+        };
+
+        let mut hidden = user_blocks::table
+            .filter(user_blocks::blocker_id.eq(viewer_id))
+            .select(user_blocks::blocked_id)
+            .load::<Uuid>(conn)?;
+        hidden.extend(
+            user_blocks::table
+                .filter(user_blocks::blocked_id.eq(viewer_id))
+                .select(user_blocks::blocker_id)
+                .load::<Uuid>(conn)?,
+        );
+        Ok(hidden)
+    }
+
+    pub fn muted_user_ids(conn: &mut PgConnection, user_id: Uuid) -> Result<Vec<Uuid>, AppError> {
+        user_mutes::table
+            .filter(user_mutes::muter_id.eq(user_id))
+            .select(user_mutes::muted_id)
+            .load::<Uuid>(conn)
+            .map_err(AppError::from)
+    }
+
+    // Whether the viewer can see a user's posts and follow lists: not across a
+    // block, and for private accounts only approved followers and admins
+    pub fn content_visible(
+        conn: &mut PgConnection,
+        owner_id: Uuid,
+        viewer_id: Option<Uuid>,
+    ) -> Result<bool, AppError> {
+        if viewer_id == Some(owner_id) {
+            return Ok(true);
+        }
+        if let Some(viewer_id) = viewer_id {
+            if Self::is_blocked_between(conn, viewer_id, owner_id)? {
+                return Ok(false);
+            }
+        }
+
+        let is_private = users::table
+            .find(owner_id)
+            .select(users::is_private)
+            .first::<bool>(conn)
+            .optional()?
+            .unwrap_or(false);
+        if !is_private {
+            return Ok(true);
+        }
+
+        match viewer_id {
+            Some(viewer_id) => {
+                let following = follows::table
+                    .filter(follows::follower_id.eq(viewer_id))
+                    .filter(follows::following_id.eq(owner_id))
+                    .count()
+                    .get_result::<i64>(conn)?;
+                Ok(following > 0 || PostService::is_admin(conn, viewer_id)?)
+            }
+            None => Ok(false),
+        }
+    }
+
+    pub fn require_content_visible(
+        conn: &mut PgConnection,
+        owner_id: Uuid,
+        viewer_id: Option<Uuid>,
+    ) -> Result<(), AppError> {
+        if Self::content_visible(conn, owner_id, viewer_id)? {
+            Ok(())
+        } else {
+            Err(AppError::AuthorizationError("This account is private".to_string()))
+        }
+    }
+
+    // Delete a request addressed to `user_id`, returning who sent it
+    fn take_follow_request(conn: &mut PgConnection, user_id: Uuid, request_id: Uuid) -> Result<Uuid, AppError> {
+        diesel::delete(
+            follow_requests::table
+                .filter(follow_requests::id.eq(request_id))
+                .filter(follow_requests::target_id.eq(user_id)),
+        )
+        .returning(follow_requests::requester_id)
+        .get_result::<Uuid>(conn)
+        .optional()?
+        .ok_or_else(|| AppError::NotFoundError("Follow request not found".to_string()))
+    }
+
+    fn find_user_id(conn: &mut PgConnection, username: &str) -> Result<Uuid, AppError> {
+        users::table
+            .filter(users::username.eq(username))
+            .filter(users::deleted_at.is_null())
+            .select(users::id)
+            .first::<Uuid>(conn)
+            .optional()
+            .map_err(|e| AppError::InternalServerError(format!("Database error: {}", e)))?
+            .ok_or_else(|| AppError::NotFoundError("User not found".to_string()))
+    }
+}
+
+fn related_user((user_id, username, avatar_url, since): (Uuid, String, Option<String>, DateTime<Utc>)) -> RelatedUser {
+    RelatedUser {
+        user_id,
+        username,
+        avatar_url,
+        since,
+    }
+}
@@ -0,0 +1,115 @@
+use crate::markets::MarketSpec;
+use crate::utils::AppError;
+
+// Account-level limits an order has to fit within
+#[derive(Debug, Clone, Copy)]
+pub struct RiskLimits {
+    pub max_leverage: f64,
+    pub max_notional: f64,
+    pub max_margin: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ApprovedOrder {
+    pub size: f64,
+    pub leverage: f64,
+}
+
+// Pre-trade checks for orders the backend builds on a user's behalf
+pub struct RiskEngine;
+
+impl RiskEngine {
+    // Fit an order into the market's rules and the account's limits, shrinking it
+    // where needed. Fails when nothing tradeable is left.
+    pub fn approve(
+        market: &MarketSpec,
+        side: &str,
+        size: f64,
+        price: f64,
+        leverage: f64,
+        limits: &RiskLimits,
+    ) -> Result<ApprovedOrder, AppError> {
+        if side != "buy" && side != "sell" {
+            return Err(AppError::ValidationError("side must be 'buy' or 'sell'".to_string()));
+        }
+        let positive = |value: f64| value.is_finite() && value > 0.0;
+        if !positive(price) || !positive(size) {
+            return Err(AppError::ValidationError("Order size and price must be positive".to_string()));
+        }
+
+        let leverage = leverage
+            .min(limits.max_leverage)
+            .min(market.max_leverage)
+            .max(1.0);
+
+        let mut notional = (size * price).min(limits.max_notional);
+        if notional / leverage > limits.max_margin {
+            notional = limits.max_margin * leverage;
+        }
+
+        // Round down to a whole number of minimum order sizes
+        let lots = (notional / price / market.min_order_size + 1e-9).floor();
+        let size = lots * market.min_order_size;
+        if size < market.min_order_size {
+            return Err(AppError::ValidationError(format!(
+                "Order is below the minimum size of {} for {}",
+                market.min_order_size, market.symbol
+            )));
+        }
+
+        Ok(ApprovedOrder { size, leverage })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MARKET: MarketSpec = MarketSpec {
+        symbol: "TEST/USDC",
+        market_id: "0",
+        base_asset: "TEST",
+        quote_asset: "USDC",
+        tick_size: 0.01,
+        min_order_size: 0.1,
+        max_leverage: 10.0,
+        maintenance_margin_rate: 0.05,
+    };
+
+    fn limits(max_leverage: f64, max_notional: f64, max_margin: f64) -> RiskLimits {
+        RiskLimits { max_leverage, max_notional, max_margin }
+    }
+
+    #[test]
+    fn test_passes_an_order_within_limits() {
+        let approved = RiskEngine::approve(&MARKET, "buy", 2.0, 100.0, 5.0, &limits(20.0, 1e6, 1e6)).unwrap();
+        assert_eq!(approved, ApprovedOrder { size: 2.0, leverage: 5.0 });
+    }
+
+    #[test]
+    fn test_caps_leverage_notional_and_margin() {
+        // Leverage is capped by the market and raised to at least 1
+        let approved = RiskEngine::approve(&MARKET, "buy", 1.0, 100.0, 50.0, &limits(20.0, 1e6, 1e6)).unwrap();
+        assert_eq!(approved.leverage, 10.0);
+        let approved = RiskEngine::approve(&MARKET, "buy", 1.0, 100.0, 0.5, &limits(20.0, 1e6, 1e6)).unwrap();
+        assert_eq!(approved.leverage, 1.0);
+
+        // 500 notional cap at 100 is 5 units
+        let approved = RiskEngine::approve(&MARKET, "sell", 8.0, 100.0, 2.0, &limits(20.0, 500.0, 1e6)).unwrap();
+        assert!((approved.size - 5.0).abs() < 1e-9);
+
+        // 50 margin at 2x buys 100 of notional, rounded down to whole lots
+        let approved = RiskEngine::approve(&MARKET, "buy", 8.0, 99.0, 2.0, &limits(20.0, 1e6, 50.0)).unwrap();
+        assert!((approved.size - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_rejects_invalid_and_too_small_orders() {
+        let wide = limits(20.0, 1e6, 1e6);
+        assert!(RiskEngine::approve(&MARKET, "hold", 1.0, 100.0, 1.0, &wide).is_err());
+        assert!(RiskEngine::approve(&MARKET, "buy", 0.0, 100.0, 1.0, &wide).is_err());
+        assert!(RiskEngine::approve(&MARKET, "buy", 1.0, f64::NAN, 1.0, &wide).is_err());
+        assert!(RiskEngine::approve(&MARKET, "buy", 0.05, 100.0, 1.0, &wide).is_err());
+        assert!(RiskEngine::approve(&MARKET, "buy", 1.0, 100.0, 1.0, &limits(20.0, 5.0, 1e6)).is_err());
+    }
+}
@@ -1,5 +1,165 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    activities (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        activity_type -> Varchar,
+        visibility -> Varchar,
+        subject_user_id -> Nullable<Uuid>,
+        symbol -> Nullable<Varchar>,
+        side -> Nullable<Varchar>,
+        size -> Nullable<Float8>,
+        price -> Nullable<Float8>,
+        pnl -> Nullable<Float8>,
+        created_at -> Timestamptz,
+        post_id -> Nullable<Uuid>,
+    }
+}
+
+diesel::table! {
+    activity_privacy_settings (user_id, activity_type) {
+        user_id -> Uuid,
+        activity_type -> Varchar,
+        visibility -> Varchar,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    alert_history (id) {
+        id -> Uuid,
+        rule_id -> Uuid,
+        user_id -> Uuid,
+        alert_type -> Varchar,
+        symbol -> Nullable<Varchar>,
+        condition -> Varchar,
+        threshold -> Float8,
+        value -> Float8,
+        delivery_status -> Varchar,
+        error -> Nullable<Text>,
+        triggered_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    alert_rules (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        alert_type -> Varchar,
+        symbol -> Nullable<Varchar>,
+        condition -> Varchar,
+        threshold -> Float8,
+        repeating -> Bool,
+        cooldown_minutes -> Int4,
+        is_active -> Bool,
+        last_value -> Nullable<Float8>,
+        last_triggered_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    avatar_uploads (id) {
+        id -> Uuid,
+        user_id -> Nullable<Uuid>,
+        url -> Text,
+        storage_keys -> Array<Text>,
+        content_type -> Varchar,
+        original_bytes -> Int4,
+        created_at -> Timestamptz,
+        replaced_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    copy_subscriptions (id) {
+        id -> Uuid,
+        follower_id -> Uuid,
+        leader_id -> Uuid,
+        allocation -> Float8,
+        max_leverage -> Float8,
+        max_trade_notional -> Float8,
+        mode -> Varchar,
+        status -> Varchar,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+        stopped_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    copy_trades (id) {
+        id -> Uuid,
+        subscription_id -> Uuid,
+        follower_id -> Uuid,
+        leader_id -> Uuid,
+        source_type -> Varchar,
+        source_id -> Uuid,
+        symbol -> Varchar,
+        side -> Varchar,
+        source_size -> Float8,
+        size -> Float8,
+        price -> Float8,
+        leverage -> Float8,
+        status -> Varchar,
+        reason -> Nullable<Text>,
+        paper_order_id -> Nullable<Uuid>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    data_exports (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        format -> Varchar,
+        status -> Varchar,
+        file_path -> Nullable<Text>,
+        file_bytes -> Nullable<Int8>,
+        error -> Nullable<Text>,
+        created_at -> Timestamptz,
+        started_at -> Nullable<Timestamptz>,
+        completed_at -> Nullable<Timestamptz>,
+        expires_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    equity_snapshots (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        wallet_address -> Text,
+        equity -> Float8,
+        free_collateral -> Float8,
+        margin_used -> Float8,
+        unrealized_pnl -> Float8,
+        resolution -> Varchar,
+        recorded_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    fills (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        wallet_address -> Text,
+        symbol -> Varchar,
+        market_id -> Varchar,
+        order_id -> Nullable<Varchar>,
+        trade_id -> Varchar,
+        side -> Varchar,
+        size -> Float8,
+        price -> Float8,
+        fee -> Float8,
+        realized_pnl -> Float8,
+        realized_pnl_avg_cost -> Float8,
+        executed_at -> Timestamptz,
+        created_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     follows (id) {
         id -> Uuid,
@@ -9,6 +169,227 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    follow_requests (id) {
+        id -> Uuid,
+        requester_id -> Uuid,
+        target_id -> Uuid,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    funding_payments (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        wallet_address -> Text,
+        symbol -> Varchar,
+        market_id -> Varchar,
+        side -> Varchar,
+        position_size -> Float8,
+        mark_price -> Float8,
+        funding_rate -> Float8,
+        payment -> Float8,
+        funding_time -> Timestamptz,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    funding_rates (id) {
+        id -> Uuid,
+        symbol -> Varchar,
+        market_id -> Varchar,
+        funding_rate -> Float8,
+        mark_price -> Float8,
+        recorded_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    notifications (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        notification_type -> Varchar,
+        title -> Text,
+        body -> Text,
+        actor_id -> Nullable<Uuid>,
+        entity_id -> Nullable<Uuid>,
+        read_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    paper_accounts (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        starting_balance -> Float8,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    paper_balances (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        asset -> Varchar,
+        available -> Float8,
+        locked -> Float8,
+        total -> Float8,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    paper_orders (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        symbol -> Varchar,
+        order_type -> Varchar,
+        side -> Varchar,
+        quantity -> Float8,
+        price -> Nullable<Float8>,
+        status -> Varchar,
+        filled_quantity -> Float8,
+        average_price -> Nullable<Float8>,
+        leverage -> Float8,
+        margin_type -> Varchar,
+        reserved_margin -> Float8,
+        fee -> Float8,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    paper_positions (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        symbol -> Varchar,
+        side -> Varchar,
+        size -> Float8,
+        entry_price -> Float8,
+        mark_price -> Float8,
+        unrealized_pnl -> Float8,
+        realized_pnl -> Float8,
+        margin -> Float8,
+        leverage -> Float8,
+        liquidation_price -> Nullable<Float8>,
+        margin_type -> Varchar,
+        funding_paid -> Float8,
+        last_funding_at -> Timestamptz,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    post_comments (id) {
+        id -> Uuid,
+        post_id -> Uuid,
+        user_id -> Uuid,
+        content -> Text,
+        hidden_at -> Nullable<Timestamptz>,
+        hidden_by -> Nullable<Uuid>,
+        hidden_reason -> Nullable<Text>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    post_likes (post_id, user_id) {
+        post_id -> Uuid,
+        user_id -> Uuid,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    posts (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        content -> Text,
+        symbol -> Nullable<Varchar>,
+        market_price -> Nullable<Float8>,
+        position_side -> Nullable<Varchar>,
+        position_size -> Nullable<Float8>,
+        entry_price -> Nullable<Float8>,
+        leverage -> Nullable<Float8>,
+        unrealized_pnl -> Nullable<Float8>,
+        like_count -> Int4,
+        comment_count -> Int4,
+        edited_at -> Nullable<Timestamptz>,
+        hidden_at -> Nullable<Timestamptz>,
+        hidden_by -> Nullable<Uuid>,
+        hidden_reason -> Nullable<Text>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    profile_audit_log (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        actor_id -> Nullable<Uuid>,
+        field -> Varchar,
+        old_value -> Nullable<Text>,
+        new_value -> Nullable<Text>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    referral_clicks (id) {
+        id -> Uuid,
+        referral_code_id -> Uuid,
+        #[max_length = 255]
+        source -> Nullable<Varchar>,
+        created_at -> Timestamptz,
+        #[max_length = 64]
+        ip_hash -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    referral_codes (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        #[max_length = 32]
+        code -> Varchar,
+        is_vanity -> Bool,
+        #[max_length = 64]
+        campaign -> Nullable<Varchar>,
+        expires_at -> Nullable<Timestamptz>,
+        max_uses -> Nullable<Int4>,
+        use_count -> Int4,
+        click_count -> Int4,
+        is_active -> Bool,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    referral_flags (id) {
+        id -> Uuid,
+        referrer_id -> Uuid,
+        referred_user_id -> Uuid,
+        #[max_length = 32]
+        reason -> Varchar,
+        details -> Nullable<Text>,
+        #[max_length = 20]
+        status -> Varchar,
+        reviewed_by -> Nullable<Uuid>,
+        reviewed_at -> Nullable<Timestamptz>,
+        review_note -> Nullable<Text>,
+        created_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     referral_rewards (id) {
         id -> Uuid,
@@ -21,6 +402,13 @@ diesel::table! {
         status -> Nullable<Varchar>,
         created_at -> Nullable<Timestamptz>,
         paid_at -> Nullable<Timestamptz>,
+        #[max_length = 255]
+        source_ref -> Nullable<Varchar>,
+        base_amount -> Nullable<Float8>,
+        rate -> Nullable<Float8>,
+        tier -> Nullable<Int4>,
+        cancelled_at -> Nullable<Timestamptz>,
+        cancel_reason -> Nullable<Text>,
     }
 }
 
@@ -34,6 +422,46 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    signup_fingerprints (user_id) {
+        user_id -> Uuid,
+        #[max_length = 45]
+        ip_address -> Nullable<Varchar>,
+        #[max_length = 128]
+        device_id -> Nullable<Varchar>,
+        user_agent -> Nullable<Text>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    trading_leaderboard (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        period -> Varchar,
+        realized_pnl -> Float8,
+        roi -> Nullable<Float8>,
+        volume -> Float8,
+        win_rate -> Nullable<Float8>,
+        trade_count -> Int8,
+        pnl_rank -> Int4,
+        roi_rank -> Nullable<Int4>,
+        volume_rank -> Int4,
+        win_rate_rank -> Nullable<Int4>,
+        computed_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    username_history (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        old_username -> Text,
+        new_username -> Text,
+        changed_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     users (id) {
         id -> Uuid,
@@ -49,6 +477,58 @@ diesel::table! {
         avatar_url -> Nullable<Text>,
         is_verified -> Nullable<Bool>,
         last_active -> Nullable<Timestamptz>,
+        leaderboard_opt_out -> Bool,
+        is_admin -> Bool,
+        referral_code_id -> Nullable<Uuid>,
+        is_private -> Bool,
+        deletion_scheduled_at -> Nullable<Timestamptz>,
+        deleted_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    user_blocks (blocker_id, blocked_id) {
+        blocker_id -> Uuid,
+        blocked_id -> Uuid,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    user_mutes (muter_id, muted_id) {
+        muter_id -> Uuid,
+        muted_id -> Uuid,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    user_wallets (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        wallet_address -> Text,
+        created_at -> Timestamptz,
+        verified_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    wallet_challenges (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        wallet_address -> Text,
+        nonce -> Text,
+        expires_at -> Timestamptz,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    wallet_link_history (id) {
+        id -> Uuid,
+        wallet_address -> Text,
+        user_id -> Uuid,
+        linked_at -> Timestamptz,
     }
 }
 
@@ -58,18 +538,82 @@ diesel::table! {
         user_id -> Uuid,
         token -> Text,
         expires_at -> Timestamptz,
-        used -> Bool,
+        used -> Nullable<Bool>,
         created_at -> Timestamptz,
     }
 }
 
+diesel::joinable!(activities -> posts (post_id));
+diesel::joinable!(activities -> users (user_id));
+diesel::joinable!(activity_privacy_settings -> users (user_id));
+diesel::joinable!(alert_history -> alert_rules (rule_id));
+diesel::joinable!(alert_history -> users (user_id));
+diesel::joinable!(alert_rules -> users (user_id));
+diesel::joinable!(avatar_uploads -> users (user_id));
+diesel::joinable!(copy_trades -> copy_subscriptions (subscription_id));
+diesel::joinable!(copy_trades -> paper_orders (paper_order_id));
+diesel::joinable!(data_exports -> users (user_id));
+diesel::joinable!(equity_snapshots -> users (user_id));
+diesel::joinable!(fills -> users (user_id));
+diesel::joinable!(funding_payments -> users (user_id));
+diesel::joinable!(notifications -> users (user_id));
 diesel::joinable!(sessions -> users (user_id));
+diesel::joinable!(signup_fingerprints -> users (user_id));
 diesel::joinable!(password_reset_tokens -> users (user_id));
+diesel::joinable!(paper_accounts -> users (user_id));
+diesel::joinable!(paper_balances -> users (user_id));
+diesel::joinable!(paper_orders -> users (user_id));
+diesel::joinable!(paper_positions -> users (user_id));
+diesel::joinable!(post_comments -> posts (post_id));
+diesel::joinable!(post_comments -> users (user_id));
+diesel::joinable!(post_likes -> posts (post_id));
+diesel::joinable!(post_likes -> users (user_id));
+diesel::joinable!(posts -> users (user_id));
+diesel::joinable!(referral_clicks -> referral_codes (referral_code_id));
+diesel::joinable!(referral_codes -> users (user_id));
+diesel::joinable!(trading_leaderboard -> users (user_id));
+diesel::joinable!(username_history -> users (user_id));
+diesel::joinable!(user_wallets -> users (user_id));
+diesel::joinable!(wallet_challenges -> users (user_id));
+diesel::joinable!(wallet_link_history -> users (user_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    activities,
+    activity_privacy_settings,
+    alert_history,
+    alert_rules,
+    avatar_uploads,
+    copy_subscriptions,
+    copy_trades,
+    data_exports,
+    equity_snapshots,
+    fills,
+    follow_requests,
     follows,
+    funding_payments,
+    funding_rates,
+    notifications,
+    paper_accounts,
+    paper_balances,
+    paper_orders,
+    paper_positions,
+    post_comments,
+    post_likes,
+    posts,
+    profile_audit_log,
+    referral_clicks,
+    referral_codes,
+    referral_flags,
     referral_rewards,
     sessions,
+    signup_fingerprints,
+    trading_leaderboard,
+    username_history,
     users,
+    user_blocks,
+    user_mutes,
+    user_wallets,
+    wallet_challenges,
+    wallet_link_history,
     password_reset_tokens,
 );
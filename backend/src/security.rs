@@ -0,0 +1,163 @@
+use actix_cors::Cors;
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{self, HeaderName, HeaderValue},
+    middleware::Logger,
+    Error,
+};
+use futures_util::future::LocalBoxFuture;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+
+const CORS_MAX_AGE_SECS: usize = 3600;
+
+// Responses are data, never documents: nothing may load from or frame them
+const JSON_CONTENT_SECURITY_POLICY: &str = "default-src 'none'; frame-ancestors 'none'";
+
+// Query parameters that carry credentials, e.g. the access token of
+// /api/ws/private, since browsers cannot set headers on WebSocket requests
+const REDACTED_QUERY_PARAMS: [&str; 2] = ["token", "access_token"];
+
+// Logger::default's format, with a request line whose credentials are redacted
+pub fn access_logger() -> Logger {
+    Logger::new(r#"%a "%{request_line}xi" %s %b "%{Referer}i" "%{User-Agent}i" %T"#).custom_request_replace(
+        "request_line",
+        |req| {
+            let mut target = req.path().to_string();
+            if !req.query_string().is_empty() {
+                target = format!("{}?{}", target, redact_query(req.query_string()));
+            }
+            format!("{} {} {:?}", req.method(), target, req.version())
+        },
+    )
+}
+
+pub fn redact_query(query: &str) -> String {
+    query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((name, _)) if REDACTED_QUERY_PARAMS.contains(&name.to_ascii_lowercase().as_str()) => {
+                format!("{}=[redacted]", name)
+            }
+            _ => pair.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+// Only the listed origins may call the API from a browser; "*" allows any.
+// Tokens travel in the Authorization header, so no credentials mode is needed.
+pub fn cors(allowed_origins: &[String]) -> Cors {
+    allowed_origins
+        .iter()
+        .fold(Cors::default(), |cors, origin| {
+            if origin == "*" {
+                cors.allow_any_origin()
+            } else {
+                cors.allowed_origin(origin)
+            }
+        })
+        .allowed_methods(["GET", "POST", "PUT", "PATCH", "DELETE"])
+        .allowed_headers([
+            header::AUTHORIZATION,
+            header::CONTENT_TYPE,
+            header::ACCEPT,
+            HeaderName::from_static("x-api-key"),
+        ])
+        .max_age(CORS_MAX_AGE_SECS)
+}
+
+// Adds the standard security headers to every response. Headers already set
+// by a handler are kept.
+pub struct SecurityHeaders {
+    hsts_max_age_secs: u64,
+}
+
+impl SecurityHeaders {
+    // An HSTS max-age of 0 leaves Strict-Transport-Security out, for plain HTTP
+    // in development
+    pub fn new(hsts_max_age_secs: u64) -> Self {
+        Self { hsts_max_age_secs }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for SecurityHeaders
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = SecurityHeadersService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        let hsts = (self.hsts_max_age_secs > 0).then(|| {
+            HeaderValue::from_str(&format!("max-age={}; includeSubDomains", self.hsts_max_age_secs))
+                .expect("HSTS header value is ASCII")
+        });
+
+        ready(Ok(SecurityHeadersService {
+            service: Rc::new(service),
+            hsts,
+        }))
+    }
+}
+
+pub struct SecurityHeadersService<S> {
+    service: Rc<S>,
+    hsts: Option<HeaderValue>,
+}
+
+impl<S, B> Service<ServiceRequest> for SecurityHeadersService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let svc = self.service.clone();
+        let hsts = self.hsts.clone();
+
+        Box::pin(async move {
+            let mut res = svc.call(req).await?;
+            let headers = res.headers_mut();
+
+            let is_json = headers
+                .get(header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(|value| value.starts_with("application/json"));
+
+            let mut defaults: Vec<(HeaderName, HeaderValue)> = vec![
+                (header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff")),
+                (header::X_FRAME_OPTIONS, HeaderValue::from_static("DENY")),
+                (header::REFERRER_POLICY, HeaderValue::from_static("no-referrer")),
+            ];
+            if let Some(hsts) = hsts {
+                defaults.push((header::STRICT_TRANSPORT_SECURITY, hsts));
+            }
+            if is_json {
+                defaults.push((
+                    header::CONTENT_SECURITY_POLICY,
+                    HeaderValue::from_static(JSON_CONTENT_SECURITY_POLICY),
+                ));
+            }
+
+            for (name, value) in defaults {
+                if !headers.contains_key(&name) {
+                    headers.insert(name, value);
+                }
+            }
+
+            Ok(res)
+        })
+    }
+}
@@ -1,34 +1,41 @@
+use crate::activity::{ActivityEvent, ActivityService};
+use crate::notifications::NotificationService;
+use crate::posts::PostService;
+use crate::referral_codes::ReferralCodeService;
+use crate::relationships::RelationshipService;
 use crate::models::{
-    User, Follow, NewFollow, 
-    PublicUserProfile, ReferralLeaderboardEntry, FollowStats
+    User, Follow, NewFollow, NewFollowRequest,
+    PublicUserProfile, ReferralLeaderboardEntry, FollowStats,
+    ReferralTree, ReferralTreeLevel, ReferralTreeNode,
 };
-use crate::schema::{users, follows};
+use crate::schema::{users, follows, follow_requests};
 use crate::DbPool;
 use crate::utils::AppError;
 use diesel::prelude::*;
 use chrono::{Utc, DateTime};
+use std::collections::HashSet;
 use uuid::Uuid;
+
+pub const MAX_REFERRAL_TREE_DEPTH: i32 = 10;
+// Nodes returned with a tree; level counts cover the whole tree
+const MAX_REFERRAL_TREE_NODES: usize = 500;
 // use rust_decimal::Decimal;
 
 pub struct SocialService;
 
 impl SocialService {
-    // Follow a user
+    // Follow a user. Private accounts get a follow request instead; returns
+    // "following" or "requested".
     pub async fn follow_user(
         pool: &DbPool,
         follower_id: Uuid,
         username_to_follow: &str,
-    ) -> Result<(), AppError> {
+    ) -> Result<&'static str, AppError> {
         let conn = &mut pool.get()
             .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
 
         // Find the user to follow by username
-        let user_to_follow = users::table
-            .filter(users::username.eq(username_to_follow))
-            .first::<User>(conn)
-            .optional()
-            .map_err(|e| AppError::InternalServerError(format!("Database error: {}", e)))?
-            .ok_or_else(|| AppError::NotFoundError("User not found".to_string()))?;
+        let user_to_follow = RelationshipService::find_visible_user(conn, username_to_follow, Some(follower_id))?;
 
         // Prevent self-following
         if follower_id == user_to_follow.id {
@@ -47,6 +54,34 @@ impl SocialService {
             return Err(AppError::BadRequest("Already following this user".to_string()));
         }
 
+        let follower_username = users::table
+            .find(follower_id)
+            .select(users::username)
+            .first::<String>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Database error: {}", e)))?;
+
+        if user_to_follow.is_private {
+            let request_id = diesel::insert_into(follow_requests::table)
+                .values(&NewFollowRequest {
+                    requester_id: follower_id,
+                    target_id: user_to_follow.id,
+                })
+                .on_conflict_do_nothing()
+                .returning(follow_requests::id)
+                .get_result::<Uuid>(conn)
+                .optional()
+                .map_err(|e| AppError::InternalServerError(format!("Failed to create follow request: {}", e)))?
+                .ok_or_else(|| AppError::BadRequest("Follow request already sent".to_string()))?;
+
+            NotificationService::notify(
+                pool,
+                vec![NotificationService::follow_request(user_to_follow.id, follower_id, &follower_username, request_id)],
+            )
+            .await;
+
+            return Ok("requested");
+        }
+
         // Create follow relationship
         let new_follow = NewFollow {
             follower_id,
@@ -58,10 +93,18 @@ impl SocialService {
             .execute(conn)
             .map_err(|e| AppError::InternalServerError(format!("Failed to create follow: {}", e)))?;
 
-        Ok(())
+        ActivityService::record(pool, vec![ActivityEvent::new_follower(user_to_follow.id, follower_id)]).await;
+
+        NotificationService::notify(
+            pool,
+            vec![NotificationService::new_follower(user_to_follow.id, follower_id, &follower_username)],
+        )
+        .await;
+
+        Ok("following")
     }
 
-    // Unfollow a user
+    // Unfollow a user, or withdraw a pending follow request
     pub async fn unfollow_user(
         pool: &DbPool,
         follower_id: Uuid,
@@ -86,7 +129,14 @@ impl SocialService {
             .map_err(|e| AppError::InternalServerError(format!("Failed to delete follow: {}", e)))?;
 
         if deleted_count == 0 {
-            return Err(AppError::BadRequest("Not following this user".to_string()));
+            let withdrawn = diesel::delete(follow_requests::table)
+                .filter(follow_requests::requester_id.eq(follower_id))
+                .filter(follow_requests::target_id.eq(user_to_unfollow.id))
+                .execute(conn)
+                .map_err(|e| AppError::InternalServerError(format!("Failed to delete follow request: {}", e)))?;
+            if withdrawn == 0 {
+                return Err(AppError::BadRequest("Not following this user".to_string()));
+            }
         }
 
         Ok(())
@@ -96,6 +146,7 @@ impl SocialService {
     pub async fn get_followers(
         pool: &DbPool,
         username: &str,
+        viewer_id: Option<Uuid>,
         limit: i64,
         offset: i64,
     ) -> Result<Vec<PublicUserProfile>, AppError> {
@@ -103,17 +154,15 @@ impl SocialService {
             .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
 
         // Find the user by username
-        let user = users::table
-            .filter(users::username.eq(username))
-            .first::<User>(conn)
-            .optional()
-            .map_err(|e| AppError::InternalServerError(format!("Database error: {}", e)))?
-            .ok_or_else(|| AppError::NotFoundError("User not found".to_string()))?;
+        let user = RelationshipService::find_visible_user(conn, username, viewer_id)?;
+        RelationshipService::require_content_visible(conn, user.id, viewer_id)?;
+        let hidden = RelationshipService::hidden_user_ids(conn, viewer_id)?;
 
         // Get followers
         let followers = follows::table
             .filter(follows::following_id.eq(user.id))
             .inner_join(users::table.on(follows::follower_id.eq(users::id)))
+            .filter(users::id.ne_all(&hidden))
             .select((
                 users::id,
                 users::username,
@@ -123,24 +172,26 @@ impl SocialService {
                 users::referral_count,
                 users::created_at,
                 users::last_active,
+                users::is_private,
             ))
             .order(follows::created_at.desc())
             .limit(limit)
             .offset(offset)
-            .load::<(Uuid, String, Option<String>, Option<String>, Option<bool>, Option<i32>, Option<chrono::DateTime<Utc>>, Option<chrono::DateTime<Utc>>)>(conn)
+            .load::<(Uuid, String, Option<String>, Option<String>, Option<bool>, Option<i32>, Option<chrono::DateTime<Utc>>, Option<chrono::DateTime<Utc>>, bool)>(conn)
             .map_err(|e| AppError::InternalServerError(format!("Failed to get followers: {}", e)))?;
 
         let profiles = followers.into_iter()
-            .map(|(id, username, bio, avatar_url, is_verified, referral_count, created_at, last_active)| {
+            .map(|(id, username, bio, avatar_url, is_verified, referral_count, created_at, last_active, is_private)| {
                 PublicUserProfile {
                     id,
                     username,
                     bio,
                     avatar_url,
-                    is_verified: is_verified,
-                    referral_count: referral_count,
-                    created_at: created_at,
-                    last_active: last_active,
+                    is_verified,
+                    referral_count,
+                    created_at,
+                    last_active,
+                    is_private,
                 }
             })
             .collect();
@@ -152,6 +203,7 @@ impl SocialService {
     pub async fn get_following(
         pool: &DbPool,
         username: &str,
+        viewer_id: Option<Uuid>,
         limit: i64,
         offset: i64,
     ) -> Result<Vec<PublicUserProfile>, AppError> {
@@ -159,17 +211,15 @@ impl SocialService {
             .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
 
         // Find the user by username
-        let user = users::table
-            .filter(users::username.eq(username))
-            .first::<User>(conn)
-            .optional()
-            .map_err(|e| AppError::InternalServerError(format!("Database error: {}", e)))?
-            .ok_or_else(|| AppError::NotFoundError("User not found".to_string()))?;
+        let user = RelationshipService::find_visible_user(conn, username, viewer_id)?;
+        RelationshipService::require_content_visible(conn, user.id, viewer_id)?;
+        let hidden = RelationshipService::hidden_user_ids(conn, viewer_id)?;
 
         // Get following
         let following = follows::table
             .filter(follows::follower_id.eq(user.id))
             .inner_join(users::table.on(follows::following_id.eq(users::id)))
+            .filter(users::id.ne_all(&hidden))
             .select((
                 users::id,
                 users::username,
@@ -179,24 +229,26 @@ impl SocialService {
                 users::referral_count,
                 users::created_at,
                 users::last_active,
+                users::is_private,
             ))
             .order(follows::created_at.desc())
             .limit(limit)
             .offset(offset)
-            .load::<(Uuid, String, Option<String>, Option<String>, Option<bool>, Option<i32>, Option<chrono::DateTime<Utc>>, Option<chrono::DateTime<Utc>>)>(conn)
+            .load::<(Uuid, String, Option<String>, Option<String>, Option<bool>, Option<i32>, Option<chrono::DateTime<Utc>>, Option<chrono::DateTime<Utc>>, bool)>(conn)
             .map_err(|e| AppError::InternalServerError(format!("Failed to get following: {}", e)))?;
 
         let profiles = following.into_iter()
-            .map(|(id, username, bio, avatar_url, is_verified, referral_count, created_at, last_active)| {
+            .map(|(id, username, bio, avatar_url, is_verified, referral_count, created_at, last_active, is_private)| {
                 PublicUserProfile {
                     id,
                     username,
                     bio,
                     avatar_url,
-                    is_verified: is_verified,
-                    referral_count: referral_count,
-                    created_at: created_at,
-                    last_active: last_active,
+                    is_verified,
+                    referral_count,
+                    created_at,
+                    last_active,
+                    is_private,
                 }
             })
             .collect();
@@ -208,17 +260,13 @@ impl SocialService {
     pub async fn get_follow_stats(
         pool: &DbPool,
         username: &str,
+        viewer_id: Option<Uuid>,
     ) -> Result<FollowStats, AppError> {
         let conn = &mut pool.get()
             .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
 
         // Find the user by username
-        let user = users::table
-            .filter(users::username.eq(username))
-            .first::<User>(conn)
-            .optional()
-            .map_err(|e| AppError::InternalServerError(format!("Database error: {}", e)))?
-            .ok_or_else(|| AppError::NotFoundError("User not found".to_string()))?;
+        let user = RelationshipService::find_visible_user(conn, username, viewer_id)?;
 
         // Count followers
         let followers_count = follows::table
@@ -240,20 +288,16 @@ impl SocialService {
         })
     }
 
-    // Get public user profile by username
+    // Get public user profile by username; blocked users get a 404
     pub async fn get_public_profile(
         pool: &DbPool,
         username: &str,
+        viewer_id: Option<Uuid>,
     ) -> Result<PublicUserProfile, AppError> {
         let conn = &mut pool.get()
             .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
 
-        let user = users::table
-            .filter(users::username.eq(username))
-            .first::<User>(conn)
-            .optional()
-            .map_err(|e| AppError::InternalServerError(format!("Database error: {}", e)))?
-            .ok_or_else(|| AppError::NotFoundError("User not found".to_string()))?;
+        let user = RelationshipService::find_visible_user(conn, username, viewer_id)?;
 
         Ok(PublicUserProfile {
             id: user.id,
@@ -264,6 +308,7 @@ impl SocialService {
             referral_count: user.referral_count,
             created_at: user.created_at,
             last_active: user.last_active,
+            is_private: user.is_private,
         })
     }
 
@@ -271,12 +316,16 @@ impl SocialService {
     pub async fn get_referral_leaderboard(
         pool: &DbPool,
         limit: i64,
+        viewer_id: Option<Uuid>,
     ) -> Result<Vec<ReferralLeaderboardEntry>, AppError> {
         let conn = &mut pool.get()
             .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
 
+        let hidden = RelationshipService::hidden_user_ids(conn, viewer_id)?;
         let leaderboard = users::table
             .filter(users::referral_count.gt(0))
+            .filter(users::id.ne_all(&hidden))
+            .filter(users::deleted_at.is_null())
             .select((
                 users::username,
                 users::referral_count,
@@ -312,12 +361,7 @@ impl SocialService {
             .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
 
         // Find the user to check by username
-        let user_to_check = users::table
-            .filter(users::username.eq(username_to_check))
-            .first::<User>(conn)
-            .optional()
-            .map_err(|e| AppError::InternalServerError(format!("Database error: {}", e)))?
-            .ok_or_else(|| AppError::NotFoundError("User not found".to_string()))?;
+        let user_to_check = RelationshipService::find_visible_user(conn, username_to_check, Some(follower_id))?;
 
         // Check if following
         let follow_exists = follows::table
@@ -330,30 +374,7 @@ impl SocialService {
         Ok(follow_exists.is_some())
     }
 
-    // Update user profile
-    pub async fn update_profile(
-        pool: &DbPool,
-        user_id: Uuid,
-        bio: Option<String>,
-        avatar_url: Option<String>,
-    ) -> Result<(), AppError> {
-        let conn = &mut pool.get()
-            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
-
-        diesel::update(users::table)
-            .filter(users::id.eq(user_id))
-            .set((
-                users::bio.eq(bio),
-                users::avatar_url.eq(avatar_url),
-                users::updated_at.eq(Utc::now()),
-            ))
-            .execute(conn)
-            .map_err(|e| AppError::InternalServerError(format!("Failed to update profile: {}", e)))?;
-
-        Ok(())
-    }
-
-    // Get user's referral code (username)
+    // Get user's default referral code
     pub async fn get_referral_code(
         pool: &DbPool,
         user_id: Uuid,
@@ -361,13 +382,7 @@ impl SocialService {
         let conn = &mut pool.get()
             .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
 
-        let username = users::table
-            .filter(users::id.eq(user_id))
-            .select(users::username)
-            .first::<String>(conn)
-            .map_err(|e| AppError::InternalServerError(format!("Failed to get username: {}", e)))?;
-
-        Ok(username)
+        ReferralCodeService::default_code(conn, user_id)
     }
 
     // Get users that were referred by a specific user
@@ -391,24 +406,26 @@ impl SocialService {
                 users::referral_count,
                 users::created_at,
                 users::last_active,
+                users::is_private,
             ))
             .order(users::created_at.desc())
             .limit(limit)
             .offset(offset)
-            .load::<(Uuid, String, Option<String>, Option<String>, Option<bool>, Option<i32>, Option<chrono::DateTime<Utc>>, Option<chrono::DateTime<Utc>>)>(conn)
+            .load::<(Uuid, String, Option<String>, Option<String>, Option<bool>, Option<i32>, Option<chrono::DateTime<Utc>>, Option<chrono::DateTime<Utc>>, bool)>(conn)
             .map_err(|e| AppError::InternalServerError(format!("Failed to get referred users: {}", e)))?;
 
         let profiles = referred_users.into_iter()
-            .map(|(id, username, bio, avatar_url, is_verified, referral_count, created_at, last_active)| {
+            .map(|(id, username, bio, avatar_url, is_verified, referral_count, created_at, last_active, is_private)| {
                 PublicUserProfile {
                     id,
                     username,
                     bio,
                     avatar_url,
-                    is_verified: is_verified,
-                    referral_count: referral_count,
-                    created_at: created_at,
-                    last_active: last_active,
+                    is_verified,
+                    referral_count,
+                    created_at,
+                    last_active,
+                    is_private,
                 }
             })
             .collect();
@@ -416,16 +433,122 @@ impl SocialService {
         Ok(profiles)
     }
 
-    // Get all users for discovery
+    // Everyone below a user in the referral tree, level by level
+    pub async fn get_referral_tree(
+        pool: &DbPool,
+        user_id: Uuid,
+        max_depth: i32,
+    ) -> Result<ReferralTree, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let username = users::table
+            .find(user_id)
+            .select(users::username)
+            .first::<String>(conn)
+            .optional()?
+            .ok_or_else(|| AppError::NotFoundError("User not found".to_string()))?;
+
+        Self::build_referral_tree(conn, user_id, username, max_depth)
+    }
+
+    // Admin only: the referral tree of any user
+    pub async fn get_user_referral_tree(
+        pool: &DbPool,
+        admin_id: Uuid,
+        username: &str,
+        max_depth: i32,
+    ) -> Result<ReferralTree, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+        PostService::require_admin(conn, admin_id)?;
+
+        let (user_id, username) = users::table
+            .filter(users::username.eq(username))
+            .select((users::id, users::username))
+            .first::<(Uuid, String)>(conn)
+            .optional()?
+            .ok_or_else(|| AppError::NotFoundError("User not found".to_string()))?;
+
+        Self::build_referral_tree(conn, user_id, username, max_depth)
+    }
+
+    fn build_referral_tree(
+        conn: &mut PgConnection,
+        user_id: Uuid,
+        username: String,
+        max_depth: i32,
+    ) -> Result<ReferralTree, AppError> {
+        let max_depth = max_depth.clamp(1, MAX_REFERRAL_TREE_DEPTH);
+
+        // `seen` guards against loops in referred_by
+        let mut seen = HashSet::from([user_id]);
+        let mut frontier = vec![user_id];
+        let mut levels = Vec::new();
+        let mut nodes = Vec::new();
+        let mut truncated = false;
+
+        for level in 1..=max_depth {
+            let children = users::table
+                .filter(users::referred_by.eq_any(&frontier))
+                .select((users::id, users::username, users::referred_by, users::created_at))
+                .order(users::created_at.asc())
+                .load::<(Uuid, String, Option<Uuid>, Option<DateTime<Utc>>)>(conn)?;
+            let children: Vec<_> = children
+                .into_iter()
+                .filter(|(id, ..)| seen.insert(*id))
+                .collect();
+            if children.is_empty() {
+                break;
+            }
+
+            levels.push(ReferralTreeLevel {
+                level,
+                count: children.len() as i64,
+            });
+            frontier = children.iter().map(|(id, ..)| *id).collect();
+
+            for (id, username, referred_by, joined_at) in children {
+                if nodes.len() >= MAX_REFERRAL_TREE_NODES {
+                    truncated = true;
+                    break;
+                }
+                nodes.push(ReferralTreeNode {
+                    user_id: id,
+                    username,
+                    referred_by: referred_by.unwrap_or(user_id),
+                    level,
+                    joined_at,
+                });
+            }
+        }
+
+        Ok(ReferralTree {
+            user_id,
+            username,
+            total: levels.iter().map(|l| l.count).sum(),
+            depth: levels.len() as i32,
+            max_depth,
+            levels,
+            nodes,
+            truncated,
+        })
+    }
+
+    // Get all users for discovery, without those on either side of a block
     pub async fn get_all_users(
         pool: &DbPool,
+        viewer_id: Option<Uuid>,
         limit: i64,
         offset: i64,
     ) -> Result<Vec<PublicUserProfile>, AppError> {
         let conn = &mut pool.get()
             .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
 
+        let hidden = RelationshipService::hidden_user_ids(conn, viewer_id)?;
         let users = users::table
+            .filter(users::id.ne_all(&hidden))
+            .filter(users::deleted_at.is_null())
             .select((
                 users::id,
                 users::username,
@@ -435,24 +558,26 @@ impl SocialService {
                 users::referral_count,
                 users::created_at,
                 users::last_active,
+                users::is_private,
             ))
             .order(users::created_at.desc())
             .limit(limit)
             .offset(offset)
-            .load::<(Uuid, String, Option<String>, Option<String>, Option<bool>, Option<i32>, Option<DateTime<Utc>>, Option<DateTime<Utc>>)>(conn)
+            .load::<(Uuid, String, Option<String>, Option<String>, Option<bool>, Option<i32>, Option<DateTime<Utc>>, Option<DateTime<Utc>>, bool)>(conn)
             .map_err(|e| AppError::InternalServerError(format!("Failed to get users: {}", e)))?;
 
         let profiles = users.into_iter()
-            .map(|(id, username, bio, avatar_url, is_verified, referral_count, created_at, last_active)| {
+            .map(|(id, username, bio, avatar_url, is_verified, referral_count, created_at, last_active, is_private)| {
                 PublicUserProfile {
                     id,
                     username,
                     bio,
                     avatar_url,
-                    is_verified: is_verified,
-                    referral_count: referral_count,
-                    created_at: created_at,
-                    last_active: last_active,
+                    is_verified,
+                    referral_count,
+                    created_at,
+                    last_active,
+                    is_private,
                 }
             })
             .collect();
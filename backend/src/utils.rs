@@ -1,5 +1,5 @@
 use actix_web::{HttpResponse, ResponseError};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::fmt;
 
 #[derive(Debug)]
@@ -17,6 +17,7 @@ pub enum AppError {
     InternalServerError(String),
     ConfigurationError(String),
     ExternalApiError(String),
+    RateLimited(String),
 }
 
 impl fmt::Display for AppError {
@@ -33,6 +34,7 @@ impl fmt::Display for AppError {
             AppError::InternalServerError(msg) => write!(f, "Internal server error: {}", msg),
             AppError::ConfigurationError(msg) => write!(f, "Configuration error: {}", msg),
             AppError::ExternalApiError(msg) => write!(f, "External API error: {}", msg),
+            AppError::RateLimited(msg) => write!(f, "Rate limited: {}", msg),
         }
     }
 }
@@ -128,6 +130,14 @@ impl ResponseError for AppError {
                     error: Some(msg.clone()),
                 })
             }
+            AppError::RateLimited(msg) => {
+                HttpResponse::TooManyRequests().json(ApiResponse::<()> {
+                    success: false,
+                    data: None,
+                    message: None,
+                    error: Some(msg.clone()),
+                })
+            }
         }
     }
 }
@@ -186,7 +196,6 @@ pub struct Pagination {
 }
 
 impl<T> PaginatedResponse<T> {
-    #[allow(dead_code)]
     pub fn new(data: Vec<T>, page: i64, per_page: i64, total: i64) -> Self {
         let total_pages = (total as f64 / per_page as f64).ceil() as i64;
         Self {
@@ -204,3 +213,92 @@ impl<T> PaginatedResponse<T> {
     }
 }
 
+
+// For PATCH bodies with `#[serde(default)]`: a missing field is None and an
+// explicit null is Some(None)
+pub fn double_option<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+// Common `page` / `per_page` / `format` query parameters for list endpoints
+#[derive(Debug, Deserialize)]
+pub struct PageQuery {
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+    pub format: Option<String>,
+}
+
+impl PageQuery {
+    pub const MAX_PER_PAGE: i64 = 100;
+    // Upper bound on rows returned by a CSV export
+    pub const MAX_EXPORT_ROWS: i64 = 10_000;
+
+    pub fn page(&self) -> i64 {
+        self.page.unwrap_or(1).max(1)
+    }
+
+    pub fn per_page(&self) -> i64 {
+        self.per_page.unwrap_or(20).clamp(1, Self::MAX_PER_PAGE)
+    }
+
+    // Saturates rather than overflowing on absurd page numbers, which then
+    // simply land past the last row
+    pub fn offset(&self) -> i64 {
+        (self.page() - 1).saturating_mul(self.per_page())
+    }
+
+    pub fn is_csv(&self) -> bool {
+        matches!(self.format.as_deref(), Some(format) if format.eq_ignore_ascii_case("csv"))
+    }
+}
+
+// Render rows as CSV, quoting fields that contain separators or quotes
+pub fn to_csv(headers: &[&str], rows: Vec<Vec<String>>) -> String {
+    fn escape(field: &str) -> String {
+        if field.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field.to_string()
+        }
+    }
+
+    let mut csv = headers.join(",");
+    csv.push('\n');
+    for row in rows {
+        let line: Vec<String> = row.iter().map(|field| escape(field)).collect();
+        csv.push_str(&line.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+pub fn csv_response(filename: &str, body: String) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"{}\"", filename),
+        ))
+        .body(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page_query(page: i64, per_page: i64) -> PageQuery {
+        PageQuery { page: Some(page), per_page: Some(per_page), format: None }
+    }
+
+    #[test]
+    fn test_offset_saturates_on_huge_pages() {
+        assert_eq!(page_query(1, 20).offset(), 0);
+        assert_eq!(page_query(3, 20).offset(), 40);
+        assert_eq!(page_query(0, 20).offset(), 0);
+        assert_eq!(page_query(i64::MAX, 100).offset(), i64::MAX);
+    }
+}
@@ -0,0 +1,359 @@
+use crate::models::{
+    NewUserWallet, NewWalletChallenge, NewWalletLink, UserWallet, WalletChallenge, WalletChallengeResponse,
+};
+use crate::referral_abuse::ReferralAbuseService;
+use crate::schema::{user_wallets, wallet_challenges, wallet_link_history};
+use crate::utils::AppError;
+use crate::DbPool;
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use ring::signature::{UnparsedPublicKey, ED25519};
+use sha3::{Digest, Sha3_256};
+use uuid::Uuid;
+
+const CHALLENGE_TTL_MINUTES: i64 = 10;
+const NONCE_LENGTH: usize = 32;
+// Appended to an Ed25519 public key before hashing it into an authentication key
+const ED25519_SCHEME: u8 = 0x00;
+// Prefix of the full message Aptos wallets sign for signMessage
+const APTOS_MESSAGE_PREFIX: &str = "APTOS\n";
+
+pub struct WalletService;
+
+impl WalletService {
+    // Issue a single-use nonce for the wallet to sign. A new challenge replaces
+    // the user's previous one for the same wallet.
+    pub async fn create_challenge(
+        pool: &DbPool,
+        user_id: Uuid,
+        wallet_address: &str,
+    ) -> Result<WalletChallengeResponse, AppError> {
+        let wallet_address = normalize_address(wallet_address)?;
+
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let nonce: String = thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(NONCE_LENGTH)
+            .map(char::from)
+            .collect();
+        let expires_at = Utc::now() + Duration::minutes(CHALLENGE_TTL_MINUTES);
+
+        let challenge = diesel::insert_into(wallet_challenges::table)
+            .values(&NewWalletChallenge {
+                user_id,
+                wallet_address,
+                nonce: nonce.clone(),
+                expires_at,
+            })
+            .on_conflict((wallet_challenges::user_id, wallet_challenges::wallet_address))
+            .do_update()
+            .set((
+                wallet_challenges::nonce.eq(&nonce),
+                wallet_challenges::expires_at.eq(expires_at),
+                wallet_challenges::created_at.eq(Utc::now()),
+            ))
+            .get_result::<WalletChallenge>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to create challenge: {}", e)))?;
+
+        Ok(WalletChallengeResponse {
+            message: challenge_message(&challenge.wallet_address, &challenge.nonce),
+            wallet_address: challenge.wallet_address,
+            nonce: challenge.nonce,
+            expires_at: challenge.expires_at,
+        })
+    }
+
+    // Link an Aptos wallet address to a user once it signed the user's pending
+    // challenge. `full_message` is what wallets return from signMessage; without
+    // it the signature must cover the challenge message itself.
+    pub async fn link_wallet(
+        pool: &DbPool,
+        user_id: Uuid,
+        wallet_address: &str,
+        public_key: &str,
+        signature: &str,
+        full_message: Option<&str>,
+    ) -> Result<UserWallet, AppError> {
+        let wallet_address = normalize_address(wallet_address)?;
+
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let challenge = wallet_challenges::table
+            .filter(wallet_challenges::user_id.eq(user_id))
+            .filter(wallet_challenges::wallet_address.eq(&wallet_address))
+            .filter(wallet_challenges::expires_at.gt(Utc::now()))
+            .first::<WalletChallenge>(conn)
+            .optional()
+            .map_err(|e| AppError::InternalServerError(format!("Database error: {}", e)))?
+            .ok_or_else(|| AppError::BadRequest("No pending challenge for this wallet".to_string()))?;
+
+        let message = challenge_message(&wallet_address, &challenge.nonce);
+        let signed = signed_message(&message, &challenge.nonce, full_message)?;
+        verify_ownership(&wallet_address, public_key, signature, signed.as_bytes())?;
+
+        // Challenges are single use; losing this race means another request
+        // already spent it
+        let consumed = diesel::delete(wallet_challenges::table.find(challenge.id))
+            .filter(wallet_challenges::nonce.eq(&challenge.nonce))
+            .execute(conn)?;
+        if consumed == 0 {
+            return Err(AppError::BadRequest("No pending challenge for this wallet".to_string()));
+        }
+
+        let existing = user_wallets::table
+            .filter(user_wallets::wallet_address.eq(&wallet_address))
+            .first::<UserWallet>(conn)
+            .optional()
+            .map_err(|e| AppError::InternalServerError(format!("Database error: {}", e)))?;
+
+        let now = Utc::now();
+        let wallet = match existing {
+            Some(wallet) if wallet.user_id == user_id => diesel::update(user_wallets::table.find(wallet.id))
+                .set(user_wallets::verified_at.eq(wallet.verified_at.unwrap_or(now)))
+                .get_result::<UserWallet>(conn)
+                .map_err(|e| AppError::InternalServerError(format!("Failed to link wallet: {}", e)))?,
+            Some(wallet) if wallet.verified_at.is_some() => {
+                return Err(AppError::BadRequest(
+                    "Wallet is already linked to another account".to_string(),
+                ))
+            }
+            // An unverified link by someone else never proved ownership, so the
+            // key holder takes the wallet over
+            existing => conn
+                .transaction::<_, diesel::result::Error, _>(|conn| {
+                    if let Some(wallet) = existing {
+                        log::info!(
+                            "Wallet {} moved from unverified user {} to {}",
+                            wallet.wallet_address, wallet.user_id, user_id
+                        );
+                        diesel::delete(user_wallets::table.find(wallet.id)).execute(conn)?;
+                    }
+                    diesel::insert_into(user_wallets::table)
+                        .values(&NewUserWallet {
+                            user_id,
+                            wallet_address,
+                            verified_at: Some(now),
+                        })
+                        .get_result::<UserWallet>(conn)
+                })
+                .map_err(|e| AppError::InternalServerError(format!("Failed to link wallet: {}", e)))?,
+        };
+
+        // Kept after unlinking, so a wallet moved between accounts is noticed
+        diesel::insert_into(wallet_link_history::table)
+            .values(&NewWalletLink {
+                wallet_address: wallet.wallet_address.clone(),
+                user_id,
+            })
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+
+        if let Err(e) = ReferralAbuseService::check_wallet(pool, user_id, &wallet.wallet_address).await {
+            log::warn!("Failed to run referral abuse checks for wallet {}: {}", wallet.wallet_address, e);
+        }
+
+        Ok(wallet)
+    }
+
+    pub fn has_verified_wallet(conn: &mut PgConnection, user_id: Uuid) -> Result<bool, AppError> {
+        let verified = user_wallets::table
+            .filter(user_wallets::user_id.eq(user_id))
+            .filter(user_wallets::verified_at.is_not_null())
+            .count()
+            .get_result::<i64>(conn)?;
+        Ok(verified > 0)
+    }
+
+    pub async fn unlink_wallet(
+        pool: &DbPool,
+        user_id: Uuid,
+        wallet_address: &str,
+    ) -> Result<(), AppError> {
+        let wallet_address = normalize_address(wallet_address)?;
+
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        let deleted_count = diesel::delete(user_wallets::table)
+            .filter(user_wallets::user_id.eq(user_id))
+            .filter(user_wallets::wallet_address.eq(wallet_address))
+            .execute(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to unlink wallet: {}", e)))?;
+
+        if deleted_count == 0 {
+            return Err(AppError::NotFoundError("Wallet not linked".to_string()));
+        }
+
+        Ok(())
+    }
+
+    pub async fn get_user_wallets(
+        pool: &DbPool,
+        user_id: Uuid,
+    ) -> Result<Vec<UserWallet>, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        user_wallets::table
+            .filter(user_wallets::user_id.eq(user_id))
+            .order(user_wallets::created_at.asc())
+            .load::<UserWallet>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get wallets: {}", e)))
+    }
+
+    // Every linked wallet, for background jobs
+    pub async fn get_all_wallets(pool: &DbPool) -> Result<Vec<UserWallet>, AppError> {
+        let conn = &mut pool.get()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get connection: {}", e)))?;
+
+        user_wallets::table
+            .order(user_wallets::created_at.asc())
+            .load::<UserWallet>(conn)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to get wallets: {}", e)))
+    }
+}
+
+pub fn challenge_message(wallet_address: &str, nonce: &str) -> String {
+    format!("Link wallet {} to Aptora (nonce {})", wallet_address, nonce)
+}
+
+// The bytes the wallet signed: the challenge message, or the Aptos wallet
+// envelope around it, which must end with our message and nonce
+fn signed_message(message: &str, nonce: &str, full_message: Option<&str>) -> Result<String, AppError> {
+    let Some(full_message) = full_message else {
+        return Ok(message.to_string());
+    };
+
+    let suffix = format!("message: {}\nnonce: {}", message, nonce);
+    if !full_message.starts_with(APTOS_MESSAGE_PREFIX) || !full_message.ends_with(&suffix) {
+        return Err(AppError::BadRequest("Signed message does not match the challenge".to_string()));
+    }
+    Ok(full_message.to_string())
+}
+
+// The signature must be valid for the public key, and the key must hash to the
+// wallet's address. Accounts that rotated their key no longer match and cannot
+// be linked.
+fn verify_ownership(
+    wallet_address: &str,
+    public_key: &str,
+    signature: &str,
+    signed: &[u8],
+) -> Result<(), AppError> {
+    let public_key = decode_hex(public_key, 32)
+        .ok_or_else(|| AppError::ValidationError("Invalid public key".to_string()))?;
+    let signature = decode_hex(signature, 64)
+        .ok_or_else(|| AppError::ValidationError("Invalid signature".to_string()))?;
+
+    if authentication_key(&public_key) != full_address(wallet_address) {
+        return Err(AppError::BadRequest("Public key does not belong to this wallet".to_string()));
+    }
+
+    UnparsedPublicKey::new(&ED25519, &public_key)
+        .verify(signed, &signature)
+        .map_err(|_| AppError::BadRequest("Invalid wallet signature".to_string()))
+}
+
+// Address of a single-key Ed25519 account: SHA3-256 of the key and scheme byte
+fn authentication_key(public_key: &[u8]) -> String {
+    let mut hasher = Sha3_256::new();
+    hasher.update(public_key);
+    hasher.update([ED25519_SCHEME]);
+    format!("0x{}", hex::encode(hasher.finalize()))
+}
+
+// Short addresses are zero-padded to the full 32 bytes
+fn full_address(address: &str) -> String {
+    format!("0x{:0>64}", address.trim_start_matches("0x"))
+}
+
+fn decode_hex(value: &str, length: usize) -> Option<Vec<u8>> {
+    let value = value.trim();
+    let bytes = hex::decode(value.strip_prefix("0x").unwrap_or(value)).ok()?;
+    (bytes.len() == length).then_some(bytes)
+}
+
+// Aptos addresses are hex, optionally 0x-prefixed; store them lowercase with the prefix
+fn normalize_address(address: &str) -> Result<String, AppError> {
+    let address = address.trim().to_lowercase();
+    let hex = address.strip_prefix("0x").unwrap_or(&address);
+
+    if hex.is_empty() || hex.len() > 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(AppError::ValidationError("Invalid wallet address".to_string()));
+    }
+
+    Ok(format!("0x{}", hex))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    fn generate_key_pair() -> Ed25519KeyPair {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap()
+    }
+
+    fn public_key_hex(key_pair: &Ed25519KeyPair) -> String {
+        format!("0x{}", hex::encode(key_pair.public_key().as_ref()))
+    }
+
+    #[test]
+    fn test_verifies_a_signature_by_the_wallet_key() {
+        let key_pair = generate_key_pair();
+        let address = authentication_key(key_pair.public_key().as_ref());
+        let message = challenge_message(&address, "nonce1");
+        let signature = hex::encode(key_pair.sign(message.as_bytes()));
+
+        assert!(verify_ownership(&address, &public_key_hex(&key_pair), &signature, message.as_bytes()).is_ok());
+        // A different message, as for another nonce, fails
+        let other = challenge_message(&address, "nonce2");
+        assert!(verify_ownership(&address, &public_key_hex(&key_pair), &signature, other.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_rejects_a_key_of_another_address() {
+        let key_pair = generate_key_pair();
+        let address = authentication_key(generate_key_pair().public_key().as_ref());
+        let message = challenge_message(&address, "nonce");
+        let signature = hex::encode(key_pair.sign(message.as_bytes()));
+
+        let error = verify_ownership(&address, &public_key_hex(&key_pair), &signature, message.as_bytes());
+        assert!(matches!(error, Err(AppError::BadRequest(_))));
+    }
+
+    #[test]
+    fn test_rejects_malformed_keys_and_signatures() {
+        let key_pair = generate_key_pair();
+        let address = authentication_key(key_pair.public_key().as_ref());
+        assert!(verify_ownership(&address, "0x1234", &"00".repeat(64), b"m").is_err());
+        assert!(verify_ownership(&address, &public_key_hex(&key_pair), "zz", b"m").is_err());
+    }
+
+    #[test]
+    fn test_signed_message_accepts_the_wallet_envelope() {
+        let message = challenge_message("0x1", "abc");
+        assert_eq!(signed_message(&message, "abc", None).unwrap(), message);
+
+        let envelope = format!("APTOS\napplication: aptora.app\nmessage: {}\nnonce: abc", message);
+        assert_eq!(signed_message(&message, "abc", Some(&envelope)).unwrap(), envelope);
+
+        let stale = format!("APTOS\nmessage: {}\nnonce: old", message);
+        assert!(signed_message(&message, "abc", Some(&stale)).is_err());
+        assert!(signed_message(&message, "abc", Some(&format!("message: {}\nnonce: abc", message))).is_err());
+    }
+
+    #[test]
+    fn test_short_addresses_are_padded() {
+        assert_eq!(full_address("0x1"), format!("0x{}1", "0".repeat(63)));
+        assert_eq!(normalize_address(" 0xABC ").unwrap(), "0xabc");
+        assert!(normalize_address("0xnothex").is_err());
+    }
+}
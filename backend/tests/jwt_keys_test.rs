@@ -0,0 +1,168 @@
+use ring::rand::SystemRandom;
+use ring::signature::Ed25519KeyPair;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// The backend is a binary crate, so the module is compiled in directly; the
+// startup globals go unused here
+#[allow(dead_code)]
+#[path = "../src/jwt_keys.rs"]
+mod jwt_keys;
+
+use jwt_keys::JwtKeys;
+
+const ISSUER: &str = "aptora";
+const AUDIENCE: &str = "aptora-api";
+// Test-only key; never used outside these tests
+const RSA_KEY: &str = include_str!("fixtures/jwt_rsa_test_key.pem");
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TestClaims {
+    sub: String,
+    exp: i64,
+    iss: String,
+    aud: String,
+}
+
+fn claims() -> TestClaims {
+    TestClaims {
+        sub: "user-1".to_string(),
+        exp: chrono::Utc::now().timestamp() + 900,
+        iss: ISSUER.to_string(),
+        aud: AUDIENCE.to_string(),
+    }
+}
+
+fn ed25519_pem() -> String {
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+    pem::encode(&pem::Pem::new("PRIVATE KEY", pkcs8.as_ref()))
+}
+
+// A fresh directory holding the given <kid>.pem files
+fn keys_dir(keys: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("jwt-keys-{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&dir).unwrap();
+    for (kid, pem) in keys {
+        fs::write(dir.join(format!("{}.pem", kid)), pem).unwrap();
+    }
+    dir
+}
+
+fn load(dir: &Path, signing_kid: &str) -> JwtKeys {
+    JwtKeys::load(Some(dir), Some(signing_kid), ISSUER, AUDIENCE).unwrap()
+}
+
+fn header_of(token: &str) -> jsonwebtoken::Header {
+    jsonwebtoken::decode_header(token).unwrap()
+}
+
+#[test]
+fn test_signs_with_the_named_key_and_algorithm() {
+    let dir = keys_dir(&[("ed-1", &ed25519_pem()), ("rsa-1", RSA_KEY)]);
+
+    let token = load(&dir, "ed-1").sign(&claims()).unwrap();
+    let header = header_of(&token);
+    assert_eq!(header.kid.as_deref(), Some("ed-1"));
+    assert_eq!(header.alg, jsonwebtoken::Algorithm::EdDSA);
+
+    let token = load(&dir, "rsa-1").sign(&claims()).unwrap();
+    let header = header_of(&token);
+    assert_eq!(header.kid.as_deref(), Some("rsa-1"));
+    assert_eq!(header.alg, jsonwebtoken::Algorithm::RS256);
+
+    let verified = load(&dir, "ed-1").verify::<TestClaims>(&token).unwrap();
+    assert_eq!(verified.claims.sub, "user-1");
+}
+
+#[test]
+fn test_tokens_of_the_previous_key_verify_after_rotation() {
+    let old_pem = ed25519_pem();
+    let old_dir = keys_dir(&[("2026-09", &old_pem)]);
+    let token = load(&old_dir, "2026-09").sign(&claims()).unwrap();
+
+    // The new key signs; the old one stays in the set until its tokens expire
+    let rotated_dir = keys_dir(&[("2026-09", &old_pem), ("2026-10", &ed25519_pem())]);
+    let rotated = load(&rotated_dir, "2026-10");
+    assert!(rotated.verify::<TestClaims>(&token).is_ok());
+    assert_eq!(header_of(&rotated.sign(&claims()).unwrap()).kid.as_deref(), Some("2026-10"));
+
+    // Once it is removed its tokens are refused
+    let retired_dir = keys_dir(&[("2026-10", &ed25519_pem())]);
+    let error = load(&retired_dir, "2026-10").verify::<TestClaims>(&token).unwrap_err();
+    assert_eq!(error, "unknown signing key");
+}
+
+#[test]
+fn test_rejects_wrong_issuer_audience_and_unsigned_keys() {
+    let dir = keys_dir(&[("ed-1", &ed25519_pem())]);
+    let keys = load(&dir, "ed-1");
+
+    let mut wrong_issuer = claims();
+    wrong_issuer.iss = "someone-else".to_string();
+    assert!(keys.verify::<TestClaims>(&keys.sign(&wrong_issuer).unwrap()).is_err());
+
+    let mut wrong_audience = claims();
+    wrong_audience.aud = "other-api".to_string();
+    assert!(keys.verify::<TestClaims>(&keys.sign(&wrong_audience).unwrap()).is_err());
+
+    // Same kid, but signed by a key outside the set
+    let other_dir = keys_dir(&[("ed-1", &ed25519_pem())]);
+    let forged = load(&other_dir, "ed-1").sign(&claims()).unwrap();
+    assert!(keys.verify::<TestClaims>(&forged).is_err());
+
+    // HS256 tokens are refused even when they name a known kid
+    let mut header = jsonwebtoken::Header::new(jsonwebtoken::Algorithm::HS256);
+    header.kid = Some("ed-1".to_string());
+    let hmac = jsonwebtoken::encode(&header, &claims(), &jsonwebtoken::EncodingKey::from_secret(b"secret")).unwrap();
+    assert!(keys.verify::<TestClaims>(&hmac).is_err());
+}
+
+#[test]
+fn test_jwk_set_publishes_every_public_key() {
+    let dir = keys_dir(&[("ed-1", &ed25519_pem()), ("rsa-1", RSA_KEY)]);
+    let keys = load(&dir, "ed-1");
+
+    let jwks = serde_json::to_value(keys.jwk_set()).unwrap();
+    let published = jwks["keys"].as_array().unwrap();
+    assert_eq!(published.len(), 2);
+
+    let ed = published.iter().find(|key| key["kid"] == "ed-1").unwrap();
+    assert_eq!(ed["kty"], "OKP");
+    assert_eq!(ed["crv"], "Ed25519");
+    assert_eq!(ed["alg"], "EdDSA");
+    assert_eq!(ed["use"], "sig");
+    assert!(ed.get("d").is_none());
+
+    let rsa = published.iter().find(|key| key["kid"] == "rsa-1").unwrap();
+    assert_eq!(rsa["kty"], "RSA");
+    assert_eq!(rsa["alg"], "RS256");
+    assert_eq!(rsa["e"], "AQAB");
+    assert!(rsa.get("d").is_none());
+
+    // A verifier holding only the JWKS accepts the token
+    let token = load(&dir, "rsa-1").sign(&claims()).unwrap();
+    let jwk_set: jsonwebtoken::jwk::JwkSet = serde_json::from_value(jwks).unwrap();
+    let decoding = jsonwebtoken::DecodingKey::from_jwk(jwk_set.find("rsa-1").unwrap()).unwrap();
+    let mut validation = jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::RS256);
+    validation.set_audience(&[AUDIENCE]);
+    assert!(jsonwebtoken::decode::<TestClaims>(&token, &decoding, &validation).is_ok());
+}
+
+#[test]
+fn test_load_errors() {
+    let dir = keys_dir(&[("ed-1", &ed25519_pem())]);
+    assert!(JwtKeys::load(Some(&dir), Some("missing"), ISSUER, AUDIENCE).is_err());
+    assert!(JwtKeys::load(Some(&dir), None, ISSUER, AUDIENCE).is_err());
+
+    let not_a_key = keys_dir(&[("bad", "-----BEGIN CERTIFICATE-----\nAAAA\n-----END CERTIFICATE-----\n")]);
+    assert!(JwtKeys::load(Some(&not_a_key), Some("bad"), ISSUER, AUDIENCE).is_err());
+
+    let empty = keys_dir(&[]);
+    assert!(JwtKeys::load(Some(&empty), Some("ed-1"), ISSUER, AUDIENCE).is_err());
+
+    let temporary = JwtKeys::load(None, None, ISSUER, AUDIENCE).unwrap();
+    assert_eq!(temporary.signing_kid(), jwt_keys::TEMPORARY_KEY_ID);
+    let token = temporary.sign(&claims()).unwrap();
+    assert!(temporary.verify::<TestClaims>(&token).is_ok());
+}
@@ -0,0 +1,195 @@
+use actix_web::http::{header, Method, StatusCode};
+use actix_web::{test, web, App, HttpResponse};
+
+// The backend is a binary crate, so the module is compiled in directly; the
+// access logger goes unused here
+#[allow(dead_code)]
+#[path = "../src/security.rs"]
+mod security;
+
+const ALLOWED_ORIGIN: &str = "https://app.aptora.test";
+
+fn origins(origins: &[&str]) -> Vec<String> {
+    origins.iter().map(|origin| origin.to_string()).collect()
+}
+
+async fn json_ok() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({ "success": true }))
+}
+
+async fn image_ok() -> HttpResponse {
+    HttpResponse::Ok().content_type("image/webp").body(vec![0u8; 4])
+}
+
+fn preflight(origin: &str, method: &str, headers: &str) -> test::TestRequest {
+    test::TestRequest::default()
+        .method(Method::OPTIONS)
+        .uri("/api/user/profile")
+        .insert_header((header::ORIGIN, origin))
+        .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, method))
+        .insert_header((header::ACCESS_CONTROL_REQUEST_HEADERS, headers))
+}
+
+#[actix_web::test]
+async fn test_preflight_from_allowed_origin() {
+    let app = test::init_service(
+        App::new()
+            .wrap(security::cors(&origins(&[ALLOWED_ORIGIN])))
+            .route("/api/user/profile", web::patch().to(json_ok)),
+    )
+    .await;
+
+    let req = preflight(ALLOWED_ORIGIN, "PATCH", "authorization, content-type").to_request();
+    let resp = test::call_service(&app, req).await;
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    let headers = resp.headers();
+    assert_eq!(headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), ALLOWED_ORIGIN);
+    let methods = headers.get(header::ACCESS_CONTROL_ALLOW_METHODS).unwrap().to_str().unwrap();
+    assert!(methods.contains("PATCH"));
+    let allowed_headers = headers.get(header::ACCESS_CONTROL_ALLOW_HEADERS).unwrap().to_str().unwrap();
+    assert!(allowed_headers.contains("authorization"));
+    assert!(allowed_headers.contains("content-type"));
+    assert_eq!(headers.get(header::ACCESS_CONTROL_MAX_AGE).unwrap(), "3600");
+    assert!(headers.get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS).is_none());
+}
+
+#[actix_web::test]
+async fn test_preflight_from_unknown_origin_is_rejected() {
+    let app = test::init_service(
+        App::new()
+            .wrap(security::cors(&origins(&[ALLOWED_ORIGIN])))
+            .route("/api/user/profile", web::patch().to(json_ok)),
+    )
+    .await;
+
+    let req = preflight("https://evil.example", "PATCH", "authorization").to_request();
+    let resp = test::call_service(&app, req).await;
+
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert!(resp.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+}
+
+#[actix_web::test]
+async fn test_preflight_with_unlisted_method_or_header_is_rejected() {
+    let app = test::init_service(
+        App::new()
+            .wrap(security::cors(&origins(&[ALLOWED_ORIGIN])))
+            .route("/api/user/profile", web::patch().to(json_ok)),
+    )
+    .await;
+
+    let req = preflight(ALLOWED_ORIGIN, "TRACE", "authorization").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let req = preflight(ALLOWED_ORIGIN, "PATCH", "x-custom-header").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn test_preflight_with_wildcard_allows_any_origin() {
+    let app = test::init_service(
+        App::new()
+            .wrap(security::cors(&origins(&["*"])))
+            .route("/api/user/profile", web::patch().to(json_ok)),
+    )
+    .await;
+
+    let req = preflight("http://localhost:5173", "PATCH", "authorization").to_request();
+    let resp = test::call_service(&app, req).await;
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_some());
+}
+
+#[actix_web::test]
+async fn test_actual_request_from_allowed_origin_gets_cors_headers() {
+    let app = test::init_service(
+        App::new()
+            .wrap(security::cors(&origins(&[ALLOWED_ORIGIN])))
+            .route("/api/user/profile", web::patch().to(json_ok)),
+    )
+    .await;
+
+    let req = test::TestRequest::patch()
+        .uri("/api/user/profile")
+        .insert_header((header::ORIGIN, ALLOWED_ORIGIN))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), ALLOWED_ORIGIN);
+}
+
+#[actix_web::test]
+async fn test_security_headers_on_json_responses() {
+    let app = test::init_service(
+        App::new()
+            .wrap(security::SecurityHeaders::new(31_536_000))
+            .route("/api/health", web::get().to(json_ok)),
+    )
+    .await;
+
+    let req = test::TestRequest::get().uri("/api/health").to_request();
+    let resp = test::call_service(&app, req).await;
+
+    let headers = resp.headers();
+    assert_eq!(headers.get(header::X_CONTENT_TYPE_OPTIONS).unwrap(), "nosniff");
+    assert_eq!(headers.get(header::X_FRAME_OPTIONS).unwrap(), "DENY");
+    assert_eq!(headers.get(header::REFERRER_POLICY).unwrap(), "no-referrer");
+    assert_eq!(
+        headers.get(header::STRICT_TRANSPORT_SECURITY).unwrap(),
+        "max-age=31536000; includeSubDomains"
+    );
+    assert_eq!(
+        headers.get(header::CONTENT_SECURITY_POLICY).unwrap(),
+        "default-src 'none'; frame-ancestors 'none'"
+    );
+}
+
+#[actix_web::test]
+async fn test_security_headers_without_hsts_and_for_other_content() {
+    let app = test::init_service(
+        App::new()
+            .wrap(security::SecurityHeaders::new(0))
+            .route("/api/avatars/a.webp", web::get().to(image_ok)),
+    )
+    .await;
+
+    let req = test::TestRequest::get().uri("/api/avatars/a.webp").to_request();
+    let resp = test::call_service(&app, req).await;
+
+    let headers = resp.headers();
+    assert_eq!(headers.get(header::X_CONTENT_TYPE_OPTIONS).unwrap(), "nosniff");
+    assert!(headers.get(header::STRICT_TRANSPORT_SECURITY).is_none());
+    assert!(headers.get(header::CONTENT_SECURITY_POLICY).is_none());
+}
+
+#[actix_web::test]
+async fn test_security_headers_on_rejected_preflight() {
+    let app = test::init_service(
+        App::new()
+            .wrap(security::cors(&origins(&[ALLOWED_ORIGIN])))
+            .wrap(security::SecurityHeaders::new(0))
+            .route("/api/user/profile", web::patch().to(json_ok)),
+    )
+    .await;
+
+    let req = preflight("https://evil.example", "PATCH", "authorization").to_request();
+    let resp = test::call_service(&app, req).await;
+
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(resp.headers().get(header::X_FRAME_OPTIONS).unwrap(), "DENY");
+}
+
+#[actix_web::test]
+async fn test_redact_query_hides_tokens_only() {
+    assert_eq!(security::redact_query("token=eyJhbGciOi.abc.def"), "token=[redacted]");
+    assert_eq!(
+        security::redact_query("channel=orders&Access_Token=secret&page=2"),
+        "channel=orders&Access_Token=[redacted]&page=2"
+    );
+    assert_eq!(security::redact_query("tokens=1&flag"), "tokens=1&flag");
+}
@@ -582,8 +582,8 @@ class ApiClient {
   }
 
   async updateProfile(profileData: Partial<User>): Promise<User> {
-    const response: AxiosResponse<ApiResponse<User>> = await this.client.put(
-      `/social/profile`,
+    const response: AxiosResponse<ApiResponse<User>> = await this.client.patch(
+      `/user/profile`,
       profileData
     );
     if (!response.data.success || !response.data.data) {